@@ -0,0 +1,148 @@
+//! Benchmarks comparing `IdentityIndex` lookups with linear scans over
+//! `IdentityEntity` components, and measuring relationship establishment and
+//! identity merges, which resolve their identities through the index
+
+use bevy::app::App;
+use bevy::ecs::prelude::*;
+use cim_domain_identity::{
+    CommandEnvelope, EstablishRelationshipCommand, IdentityEntity, IdentityIndex, IdentityPlugin,
+    IdentityPluginConfig, IdentityStatus, IdentityType, IdentityVerification,
+    MergeIdentitiesCommand, RelationshipRules, RelationshipType, VerificationLevel,
+};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use uuid::Uuid;
+
+fn spawn_identity(world: &mut World) -> Uuid {
+    let identity_id = Uuid::new_v4();
+    world.spawn((
+        IdentityEntity {
+            identity_id,
+            identity_type: IdentityType::Person,
+            status: IdentityStatus::Active,
+        },
+        IdentityVerification {
+            verification_level: VerificationLevel::Unverified,
+            verified_at: None,
+            verified_by: None,
+            verification_method: None,
+        },
+    ));
+    identity_id
+}
+
+fn populate(world: &mut World, count: usize) -> Vec<Uuid> {
+    world.init_resource::<IdentityIndex>();
+
+    (0..count).map(|_| spawn_identity(world)).collect()
+}
+
+/// App running only the command systems, populated with `count` identities
+fn populated_app(count: usize) -> (App, Vec<Uuid>) {
+    let mut app = App::new();
+    app.add_plugins(IdentityPlugin::new(IdentityPluginConfig {
+        enable_projections: false,
+        enable_markers: false,
+        enable_expiry: false,
+    }));
+    let ids = populate(app.world_mut(), count);
+    app.update();
+    (app, ids)
+}
+
+fn identity_lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("identity_lookup");
+
+    for count in [10_000, 100_000] {
+        let mut world = World::new();
+        let ids = populate(&mut world, count);
+        let target = ids[count / 2];
+
+        let mut query = world.query::<(Entity, &IdentityEntity)>();
+        group.bench_with_input(BenchmarkId::new("linear_scan", count), &target, |b, id| {
+            b.iter(|| {
+                let id = black_box(*id);
+                query
+                    .iter(&world)
+                    .find(|(_, identity)| identity.identity_id == id)
+                    .map(|(entity, _)| entity)
+            })
+        });
+
+        let index = world.resource::<IdentityIndex>();
+        group.bench_with_input(BenchmarkId::new("index", count), &target, |b, id| {
+            b.iter(|| index.identity(black_box(*id)))
+        });
+    }
+
+    group.finish();
+}
+
+fn relationship_establishment(c: &mut Criterion) {
+    let mut group = c.benchmark_group("relationship_establishment");
+
+    for count in [10_000, 100_000] {
+        let (mut app, ids) = populated_app(count);
+        let manager = ids[count / 2];
+
+        // Each iteration relates the same manager to a new identity, so the
+        // duplicate check covers a growing set of outgoing relationships
+        group.bench_function(BenchmarkId::from_parameter(count), |b| {
+            b.iter(|| {
+                let employee = spawn_identity(app.world_mut());
+                app.world_mut()
+                    .send_event(CommandEnvelope::new(EstablishRelationshipCommand {
+                        from_identity: manager,
+                        to_identity: employee,
+                        relationship_type: RelationshipType::Manages,
+                        rules: RelationshipRules {
+                            allowed_types: vec![],
+                            constraints: vec![],
+                            require_mutual_consent: false,
+                            allow_multiple: false,
+                        },
+                        established_by: manager,
+                        metadata: None,
+                    }));
+                app.update();
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn identity_merge(c: &mut Criterion) {
+    let mut group = c.benchmark_group("identity_merge");
+
+    for count in [10_000, 100_000] {
+        let (mut app, ids) = populated_app(count);
+
+        // Each iteration merges a new duplicate into one of the identities
+        let mut next = 0;
+        group.bench_function(BenchmarkId::from_parameter(count), |b| {
+            b.iter(|| {
+                let source = spawn_identity(app.world_mut());
+                let target = ids[next % count];
+                next += 1;
+                app.world_mut()
+                    .send_event(CommandEnvelope::new(MergeIdentitiesCommand {
+                        source_identity: source,
+                        target_identity: target,
+                        merged_by: target,
+                        merge_reason: "Duplicate".to_string(),
+                    }));
+                app.update();
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    identity_lookup,
+    relationship_establishment,
+    identity_merge
+);
+criterion_main!(benches);
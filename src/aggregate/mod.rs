@@ -3,7 +3,7 @@
 //! The IdentityAggregate enforces business rules and invariants for identity operations.
 //! It works with ECS components and systems to maintain consistency.
 
-use crate::{commands::*, components::*, IdentityError, IdentityResult};
+use crate::{commands::*, components::*, IdentityError, IdentityIndex, IdentityResult};
 use bevy::ecs::prelude::*;
 
 /// Identity Aggregate that enforces business rules
@@ -16,7 +16,7 @@ impl IdentityAggregate {
     /// Validate identity creation
     pub fn validate_create(
         _command: &CreateIdentityCommand,
-        _existing_identities: &[IdentityEntity],
+        _existing_identities: &IdentityIndex,
     ) -> IdentityResult<()> {
         // Business rule: Cannot create duplicate identities with same claims
         // This would check for existing identities with same email/phone
@@ -89,6 +89,56 @@ impl IdentityAggregate {
         Ok(())
     }
 
+    /// Validate adding or changing a claim of an identity
+    pub fn validate_claim_change(
+        identity: &IdentityEntity,
+        expires_at: Option<chrono::DateTime<chrono::Utc>>,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> IdentityResult<()> {
+        // Business rule: Archived and merged identities keep their claims as they are
+        match identity.status {
+            IdentityStatus::Archived => return Err(IdentityError::IdentityArchived),
+            IdentityStatus::Merged { .. } => return Err(IdentityError::IdentityMerged),
+            _ => {}
+        }
+
+        // Business rule: A claim cannot be added or extended into the past
+        if expires_at.is_some_and(|expires_at| expires_at <= now) {
+            return Err(IdentityError::InvalidOperation(
+                "Claim expiry must be in the future".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Validate publishing a DID or rotating its key
+    pub fn validate_did_change(
+        identity: &IdentityEntity,
+        current: Option<&IdentityDid>,
+        rotating: bool,
+    ) -> IdentityResult<()> {
+        // Business rule: Archived and merged identities no longer control keys
+        match identity.status {
+            IdentityStatus::Archived => return Err(IdentityError::IdentityArchived),
+            IdentityStatus::Merged { .. } => return Err(IdentityError::IdentityMerged),
+            _ => {}
+        }
+
+        match (current, rotating) {
+            // Business rule: An identity publishes a single DID
+            (Some(_), false) => Err(IdentityError::InvalidOperation(
+                "Identity already has a DID".to_string(),
+            )),
+            (None, true) => Err(IdentityError::DidNotFound(identity.identity_id.to_string())),
+            // Business rule: A did:key DID is derived from its key and cannot rotate it
+            (Some(did), true) if did.method == DidMethod::Key => Err(
+                IdentityError::InvalidOperation("did:key DIDs cannot rotate keys".to_string()),
+            ),
+            _ => Ok(()),
+        }
+    }
+
     /// Validate relationship establishment
     pub fn validate_relationship(
         from_identity: IdentityId,
@@ -150,6 +200,86 @@ impl IdentityAggregate {
         }
     }
 
+    /// Validate pausing a workflow
+    pub fn validate_workflow_pause(workflow: &IdentityWorkflow) -> IdentityResult<()> {
+        // Business rule: Only running workflows can be paused
+        if workflow.is_finished() {
+            return Err(IdentityError::WorkflowError(
+                "Workflow has already finished".to_string(),
+            ));
+        }
+        if workflow.status == WorkflowStatus::Paused {
+            return Err(IdentityError::WorkflowError(
+                "Workflow is already paused".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Validate resuming a workflow
+    pub fn validate_workflow_resume(workflow: &IdentityWorkflow) -> IdentityResult<()> {
+        if workflow.status != WorkflowStatus::Paused {
+            return Err(IdentityError::WorkflowError(
+                "Workflow is not paused".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Validate cancelling a workflow
+    pub fn validate_workflow_cancel(workflow: &IdentityWorkflow) -> IdentityResult<()> {
+        // Business rule: Running and paused workflows can be cancelled
+        if workflow.is_finished() {
+            return Err(IdentityError::WorkflowError(
+                "Workflow has already finished".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Validate retrying the step a workflow failed on
+    pub fn validate_step_retry(
+        workflow: &IdentityWorkflow,
+        step_id: &str,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> IdentityResult<()> {
+        // Business rule: Only the step that failed the workflow can be retried
+        let step = workflow
+            .active_step()
+            .filter(|step| {
+                matches!(workflow.status, WorkflowStatus::Failed(_))
+                    && step.step_id == step_id
+                    && step.status == StepStatus::Failed
+            })
+            .ok_or_else(|| {
+                IdentityError::WorkflowError(format!("Step {step_id} has not failed the workflow"))
+            })?;
+
+        // Business rule: Retries follow the step's retry policy
+        let Some(policy) = &step.retry else {
+            return Err(IdentityError::InvalidOperation(format!(
+                "Step {step_id} cannot be retried"
+            )));
+        };
+        if step.retries + 1 >= policy.max_attempts {
+            return Err(IdentityError::InvalidOperation(format!(
+                "Step {step_id} has used all {} attempts",
+                policy.max_attempts
+            )));
+        }
+        let failed_at = step.completed_at.or(workflow.completed_at).unwrap_or(now);
+        let retry_at = failed_at
+            .checked_add_signed(policy.backoff(step.retries))
+            .unwrap_or(chrono::DateTime::<chrono::Utc>::MAX_UTC);
+        if now < retry_at {
+            return Err(IdentityError::InvalidOperation(format!(
+                "Step {step_id} cannot be retried before {retry_at}"
+            )));
+        }
+
+        Ok(())
+    }
+
     /// Validate verification level transition
     pub fn validate_verification_transition(
         current_level: VerificationLevel,
@@ -173,6 +303,119 @@ impl IdentityAggregate {
         Ok(())
     }
 
+    /// Validate that an identity may hold API keys
+    pub fn validate_api_key_holder(identity: &IdentityEntity) -> IdentityResult<()> {
+        // Business rule: Only machine identities authenticate with API keys
+        if !matches!(
+            identity.identity_type,
+            IdentityType::Service | IdentityType::System
+        ) {
+            return Err(IdentityError::InvalidIdentityType);
+        }
+
+        match identity.status {
+            IdentityStatus::Archived => Err(IdentityError::IdentityArchived),
+            IdentityStatus::Merged { .. } => Err(IdentityError::IdentityMerged),
+            _ => Ok(()),
+        }
+    }
+
+    /// Validate that an identity may hold cryptographic keys
+    pub fn validate_key_holder(identity: &IdentityEntity) -> IdentityResult<()> {
+        // Business rule: Devices, services and systems hold their own keys
+        if !matches!(
+            identity.identity_type,
+            IdentityType::Device | IdentityType::Service | IdentityType::System
+        ) {
+            return Err(IdentityError::InvalidIdentityType);
+        }
+
+        match identity.status {
+            IdentityStatus::Archived => Err(IdentityError::IdentityArchived),
+            IdentityStatus::Merged { .. } => Err(IdentityError::IdentityMerged),
+            _ => Ok(()),
+        }
+    }
+
+    /// Validate the purposes requested for a key
+    pub fn validate_key_purposes(
+        algorithm: KeyAlgorithm,
+        purposes: &[KeyPurpose],
+    ) -> IdentityResult<()> {
+        if purposes.is_empty() {
+            return Err(IdentityError::InvalidOperation(
+                "Key needs at least one purpose".to_string(),
+            ));
+        }
+
+        // Business rule: Ed25519 keys sign and authenticate, X25519 keys encrypt
+        if let Some(purpose) = purposes.iter().find(|p| !algorithm.supports(**p)) {
+            return Err(IdentityError::InvalidOperation(format!(
+                "{algorithm:?} keys cannot be used for {purpose:?}"
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Validate an API key authentication request
+    pub fn validate_api_key_use(
+        identity: &IdentityEntity,
+        key: &ApiKeyRecord,
+        required_permissions: &[String],
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> IdentityResult<()> {
+        // Business rule: Keys of inactive identities do not authenticate
+        if identity.status != IdentityStatus::Active {
+            return Err(IdentityError::IdentityNotActive);
+        }
+
+        if !key.is_usable(now) {
+            return Err(IdentityError::ApiKeyInactive(key.key_id));
+        }
+
+        if let Some(missing) = required_permissions.iter().find(|p| !key.grants(p)) {
+            return Err(IdentityError::PermissionDenied(missing.clone()));
+        }
+
+        Ok(())
+    }
+
+    /// Validate that an identity may hold a password
+    pub fn validate_password_holder(identity: &IdentityEntity) -> IdentityResult<()> {
+        // Business rule: Only people authenticate with passwords
+        if identity.identity_type != IdentityType::Person {
+            return Err(IdentityError::InvalidIdentityType);
+        }
+
+        match identity.status {
+            IdentityStatus::Archived => Err(IdentityError::IdentityArchived),
+            IdentityStatus::Merged { .. } => Err(IdentityError::IdentityMerged),
+            _ => Ok(()),
+        }
+    }
+
+    /// Validate that an identity may authenticate
+    pub fn validate_authentication(identity: &IdentityEntity) -> IdentityResult<()> {
+        // Business rule: Only active identities authenticate
+        if identity.status != IdentityStatus::Active {
+            return Err(IdentityError::IdentityNotActive);
+        }
+
+        Ok(())
+    }
+
+    /// Validate that tokens may be issued to or accepted for an identity
+    pub fn validate_token_subject(identity: &IdentityEntity) -> IdentityResult<()> {
+        // Business rule: Suspended, archived and merged identities hold no tokens
+        match identity.status {
+            IdentityStatus::Suspended => Err(IdentityError::IdentityNotActive),
+            IdentityStatus::Archived => Err(IdentityError::IdentityArchived),
+            IdentityStatus::Merged { .. } => Err(IdentityError::IdentityMerged),
+            IdentityStatus::Pending | IdentityStatus::Active => Ok(()),
+        }
+    }
+
     /// Calculate aggregate state from components
     pub fn calculate_state(
         identity: &IdentityEntity,
@@ -0,0 +1,136 @@
+//! Authentication attempts with several factors
+//!
+//! An attempt decides up front which factors a person must present, from
+//! the verification level of the identity, whether it enabled MFA and the
+//! risk the [`RiskEngine`](super::RiskEngine) scores for the location the
+//! attempt comes from. The factors are challenged one at a time in the order
+//! [`required_factors`] returns them, and the attempt ends with a single
+//! [`AuthenticationDecision`].
+
+use super::{LocationContext, MfaCode, Password};
+use crate::components::VerificationLevel;
+use crate::{IdentityError, IdentityResult};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Factor presented during an authentication attempt
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AuthFactor {
+    Password,
+    /// TOTP code, or one of the backup codes
+    Totp,
+}
+
+/// Risk attributed to the location an attempt comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum LocationRisk {
+    Low,
+    Elevated,
+    High,
+}
+
+/// Factors a person must present, in order
+///
+/// A password is always required and enabled MFA always adds its code.
+/// Without MFA, high-risk locations are refused outright, and elevated risk
+/// is refused below `VerificationLevel::Enhanced`.
+pub fn required_factors(
+    verification_level: VerificationLevel,
+    mfa_enabled: bool,
+    risk: LocationRisk,
+) -> IdentityResult<Vec<AuthFactor>> {
+    if mfa_enabled {
+        return Ok(vec![AuthFactor::Password, AuthFactor::Totp]);
+    }
+
+    match risk {
+        LocationRisk::Low => Ok(vec![AuthFactor::Password]),
+        LocationRisk::Elevated if verification_level >= VerificationLevel::Enhanced => {
+            Ok(vec![AuthFactor::Password])
+        }
+        LocationRisk::Elevated | LocationRisk::High => {
+            Err(IdentityError::LocationRiskTooHigh(risk))
+        }
+    }
+}
+
+/// Answer to the challenge of an authentication attempt
+///
+/// Redacted in `Debug` and `Serialize` output like the secrets it carries.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FactorResponse {
+    Password(Password),
+    /// TOTP or backup code
+    Code(MfaCode),
+}
+
+impl FactorResponse {
+    /// Factor this response can answer
+    pub fn factor(&self) -> AuthFactor {
+        match self {
+            FactorResponse::Password(_) => AuthFactor::Password,
+            FactorResponse::Code(_) => AuthFactor::Totp,
+        }
+    }
+}
+
+/// Request for the next factor of an attempt
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthenticationChallenge {
+    pub challenge_id: Uuid,
+    pub factor: AuthFactor,
+    pub issued_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl AuthenticationChallenge {
+    /// Time a challenge stays open
+    pub const TTL_SECS: i64 = 300;
+
+    /// Challenge `factor` at `now`
+    pub fn issue(factor: AuthFactor, now: DateTime<Utc>) -> Self {
+        AuthenticationChallenge {
+            challenge_id: Uuid::new_v4(),
+            factor,
+            issued_at: now,
+            expires_at: now + chrono::Duration::seconds(Self::TTL_SECS),
+        }
+    }
+
+    /// Whether the challenge can still be answered at `now`
+    pub fn is_open(&self, now: DateTime<Utc>) -> bool {
+        now < self.expires_at
+    }
+}
+
+/// Authentication attempt waiting for its factors
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuthenticationAttempt {
+    pub attempt_id: Uuid,
+    pub required_factors: Vec<AuthFactor>,
+    /// Factors presented so far, in order
+    pub completed_factors: Vec<AuthFactor>,
+    pub location_risk: LocationRisk,
+    /// Where the attempt comes from, added to the history once granted
+    pub location: LocationContext,
+    pub started_at: DateTime<Utc>,
+    /// Challenge of the factor presented next
+    pub challenge: Option<AuthenticationChallenge>,
+}
+
+impl AuthenticationAttempt {
+    /// Factor to challenge once the current one is presented
+    pub fn factor_after_current(&self) -> Option<AuthFactor> {
+        self.required_factors
+            .get(self.completed_factors.len() + 1)
+            .copied()
+    }
+}
+
+/// Outcome of an authentication attempt
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AuthenticationDecision {
+    Granted,
+    Denied(IdentityError),
+}
@@ -0,0 +1,108 @@
+//! Account lockout policy and clocks
+//!
+//! The lockout policy decides when repeated authentication failures lock an
+//! account and for how long. The authentication systems read the time through
+//! the [`Clock`] of the [`super::AuthenticationClock`] resource, so lockouts
+//! can be driven deterministically in tests.
+
+use chrono::{DateTime, Duration, Utc};
+use std::sync::{Arc, Mutex};
+
+/// Source of the current time
+pub trait Clock: std::fmt::Debug + Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// Clock reading the system time
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Clock that only moves when told to
+///
+/// Clones share the same time, so a test can keep a handle while the
+/// [`super::AuthenticationClock`] owns another.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    now: Arc<Mutex<DateTime<Utc>>>,
+}
+
+impl ManualClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        ManualClock {
+            now: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    /// Move the clock forward
+    pub fn advance(&self, by: Duration) {
+        let mut now = self.now.lock().unwrap();
+        *now += by;
+    }
+
+    /// Set the clock to a specific instant
+    pub fn set(&self, to: DateTime<Utc>) {
+        *self.now.lock().unwrap() = to;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap()
+    }
+}
+
+/// Policy locking accounts after repeated authentication failures
+///
+/// After `threshold` consecutive failures the account is locked for
+/// `initial_lockout`. Every further lockout before a successful login
+/// multiplies the window by `backoff_factor`, up to `max_lockout`. Locks
+/// expire on their own once the window has passed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockoutPolicy {
+    /// Consecutive failures that trigger a lockout; 0 disables locking
+    pub threshold: u32,
+    /// Duration of the first lockout
+    pub initial_lockout: Duration,
+    /// Growth factor applied to each subsequent lockout
+    pub backoff_factor: u32,
+    /// Upper bound for a single lockout window
+    pub max_lockout: Duration,
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> Self {
+        LockoutPolicy {
+            threshold: 5,
+            initial_lockout: Duration::minutes(5),
+            backoff_factor: 2,
+            max_lockout: Duration::hours(24),
+        }
+    }
+}
+
+impl LockoutPolicy {
+    /// Whether this many consecutive failures should lock the account
+    pub fn should_lock(&self, failed_attempts: u32) -> bool {
+        self.threshold > 0 && failed_attempts >= self.threshold
+    }
+
+    /// Length of the lockout following `previous_lockouts` earlier ones
+    pub fn lockout_duration(&self, previous_lockouts: u32) -> Duration {
+        let factor = i32::try_from(self.backoff_factor.max(1)).unwrap_or(i32::MAX);
+        let mut duration = self.initial_lockout;
+        for _ in 0..previous_lockouts {
+            let next = duration.checked_mul(factor).unwrap_or(self.max_lockout);
+            if duration >= self.max_lockout || next <= duration {
+                break;
+            }
+            duration = next;
+        }
+        duration.min(self.max_lockout)
+    }
+}
@@ -0,0 +1,368 @@
+//! Multi-factor authentication primitives
+//!
+//! TOTP follows RFC 6238 on top of the HOTP construction from RFC 4226.
+//! Verification accepts codes from a small window of neighbouring time steps
+//! to tolerate clock drift, and never accepts a step at or before the last
+//! one used, so an observed code cannot be replayed.
+//!
+//! Backup codes are random, shown to the person once and only stored as
+//! SHA-256 hashes. Each code can be consumed a single time.
+//!
+//! The shared secret has to be kept to verify codes, so it is recorded in
+//! [`crate::events::MfaEnabled`] and [`crate::components::IdentityMfa`]
+//! through [`exposed`]. Everywhere else, such as the command enabling MFA and
+//! its rejection, it is serialized as `"<redacted>"`.
+
+use crate::IdentityError;
+use chrono::{DateTime, Utc};
+use data_encoding::{BASE32_NOPAD, HEXLOWER};
+use hmac::digest::KeyInit;
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+use subtle::ConstantTimeEq;
+use uuid::Uuid;
+
+/// Length of generated TOTP secrets in bytes (160 bits, as recommended by RFC 4226)
+const SECRET_LEN: usize = 20;
+
+/// Characters in a backup code, excluding the separator
+const BACKUP_CODE_LEN: usize = 10;
+
+/// HMAC algorithm used to derive TOTP codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TotpAlgorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl TotpAlgorithm {
+    fn uri_name(&self) -> &'static str {
+        match self {
+            TotpAlgorithm::Sha1 => "SHA1",
+            TotpAlgorithm::Sha256 => "SHA256",
+            TotpAlgorithm::Sha512 => "SHA512",
+        }
+    }
+}
+
+/// TOTP parameters, shared with the authenticator through the provisioning URI
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TotpConfig {
+    pub algorithm: TotpAlgorithm,
+    /// Number of digits in a code
+    pub digits: u32,
+    /// Length of a time step in seconds
+    pub period_secs: u64,
+    /// Time steps accepted on either side of the current one
+    pub skew: u64,
+}
+
+impl Default for TotpConfig {
+    /// Settings understood by all common authenticator apps
+    fn default() -> Self {
+        TotpConfig {
+            algorithm: TotpAlgorithm::Sha1,
+            digits: 6,
+            period_secs: 30,
+            skew: 1,
+        }
+    }
+}
+
+impl TotpConfig {
+    /// Time step containing `at`
+    pub fn step_at(&self, at: DateTime<Utc>) -> u64 {
+        at.timestamp().max(0) as u64 / self.period_secs.max(1)
+    }
+}
+
+/// Shared TOTP secret, left out of `Debug` output and serialized as `"<redacted>"`
+#[derive(Clone, PartialEq, Eq)]
+pub struct TotpSecret(Vec<u8>);
+
+impl TotpSecret {
+    /// Generate a new random secret
+    pub fn generate() -> Self {
+        let mut bytes = vec![0u8; SECRET_LEN];
+        OsRng.fill_bytes(&mut bytes);
+        TotpSecret(bytes)
+    }
+
+    /// Wrap raw secret bytes
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        TotpSecret(bytes)
+    }
+
+    /// Parse a base32 secret, ignoring padding, spaces and case
+    pub fn from_base32(encoded: &str) -> Result<Self, IdentityError> {
+        let normalized: String = encoded
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '=')
+            .map(|c| c.to_ascii_uppercase())
+            .collect();
+        BASE32_NOPAD
+            .decode(normalized.as_bytes())
+            .map(TotpSecret)
+            .map_err(|e| IdentityError::InvalidOperation(format!("Invalid TOTP secret: {e}")))
+    }
+
+    /// Unpadded base32 encoding, as entered into authenticator apps
+    pub fn to_base32(&self) -> String {
+        BASE32_NOPAD.encode(&self.0)
+    }
+
+    /// Raw secret bytes
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// `otpauth://` URI for enrolling the secret in an authenticator app
+    pub fn provisioning_uri(&self, issuer: &str, account: &str, config: &TotpConfig) -> String {
+        format!(
+            "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm={}&digits={}&period={}",
+            uri_encode(issuer),
+            uri_encode(account),
+            self.to_base32(),
+            uri_encode(issuer),
+            config.algorithm.uri_name(),
+            config.digits,
+            config.period_secs,
+        )
+    }
+}
+
+impl std::fmt::Debug for TotpSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("TotpSecret(<redacted>)")
+    }
+}
+
+impl Serialize for TotpSecret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str("<redacted>")
+    }
+}
+
+impl<'de> Deserialize<'de> for TotpSecret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        exposed::deserialize(deserializer)
+    }
+}
+
+/// Serialize a [`TotpSecret`] as unpadded base32, for the state it is verified against
+///
+/// Use with `#[serde(with = "crate::authentication::mfa::exposed")]`.
+pub mod exposed {
+    use super::TotpSecret;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(secret: &TotpSecret, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&secret.to_base32())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<TotpSecret, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        TotpSecret::from_base32(&encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+/// One-time code presented as a second factor, either a TOTP or a backup code
+///
+/// Left out of `Debug` output and serialized as `"<redacted>"` like
+/// [`super::Password`].
+#[derive(Clone, PartialEq, Eq)]
+pub struct MfaCode(String);
+
+impl MfaCode {
+    pub fn new(code: impl Into<String>) -> Self {
+        MfaCode(code.into())
+    }
+
+    /// The plaintext code
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for MfaCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("MfaCode(<redacted>)")
+    }
+}
+
+impl Serialize for MfaCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str("<redacted>")
+    }
+}
+
+impl<'de> Deserialize<'de> for MfaCode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(MfaCode)
+    }
+}
+
+/// HOTP value for a counter (RFC 4226), zero-padded to `config.digits`
+pub fn hotp(secret: &TotpSecret, counter: u64, config: &TotpConfig) -> String {
+    let digest = match config.algorithm {
+        TotpAlgorithm::Sha1 => hmac_digest::<Hmac<Sha1>>(secret.as_bytes(), counter),
+        TotpAlgorithm::Sha256 => hmac_digest::<Hmac<Sha256>>(secret.as_bytes(), counter),
+        TotpAlgorithm::Sha512 => hmac_digest::<Hmac<Sha512>>(secret.as_bytes(), counter),
+    };
+
+    // Dynamic truncation
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    let digits = config.digits.clamp(1, 9);
+    format!(
+        "{:0width$}",
+        binary % 10u32.pow(digits),
+        width = digits as usize
+    )
+}
+
+fn hmac_digest<M: Mac + KeyInit>(key: &[u8], counter: u64) -> Vec<u8> {
+    let mut mac = <M as KeyInit>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// TOTP code for the time step containing `at`
+pub fn totp(secret: &TotpSecret, at: DateTime<Utc>, config: &TotpConfig) -> String {
+    hotp(secret, config.step_at(at), config)
+}
+
+/// Verify a TOTP code at `now`
+///
+/// Steps within `config.skew` of the current one are accepted, except those
+/// at or before `last_used_step`. Returns the matched step, which the caller
+/// must remember to reject replays.
+pub fn verify_totp(
+    secret: &TotpSecret,
+    code: &str,
+    now: DateTime<Utc>,
+    config: &TotpConfig,
+    last_used_step: Option<u64>,
+) -> Option<u64> {
+    let code = code.trim();
+    let current = config.step_at(now);
+    let first = current.saturating_sub(config.skew);
+    let mut matched = None;
+
+    // Check every candidate so timing does not reveal which step matched
+    for step in first..=current + config.skew {
+        let candidate = hotp(secret, step, config);
+        let equal: bool = candidate.as_bytes().ct_eq(code.as_bytes()).into();
+        let fresh = last_used_step.is_none_or(|last| step > last);
+        if equal && fresh && matched.is_none() {
+            matched = Some(step);
+        }
+    }
+
+    matched
+}
+
+/// Generate `count` random backup codes formatted as `XXXXX-XXXXX`
+pub fn generate_backup_codes(count: usize) -> Vec<String> {
+    (0..count)
+        .map(|_| {
+            let mut bytes = [0u8; 8];
+            OsRng.fill_bytes(&mut bytes);
+            let encoded = BASE32_NOPAD.encode(&bytes);
+            let code = &encoded[..BACKUP_CODE_LEN];
+            format!(
+                "{}-{}",
+                &code[..BACKUP_CODE_LEN / 2],
+                &code[BACKUP_CODE_LEN / 2..]
+            )
+        })
+        .collect()
+}
+
+/// Hash a backup code for storage; separators, spaces and case are ignored
+pub fn hash_backup_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    HEXLOWER.encode(&Sha256::digest(normalized.as_bytes()))
+}
+
+/// Stored hash matching `code`, if it is an unused backup code
+///
+/// The caller consumes the code by removing the returned hash.
+pub fn match_backup_code(hashes: &[String], code: &str) -> Option<String> {
+    let candidate = hash_backup_code(code);
+    let mut matched = None;
+
+    // Compare against every hash so timing does not reveal which one matched
+    for stored in hashes {
+        let equal: bool = stored.as_bytes().ct_eq(candidate.as_bytes()).into();
+        if equal && matched.is_none() {
+            matched = Some(stored.clone());
+        }
+    }
+
+    matched
+}
+
+/// Second factor that answered a challenge
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MfaFactor {
+    /// TOTP code of the given time step
+    Totp { step: u64 },
+    /// Backup code with the given hash, which is consumed
+    BackupCode { code_hash: String },
+}
+
+/// Outstanding request for a second factor
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MfaChallenge {
+    pub challenge_id: Uuid,
+    pub issued_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl MfaChallenge {
+    /// Time a challenge stays open
+    pub const TTL_SECS: i64 = 300;
+
+    /// Open a new challenge at `now`
+    pub fn issue(now: DateTime<Utc>) -> Self {
+        MfaChallenge {
+            challenge_id: Uuid::new_v4(),
+            issued_at: now,
+            expires_at: now + chrono::Duration::seconds(Self::TTL_SECS),
+        }
+    }
+
+    /// Whether the challenge can still be answered at `now`
+    pub fn is_open(&self, now: DateTime<Utc>) -> bool {
+        now < self.expires_at
+    }
+}
+
+/// Percent-encode everything outside the RFC 3986 unreserved set
+fn uri_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
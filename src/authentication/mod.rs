@@ -0,0 +1,90 @@
+//! Authentication of person identities
+//!
+//! Passwords are hashed with argon2id by [`hash_password`] and only their
+//! hash is kept in [`IdentityCredentials`], next to the failure counters the
+//! [`LockoutPolicy`] locks accounts by. That component is changed by the
+//! authentication systems solely through the events they emit, and replay
+//! applies the same events, so a rebuilt world authenticates and locks
+//! exactly like the one that recorded them.
+//!
+//! Person identities with a password can add a TOTP second factor with
+//! single-use backup codes, kept in [`IdentityMfa`]. Wrong codes count
+//! towards the same lockout as wrong passwords.
+//!
+//! An authentication attempt combines these checks: it chooses the factors
+//! required from the identity's verification level, its MFA and the risk the
+//! [`RiskEngine`] assigns its [`LocationContext`] given the identity's
+//! [`AuthenticationHistory`], challenges them in turn and ends with an
+//! [`AuthenticationDecision`]. Granted attempts extend that history.
+//!
+//! Once authenticated, an identity holds one session per device in
+//! [`IdentitySessions`], ended by revocation or by the timeouts of the
+//! [`SessionPolicy`].
+//!
+//! [`IdentityCredentials`]: crate::components::IdentityCredentials
+//! [`IdentityMfa`]: crate::components::IdentityMfa
+//! [`IdentitySessions`]: crate::components::IdentitySessions
+
+pub mod factors;
+pub mod lockout;
+pub mod mfa;
+pub mod password;
+pub mod risk;
+pub mod session;
+
+pub use factors::*;
+pub use lockout::*;
+pub use mfa::*;
+pub use password::*;
+pub use risk::*;
+pub use session::*;
+
+use bevy::ecs::prelude::*;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Settings applied by the authentication systems
+#[derive(Resource, Debug, Clone, Default)]
+pub struct AuthenticationPolicy {
+    /// Parameters new password hashes are produced with
+    pub password: PasswordHashParams,
+    pub lockout: LockoutPolicy,
+    /// TOTP parameters codes are verified with
+    pub totp: TotpConfig,
+    pub session: SessionPolicy,
+    /// Scores the location of authentication attempts
+    pub risk: RiskEngine,
+}
+
+/// Where an authentication attempt comes from, as reported by the client
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LocationContext {
+    pub ip_address: Option<String>,
+    /// Latitude and longitude in degrees
+    pub coordinates: Option<(f64, f64)>,
+    /// ISO 3166-1 alpha-2 country code
+    pub country: Option<String>,
+    pub network_type: Option<String>,
+    pub device_id: Option<String>,
+}
+
+/// Time source of the authentication systems
+#[derive(Resource, Debug, Clone)]
+pub struct AuthenticationClock(Arc<dyn Clock>);
+
+impl AuthenticationClock {
+    pub fn new(clock: impl Clock + 'static) -> Self {
+        AuthenticationClock(Arc::new(clock))
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        self.0.now()
+    }
+}
+
+impl Default for AuthenticationClock {
+    fn default() -> Self {
+        AuthenticationClock::new(SystemClock)
+    }
+}
@@ -0,0 +1,125 @@
+//! Password hashing
+//!
+//! Passwords are stored as argon2id PHC strings, which embed the salt and the
+//! parameters used. Verification reads the parameters back from the stored
+//! hash, so existing hashes keep verifying after the configured parameters
+//! change; [`needs_rehash`] tells the authentication system when a hash
+//! should be upgraded.
+
+use crate::{IdentityError, IdentityResult};
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Plaintext password presented by or set for an identity
+///
+/// Only ever hashed. It is left out of `Debug` output and serialized as
+/// `"<redacted>"`, so commands and rejections carrying it can be logged or
+/// forwarded without exposing it.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Password(String);
+
+impl Password {
+    pub fn new(password: impl Into<String>) -> Self {
+        Password(password.into())
+    }
+
+    /// The plaintext password
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for Password {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Password(<redacted>)")
+    }
+}
+
+impl Serialize for Password {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str("<redacted>")
+    }
+}
+
+impl<'de> Deserialize<'de> for Password {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Password)
+    }
+}
+
+/// Argon2id cost parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PasswordHashParams {
+    /// Memory cost in KiB
+    pub memory_kib: u32,
+    /// Number of passes over memory
+    pub iterations: u32,
+    /// Degree of parallelism
+    pub parallelism: u32,
+}
+
+impl Default for PasswordHashParams {
+    /// OWASP recommended minimum for argon2id
+    fn default() -> Self {
+        PasswordHashParams {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+impl PasswordHashParams {
+    fn argon2(&self) -> IdentityResult<Argon2<'static>> {
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, None)
+            .map_err(|e| {
+                IdentityError::InvalidOperation(format!("Invalid argon2 parameters: {e}"))
+            })?;
+        Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+    }
+}
+
+/// Hash a plaintext password with a fresh random salt
+pub fn hash_password(password: &Password, params: &PasswordHashParams) -> IdentityResult<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    params
+        .argon2()?
+        .hash_password(password.expose().as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| IdentityError::InvalidOperation(format!("Password hashing failed: {e}")))
+}
+
+/// Verify a plaintext password against a stored PHC hash
+///
+/// The comparison of the derived key is constant time. Hashes that cannot be
+/// parsed never verify.
+pub fn verify_password(password: &Password, password_hash: &str) -> bool {
+    let Ok(parsed) = PasswordHash::new(password_hash) else {
+        return false;
+    };
+    Argon2::default()
+        .verify_password(password.expose().as_bytes(), &parsed)
+        .is_ok()
+}
+
+/// Whether a stored hash was produced with anything other than argon2id and `params`
+pub fn needs_rehash(password_hash: &str, params: &PasswordHashParams) -> bool {
+    let Ok(parsed) = PasswordHash::new(password_hash) else {
+        return true;
+    };
+    if parsed.algorithm != Algorithm::Argon2id.ident()
+        || parsed.version != Some(Version::V0x13.into())
+    {
+        return true;
+    }
+    match Params::try_from(&parsed) {
+        Ok(current) => {
+            current.m_cost() != params.memory_kib
+                || current.t_cost() != params.iterations
+                || current.p_cost() != params.parallelism
+        }
+        Err(_) => true,
+    }
+}
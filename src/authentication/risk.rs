@@ -0,0 +1,268 @@
+//! Risk scoring for authentication attempts
+//!
+//! The risk engine compares an attempt with the identity's earlier successful
+//! authentications. Each signal adds to a score, and the score maps to a
+//! [`LocationRisk`] that drives step-up requirements. The engine only does
+//! arithmetic on its inputs, so it runs and tests without any network access.
+
+use super::{LocationContext, LocationRisk};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+/// Mean Earth radius used for great-circle distances
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// Where and from what an authentication came
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoginObservation {
+    pub at: DateTime<Utc>,
+    pub ip_address: Option<String>,
+    /// Latitude and longitude in degrees
+    pub coordinates: Option<(f64, f64)>,
+    pub country: Option<String>,
+    pub network_type: Option<String>,
+    pub device_id: Option<String>,
+}
+
+impl LoginObservation {
+    /// Observation of an authentication from `location` at `at`
+    pub fn new(location: &LocationContext, at: DateTime<Utc>) -> Self {
+        LoginObservation {
+            at,
+            ip_address: location.ip_address.clone(),
+            coordinates: location.coordinates,
+            country: location.country.clone(),
+            network_type: location.network_type.clone(),
+            device_id: location.device_id.clone(),
+        }
+    }
+}
+
+/// Recent successful authentications of an identity, oldest first
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AuthenticationHistory {
+    observations: VecDeque<LoginObservation>,
+}
+
+impl AuthenticationHistory {
+    /// Observations kept per identity
+    pub const MAX_OBSERVATIONS: usize = 50;
+
+    /// Record a successful authentication, dropping the oldest beyond the limit
+    pub fn record(&mut self, observation: LoginObservation) {
+        self.observations.push_back(observation);
+        while self.observations.len() > Self::MAX_OBSERVATIONS {
+            self.observations.pop_front();
+        }
+    }
+
+    pub fn observations(&self) -> impl Iterator<Item = &LoginObservation> {
+        self.observations.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.observations.is_empty()
+    }
+
+    fn knows_device(&self, device_id: &str) -> bool {
+        self.observations
+            .iter()
+            .any(|o| o.device_id.as_deref() == Some(device_id))
+    }
+
+    fn knows_country(&self, country: &str) -> bool {
+        self.observations.iter().any(|o| {
+            o.country
+                .as_deref()
+                .is_some_and(|c| c.eq_ignore_ascii_case(country))
+        })
+    }
+
+    fn last_located(&self) -> Option<(&LoginObservation, (f64, f64))> {
+        self.observations
+            .iter()
+            .rev()
+            .find_map(|o| o.coordinates.map(|coordinates| (o, coordinates)))
+    }
+}
+
+/// Reason an attempt looks unusual
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RiskSignal {
+    /// The attempt carries no device id
+    UnknownDevice,
+    /// The device has not authenticated this identity before
+    NewDevice { device_id: String },
+    /// The country has not been seen for this identity before
+    NewCountry { country: String },
+    /// The distance from the last located authentication cannot have been travelled
+    ImpossibleTravel { distance_km: f64, speed_kmh: f64 },
+    /// The network type is associated with abuse
+    UnusualNetwork { network_type: String },
+}
+
+/// Weights and thresholds of the risk engine
+#[derive(Debug, Clone, PartialEq)]
+pub struct RiskPolicy {
+    pub unknown_device_score: u32,
+    pub new_device_score: u32,
+    pub new_country_score: u32,
+    pub impossible_travel_score: u32,
+    /// Score per network type, matched case-insensitively
+    pub network_scores: HashMap<String, u32>,
+    /// Fastest plausible travel between two authentications
+    pub max_travel_speed_kmh: f64,
+    /// Distances below this are never impossible travel, absorbing geolocation error
+    pub min_travel_distance_km: f64,
+    /// Scores from here on are `LocationRisk::Elevated`
+    pub elevated_threshold: u32,
+    /// Scores from here on are `LocationRisk::High`
+    pub high_threshold: u32,
+}
+
+impl Default for RiskPolicy {
+    fn default() -> Self {
+        RiskPolicy {
+            unknown_device_score: 20,
+            new_device_score: 25,
+            new_country_score: 30,
+            impossible_travel_score: 60,
+            network_scores: HashMap::from([
+                ("tor".to_string(), 60),
+                ("anonymizer".to_string(), 60),
+                ("proxy".to_string(), 30),
+                ("hosting".to_string(), 30),
+                ("vpn".to_string(), 20),
+            ]),
+            max_travel_speed_kmh: 1000.0,
+            min_travel_distance_km: 500.0,
+            elevated_threshold: 30,
+            high_threshold: 60,
+        }
+    }
+}
+
+/// Result of scoring an attempt
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RiskAssessment {
+    pub score: u32,
+    pub level: LocationRisk,
+    pub signals: Vec<RiskSignal>,
+}
+
+impl RiskAssessment {
+    /// Whether the attempt should be reported as suspicious
+    pub fn is_suspicious(&self) -> bool {
+        self.level > LocationRisk::Low
+    }
+}
+
+/// Scores authentication attempts against an identity's history
+#[derive(Debug, Clone, Default)]
+pub struct RiskEngine {
+    policy: RiskPolicy,
+}
+
+impl RiskEngine {
+    pub fn new(policy: RiskPolicy) -> Self {
+        RiskEngine { policy }
+    }
+
+    pub fn policy(&self) -> &RiskPolicy {
+        &self.policy
+    }
+
+    /// Score an attempt
+    ///
+    /// New devices and countries are only signalled once the identity has a
+    /// history to compare with.
+    pub fn assess(
+        &self,
+        history: &AuthenticationHistory,
+        attempt: &LoginObservation,
+    ) -> RiskAssessment {
+        let policy = &self.policy;
+        let mut score = 0;
+        let mut signals = Vec::new();
+
+        match &attempt.device_id {
+            None => {
+                score += policy.unknown_device_score;
+                signals.push(RiskSignal::UnknownDevice);
+            }
+            Some(device_id) if !history.is_empty() && !history.knows_device(device_id) => {
+                score += policy.new_device_score;
+                signals.push(RiskSignal::NewDevice {
+                    device_id: device_id.clone(),
+                });
+            }
+            Some(_) => {}
+        }
+
+        if let Some(country) = &attempt.country {
+            if !history.is_empty() && !history.knows_country(country) {
+                score += policy.new_country_score;
+                signals.push(RiskSignal::NewCountry {
+                    country: country.clone(),
+                });
+            }
+        }
+
+        if let (Some(to), Some((last, from))) = (attempt.coordinates, history.last_located()) {
+            let distance_km = haversine_km(from, to);
+            let hours = (attempt.at - last.at).num_seconds().max(0) as f64 / 3600.0;
+            let speed_kmh = if hours > 0.0 {
+                distance_km / hours
+            } else {
+                f64::INFINITY
+            };
+            if distance_km >= policy.min_travel_distance_km
+                && speed_kmh > policy.max_travel_speed_kmh
+            {
+                score += policy.impossible_travel_score;
+                signals.push(RiskSignal::ImpossibleTravel {
+                    distance_km,
+                    speed_kmh,
+                });
+            }
+        }
+
+        if let Some(network_type) = &attempt.network_type {
+            let network_score = policy
+                .network_scores
+                .iter()
+                .find(|(kind, _)| kind.eq_ignore_ascii_case(network_type))
+                .map(|(_, score)| *score);
+            if let Some(network_score) = network_score {
+                score += network_score;
+                signals.push(RiskSignal::UnusualNetwork {
+                    network_type: network_type.clone(),
+                });
+            }
+        }
+
+        let level = if score >= policy.high_threshold {
+            LocationRisk::High
+        } else if score >= policy.elevated_threshold {
+            LocationRisk::Elevated
+        } else {
+            LocationRisk::Low
+        };
+
+        RiskAssessment {
+            score,
+            level,
+            signals,
+        }
+    }
+}
+
+/// Great-circle distance between two latitude/longitude pairs in degrees
+pub fn haversine_km(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (lat1, lon1) = (from.0.to_radians(), from.1.to_radians());
+    let (lat2, lon2) = (to.0.to_radians(), to.1.to_radians());
+    let a = ((lat2 - lat1) / 2.0).sin().powi(2)
+        + lat1.cos() * lat2.cos() * ((lon2 - lon1) / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().min(1.0).asin()
+}
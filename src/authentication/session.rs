@@ -0,0 +1,168 @@
+//! Authenticated sessions
+//!
+//! A session is started once an identity has authenticated, using up the
+//! [`SessionGrant`] of that authentication, and is bound to the device it was
+//! started from. It ends when it is revoked, when it has
+//! been idle for longer than the idle timeout, or when it reaches its absolute
+//! lifetime; refreshing extends the idle deadline but never the absolute one.
+//!
+//! Refresh tokens have the form `<session id>.<secret>`. Only a SHA-256 hash
+//! of the token is stored and every refresh replaces it.
+
+use super::LocationContext;
+use chrono::{DateTime, Duration, Utc};
+use data_encoding::HEXLOWER;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// Timeouts applied to sessions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionPolicy {
+    /// Inactivity after which a session ends
+    pub idle_timeout: Duration,
+    /// Lifetime after which a session ends regardless of activity
+    pub absolute_timeout: Duration,
+    /// Time after authenticating within which a session may be started
+    pub grant_timeout: Duration,
+}
+
+impl Default for SessionPolicy {
+    fn default() -> Self {
+        SessionPolicy {
+            idle_timeout: Duration::minutes(30),
+            absolute_timeout: Duration::hours(12),
+            grant_timeout: Duration::minutes(5),
+        }
+    }
+}
+
+/// Authentication that may start one session
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionGrant {
+    /// Granted attempt, if the identity authenticated through one
+    pub attempt_id: Option<Uuid>,
+    pub authenticated_at: DateTime<Utc>,
+}
+
+impl SessionGrant {
+    /// Whether a session for `attempt_id` may be started with this grant at `now`
+    pub fn allows(
+        &self,
+        attempt_id: Option<Uuid>,
+        now: DateTime<Utc>,
+        policy: &SessionPolicy,
+    ) -> bool {
+        now < self.authenticated_at + policy.grant_timeout
+            && attempt_id.is_none_or(|attempt_id| self.attempt_id == Some(attempt_id))
+    }
+}
+
+/// Device a session was started from
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionDevice {
+    pub device_id: Option<String>,
+    pub ip_address: Option<String>,
+    pub country: Option<String>,
+}
+
+impl From<&LocationContext> for SessionDevice {
+    fn from(location: &LocationContext) -> Self {
+        SessionDevice {
+            device_id: location.device_id.clone(),
+            ip_address: location.ip_address.clone(),
+            country: location.country.clone(),
+        }
+    }
+}
+
+/// How the identity authenticated before its session was started
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AuthMethod {
+    Password,
+    /// Password followed by a second factor
+    PasswordAndMfa,
+}
+
+/// Why a session ended
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SessionEndReason {
+    /// Revoked explicitly, e.g. by logging out
+    Revoked(String),
+    /// Replaced by a newer session on the same device
+    Superseded,
+    /// Idle for longer than the idle timeout
+    IdleTimeout,
+    /// Reached its absolute lifetime
+    AbsoluteTimeout,
+}
+
+/// Refresh token of a session
+///
+/// Generated by the caller and handed to the client; the session only keeps
+/// [`RefreshToken::hash`]. Left out of `Debug` output and serialized as
+/// `"<redacted>"` like [`super::Password`].
+#[derive(Clone, PartialEq, Eq)]
+pub struct RefreshToken(String);
+
+impl RefreshToken {
+    /// Generate the first token of a new session, with a fresh session id
+    pub fn generate() -> Self {
+        Self::for_session(Uuid::new_v4())
+    }
+
+    /// Generate a new token for an existing session
+    pub fn for_session(session_id: Uuid) -> Self {
+        let mut secret = [0u8; 32];
+        OsRng.fill_bytes(&mut secret);
+        RefreshToken(format!(
+            "{}.{}",
+            session_id.simple(),
+            HEXLOWER.encode(&secret)
+        ))
+    }
+
+    /// Wrap a token presented by a client
+    pub fn from_presented(token: impl Into<String>) -> Self {
+        RefreshToken(token.into())
+    }
+
+    /// Session id embedded in the token, if it is well formed
+    pub fn session_id(&self) -> Option<Uuid> {
+        let (session_id, secret) = self.0.split_once('.')?;
+        if secret.is_empty() {
+            return None;
+        }
+        Uuid::try_parse(session_id).ok()
+    }
+
+    /// SHA-256 hash stored in place of the token
+    pub fn hash(&self) -> String {
+        HEXLOWER.encode(&Sha256::digest(self.0.as_bytes()))
+    }
+
+    /// The plaintext token, to be handed to the client
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for RefreshToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("RefreshToken(<redacted>)")
+    }
+}
+
+impl Serialize for RefreshToken {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str("<redacted>")
+    }
+}
+
+impl<'de> Deserialize<'de> for RefreshToken {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(RefreshToken)
+    }
+}
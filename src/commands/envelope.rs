@@ -0,0 +1,45 @@
+//! Command envelope carrying correlation identifiers
+//!
+//! Every identity command is sent wrapped in a `CommandEnvelope`. The
+//! envelope's `command_id` becomes the `causation_id` of every event the
+//! command produces and its `correlation_id` is copied unchanged, so callers
+//! can link emitted events back to the request that caused them.
+
+use bevy::ecs::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Envelope wrapping a command with its identifiers
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct CommandEnvelope<C: Send + Sync + 'static> {
+    /// Unique id of this command
+    pub command_id: Uuid,
+    /// Id shared by every message belonging to the same request flow
+    pub correlation_id: Uuid,
+    /// Id of the message that caused this command, if any
+    pub causation_id: Option<Uuid>,
+    pub command: C,
+}
+
+impl<C: Send + Sync + 'static> CommandEnvelope<C> {
+    /// Wrap a command starting a new correlation flow
+    pub fn new(command: C) -> Self {
+        let command_id = Uuid::new_v4();
+        Self {
+            command_id,
+            correlation_id: command_id,
+            causation_id: None,
+            command,
+        }
+    }
+
+    /// Wrap a command caused by another message in an existing flow
+    pub fn caused_by(command: C, correlation_id: Uuid, causation_id: Uuid) -> Self {
+        Self {
+            command_id: Uuid::new_v4(),
+            correlation_id,
+            causation_id: Some(causation_id),
+            command,
+        }
+    }
+}
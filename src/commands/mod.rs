@@ -1,10 +1,24 @@
 //! Commands for the Identity domain
+//!
+//! Commands are sent wrapped in a [`CommandEnvelope`] carrying their command,
+//! correlation and causation ids.
+
+pub mod envelope;
+pub mod outcome;
+
+pub use envelope::CommandEnvelope;
+pub use outcome::{CommandOutcome, CommandOutcomes, CorrelatedEvent, DEFAULT_OUTCOME_TIMEOUT};
 
 use crate::components::{
-    ClaimType, IdentityId, IdentityStatus, IdentityType, ProjectionContext, ProjectionType,
-    RelationshipId, RelationshipRules, RelationshipType, VerificationLevel, VerificationMethod,
-    WorkflowType,
+    ApiKeySecret, ClaimType, DidMethod, DidService, IdentityId, IdentityStatus, IdentityType,
+    KeyAlgorithm, KeyPurpose, ProjectionContext, ProjectionType, RelationshipId, RelationshipRules,
+    RelationshipType, StepOutcome, VerificationLevel, VerificationMethod, WorkflowType,
+};
+use crate::authentication::{
+    AuthFactor, AuthMethod, FactorResponse, LocationContext, MfaCode, Password, RefreshToken,
+    TotpSecret,
 };
+use crate::keys::KeySecret;
 use bevy::ecs::prelude::*;
 use serde::{Deserialize, Serialize};
 
@@ -43,6 +57,43 @@ pub struct ArchiveIdentityCommand {
     pub force: bool,
 }
 
+// Claim commands
+
+/// Add a claim to an identity
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct AddClaimCommand {
+    pub identity_id: IdentityId,
+    pub claim_type: ClaimType,
+    pub value: String,
+    /// Identity vouching for the claim, if any
+    pub issuer: Option<IdentityId>,
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub added_by: IdentityId,
+}
+
+/// Change the value or expiry of a held claim
+///
+/// A new value must be verified again.
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateClaimCommand {
+    pub identity_id: IdentityId,
+    pub claim_type: ClaimType,
+    pub value: String,
+    pub new_value: Option<String>,
+    /// New expiry, keeping the current one if `None`
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub updated_by: IdentityId,
+}
+
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct RevokeClaimCommand {
+    pub identity_id: IdentityId,
+    pub claim_type: ClaimType,
+    pub value: String,
+    pub revoked_by: IdentityId,
+    pub reason: Option<String>,
+}
+
 // Relationship commands
 
 #[derive(Event, Debug, Clone, Serialize, Deserialize)]
@@ -91,6 +142,8 @@ pub struct ProcessWorkflowStepCommand {
     pub workflow_id: cim_domain::WorkflowId,
     pub step_name: String,
     pub step_data: serde_json::Value,
+    #[serde(default)]
+    pub outcome: StepOutcome,
     pub processed_by: IdentityId,
 }
 
@@ -106,6 +159,55 @@ pub struct TimeoutWorkflowCommand {
     pub workflow_id: cim_domain::WorkflowId,
 }
 
+/// Approve an approval step as one of its assigned approvers
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct ApproveWorkflowStepCommand {
+    pub workflow_id: cim_domain::WorkflowId,
+    pub step_name: String,
+    pub approver: IdentityId,
+    pub comment: Option<String>,
+}
+
+/// Reject an approval step as one of its assigned approvers
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct RejectWorkflowStepCommand {
+    pub workflow_id: cim_domain::WorkflowId,
+    pub step_name: String,
+    pub approver: IdentityId,
+    pub comment: Option<String>,
+}
+
+/// Hold a running workflow on its active step
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct PauseWorkflowCommand {
+    pub workflow_id: cim_domain::WorkflowId,
+    pub paused_by: IdentityId,
+    pub reason: Option<String>,
+}
+
+/// Continue a paused workflow
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct ResumeWorkflowCommand {
+    pub workflow_id: cim_domain::WorkflowId,
+    pub resumed_by: IdentityId,
+}
+
+/// Stop a running or paused workflow for good
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct CancelWorkflowCommand {
+    pub workflow_id: cim_domain::WorkflowId,
+    pub cancelled_by: IdentityId,
+    pub reason: Option<String>,
+}
+
+/// Run the step a workflow failed on again, as its retry policy allows
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct RetryWorkflowStepCommand {
+    pub workflow_id: cim_domain::WorkflowId,
+    pub step_name: String,
+    pub retried_by: IdentityId,
+}
+
 // Verification commands
 
 #[derive(Event, Debug, Clone, Serialize, Deserialize)]
@@ -113,6 +215,10 @@ pub struct StartVerificationCommand {
     pub identity_id: IdentityId,
     pub verification_method: VerificationMethod,
     pub initiated_by: IdentityId,
+    /// Email address or phone number to send the code to; required for email
+    /// and phone verification
+    #[serde(default)]
+    pub claim_value: Option<String>,
 }
 
 #[derive(Event, Debug, Clone, Serialize, Deserialize)]
@@ -131,6 +237,282 @@ pub struct CompleteVerificationCommand {
     pub verified_by: IdentityId,
 }
 
+/// Send a one-time code to an email or phone claim of an identity
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct SendVerificationCodeCommand {
+    pub identity_id: IdentityId,
+    pub claim_type: ClaimType,
+    pub value: String,
+}
+
+/// Confirm the one-time code sent to a claim, verifying that claim
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct ConfirmVerificationCodeCommand {
+    pub identity_id: IdentityId,
+    pub claim_type: ClaimType,
+    pub value: String,
+    pub code: String,
+}
+
+// API key commands
+
+/// Issue an API key to a service or system identity
+///
+/// `key` is generated by the caller with [`ApiKeySecret::generate`] and shown
+/// to the key holder; only its hash is kept.
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct IssueApiKeyCommand {
+    pub identity_id: IdentityId,
+    pub key: ApiKeySecret,
+    pub name: String,
+    pub permissions: Vec<String>,
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub issued_by: IdentityId,
+}
+
+/// Replace an API key with a new one carrying the same name and permissions
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct RotateApiKeyCommand {
+    pub identity_id: IdentityId,
+    pub key_id: uuid::Uuid,
+    pub new_key: ApiKeySecret,
+    /// Keep the old key usable until this time instead of revoking it now
+    pub old_key_valid_until: Option<chrono::DateTime<chrono::Utc>>,
+    pub rotated_by: IdentityId,
+}
+
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct RevokeApiKeyCommand {
+    pub identity_id: IdentityId,
+    pub key_id: uuid::Uuid,
+    pub revoked_by: IdentityId,
+    pub reason: Option<String>,
+}
+
+/// Authenticate a caller by a presented API key
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct AuthenticateApiKeyCommand {
+    pub key: ApiKeySecret,
+    /// Permissions the key must grant for the request
+    pub required_permissions: Vec<String>,
+}
+
+// Authentication commands
+
+/// Set or replace the password of a person identity
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct SetPasswordCommand {
+    pub identity_id: IdentityId,
+    pub password: Password,
+    pub set_by: IdentityId,
+}
+
+/// Authenticate a person identity with its password
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct AuthenticatePasswordCommand {
+    pub identity_id: IdentityId,
+    pub password: Password,
+}
+
+/// Enable TOTP MFA for a person identity that has a password
+///
+/// The secret and backup codes come from [`TotpSecret::generate`] and
+/// [`crate::authentication::generate_backup_codes`]; only the hashes of the
+/// backup codes are kept. A code from the authenticator app confirms the
+/// secret was enrolled.
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct EnableMfaCommand {
+    pub identity_id: IdentityId,
+    pub secret: TotpSecret,
+    pub backup_codes: Vec<MfaCode>,
+    pub confirmation_code: MfaCode,
+}
+
+/// Ask an identity with MFA enabled for a second factor
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct IssueMfaChallengeCommand {
+    pub identity_id: IdentityId,
+}
+
+/// Answer an MFA challenge with a TOTP or backup code
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct VerifyMfaCommand {
+    pub identity_id: IdentityId,
+    pub challenge_id: uuid::Uuid,
+    pub code: MfaCode,
+}
+
+/// Start authenticating a person identity with the factors it requires
+///
+/// The factors are chosen from the identity and the risk of `location`; an
+/// attempt needing a factor missing from `available_factors` is denied.
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct BeginAuthenticationCommand {
+    pub identity_id: IdentityId,
+    pub available_factors: Vec<AuthFactor>,
+    pub location: LocationContext,
+}
+
+/// Answer the open challenge of an authentication attempt
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct SubmitAuthenticationFactorCommand {
+    pub identity_id: IdentityId,
+    pub attempt_id: uuid::Uuid,
+    pub challenge_id: uuid::Uuid,
+    pub response: FactorResponse,
+}
+
+// Session commands
+
+/// Start a session for an identity that has just authenticated
+///
+/// The session id is the one embedded in the refresh token from
+/// [`RefreshToken::generate`]. A live session on the same
+/// `location.device_id` is superseded. Each authentication starts one
+/// session, within [`crate::authentication::SessionPolicy::grant_timeout`].
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct StartSessionCommand {
+    pub identity_id: IdentityId,
+    pub auth_method: AuthMethod,
+    pub location: LocationContext,
+    pub refresh_token: RefreshToken,
+    /// Granted authentication attempt the session is started for, if the
+    /// identity authenticated through one
+    #[serde(default)]
+    pub attempt_id: Option<uuid::Uuid>,
+}
+
+/// Exchange the refresh token of a live session for a new one
+///
+/// The new token comes from [`RefreshToken::for_session`] with the same
+/// session id.
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct RefreshSessionCommand {
+    pub refresh_token: RefreshToken,
+    pub new_refresh_token: RefreshToken,
+}
+
+/// End a single session of an identity
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct RevokeSessionCommand {
+    pub identity_id: IdentityId,
+    pub session_id: uuid::Uuid,
+    pub revoked_by: IdentityId,
+    pub reason: String,
+}
+
+/// End every live session of an identity, optionally keeping one
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct RevokeAllSessionsCommand {
+    pub identity_id: IdentityId,
+    pub keep: Option<uuid::Uuid>,
+    pub revoked_by: IdentityId,
+    pub reason: String,
+}
+
+// Key commands
+
+/// Generate a key for a device, service or system identity
+///
+/// The private key goes to the [`crate::keys::IdentityKeyStore`].
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct GenerateKeyCommand {
+    pub identity_id: IdentityId,
+    pub algorithm: KeyAlgorithm,
+    pub purposes: Vec<KeyPurpose>,
+    pub generated_by: IdentityId,
+}
+
+/// Import an existing private key for a device, service or system identity
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct ImportKeyCommand {
+    pub identity_id: IdentityId,
+    pub secret: KeySecret,
+    pub purposes: Vec<KeyPurpose>,
+    pub imported_by: IdentityId,
+}
+
+/// Replace a key with a newly generated one serving the same purposes
+///
+/// The old key keeps verifying signatures it made.
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct RotateKeyCommand {
+    pub identity_id: IdentityId,
+    pub key_id: uuid::Uuid,
+    pub rotated_by: IdentityId,
+}
+
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct RevokeKeyCommand {
+    pub identity_id: IdentityId,
+    pub key_id: uuid::Uuid,
+    pub reason: String,
+    pub revoked_by: IdentityId,
+}
+
+// Token commands
+
+/// Issue a signed token asserting who an identity is
+///
+/// The token carries the identity's verified claims of the requested types.
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct IssueIdentityTokenCommand {
+    pub identity_id: IdentityId,
+    pub claim_types: Vec<ClaimType>,
+    /// Expiry instead of the issuer's default token lifetime
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Validate a presented token against the current state of its identity
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct ValidateIdentityTokenCommand {
+    pub token: String,
+}
+
+// Credential commands
+
+/// Export a verified claim as a signed Verifiable Credential
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct ExportCredentialCommand {
+    pub identity_id: IdentityId,
+    pub claim_type: ClaimType,
+    pub value: String,
+}
+
+/// Import an externally issued Verifiable Credential as a verified claim
+///
+/// The issuer must be trusted by `imported_by` through a `Trusts` relationship.
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct ImportCredentialCommand {
+    pub identity_id: IdentityId,
+    /// The credential as received, in JSON-LD
+    pub credential: serde_json::Value,
+    pub imported_by: IdentityId,
+}
+
+// DID commands
+
+/// Publish a DID document for an identity
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct PublishDidCommand {
+    pub identity_id: IdentityId,
+    pub method: DidMethod,
+    /// Ed25519 public key listed as the document's first verification key
+    pub public_key: [u8; 32],
+    pub services: Vec<DidService>,
+    pub published_by: IdentityId,
+}
+
+/// Replace the verification key of a published DID
+///
+/// Only `did:web` DIDs rotate; a `did:key` DID is its key.
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct RotateDidKeyCommand {
+    pub identity_id: IdentityId,
+    pub public_key: [u8; 32],
+    pub rotated_by: IdentityId,
+}
+
 // Projection commands
 
 #[derive(Event, Debug, Clone, Serialize, Deserialize)]
@@ -0,0 +1,216 @@
+//! Request/response outcome channel for identity commands
+//!
+//! Callers outside the ECS world subscribe to the outcome of a command by its
+//! `command_id` before sending the envelope, then await the returned receiver.
+//! The outcome is resolved by the first event or rejection whose causation id
+//! matches the command, or times out when neither arrives within the
+//! subscription's timeout.
+
+use crate::events::*;
+use crate::IdentityError;
+use bevy::ecs::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+/// Outcome of a command
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum CommandOutcome {
+    /// The command was applied and produced the given event
+    Accepted {
+        command_id: Uuid,
+        correlation_id: Uuid,
+        event_type: String,
+        event: serde_json::Value,
+    },
+    /// The command was rejected
+    Rejected {
+        command_id: Uuid,
+        correlation_id: Uuid,
+        error: IdentityError,
+    },
+    /// Neither an event nor a rejection arrived before the subscription timed out
+    TimedOut { command_id: Uuid },
+}
+
+/// Default time a subscription waits for its outcome
+pub const DEFAULT_OUTCOME_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+struct PendingOutcome {
+    sender: oneshot::Sender<CommandOutcome>,
+    deadline: Instant,
+}
+
+/// Resource holding pending outcome subscriptions
+///
+/// The resource is cheap to clone; a clone can be taken out of the world and
+/// shared with async callers. Subscriptions whose command is never answered
+/// are resolved as [`CommandOutcome::TimedOut`] once their timeout passes, and
+/// subscriptions whose receiver was dropped are discarded.
+#[derive(Resource, Debug, Clone)]
+pub struct CommandOutcomes {
+    pending: Arc<Mutex<HashMap<Uuid, PendingOutcome>>>,
+    timeout: Duration,
+}
+
+impl Default for CommandOutcomes {
+    fn default() -> Self {
+        Self::with_timeout(DEFAULT_OUTCOME_TIMEOUT)
+    }
+}
+
+impl CommandOutcomes {
+    /// Create an outcome channel whose subscriptions time out after `timeout`
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self {
+            pending: Arc::default(),
+            timeout,
+        }
+    }
+
+    /// Subscribe to the outcome of the command with the given id
+    pub fn subscribe(&self, command_id: Uuid) -> oneshot::Receiver<CommandOutcome> {
+        let (sender, receiver) = oneshot::channel();
+        let deadline = Instant::now() + self.timeout;
+        self.pending
+            .lock()
+            .unwrap()
+            .insert(command_id, PendingOutcome { sender, deadline });
+        receiver
+    }
+
+    /// Resolve a pending subscription, returning false if nobody was waiting
+    pub fn resolve(&self, command_id: Uuid, outcome: CommandOutcome) -> bool {
+        match self.pending.lock().unwrap().remove(&command_id) {
+            Some(pending) => pending.sender.send(outcome).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Time out subscriptions past their deadline and drop abandoned ones,
+    /// returning the number of subscriptions removed
+    pub fn expire(&self, now: Instant) -> usize {
+        let mut pending = self.pending.lock().unwrap();
+        let expired: Vec<Uuid> = pending
+            .iter()
+            .filter(|(_, p)| p.deadline <= now || p.sender.is_closed())
+            .map(|(command_id, _)| *command_id)
+            .collect();
+
+        for command_id in &expired {
+            if let Some(p) = pending.remove(command_id) {
+                let _ = p.sender.send(CommandOutcome::TimedOut {
+                    command_id: *command_id,
+                });
+            }
+        }
+        expired.len()
+    }
+
+    /// Number of commands still awaiting an outcome
+    pub fn pending_count(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+}
+
+/// Events that can resolve the outcome of the command that caused them
+pub trait CorrelatedEvent: Event + Serialize {
+    /// Name of the event type reported in accepted outcomes
+    const EVENT_TYPE: &'static str;
+
+    fn correlation_id(&self) -> Uuid;
+
+    fn causation_id(&self) -> Option<Uuid>;
+}
+
+macro_rules! impl_correlated_event {
+    ($($event:ident),* $(,)?) => {
+        $(
+            impl CorrelatedEvent for $event {
+                const EVENT_TYPE: &'static str = stringify!($event);
+
+                fn correlation_id(&self) -> Uuid {
+                    self.correlation_id
+                }
+
+                fn causation_id(&self) -> Option<Uuid> {
+                    self.causation_id
+                }
+            }
+        )*
+    };
+}
+
+impl_correlated_event!(
+    IdentityCreated,
+    IdentityUpdated,
+    IdentitiesMerged,
+    IdentityArchived,
+    ClaimAdded,
+    ClaimUpdated,
+    ClaimRevoked,
+    ClaimExpired,
+    VerificationLevelDowngraded,
+    RelationshipEstablished,
+    RelationshipValidated,
+    RelationshipExpired,
+    RelationshipsTraversed,
+    RelationshipRevoked,
+    WorkflowStarted,
+    WorkflowStepCompleted,
+    WorkflowCompleted,
+    WorkflowTimedOut,
+    WorkflowPaused,
+    WorkflowResumed,
+    WorkflowCancelled,
+    WorkflowStepRetried,
+    ApprovalRequested,
+    ApprovalDecided,
+    ApprovalEscalated,
+    VerificationStarted,
+    VerificationCompleted,
+    VerificationCodeSent,
+    VerificationCodeFailed,
+    ClaimVerified,
+    ApiKeyIssued,
+    ApiKeyRotated,
+    ApiKeyRevoked,
+    ApiKeyAuthenticated,
+    PasswordSet,
+    PasswordRehashed,
+    AuthenticationSucceeded,
+    AuthenticationFailed,
+    AccountLocked,
+    AccountUnlocked,
+    MfaEnabled,
+    MfaChallengeIssued,
+    MfaVerified,
+    MfaFailed,
+    AuthenticationAttemptStarted,
+    SuspiciousAuthenticationDetected,
+    AuthenticationChallengeIssued,
+    AuthenticationDecided,
+    SessionStarted,
+    SessionRefreshed,
+    SessionEnded,
+    SessionsRevoked,
+    KeyGenerated,
+    KeyImported,
+    KeyRotated,
+    KeyRevoked,
+    IdentityTokenIssued,
+    IdentityTokenValidated,
+    CredentialExported,
+    CredentialImported,
+    DidPublished,
+    DidKeyRotated,
+    ProjectionCreated,
+    ProjectionsSynced,
+    IdentityLinkedToPerson,
+    IdentityLinkedToOrganization,
+    IdentityAuthenticationRequested,
+);
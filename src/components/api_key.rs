@@ -0,0 +1,141 @@
+//! API key components for service and system identities
+//!
+//! A key is presented as `cimk_<key id>_<secret>`. Only a SHA-256 hash of the
+//! full key is stored; the key id lets the owning identity be found without
+//! scanning, and the hash is compared in constant time.
+
+use bevy::ecs::prelude::*;
+use chrono::{DateTime, Utc};
+use data_encoding::HEXLOWER;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use uuid::Uuid;
+
+const KEY_PREFIX: &str = "cimk_";
+
+/// Plaintext API key
+///
+/// Generated by the caller, handed to the key holder once and sent with the
+/// issuing command; the identity only ever keeps [`ApiKeySecret::hash`].
+#[derive(Clone, PartialEq, Eq)]
+pub struct ApiKeySecret(String);
+
+impl ApiKeySecret {
+    /// Generate a new key with a fresh key id and 256 random bits
+    pub fn generate() -> Self {
+        let mut secret = [0u8; 32];
+        OsRng.fill_bytes(&mut secret);
+        ApiKeySecret(format!(
+            "{KEY_PREFIX}{}_{}",
+            Uuid::new_v4().simple(),
+            HEXLOWER.encode(&secret)
+        ))
+    }
+
+    /// Wrap a key presented by a client
+    pub fn from_presented(key: impl Into<String>) -> Self {
+        ApiKeySecret(key.into())
+    }
+
+    /// Key id embedded in the key, if it is well formed
+    pub fn key_id(&self) -> Option<Uuid> {
+        let rest = self.0.strip_prefix(KEY_PREFIX)?;
+        let (key_id, secret) = rest.split_once('_')?;
+        if secret.is_empty() {
+            return None;
+        }
+        Uuid::try_parse(key_id).ok()
+    }
+
+    /// SHA-256 hash stored in place of the key
+    pub fn hash(&self) -> String {
+        HEXLOWER.encode(&Sha256::digest(self.0.as_bytes()))
+    }
+
+    /// The plaintext key, to be shown to its holder exactly once
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for ApiKeySecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ApiKeySecret(<redacted>)")
+    }
+}
+
+impl Serialize for ApiKeySecret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for ApiKeySecret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(ApiKeySecret)
+    }
+}
+
+/// Stored record of an issued API key
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiKeyRecord {
+    pub key_id: Uuid,
+    pub name: String,
+    pub key_hash: String,
+    pub permissions: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl ApiKeyRecord {
+    /// Whether the key is neither revoked nor expired at `now`
+    pub fn is_usable(&self, now: DateTime<Utc>) -> bool {
+        self.revoked_at.is_none() && self.expires_at.is_none_or(|expires| now < expires)
+    }
+
+    /// Whether the presented key hashes to this record, in constant time
+    pub fn matches(&self, key: &ApiKeySecret) -> bool {
+        self.key_hash.as_bytes().ct_eq(key.hash().as_bytes()).into()
+    }
+
+    /// Whether the key grants `permission`
+    ///
+    /// `*` grants everything and `scope:*` grants every permission starting
+    /// with `scope:`.
+    pub fn grants(&self, permission: &str) -> bool {
+        self.permissions.iter().any(|granted| {
+            granted == "*"
+                || granted == permission
+                || granted
+                    .strip_suffix('*')
+                    .is_some_and(|prefix| prefix.ends_with(':') && permission.starts_with(prefix))
+        })
+    }
+}
+
+/// API keys held by a service or system identity
+#[derive(Component, Debug, Clone, Default, Serialize, Deserialize)]
+#[component(
+    on_insert = crate::index::on_api_keys_inserted,
+    on_replace = crate::index::on_api_keys_replaced
+)]
+pub struct IdentityApiKeys {
+    pub keys: Vec<ApiKeyRecord>,
+}
+
+impl IdentityApiKeys {
+    /// Key with the given id
+    pub fn key(&self, key_id: Uuid) -> Option<&ApiKeyRecord> {
+        self.keys.iter().find(|key| key.key_id == key_id)
+    }
+
+    /// Mutable key with the given id
+    pub fn key_mut(&mut self, key_id: Uuid) -> Option<&mut ApiKeyRecord> {
+        self.keys.iter_mut().find(|key| key.key_id == key_id)
+    }
+}
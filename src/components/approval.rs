@@ -0,0 +1,114 @@
+//! Approval components for workflow approval steps
+
+use super::relationship::RelationshipType;
+use super::IdentityId;
+use crate::{IdentityError, IdentityResult};
+use bevy::ecs::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Who decides an approval step and how many of them must approve it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApprovalPolicy {
+    pub approvers: Vec<ApproverRule>,
+    /// Approvals needed out of the assigned approvers
+    #[serde(default = "single_approval")]
+    pub quorum: usize,
+    /// Seconds after which an undecided step is escalated to `escalate_to`
+    #[serde(default)]
+    pub escalate_after_seconds: Option<u64>,
+    #[serde(default)]
+    pub escalate_to: Vec<ApproverRule>,
+}
+
+fn single_approval() -> usize {
+    1
+}
+
+impl ApprovalPolicy {
+    /// Check the policy of the approval step `step_id`
+    pub fn validate(&self, step_id: &str) -> IdentityResult<()> {
+        let problem = if self.approvers.is_empty() {
+            "names no approvers"
+        } else if self.quorum == 0 {
+            "needs a quorum of at least one"
+        } else if self.escalate_after_seconds.is_some() && self.escalate_to.is_empty() {
+            "escalates to nobody"
+        } else {
+            return Ok(());
+        };
+        Err(IdentityError::WorkflowError(format!(
+            "Approval policy of step {step_id} {problem}"
+        )))
+    }
+}
+
+/// Rule assigning approvers to an approval step
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ApproverRule {
+    /// A named identity
+    Identity(IdentityId),
+    /// Every identity holding a relationship of this type to the workflow's identity,
+    /// such as anyone who `Manages` it
+    Relationship(RelationshipType),
+}
+
+/// Approval step waiting for decisions, carried next to its `IdentityWorkflow`
+///
+/// A new task replaces the previous one each time an approval step with an
+/// `ApprovalPolicy` becomes active.
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+pub struct ApprovalTask {
+    pub workflow_id: Uuid,
+    /// Identity the workflow runs for
+    pub identity_id: IdentityId,
+    pub step_id: String,
+    pub approvers: Vec<IdentityId>,
+    pub quorum: usize,
+    pub decisions: Vec<ApprovalDecision>,
+    pub requested_at: chrono::DateTime<chrono::Utc>,
+    pub escalated_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl ApprovalTask {
+    /// Whether the step passed, failed or still waits for decisions
+    ///
+    /// The step passes once `quorum` approvers approve it and fails once
+    /// enough of them reject it that the quorum can no longer be reached.
+    pub fn status(&self) -> ApprovalStatus {
+        let approvals = self.decisions.iter().filter(|d| d.approved).count();
+        let rejections = self.decisions.len() - approvals;
+        if approvals >= self.quorum {
+            ApprovalStatus::Approved
+        } else if rejections > self.approvers.len().saturating_sub(self.quorum) {
+            ApprovalStatus::Rejected
+        } else {
+            ApprovalStatus::Pending
+        }
+    }
+
+    /// Whether `approver` is assigned and has yet to decide
+    pub fn awaits(&self, approver: IdentityId) -> bool {
+        self.status() == ApprovalStatus::Pending
+            && self.approvers.contains(&approver)
+            && !self.decisions.iter().any(|d| d.approver == approver)
+    }
+}
+
+/// State of an approval task
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ApprovalStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+/// An approver's decision on an approval step
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApprovalDecision {
+    pub step_id: String,
+    pub approver: IdentityId,
+    pub approved: bool,
+    pub comment: Option<String>,
+    pub decided_at: chrono::DateTime<chrono::Utc>,
+}
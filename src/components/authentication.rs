@@ -0,0 +1,54 @@
+//! Authentication components for person identities
+
+use crate::authentication::{
+    AuthenticationAttempt, AuthenticationHistory, MfaChallenge, SessionGrant, TotpSecret,
+};
+use bevy::ecs::prelude::*;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Password credential, lockout state, open authentication attempt and
+/// authentication history of a person identity
+///
+/// Only changed by applying authentication events, see
+/// [`crate::authentication`].
+#[derive(Component, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IdentityCredentials {
+    /// Argon2id hash in PHC string format
+    pub password_hash: String,
+    pub password_changed_at: DateTime<Utc>,
+    pub last_authenticated_at: Option<DateTime<Utc>>,
+    /// Consecutive failed attempts since the last success or unlock
+    #[serde(default)]
+    pub failed_attempts: u32,
+    /// Lockouts since the last success, drives the lockout backoff
+    #[serde(default)]
+    pub lockout_count: u32,
+    pub locked_until: Option<DateTime<Utc>>,
+    /// Authentication attempt waiting for its factors
+    #[serde(default)]
+    pub attempt: Option<AuthenticationAttempt>,
+    /// Granted attempts new ones are scored against
+    #[serde(default)]
+    pub history: AuthenticationHistory,
+    /// Latest authentication, until a session is started with it
+    #[serde(default)]
+    pub session_grant: Option<SessionGrant>,
+}
+
+/// TOTP second factor of a person identity
+///
+/// Only changed by applying MFA events, like [`IdentityCredentials`].
+#[derive(Component, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdentityMfa {
+    #[serde(with = "crate::authentication::mfa::exposed")]
+    pub secret: TotpSecret,
+    /// SHA-256 hashes of the backup codes not used yet
+    pub backup_code_hashes: Vec<String>,
+    /// Last accepted TOTP time step; codes of this step or earlier are refused
+    pub last_totp_step: Option<u64>,
+    pub enabled_at: DateTime<Utc>,
+    pub last_verified_at: Option<DateTime<Utc>>,
+    /// Challenge waiting for a code
+    pub challenge: Option<MfaChallenge>,
+}
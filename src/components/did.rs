@@ -0,0 +1,75 @@
+//! Decentralized identifier components
+
+use bevy::ecs::prelude::*;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// DID method an identity publishes its document under
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DidMethod {
+    /// `did:key`, derived from the first key; its key never rotates
+    Key,
+    /// `did:web`, hosted under the given domain
+    Web { domain: String },
+}
+
+/// Verification key of a DID
+///
+/// Rotated keys are kept with their retirement time as history.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DidKey {
+    /// Ed25519 public key as a multibase Multikey, also the key's fragment
+    pub public_key_multibase: String,
+    pub added_at: DateTime<Utc>,
+    pub retired_at: Option<DateTime<Utc>>,
+}
+
+impl DidKey {
+    pub fn is_active(&self) -> bool {
+        self.retired_at.is_none()
+    }
+}
+
+/// Service endpoint listed in a DID document
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DidService {
+    /// Fragment naming the service within the document
+    pub id: String,
+    pub service_type: String,
+    pub endpoint: String,
+}
+
+/// DID published by an identity, with its keys and services
+#[derive(Component, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdentityDid {
+    pub did: String,
+    pub method: DidMethod,
+    pub keys: Vec<DidKey>,
+    pub services: Vec<DidService>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl IdentityDid {
+    /// Keys currently listed in the DID document
+    pub fn active_keys(&self) -> impl Iterator<Item = &DidKey> {
+        self.keys.iter().filter(|key| key.is_active())
+    }
+
+    /// Retire the active keys and add `public_key_multibase` in their place
+    ///
+    /// Returns the keys that were retired.
+    pub fn rotate(&mut self, public_key_multibase: String, now: DateTime<Utc>) -> Vec<String> {
+        let mut retired = Vec::new();
+        for key in self.keys.iter_mut().filter(|key| key.is_active()) {
+            key.retired_at = Some(now);
+            retired.push(key.public_key_multibase.clone());
+        }
+        self.keys.push(DidKey {
+            public_key_multibase,
+            added_at: now,
+            retired_at: None,
+        });
+        self.updated_at = now;
+        retired
+    }
+}
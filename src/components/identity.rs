@@ -6,6 +6,10 @@ use uuid::Uuid;
 
 /// Core identity component
 #[derive(Component, Debug, Clone, Serialize, Deserialize)]
+#[component(
+    on_insert = crate::index::on_identity_inserted,
+    on_replace = crate::index::on_identity_replaced
+)]
 pub struct IdentityEntity {
     pub identity_id: Uuid,
     pub identity_type: IdentityType,
@@ -89,8 +93,8 @@ pub enum VerificationMethod {
     ThirdParty { provider: String },
 }
 
-/// Claims about an identity
-#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+/// Claim about an identity, held in its [`IdentityClaims`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IdentityClaim {
     pub claim_type: ClaimType,
     pub value: String,
@@ -100,6 +104,106 @@ pub struct IdentityClaim {
     pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
+/// All claims of an identity
+///
+/// An identity may hold several claims of one type, such as two email
+/// addresses; a claim is identified by its type and value.
+#[derive(Component, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IdentityClaims {
+    claims: Vec<IdentityClaim>,
+}
+
+impl IdentityClaims {
+    pub fn new(claims: Vec<IdentityClaim>) -> Self {
+        let mut collection = Self::default();
+        for claim in claims {
+            collection.add(claim);
+        }
+        collection
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &IdentityClaim> {
+        self.claims.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut IdentityClaim> {
+        self.claims.iter_mut()
+    }
+
+    pub fn len(&self) -> usize {
+        self.claims.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.claims.is_empty()
+    }
+
+    /// Claims of one type, in the order they were added
+    pub fn of_type<'a>(
+        &'a self,
+        claim_type: &'a ClaimType,
+    ) -> impl Iterator<Item = &'a IdentityClaim> + 'a {
+        self.claims
+            .iter()
+            .filter(move |c| &c.claim_type == claim_type)
+    }
+
+    /// The first claim of a type
+    pub fn first(&self, claim_type: &ClaimType) -> Option<&IdentityClaim> {
+        self.claims.iter().find(|c| &c.claim_type == claim_type)
+    }
+
+    pub fn find(&self, claim_type: &ClaimType, value: &str) -> Option<&IdentityClaim> {
+        self.claims
+            .iter()
+            .find(|c| &c.claim_type == claim_type && c.value == value)
+    }
+
+    pub fn find_mut(&mut self, claim_type: &ClaimType, value: &str) -> Option<&mut IdentityClaim> {
+        self.claims
+            .iter_mut()
+            .find(|c| &c.claim_type == claim_type && c.value == value)
+    }
+
+    /// Add a claim, replacing a held claim with the same type and value
+    pub fn add(&mut self, claim: IdentityClaim) {
+        match self.find_mut(&claim.claim_type, &claim.value) {
+            Some(existing) => *existing = claim,
+            None => self.claims.push(claim),
+        }
+    }
+
+    /// Remove a held claim
+    pub fn remove(&mut self, claim_type: &ClaimType, value: &str) -> Option<IdentityClaim> {
+        let position = self
+            .claims
+            .iter()
+            .position(|c| &c.claim_type == claim_type && c.value == value)?;
+        Some(self.claims.remove(position))
+    }
+
+    /// Remove and return the claims that expired before `now`
+    pub fn remove_expired(&mut self, now: chrono::DateTime<chrono::Utc>) -> Vec<IdentityClaim> {
+        let (expired, kept) = self
+            .claims
+            .drain(..)
+            .partition(|c| c.expires_at.is_some_and(|expires_at| expires_at < now));
+        self.claims = kept;
+        expired
+    }
+
+    /// Mark a held claim as verified, returning false if it is not held
+    pub fn verify(&mut self, claim_type: &ClaimType, value: &str) -> bool {
+        match self.find_mut(claim_type, value) {
+            Some(claim) => {
+                claim.verified = true;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
 /// Types of claims
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum ClaimType {
@@ -0,0 +1,97 @@
+//! Cryptographic key components for device, service and system identities
+//!
+//! Only public keys are held on the identity; private keys live in the
+//! [`crate::keys::KeyStore`] under their key id.
+
+use bevy::ecs::prelude::*;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Algorithm of an identity key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum KeyAlgorithm {
+    /// Ed25519 signatures
+    Ed25519,
+    /// X25519 key agreement
+    X25519,
+}
+
+impl KeyAlgorithm {
+    /// Whether keys of this algorithm can serve `purpose`
+    pub fn supports(&self, purpose: KeyPurpose) -> bool {
+        match self {
+            KeyAlgorithm::Ed25519 => {
+                matches!(purpose, KeyPurpose::Signing | KeyPurpose::Authentication)
+            }
+            KeyAlgorithm::X25519 => purpose == KeyPurpose::Encryption,
+        }
+    }
+}
+
+/// What an identity key is used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum KeyPurpose {
+    Signing,
+    Encryption,
+    Authentication,
+}
+
+/// Public part of a key held by an identity
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdentityKey {
+    pub key_id: Uuid,
+    pub algorithm: KeyAlgorithm,
+    pub purposes: Vec<KeyPurpose>,
+    pub public_key: [u8; 32],
+    pub created_at: DateTime<Utc>,
+    /// Key that replaced this one on rotation
+    pub superseded_by: Option<Uuid>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub revocation_reason: Option<String>,
+}
+
+impl IdentityKey {
+    /// Whether the key is used for new signatures and key agreements
+    pub fn is_current(&self) -> bool {
+        self.superseded_by.is_none() && self.revoked_at.is_none()
+    }
+
+    pub fn has_purpose(&self, purpose: KeyPurpose) -> bool {
+        self.purposes.contains(&purpose)
+    }
+}
+
+/// Keys held by a device, service or system identity
+///
+/// Rotated keys stay listed so signatures made with them keep verifying;
+/// revoked keys stay listed so they are known to be revoked.
+#[derive(Component, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IdentityKeys {
+    pub keys: Vec<IdentityKey>,
+}
+
+impl IdentityKeys {
+    /// Key with the given id
+    pub fn key(&self, key_id: Uuid) -> Option<&IdentityKey> {
+        self.keys.iter().find(|key| key.key_id == key_id)
+    }
+
+    /// Mutable key with the given id
+    pub fn key_mut(&mut self, key_id: Uuid) -> Option<&mut IdentityKey> {
+        self.keys.iter_mut().find(|key| key.key_id == key_id)
+    }
+
+    /// Most recent current key serving `purpose`
+    pub fn current(&self, purpose: KeyPurpose) -> Option<&IdentityKey> {
+        self.keys
+            .iter()
+            .rev()
+            .find(|key| key.is_current() && key.has_purpose(purpose))
+    }
+
+    /// Whether a key with this public key is held
+    pub fn holds_public_key(&self, public_key: &[u8; 32]) -> bool {
+        self.keys.iter().any(|key| key.public_key == *public_key)
+    }
+}
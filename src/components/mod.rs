@@ -3,30 +3,52 @@
 //! This module contains all ECS components used in the identity domain.
 //! Components represent the data/state of entities in the system.
 
+pub mod api_key;
+pub mod approval;
+pub mod authentication;
+pub mod did;
 pub mod identity;
+pub mod keys;
 pub mod projection;
 pub mod relationship;
+pub mod session;
+pub mod verification;
 pub mod workflow;
 
 // Re-export commonly used types
+pub use api_key::{ApiKeyRecord, ApiKeySecret, IdentityApiKeys};
+
+pub use approval::{ApprovalDecision, ApprovalPolicy, ApprovalStatus, ApprovalTask, ApproverRule};
+
+pub use authentication::{IdentityCredentials, IdentityMfa};
+
+pub use did::{DidKey, DidMethod, DidService, IdentityDid};
+
 pub use identity::{
-    ClaimType, ExternalIdentity, IdentityClaim, IdentityEntity, IdentityMetadata, IdentityStatus,
-    IdentityType, IdentityVerification, VerificationLevel, VerificationMethod,
+    ClaimType, ExternalIdentity, IdentityClaim, IdentityClaims, IdentityEntity, IdentityMetadata,
+    IdentityStatus, IdentityType, IdentityVerification, VerificationLevel, VerificationMethod,
 };
 
+pub use keys::{IdentityKey, IdentityKeys, KeyAlgorithm, KeyPurpose};
+
 pub use relationship::{
     IdentityRelationship, RelationshipConstraint, RelationshipGraph, RelationshipRules,
     RelationshipType,
 };
 
+pub use session::{IdentitySessions, SessionRecord};
+
+pub use verification::VerificationWorkflow;
+
 pub use workflow::{
-    IdentityWorkflow, StepStatus, StepType, TransitionCondition, WorkflowStatus, WorkflowStep,
-    WorkflowTransition, WorkflowType,
+    IdentityWorkflow, RetryPolicy, StepOutcome, StepStatus, StepTransition, StepType,
+    TransitionCondition, WorkflowHistory, WorkflowStatus, WorkflowStep, WorkflowTransition,
+    WorkflowType,
 };
 
 pub use projection::{
     CrossDomainReference, IdentityProjection, ProjectionContext, ProjectionSyncStatus,
-    ProjectionType,
+    ProjectionType, ReferenceType,
 };
 
 // Type aliases for common types
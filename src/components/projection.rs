@@ -44,6 +44,25 @@ pub struct CrossDomainReference {
     pub reference_type: ReferenceType,
 }
 
+impl CrossDomainReference {
+    /// Reference to an identity by its DID rather than its id
+    pub fn did(did: impl Into<String>, reference_type: ReferenceType) -> Self {
+        CrossDomainReference {
+            domain: "identity".to_string(),
+            entity_type: "did".to_string(),
+            entity_id: did.into(),
+            reference_type,
+        }
+    }
+
+    /// The DID referenced, if the reference names one
+    pub fn as_did(&self) -> Option<&str> {
+        self.entity_id
+            .starts_with("did:")
+            .then_some(self.entity_id.as_str())
+    }
+}
+
 /// Type of cross-domain reference
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ReferenceType {
@@ -22,6 +22,10 @@ impl Default for RelationshipId {
 
 /// Component representing a relationship between identities
 #[derive(Component, Debug, Clone, Serialize, Deserialize)]
+#[component(
+    on_insert = crate::index::on_relationship_inserted,
+    on_replace = crate::index::on_relationship_replaced
+)]
 pub struct IdentityRelationship {
     pub relationship_id: Uuid,
     pub source_identity: Uuid,
@@ -0,0 +1,91 @@
+//! Session components for authenticated identities
+
+use crate::authentication::{
+    AuthMethod, RefreshToken, SessionDevice, SessionEndReason, SessionPolicy,
+};
+use bevy::ecs::prelude::*;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+use uuid::Uuid;
+
+/// Stored record of a session
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionRecord {
+    pub session_id: Uuid,
+    pub device: SessionDevice,
+    pub auth_method: AuthMethod,
+    pub started_at: DateTime<Utc>,
+    /// Start or last refresh of the session
+    pub last_seen_at: DateTime<Utc>,
+    /// End of the absolute lifetime
+    pub expires_at: DateTime<Utc>,
+    /// SHA-256 hash of the current refresh token
+    pub refresh_token_hash: String,
+    pub ended_at: Option<DateTime<Utc>>,
+    pub end_reason: Option<SessionEndReason>,
+}
+
+impl SessionRecord {
+    /// Time at which the session ends unless it is refreshed
+    pub fn idle_deadline(&self, policy: &SessionPolicy) -> DateTime<Utc> {
+        (self.last_seen_at + policy.idle_timeout).min(self.expires_at)
+    }
+
+    /// Reason the session is over at `now`, if it is
+    pub fn end_reason_at(
+        &self,
+        now: DateTime<Utc>,
+        policy: &SessionPolicy,
+    ) -> Option<SessionEndReason> {
+        if let Some(reason) = &self.end_reason {
+            return Some(reason.clone());
+        }
+        if now >= self.expires_at {
+            return Some(SessionEndReason::AbsoluteTimeout);
+        }
+        if now >= self.idle_deadline(policy) {
+            return Some(SessionEndReason::IdleTimeout);
+        }
+        None
+    }
+
+    /// Whether the session can be used at `now`
+    pub fn is_active(&self, now: DateTime<Utc>, policy: &SessionPolicy) -> bool {
+        self.end_reason_at(now, policy).is_none()
+    }
+
+    /// Whether `token` is the current refresh token, compared in constant time
+    pub fn refresh_token_matches(&self, token: &RefreshToken) -> bool {
+        self.refresh_token_hash
+            .as_bytes()
+            .ct_eq(token.hash().as_bytes())
+            .into()
+    }
+}
+
+/// Sessions of an identity, ended ones included
+#[derive(Component, Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[component(
+    on_insert = crate::index::on_sessions_inserted,
+    on_replace = crate::index::on_sessions_replaced
+)]
+pub struct IdentitySessions {
+    pub sessions: Vec<SessionRecord>,
+}
+
+impl IdentitySessions {
+    /// Session with the given id
+    pub fn session(&self, session_id: Uuid) -> Option<&SessionRecord> {
+        self.sessions
+            .iter()
+            .find(|session| session.session_id == session_id)
+    }
+
+    /// Mutable session with the given id
+    pub fn session_mut(&mut self, session_id: Uuid) -> Option<&mut SessionRecord> {
+        self.sessions
+            .iter_mut()
+            .find(|session| session.session_id == session_id)
+    }
+}
@@ -0,0 +1,27 @@
+//! Verification workflow components
+
+use super::identity::{ClaimType, VerificationLevel, VerificationMethod};
+use bevy::ecs::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Verification state carried next to the `IdentityWorkflow` it drives
+///
+/// Removed from the finished workflow once the matching
+/// `CompleteVerificationCommand` has been produced.
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+pub struct VerificationWorkflow {
+    pub verification_method: VerificationMethod,
+    /// Level granted when every step succeeds
+    pub target_level: VerificationLevel,
+    pub initiated_by: Uuid,
+    /// Reviewer of the approval step, if any, else the initiator
+    pub verified_by: Uuid,
+    /// Claim type and value whose one-time code confirms email and phone verification
+    pub code_claim: Option<(ClaimType, String)>,
+    /// Reference returned by the third-party provider
+    pub provider_reference: Option<String>,
+    pub correlation_id: Uuid,
+    /// Last command that advanced the workflow
+    pub last_command_id: Uuid,
+}
@@ -1,11 +1,19 @@
 //! Identity workflow components
 
+use super::approval::{ApprovalDecision, ApprovalPolicy};
+use crate::workflows::Expression;
+use crate::{IdentityError, IdentityResult};
 use bevy::ecs::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use uuid::Uuid;
 
 /// Identity workflow instance
 #[derive(Component, Debug, Clone, Serialize, Deserialize)]
+#[component(
+    on_insert = crate::index::on_workflow_inserted,
+    on_replace = crate::index::on_workflow_replaced
+)]
 pub struct IdentityWorkflow {
     pub workflow_id: Uuid,
     pub identity_id: Uuid,
@@ -13,8 +21,277 @@ pub struct IdentityWorkflow {
     pub status: WorkflowStatus,
     pub current_step: Option<String>,
     pub steps: Vec<WorkflowStep>,
+    /// Transitions between `steps`; without any, steps run in order
+    #[serde(default)]
+    pub transitions: Vec<WorkflowTransition>,
+    /// Context the workflow was started with
+    #[serde(default)]
+    pub context: serde_json::Value,
     pub started_at: Option<chrono::DateTime<chrono::Utc>>,
     pub completed_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// When the workflow was paused, while it is
+    #[serde(default)]
+    pub paused_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl IdentityWorkflow {
+    /// Whether the workflow has completed, failed or been cancelled
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self.status,
+            WorkflowStatus::Completed | WorkflowStatus::Failed(_) | WorkflowStatus::Cancelled
+        )
+    }
+
+    /// The step named by `current_step`
+    pub fn active_step(&self) -> Option<&WorkflowStep> {
+        let step_id = self.current_step.as_ref()?;
+        self.steps.iter().find(|s| &s.step_id == step_id)
+    }
+
+    /// Id of the active step, or none once the workflow has finished
+    pub fn running_step(&self) -> Option<String> {
+        self.current_step.clone().filter(|_| !self.is_finished())
+    }
+
+    /// Mutable access to the step named by `current_step`
+    pub fn active_step_mut(&mut self) -> Option<&mut WorkflowStep> {
+        let step_id = self.current_step.clone()?;
+        self.steps.iter_mut().find(|s| s.step_id == step_id)
+    }
+
+    /// Activate the first pending step, or complete the workflow if none is left
+    ///
+    /// Returns the id of the activated step.
+    pub fn activate_next_step(&mut self, now: chrono::DateTime<chrono::Utc>) -> Option<String> {
+        let Some(step_id) = self
+            .steps
+            .iter()
+            .find(|s| s.status == StepStatus::Pending)
+            .map(|s| s.step_id.clone())
+        else {
+            self.current_step = None;
+            self.status = WorkflowStatus::Completed;
+            self.completed_at = Some(now);
+            return None;
+        };
+
+        self.activate_step(&step_id, now);
+        Some(step_id)
+    }
+
+    /// Make `step_id` the active step and wait on it
+    ///
+    /// Returns false if the workflow has no such step.
+    pub fn activate_step(&mut self, step_id: &str, now: chrono::DateTime<chrono::Utc>) -> bool {
+        let Some(step) = self.steps.iter_mut().find(|s| s.step_id == step_id) else {
+            return false;
+        };
+
+        step.status = StepStatus::Active;
+        step.started_at = Some(now);
+        self.status = step.step_type.waiting_status();
+        self.current_step = Some(step.step_id.clone());
+        true
+    }
+
+    /// Complete the active step and activate the next one
+    ///
+    /// Returns the id of the completed step.
+    pub fn complete_active_step(&mut self, now: chrono::DateTime<chrono::Utc>) -> Option<String> {
+        let step_id = self.current_step.clone()?;
+        let step = self.steps.iter_mut().find(|s| s.step_id == step_id)?;
+        step.status = StepStatus::Completed;
+        step.completed_at = Some(now);
+        self.activate_next_step(now);
+        Some(step_id)
+    }
+
+    /// End the active step with `outcome` and move to the step it leads to
+    ///
+    /// The first transition out of the step whose condition holds for the
+    /// outcome, the step's `output` and the `identity` attributes picks the
+    /// next step. Without any transitions the workflow runs its steps in
+    /// order. A terminal step completes the workflow, or fails it if the step
+    /// failed. Returns the id of the activated step.
+    pub fn finish_active_step(
+        &mut self,
+        outcome: &StepOutcome,
+        output: &serde_json::Value,
+        identity: &serde_json::Value,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> IdentityResult<Option<String>> {
+        let step = self.active_step().ok_or_else(|| {
+            IdentityError::WorkflowError("Workflow has no active step".to_string())
+        })?;
+        if *outcome == StepOutcome::Skipped && step.required {
+            return Err(IdentityError::InvalidOperation(format!(
+                "Step {} is required",
+                step.step_id
+            )));
+        }
+
+        let step_id = step.step_id.clone();
+        let next_step = self.next_step(step, outcome, output, identity)?;
+        self.apply_step_outcome(&step_id, outcome, next_step.as_deref(), now);
+        Ok(next_step)
+    }
+
+    /// Record how `step_id` ended and activate `next_step`, or finish the workflow without one
+    pub fn apply_step_outcome(
+        &mut self,
+        step_id: &str,
+        outcome: &StepOutcome,
+        next_step: Option<&str>,
+        now: chrono::DateTime<chrono::Utc>,
+    ) {
+        if let Some(step) = self.steps.iter_mut().find(|s| s.step_id == step_id) {
+            step.status = outcome.step_status();
+            step.completed_at = Some(now);
+        }
+
+        match (next_step, outcome) {
+            (Some(next_step), _) => {
+                self.activate_step(next_step, now);
+            }
+            (None, StepOutcome::Failed(reason)) => {
+                self.status = WorkflowStatus::Failed(reason.clone());
+                self.completed_at = Some(now);
+            }
+            (None, _) => {
+                self.current_step = None;
+                self.status = WorkflowStatus::Completed;
+                self.completed_at = Some(now);
+            }
+        }
+    }
+
+    /// Step that follows `step` after it ended with `outcome`
+    fn next_step(
+        &self,
+        step: &WorkflowStep,
+        outcome: &StepOutcome,
+        output: &serde_json::Value,
+        identity: &serde_json::Value,
+    ) -> IdentityResult<Option<String>> {
+        if self.transitions.is_empty() {
+            if matches!(outcome, StepOutcome::Failed(_)) && step.required {
+                return Ok(None);
+            }
+            return Ok(self
+                .steps
+                .iter()
+                .find(|s| s.status == StepStatus::Pending && s.step_id != step.step_id)
+                .map(|s| s.step_id.clone()));
+        }
+
+        let mut outgoing = self
+            .transitions
+            .iter()
+            .filter(|t| t.from_step == step.step_id)
+            .peekable();
+        if outgoing.peek().is_none() {
+            return Ok(None);
+        }
+        let scope = self.condition_scope(outcome, output, identity);
+        for transition in outgoing {
+            if transition
+                .condition
+                .matches(outcome, &transition.to_step, &scope)?
+            {
+                return Ok(Some(transition.to_step.clone()));
+            }
+        }
+
+        // A failure nothing recovers from fails the workflow
+        match outcome {
+            StepOutcome::Failed(_) => Ok(None),
+            _ => Err(IdentityError::WorkflowError(format!(
+                "No transition from step {} matches",
+                step.step_id
+            ))),
+        }
+    }
+
+    /// Fields transition conditions of a step that ended with `outcome` read
+    pub fn condition_scope(
+        &self,
+        outcome: &StepOutcome,
+        output: &serde_json::Value,
+        identity: &serde_json::Value,
+    ) -> serde_json::Value {
+        serde_json::json!({
+            "context": self.context,
+            "output": output,
+            "outcome": outcome.step_status(),
+            "identity": identity,
+        })
+    }
+
+    /// Hold the workflow on its active step until it is resumed
+    pub fn pause(&mut self, now: chrono::DateTime<chrono::Utc>) {
+        self.status = WorkflowStatus::Paused;
+        self.paused_at = Some(now);
+    }
+
+    /// Wait on the active step again
+    ///
+    /// The time spent paused does not count towards the step's timeout.
+    /// Returns how long the workflow was paused.
+    pub fn resume(&mut self, now: chrono::DateTime<chrono::Utc>) -> chrono::Duration {
+        let paused_for = self
+            .paused_at
+            .take()
+            .map_or(chrono::Duration::zero(), |paused_at| now - paused_at);
+        self.status = match self.active_step_mut() {
+            Some(step) => {
+                step.started_at = step.started_at.map(|started_at| started_at + paused_for);
+                step.step_type.waiting_status()
+            }
+            None => WorkflowStatus::InProgress,
+        };
+        paused_for
+    }
+
+    /// Stop the workflow for good, leaving its steps as they are
+    pub fn cancel(&mut self, now: chrono::DateTime<chrono::Utc>) {
+        self.status = WorkflowStatus::Cancelled;
+        self.paused_at = None;
+        self.completed_at = Some(now);
+    }
+
+    /// Activate the failed step `step_id` again, reopening the workflow it failed
+    ///
+    /// Returns the number of the attempt started, or none if the workflow has
+    /// no such step.
+    pub fn retry_step(&mut self, step_id: &str, now: chrono::DateTime<chrono::Utc>) -> Option<u32> {
+        let step = self.steps.iter_mut().find(|s| s.step_id == step_id)?;
+        step.retries += 1;
+        step.completed_at = None;
+        let attempt = step.retries + 1;
+        self.activate_step(step_id, now);
+        self.completed_at = None;
+        Some(attempt)
+    }
+
+    /// Fail the active step and the workflow with it
+    pub fn fail_active_step(
+        &mut self,
+        reason: impl Into<String>,
+        now: chrono::DateTime<chrono::Utc>,
+    ) {
+        let step_id = self.current_step.clone();
+        if let Some(step) = self
+            .steps
+            .iter_mut()
+            .find(|s| Some(&s.step_id) == step_id.as_ref())
+        {
+            step.status = StepStatus::Failed;
+            step.completed_at = Some(now);
+        }
+        self.status = WorkflowStatus::Failed(reason.into());
+        self.completed_at = Some(now);
+    }
 }
 
 /// Type of identity workflow
@@ -45,13 +322,53 @@ pub enum WorkflowStatus {
 pub struct WorkflowStep {
     pub step_id: String,
     pub step_type: StepType,
+    #[serde(default)]
     pub status: StepStatus,
     pub name: String,
     pub description: Option<String>,
+    #[serde(default = "required_by_default")]
     pub required: bool,
     pub timeout_seconds: Option<u64>,
     pub started_at: Option<chrono::DateTime<chrono::Utc>>,
     pub completed_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Approvers deciding an approval step; without one the step is processed directly
+    #[serde(default)]
+    pub approval: Option<ApprovalPolicy>,
+    /// How often and when the step may be retried after failing
+    #[serde(default)]
+    pub retry: Option<RetryPolicy>,
+    /// Times the step has been retried
+    #[serde(default)]
+    pub retries: u32,
+}
+
+fn required_by_default() -> bool {
+    true
+}
+
+/// Retries allowed for a failed workflow step
+///
+/// Each retry waits twice as long after the failure as the one before it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    /// Attempts including the first one
+    pub max_attempts: u32,
+    /// Seconds to wait before the first retry
+    #[serde(default)]
+    pub backoff_seconds: u64,
+}
+
+impl RetryPolicy {
+    /// Time to wait after a failure before retrying a step retried `retries` times
+    pub fn backoff(&self, retries: u32) -> chrono::Duration {
+        let seconds = self
+            .backoff_seconds
+            .saturating_mul(2u64.saturating_pow(retries));
+        i64::try_from(seconds)
+            .ok()
+            .and_then(chrono::Duration::try_seconds)
+            .unwrap_or(chrono::Duration::MAX)
+    }
 }
 
 /// Type of workflow step
@@ -64,9 +381,21 @@ pub enum StepType {
     Notification,
 }
 
+impl StepType {
+    /// Status of a workflow while a step of this type is active
+    pub fn waiting_status(&self) -> WorkflowStatus {
+        match self {
+            StepType::Manual | StepType::Verification => WorkflowStatus::WaitingForInput,
+            StepType::Approval => WorkflowStatus::WaitingForApproval,
+            StepType::Automated | StepType::Notification => WorkflowStatus::InProgress,
+        }
+    }
+}
+
 /// Status of a workflow step
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum StepStatus {
+    #[default]
     Pending,
     Active,
     Completed,
@@ -74,12 +403,44 @@ pub enum StepStatus {
     Skipped,
 }
 
+/// How a processed workflow step ended
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StepOutcome {
+    #[default]
+    Completed,
+    Failed(String),
+    /// Only steps that are not required can be skipped
+    Skipped,
+}
+
+impl StepOutcome {
+    /// Status of a step that ended this way
+    pub fn step_status(&self) -> StepStatus {
+        match self {
+            StepOutcome::Completed => StepStatus::Completed,
+            StepOutcome::Failed(_) => StepStatus::Failed,
+            StepOutcome::Skipped => StepStatus::Skipped,
+        }
+    }
+}
+
+impl fmt::Display for StepOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StepOutcome::Completed => write!(f, "completed"),
+            StepOutcome::Failed(reason) => write!(f, "failed: {reason}"),
+            StepOutcome::Skipped => write!(f, "skipped"),
+        }
+    }
+}
+
 /// Workflow transition between steps
 #[derive(Component, Debug, Clone, Serialize, Deserialize)]
 pub struct WorkflowTransition {
     pub from_step: String,
     pub to_step: String,
     pub condition: TransitionCondition,
+    #[serde(default)]
     pub metadata: serde_json::Value,
 }
 
@@ -103,6 +464,38 @@ pub enum TransitionCondition {
     Manual,
 }
 
+impl TransitionCondition {
+    /// Whether a step that ended with `outcome` moves on to `to_step`
+    ///
+    /// `scope` holds the fields built by `IdentityWorkflow::condition_scope`.
+    /// Skipped steps count as successful. `FieldEquals` reads dotted paths
+    /// such as `document.country` from the step output, `Expression`s are
+    /// evaluated against the whole scope, and `Manual` transitions are taken
+    /// when the output names their target as `next_step`.
+    pub fn matches(
+        &self,
+        outcome: &StepOutcome,
+        to_step: &str,
+        scope: &serde_json::Value,
+    ) -> IdentityResult<bool> {
+        let output = &scope["output"];
+        Ok(match self {
+            TransitionCondition::Always => true,
+            TransitionCondition::OnSuccess => !matches!(outcome, StepOutcome::Failed(_)),
+            TransitionCondition::OnFailure => matches!(outcome, StepOutcome::Failed(_)),
+            TransitionCondition::FieldEquals { field, value } => {
+                output.pointer(&format!("/{}", field.replace('.', "/"))) == Some(value)
+            }
+            TransitionCondition::Expression { expr } => {
+                Expression::parse(expr)?.evaluate_bool(scope)?
+            }
+            TransitionCondition::Manual => {
+                output.get("next_step").and_then(|v| v.as_str()) == Some(to_step)
+            }
+        })
+    }
+}
+
 /// Workflow history record
 #[derive(Component, Debug, Clone, Serialize, Deserialize)]
 pub struct WorkflowHistory {
@@ -110,6 +503,21 @@ pub struct WorkflowHistory {
     pub step_transitions: Vec<StepTransition>,
     pub total_duration: Option<chrono::Duration>,
     pub completion_data: Option<serde_json::Value>,
+    /// Decisions taken on the workflow's approval steps
+    #[serde(default)]
+    pub approvals: Vec<ApprovalDecision>,
+}
+
+impl WorkflowHistory {
+    pub fn new(workflow_id: Uuid) -> Self {
+        Self {
+            workflow_id,
+            step_transitions: Vec::new(),
+            total_duration: None,
+            completion_data: None,
+            approvals: Vec::new(),
+        }
+    }
 }
 
 /// Record of a step transition
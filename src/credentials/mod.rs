@@ -0,0 +1,358 @@
+//! W3C Verifiable Credentials for identity claims
+//!
+//! A verified [`IdentityClaim`] is exported as a Verifiable Credential whose
+//! subject is the identity (`urn:uuid:<identity id>`). Credentials carry a
+//! `DataIntegrityProof` using the `eddsa-jcs-2022` cryptosuite: the proof
+//! configuration and the credential without its proof are canonicalized with
+//! JSON Canonicalization (RFC 8785), hashed with SHA-256 and signed with a
+//! locally held Ed25519 key, so issuing and verifying never needs the network.
+//!
+//! Imported credentials are verified against the public keys registered in
+//! [`IssuerKeys`]. Whether the issuer is trusted is decided by
+//! `import_credential_system` from the `Trusts` relationships of the
+//! importing identity.
+
+use crate::components::{ClaimType, IdentityClaim, IdentityId};
+use crate::tokens::TokenSigningKey;
+use crate::{IdentityError, IdentityResult};
+use bevy::ecs::prelude::*;
+use chrono::{DateTime, Utc};
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// JSON-LD context of the W3C Verifiable Credentials data model
+pub const CREDENTIALS_CONTEXT: &str = "https://www.w3.org/2018/credentials/v1";
+/// JSON-LD context defining `DataIntegrityProof`
+pub const DATA_INTEGRITY_CONTEXT: &str = "https://w3id.org/security/data-integrity/v2";
+/// Credential type of exported identity claims
+pub const IDENTITY_CLAIM_CREDENTIAL: &str = "IdentityClaimCredential";
+
+const VERIFIABLE_CREDENTIAL: &str = "VerifiableCredential";
+const PROOF_TYPE: &str = "DataIntegrityProof";
+const CRYPTOSUITE: &str = "eddsa-jcs-2022";
+const PROOF_PURPOSE: &str = "assertionMethod";
+const BASE58_ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// URI naming an identity in credentials
+pub fn identity_urn(identity_id: IdentityId) -> String {
+    format!("urn:uuid:{identity_id}")
+}
+
+fn parse_identity_urn(uri: &str) -> Option<IdentityId> {
+    uri.strip_prefix("urn:uuid:")
+        .and_then(|id| Uuid::parse_str(id).ok())
+}
+
+/// Verifiable Credential asserting one claim about an identity
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifiableCredential {
+    #[serde(rename = "@context")]
+    pub context: Vec<String>,
+    pub id: String,
+    #[serde(rename = "type")]
+    pub types: Vec<String>,
+    pub issuer: String,
+    pub issuance_date: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expiration_date: Option<DateTime<Utc>>,
+    pub credential_subject: ClaimSubject,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proof: Option<DataIntegrityProof>,
+}
+
+impl VerifiableCredential {
+    /// Identity the credential is about
+    pub fn subject_identity(&self) -> Option<IdentityId> {
+        parse_identity_urn(&self.credential_subject.id)
+    }
+
+    /// The credential as a JSON-LD document
+    pub fn to_json(&self) -> IdentityResult<Value> {
+        encode(self)
+    }
+}
+
+/// Subject of an identity claim credential
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClaimSubject {
+    pub id: String,
+    pub claim_type: ClaimType,
+    pub value: String,
+}
+
+/// Data Integrity proof of a credential
+///
+/// `proof_value` is absent from the proof configuration that is signed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DataIntegrityProof {
+    #[serde(rename = "type")]
+    pub proof_type: String,
+    pub cryptosuite: String,
+    pub created: DateTime<Utc>,
+    pub verification_method: String,
+    pub proof_purpose: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proof_value: Option<String>,
+}
+
+/// Resource signing credentials for the claims this domain has verified
+///
+/// Inserted by `IdentityPlugin` with a fresh issuer id and key unless the
+/// application inserts its own first.
+#[derive(Resource, Debug)]
+pub struct CredentialIssuer {
+    issuer_id: IdentityId,
+    key: TokenSigningKey,
+}
+
+impl CredentialIssuer {
+    pub fn new(issuer_id: IdentityId, key: TokenSigningKey) -> Self {
+        CredentialIssuer { issuer_id, key }
+    }
+
+    pub fn issuer_id(&self) -> IdentityId {
+        self.issuer_id
+    }
+
+    /// Key id and public key verifying credentials of this issuer
+    pub fn public_key(&self) -> (&str, VerifyingKey) {
+        (self.key.kid(), self.key.verifying_key())
+    }
+
+    /// Sign a credential asserting `claim` about `subject`
+    pub fn issue(
+        &self,
+        subject: IdentityId,
+        claim: &IdentityClaim,
+        now: DateTime<Utc>,
+    ) -> IdentityResult<VerifiableCredential> {
+        let mut credential = VerifiableCredential {
+            context: vec![
+                CREDENTIALS_CONTEXT.to_string(),
+                DATA_INTEGRITY_CONTEXT.to_string(),
+            ],
+            id: identity_urn(Uuid::new_v4()),
+            types: vec![
+                VERIFIABLE_CREDENTIAL.to_string(),
+                IDENTITY_CLAIM_CREDENTIAL.to_string(),
+            ],
+            issuer: identity_urn(self.issuer_id),
+            issuance_date: now,
+            expiration_date: claim.expires_at,
+            credential_subject: ClaimSubject {
+                id: identity_urn(subject),
+                claim_type: claim.claim_type.clone(),
+                value: claim.value.clone(),
+            },
+            proof: None,
+        };
+        let mut proof = DataIntegrityProof {
+            proof_type: PROOF_TYPE.to_string(),
+            cryptosuite: CRYPTOSUITE.to_string(),
+            created: now,
+            verification_method: verification_method(self.issuer_id, self.key.kid()),
+            proof_purpose: PROOF_PURPOSE.to_string(),
+            proof_value: None,
+        };
+
+        let document = credential.to_json()?;
+        let proof_config = proof_config(&document, encode(&proof)?);
+        let signature = self.key.sign_message(&hash_data(&proof_config, &document));
+        proof.proof_value = Some(format!("z{}", base58_encode(&signature.to_bytes())));
+        credential.proof = Some(proof);
+        Ok(credential)
+    }
+}
+
+impl Default for CredentialIssuer {
+    fn default() -> Self {
+        CredentialIssuer::new(Uuid::new_v4(), TokenSigningKey::generate())
+    }
+}
+
+/// Public keys of credential issuers, by verification method
+#[derive(Resource, Debug, Clone, Default)]
+pub struct IssuerKeys {
+    keys: HashMap<String, (IdentityId, VerifyingKey)>,
+}
+
+impl IssuerKeys {
+    /// Accept credentials of `issuer_id` signed with the key `kid`
+    ///
+    /// Returns the verification method naming the key in proofs.
+    pub fn register(&mut self, issuer_id: IdentityId, kid: &str, key: VerifyingKey) -> String {
+        let method = verification_method(issuer_id, kid);
+        self.keys.insert(method.clone(), (issuer_id, key));
+        method
+    }
+
+    /// Check the proof and expiry of a credential, returning its issuer and contents
+    ///
+    /// The proof is checked against the document as received, so credentials
+    /// of other issuers keep verifying whatever their date formatting.
+    pub fn verify(
+        &self,
+        document: &Value,
+        now: DateTime<Utc>,
+    ) -> IdentityResult<(IdentityId, VerifiableCredential)> {
+        let credential: VerifiableCredential = serde_json::from_value(document.clone())
+            .map_err(|_| invalid_credential("malformed credential"))?;
+        if !credential.types.iter().any(|t| t == VERIFIABLE_CREDENTIAL) {
+            return Err(invalid_credential("not a verifiable credential"));
+        }
+
+        let proof = credential
+            .proof
+            .as_ref()
+            .ok_or_else(|| invalid_credential("missing proof"))?;
+        if proof.proof_type != PROOF_TYPE || proof.cryptosuite != CRYPTOSUITE {
+            return Err(invalid_credential("unsupported proof"));
+        }
+        if proof.proof_purpose != PROOF_PURPOSE {
+            return Err(invalid_credential("unexpected proof purpose"));
+        }
+        let (issuer_id, key) = self
+            .keys
+            .get(&proof.verification_method)
+            .ok_or_else(|| invalid_credential("unknown verification method"))?;
+        if credential.issuer != identity_urn(*issuer_id) {
+            return Err(invalid_credential("issuer does not own the signing key"));
+        }
+
+        let signature = proof
+            .proof_value
+            .as_deref()
+            .and_then(|value| value.strip_prefix('z'))
+            .and_then(base58_decode)
+            .and_then(|bytes| Signature::from_slice(&bytes).ok())
+            .ok_or_else(|| invalid_credential("malformed proof value"))?;
+
+        let mut unsecured = document.clone();
+        let mut proof_options = unsecured
+            .as_object_mut()
+            .and_then(|document| document.remove("proof"))
+            .unwrap_or_default();
+        if let Some(options) = proof_options.as_object_mut() {
+            options.remove("proofValue");
+        }
+        let proof_config = proof_config(&unsecured, proof_options);
+        key.verify_strict(&hash_data(&proof_config, &unsecured), &signature)
+            .map_err(|_| invalid_credential("signature mismatch"))?;
+
+        if credential
+            .expiration_date
+            .is_some_and(|expires_at| expires_at <= now)
+        {
+            return Err(invalid_credential("credential has expired"));
+        }
+
+        Ok((*issuer_id, credential))
+    }
+}
+
+fn verification_method(issuer_id: IdentityId, kid: &str) -> String {
+    format!("{}#{kid}", identity_urn(issuer_id))
+}
+
+fn invalid_credential(reason: &str) -> IdentityError {
+    IdentityError::InvalidCredential(reason.to_string())
+}
+
+fn encode<T: Serialize>(value: &T) -> IdentityResult<Value> {
+    serde_json::to_value(value)
+        .map_err(|e| IdentityError::InvalidCredential(format!("credential encoding failed: {e}")))
+}
+
+/// Proof options with the `@context` of the secured document
+fn proof_config(document: &Value, mut options: Value) -> Value {
+    if let (Some(options), Some(context)) = (options.as_object_mut(), document.get("@context")) {
+        options.insert("@context".to_string(), context.clone());
+    }
+    options
+}
+
+/// SHA-256 of the canonical proof configuration followed by that of the document
+fn hash_data(proof_config: &Value, document: &Value) -> Vec<u8> {
+    let mut data = Sha256::digest(canonicalize(proof_config).as_bytes()).to_vec();
+    data.extend_from_slice(&Sha256::digest(canonicalize(document).as_bytes()));
+    data
+}
+
+/// JSON Canonicalization Scheme: members sorted by their UTF-16 code units,
+/// no insignificant whitespace
+fn canonicalize(value: &Value) -> String {
+    match value {
+        Value::Object(members) => {
+            let mut members: Vec<_> = members.iter().collect();
+            members.sort_by(|(a, _), (b, _)| a.encode_utf16().cmp(b.encode_utf16()));
+            let members: Vec<String> = members
+                .into_iter()
+                .map(|(name, value)| {
+                    format!("{}:{}", Value::from(name.as_str()), canonicalize(value))
+                })
+                .collect();
+            format!("{{{}}}", members.join(","))
+        }
+        Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(canonicalize).collect();
+            format!("[{}]", items.join(","))
+        }
+        scalar => scalar.to_string(),
+    }
+}
+
+pub(crate) fn base58_encode(bytes: &[u8]) -> String {
+    let zeros = bytes.iter().take_while(|byte| **byte == 0).count();
+    // Base58 digits, least significant first
+    let mut digits: Vec<u8> = Vec::new();
+    for &byte in &bytes[zeros..] {
+        let mut carry = byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+
+    let mut encoded = "1".repeat(zeros);
+    encoded.extend(
+        digits
+            .iter()
+            .rev()
+            .map(|digit| BASE58_ALPHABET[*digit as usize] as char),
+    );
+    encoded
+}
+
+pub(crate) fn base58_decode(encoded: &str) -> Option<Vec<u8>> {
+    let zeros = encoded.bytes().take_while(|c| *c == b'1').count();
+    // Bytes, least significant first
+    let mut bytes: Vec<u8> = Vec::new();
+    for c in encoded.bytes().skip(zeros) {
+        let mut carry = BASE58_ALPHABET.iter().position(|a| *a == c)? as u32;
+        for byte in bytes.iter_mut() {
+            carry += (*byte as u32) * 58;
+            *byte = (carry & 0xff) as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push((carry & 0xff) as u8);
+            carry >>= 8;
+        }
+    }
+
+    let mut decoded = vec![0; zeros];
+    decoded.extend(bytes.into_iter().rev());
+    Some(decoded)
+}
@@ -0,0 +1,217 @@
+//! Decentralized identifiers (DIDs) for identities
+//!
+//! An identity publishes a DID document listing its Ed25519 verification keys
+//! as `Multikey` entries and its service endpoints, under either method:
+//!
+//! - `did:key:z6Mk…`, derived from the identity's first key
+//! - `did:web:<domain>:identities:<identity id>`, which keeps its DID across
+//!   key rotations
+//!
+//! Documents are resolved locally through the [`DidResolver`] registry, kept
+//! up to date from the [`IdentityDid`] components; `did:key` DIDs of other
+//! parties resolve from the DID itself.
+
+use crate::components::{CrossDomainReference, DidMethod, DidService, IdentityDid, IdentityId};
+use crate::credentials::{base58_decode, base58_encode};
+use crate::{IdentityError, IdentityResult};
+use bevy::ecs::prelude::*;
+use ed25519_dalek::VerifyingKey;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// JSON-LD context of DID documents
+pub const DID_CONTEXT: &str = "https://www.w3.org/ns/did/v1";
+/// JSON-LD context defining `Multikey`
+pub const MULTIKEY_CONTEXT: &str = "https://w3id.org/security/multikey/v1";
+
+/// Multicodec prefix of Ed25519 public keys
+const ED25519_MULTICODEC: [u8; 2] = [0xed, 0x01];
+const MULTIKEY: &str = "Multikey";
+
+/// Ed25519 public key as a base58btc multibase Multikey (`z6Mk…`)
+pub fn ed25519_multikey(key: &VerifyingKey) -> String {
+    let mut bytes = ED25519_MULTICODEC.to_vec();
+    bytes.extend_from_slice(key.as_bytes());
+    format!("z{}", base58_encode(&bytes))
+}
+
+/// Ed25519 public key of a multibase Multikey
+pub fn decode_multikey(multikey: &str) -> IdentityResult<VerifyingKey> {
+    let bytes = multikey
+        .strip_prefix('z')
+        .and_then(base58_decode)
+        .ok_or_else(|| invalid_did("malformed multikey"))?;
+    let key = bytes
+        .strip_prefix(&ED25519_MULTICODEC[..])
+        .and_then(|key| <[u8; 32]>::try_from(key).ok())
+        .ok_or_else(|| invalid_did("not an Ed25519 multikey"))?;
+    VerifyingKey::from_bytes(&key).map_err(|_| invalid_did("invalid Ed25519 public key"))
+}
+
+/// DID of an identity publishing under `method` with its first key
+pub fn did_for(method: &DidMethod, identity_id: IdentityId, first_key: &VerifyingKey) -> String {
+    match method {
+        DidMethod::Key => format!("did:key:{}", ed25519_multikey(first_key)),
+        // Ports are percent-encoded, as colons separate did:web path segments
+        DidMethod::Web { domain } => format!(
+            "did:web:{}:identities:{identity_id}",
+            domain.replace(':', "%3A")
+        ),
+    }
+}
+
+/// DID document, as published to other domains
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidDocument {
+    #[serde(rename = "@context")]
+    pub context: Vec<String>,
+    pub id: String,
+    pub verification_method: Vec<DidVerificationMethod>,
+    pub authentication: Vec<String>,
+    pub assertion_method: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub service: Vec<DidServiceEndpoint>,
+}
+
+/// Verification key entry of a DID document
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidVerificationMethod {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub method_type: String,
+    pub controller: String,
+    pub public_key_multibase: String,
+}
+
+/// Service entry of a DID document
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidServiceEndpoint {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub service_type: String,
+    pub service_endpoint: String,
+}
+
+impl DidDocument {
+    /// Document listing the active keys and the services of a published DID
+    pub fn from_identity_did(identity_did: &IdentityDid) -> Self {
+        let did = &identity_did.did;
+        let verification_method: Vec<DidVerificationMethod> = identity_did
+            .active_keys()
+            .map(|key| DidVerificationMethod {
+                id: format!("{did}#{}", key.public_key_multibase),
+                method_type: MULTIKEY.to_string(),
+                controller: did.clone(),
+                public_key_multibase: key.public_key_multibase.clone(),
+            })
+            .collect();
+        let key_ids: Vec<String> = verification_method
+            .iter()
+            .map(|method| method.id.clone())
+            .collect();
+
+        DidDocument {
+            context: vec![DID_CONTEXT.to_string(), MULTIKEY_CONTEXT.to_string()],
+            id: did.clone(),
+            verification_method,
+            authentication: key_ids.clone(),
+            assertion_method: key_ids,
+            service: service_endpoints(did, &identity_did.services),
+        }
+    }
+
+    /// Document of a `did:key` DID, derived from the DID alone
+    pub fn from_did_key(did: &str) -> IdentityResult<Self> {
+        let multikey = did
+            .strip_prefix("did:key:")
+            .ok_or_else(|| invalid_did("not a did:key DID"))?;
+        decode_multikey(multikey)?;
+
+        let key_id = format!("{did}#{multikey}");
+        Ok(DidDocument {
+            context: vec![DID_CONTEXT.to_string(), MULTIKEY_CONTEXT.to_string()],
+            id: did.to_string(),
+            verification_method: vec![DidVerificationMethod {
+                id: key_id.clone(),
+                method_type: MULTIKEY.to_string(),
+                controller: did.to_string(),
+                public_key_multibase: multikey.to_string(),
+            }],
+            authentication: vec![key_id.clone()],
+            assertion_method: vec![key_id],
+            service: Vec::new(),
+        })
+    }
+
+    /// Public key of a verification method listed in the document
+    pub fn verifying_key(&self, method_id: &str) -> IdentityResult<VerifyingKey> {
+        let method = self
+            .verification_method
+            .iter()
+            .find(|method| method.id == method_id)
+            .ok_or_else(|| invalid_did("unknown verification method"))?;
+        decode_multikey(&method.public_key_multibase)
+    }
+}
+
+fn service_endpoints(did: &str, services: &[DidService]) -> Vec<DidServiceEndpoint> {
+    services
+        .iter()
+        .map(|service| DidServiceEndpoint {
+            id: format!("{did}#{}", service.id),
+            service_type: service.service_type.clone(),
+            service_endpoint: service.endpoint.clone(),
+        })
+        .collect()
+}
+
+/// Local registry resolving DIDs to their documents and identities
+///
+/// Kept up to date by `sync_did_resolver_system`.
+#[derive(Resource, Debug, Clone, Default)]
+pub struct DidResolver {
+    documents: HashMap<String, DidDocument>,
+    identities: HashMap<String, IdentityId>,
+}
+
+impl DidResolver {
+    /// Register the current document of an identity's DID
+    pub fn publish(&mut self, identity_id: IdentityId, document: DidDocument) {
+        self.identities.insert(document.id.clone(), identity_id);
+        self.documents.insert(document.id.clone(), document);
+    }
+
+    /// Resolve a DID, or a DID URL, to its document
+    pub fn resolve(&self, did_url: &str) -> IdentityResult<DidDocument> {
+        let did = did_of_url(did_url);
+        if let Some(document) = self.documents.get(did) {
+            return Ok(document.clone());
+        }
+        if did.starts_with("did:key:") {
+            return DidDocument::from_did_key(did);
+        }
+        Err(IdentityError::DidNotFound(did.to_string()))
+    }
+
+    /// Identity that published a DID
+    pub fn identity(&self, did_url: &str) -> Option<IdentityId> {
+        self.identities.get(did_of_url(did_url)).copied()
+    }
+
+    /// Identity a cross-domain reference points to by DID
+    pub fn resolve_reference(&self, reference: &CrossDomainReference) -> Option<IdentityId> {
+        reference.as_did().and_then(|did| self.identity(did))
+    }
+}
+
+/// DID of a DID URL, without path, query or fragment
+fn did_of_url(did_url: &str) -> &str {
+    did_url.split(['#', '?', '/']).next().unwrap_or(did_url)
+}
+
+fn invalid_did(reason: &str) -> IdentityError {
+    IdentityError::InvalidDid(reason.to_string())
+}
@@ -1,8 +1,19 @@
 //! Events for the Identity domain
 
+pub mod rejections;
+
+pub use rejections::*;
+
 use crate::components::{
-    CrossDomainReference, IdentityId, IdentityStatus, IdentityType, ProjectionType, RelationshipId,
-    RelationshipType, VerificationLevel, VerificationMethod, WorkflowStatus, WorkflowType,
+    ClaimType, CrossDomainReference, DidMethod, DidService, IdentityId, IdentityStatus,
+    IdentityType, KeyAlgorithm, KeyPurpose, ProjectionType, RelationshipId, RelationshipRules,
+    RelationshipType, StepOutcome, VerificationLevel, VerificationMethod, WorkflowStatus,
+    WorkflowStep, WorkflowTransition, WorkflowType,
+};
+use crate::authentication::{
+    AuthFactor, AuthMethod, AuthenticationChallenge, AuthenticationDecision, LocationContext,
+    LocationRisk, MfaChallenge, MfaFactor, RiskAssessment, SessionDevice, SessionEndReason,
+    TotpSecret,
 };
 use bevy::ecs::prelude::*;
 use chrono::{DateTime, Utc};
@@ -17,6 +28,8 @@ pub struct IdentityCreated {
     pub created_by: Option<Uuid>,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub external_reference: Option<CrossDomainReference>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
 }
 
 /// Event fired when an identity is updated
@@ -27,6 +40,8 @@ pub struct IdentityUpdated {
     pub new_status: IdentityStatus,
     pub updated_by: IdentityId,
     pub updated_at: chrono::DateTime<chrono::Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
 }
 
 /// Event fired when identities are merged
@@ -39,6 +54,8 @@ pub struct IdentitiesMerged {
     pub migrated_relationships: usize,
     pub migrated_workflows: usize,
     pub retained_verification_level: VerificationLevel,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
 }
 
 /// Event fired when an identity is archived
@@ -49,6 +66,76 @@ pub struct IdentityArchived {
     pub archived_by: IdentityId,
     pub archived_at: chrono::DateTime<chrono::Utc>,
     pub reason: Option<String>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when a claim is added to an identity
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct ClaimAdded {
+    pub identity_id: IdentityId,
+    pub claim_type: ClaimType,
+    pub value: String,
+    pub issuer: Option<IdentityId>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub added_by: IdentityId,
+    pub added_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when the value or expiry of a claim changes
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct ClaimUpdated {
+    pub identity_id: IdentityId,
+    pub claim_type: ClaimType,
+    pub old_value: String,
+    pub value: String,
+    /// Whether the claim is still verified; a new value is not
+    pub verified: bool,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub updated_by: IdentityId,
+    pub updated_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when a claim is revoked
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct ClaimRevoked {
+    pub identity_id: IdentityId,
+    pub claim_type: ClaimType,
+    pub value: String,
+    pub revoked_by: IdentityId,
+    pub revoked_at: DateTime<Utc>,
+    pub reason: Option<String>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when a claim expires
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct ClaimExpired {
+    pub identity_id: IdentityId,
+    pub claim_type: ClaimType,
+    pub value: String,
+    pub expired_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when a verification level drops because a supporting claim lapsed
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct VerificationLevelDowngraded {
+    pub identity_id: IdentityId,
+    pub previous_level: VerificationLevel,
+    pub new_level: VerificationLevel,
+    /// Claim whose revocation, change or expiry lowered the level
+    pub claim_type: ClaimType,
+    pub value: String,
+    pub downgraded_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
 }
 
 /// Event fired when a relationship is established
@@ -58,8 +145,11 @@ pub struct RelationshipEstablished {
     pub from_identity: IdentityId,
     pub to_identity: IdentityId,
     pub relationship_type: RelationshipType,
+    pub rules: RelationshipRules,
     pub established_by: IdentityId,
     pub established_at: chrono::DateTime<chrono::Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
 }
 
 /// Event fired when a relationship is validated
@@ -69,6 +159,8 @@ pub struct RelationshipValidated {
     pub is_valid: bool,
     pub reason: String,
     pub validated_at: chrono::DateTime<chrono::Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
 }
 
 /// Event fired when a relationship expires
@@ -79,6 +171,8 @@ pub struct RelationshipExpired {
     pub to_identity: IdentityId,
     pub relationship_type: RelationshipType,
     pub expired_at: chrono::DateTime<chrono::Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
 }
 
 /// Event fired when relationships are traversed
@@ -89,6 +183,8 @@ pub struct RelationshipsTraversed {
     pub paths: Vec<(Vec<IdentityId>, Vec<RelationshipId>)>,
     pub total_identities_visited: usize,
     pub traversed_at: chrono::DateTime<chrono::Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
 }
 
 /// Event fired when a relationship is revoked
@@ -98,6 +194,8 @@ pub struct RelationshipRevoked {
     pub revoked_by: IdentityId,
     pub revoked_at: chrono::DateTime<chrono::Utc>,
     pub reason: Option<String>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
 }
 
 /// Event fired when a workflow is started
@@ -109,6 +207,13 @@ pub struct WorkflowStarted {
     pub started_by: IdentityId,
     pub started_at: DateTime<Utc>,
     pub context: serde_json::Value,
+    /// Steps instantiated from the workflow definition
+    #[serde(default)]
+    pub steps: Vec<WorkflowStep>,
+    #[serde(default)]
+    pub transitions: Vec<WorkflowTransition>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
 }
 
 /// Event fired when a workflow step is completed
@@ -118,7 +223,18 @@ pub struct WorkflowStepCompleted {
     pub identity_id: IdentityId,
     pub workflow_type: WorkflowType,
     pub step_id: String,
+    #[serde(default)]
+    pub outcome: StepOutcome,
+    /// Step activated next, or none once the workflow has finished
+    #[serde(default)]
+    pub next_step: Option<String>,
+    #[serde(default)]
+    pub processed_by: Option<IdentityId>,
+    #[serde(default)]
+    pub step_data: serde_json::Value,
     pub completed_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
 }
 
 /// Event fired when a workflow is completed
@@ -129,6 +245,8 @@ pub struct WorkflowCompleted {
     pub workflow_type: WorkflowType,
     pub final_status: WorkflowStatus,
     pub completed_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
 }
 
 /// Event fired when a workflow times out
@@ -139,15 +257,165 @@ pub struct WorkflowTimedOut {
     pub workflow_type: WorkflowType,
     pub step_id: String,
     pub timed_out_at: chrono::DateTime<chrono::Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when a workflow is paused on its active step
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowPaused {
+    pub workflow_id: Uuid,
+    pub identity_id: IdentityId,
+    pub paused_by: IdentityId,
+    pub reason: Option<String>,
+    pub paused_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when a paused workflow is resumed
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowResumed {
+    pub workflow_id: Uuid,
+    pub identity_id: IdentityId,
+    pub resumed_by: IdentityId,
+    pub resumed_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when a workflow is cancelled
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowCancelled {
+    pub workflow_id: Uuid,
+    pub identity_id: IdentityId,
+    pub cancelled_by: IdentityId,
+    pub reason: Option<String>,
+    pub cancelled_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when the step a workflow failed on is activated again
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowStepRetried {
+    pub workflow_id: Uuid,
+    pub identity_id: IdentityId,
+    pub step_id: String,
+    /// Attempt started, counting the first one
+    pub attempt: u32,
+    pub retried_by: IdentityId,
+    pub retried_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when an approval step with an approval policy becomes active
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct ApprovalRequested {
+    pub workflow_id: Uuid,
+    pub identity_id: IdentityId,
+    pub step_id: String,
+    pub approvers: Vec<IdentityId>,
+    pub quorum: usize,
+    pub requested_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when an approver approves or rejects an approval step
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct ApprovalDecided {
+    pub workflow_id: Uuid,
+    pub identity_id: IdentityId,
+    pub step_id: String,
+    pub approver: IdentityId,
+    pub approved: bool,
+    pub comment: Option<String>,
+    pub decided_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when an undecided approval step is escalated to further approvers
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct ApprovalEscalated {
+    pub workflow_id: Uuid,
+    pub identity_id: IdentityId,
+    pub step_id: String,
+    pub added_approvers: Vec<IdentityId>,
+    pub escalated_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
 }
 
 /// Event fired when verification is started
 #[derive(Event, Debug, Clone, Serialize, Deserialize)]
 pub struct VerificationStarted {
     pub identity_id: IdentityId,
+    /// Workflow running the verification steps
+    pub workflow_id: Uuid,
     pub verification_method: VerificationMethod,
+    pub target_level: VerificationLevel,
     pub initiated_by: IdentityId,
+    /// Reference returned by the third-party provider the check was requested from
+    #[serde(default)]
+    pub provider_reference: Option<String>,
     pub started_at: chrono::DateTime<chrono::Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when a one-time code has been sent to a claim
+///
+/// Carries the pending challenge, so replay can put it back; the code itself
+/// is never recorded.
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct VerificationCodeSent {
+    pub identity_id: IdentityId,
+    /// Verification workflow the code was sent for, none for codes sent on request
+    #[serde(default)]
+    pub workflow_id: Option<Uuid>,
+    pub claim_type: ClaimType,
+    pub value: String,
+    /// HMAC of the code under the server's [`crate::verification::CodeHashKey`]
+    pub code_hash: String,
+    pub sent_at: chrono::DateTime<chrono::Utc>,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+    /// Wrong codes presented so far, zero for a newly sent code
+    #[serde(default)]
+    pub failed_attempts: u32,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when a one-time code sent to a claim could not be confirmed
+///
+/// A wrong code counts against the attempts of the pending code; an expired
+/// or exhausted code is dropped and can no longer be confirmed.
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct VerificationCodeFailed {
+    pub identity_id: IdentityId,
+    pub claim_type: ClaimType,
+    pub value: String,
+    /// Wrong codes presented for the pending code, including this one
+    pub failed_attempts: u32,
+    /// Whether the code expired or ran out of attempts
+    pub dropped: bool,
+    pub failed_at: chrono::DateTime<chrono::Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when a single claim of an identity has been verified
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct ClaimVerified {
+    pub identity_id: IdentityId,
+    pub claim_type: ClaimType,
+    pub value: String,
+    pub verified_at: chrono::DateTime<chrono::Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
 }
 
 /// Event fired when verification is completed
@@ -156,8 +424,426 @@ pub struct VerificationCompleted {
     pub identity_id: IdentityId,
     pub verification_successful: bool,
     pub new_verification_level: VerificationLevel,
+    pub verification_method: VerificationMethod,
     pub verified_by: IdentityId,
+    /// Claim type and value whose one-time code the verification confirmed
+    #[serde(default)]
+    pub verified_claim: Option<(ClaimType, String)>,
     pub completed_at: chrono::DateTime<chrono::Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Claim whose one-time code a finished verification workflow confirmed
+///
+/// Only the verification systems write it, next to the
+/// `CompleteVerificationCommand` of the workflow, so a completion command sent
+/// from outside never verifies a claim.
+#[derive(Event, Debug, Clone)]
+pub struct VerificationCodeClaimConfirmed {
+    pub(crate) command_id: Uuid,
+    pub(crate) claim: (ClaimType, String),
+}
+
+/// Event fired when an API key is issued
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKeyIssued {
+    pub identity_id: IdentityId,
+    pub key_id: Uuid,
+    pub name: String,
+    pub key_hash: String,
+    pub permissions: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub issued_by: IdentityId,
+    pub issued_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when an API key is replaced by a new one
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKeyRotated {
+    pub identity_id: IdentityId,
+    pub old_key_id: Uuid,
+    pub new_key_id: Uuid,
+    pub new_key_hash: String,
+    /// When the old key stops working; the rotation time if it was revoked
+    pub old_key_valid_until: DateTime<Utc>,
+    pub rotated_by: IdentityId,
+    pub rotated_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when an API key is revoked
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKeyRevoked {
+    pub identity_id: IdentityId,
+    pub key_id: Uuid,
+    pub revoked_by: IdentityId,
+    pub revoked_at: DateTime<Utc>,
+    pub reason: Option<String>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when a caller authenticates with an API key
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKeyAuthenticated {
+    pub identity_id: IdentityId,
+    pub key_id: Uuid,
+    pub granted_permissions: Vec<String>,
+    pub authenticated_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when the password of an identity is set or replaced
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct PasswordSet {
+    pub identity_id: IdentityId,
+    pub password_hash: String,
+    pub set_by: IdentityId,
+    pub set_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when a password hash is upgraded to the configured parameters
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct PasswordRehashed {
+    pub identity_id: IdentityId,
+    pub password_hash: String,
+    pub rehashed_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when an identity authenticates with its password
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct AuthenticationSucceeded {
+    pub identity_id: IdentityId,
+    /// Granted attempt completed by the password, if any
+    #[serde(default)]
+    pub attempt_id: Option<Uuid>,
+    pub authenticated_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when an identity presents a wrong password
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct AuthenticationFailed {
+    pub identity_id: IdentityId,
+    /// Consecutive failures including this one
+    pub failed_attempts: u32,
+    pub failed_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when repeated failures lock an account
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct AccountLocked {
+    pub identity_id: IdentityId,
+    pub locked_until: DateTime<Utc>,
+    /// Lockouts since the last success including this one
+    pub lockout_count: u32,
+    pub locked_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when an attempt arrives after a lockout has run out
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct AccountUnlocked {
+    pub identity_id: IdentityId,
+    pub unlocked_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when TOTP MFA is enabled for an identity
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct MfaEnabled {
+    pub identity_id: IdentityId,
+    #[serde(with = "crate::authentication::mfa::exposed")]
+    pub secret: TotpSecret,
+    pub backup_code_hashes: Vec<String>,
+    /// Time step of the confirmation code, which cannot be used again
+    pub confirmed_step: u64,
+    pub enabled_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when an identity is asked for a second factor
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct MfaChallengeIssued {
+    pub identity_id: IdentityId,
+    pub challenge: MfaChallenge,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when an MFA challenge is answered
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct MfaVerified {
+    pub identity_id: IdentityId,
+    pub challenge_id: Uuid,
+    pub factor: MfaFactor,
+    pub verified_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when a wrong code is presented for an MFA challenge
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct MfaFailed {
+    pub identity_id: IdentityId,
+    pub challenge_id: Uuid,
+    /// Consecutive failures including this one, shared with password attempts
+    pub failed_attempts: u32,
+    pub failed_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when an authentication attempt is started
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct AuthenticationAttemptStarted {
+    pub identity_id: IdentityId,
+    pub attempt_id: Uuid,
+    pub required_factors: Vec<AuthFactor>,
+    pub location_risk: LocationRisk,
+    pub location: LocationContext,
+    pub started_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when the location of an authentication attempt scores above
+/// `LocationRisk::Low`
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct SuspiciousAuthenticationDetected {
+    pub identity_id: IdentityId,
+    /// Attempt the assessment belongs to, whether or not it was started
+    pub attempt_id: Uuid,
+    pub assessment: RiskAssessment,
+    pub location: LocationContext,
+    pub detected_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when the next factor of an authentication attempt is challenged
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct AuthenticationChallengeIssued {
+    pub identity_id: IdentityId,
+    pub attempt_id: Uuid,
+    pub challenge: AuthenticationChallenge,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when an authentication attempt is granted or denied
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct AuthenticationDecided {
+    pub identity_id: IdentityId,
+    pub attempt_id: Uuid,
+    pub decision: AuthenticationDecision,
+    /// Factors presented successfully, in order
+    pub factors_used: Vec<AuthFactor>,
+    pub location_risk: LocationRisk,
+    pub decided_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when a session is started
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct SessionStarted {
+    pub identity_id: IdentityId,
+    pub session_id: Uuid,
+    pub device: SessionDevice,
+    pub auth_method: AuthMethod,
+    pub refresh_token_hash: String,
+    pub started_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when the refresh token of a session is replaced
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct SessionRefreshed {
+    pub identity_id: IdentityId,
+    pub session_id: Uuid,
+    pub refresh_token_hash: String,
+    pub refreshed_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when a session is revoked, superseded or found timed out
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct SessionEnded {
+    pub identity_id: IdentityId,
+    pub session_id: Uuid,
+    pub reason: SessionEndReason,
+    /// Revocation time, or the deadline a timed out session passed
+    pub ended_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when every live session of an identity is revoked
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct SessionsRevoked {
+    pub identity_id: IdentityId,
+    pub session_ids: Vec<Uuid>,
+    pub revoked_by: IdentityId,
+    pub reason: String,
+    pub revoked_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when a key is generated for an identity
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct KeyGenerated {
+    pub identity_id: IdentityId,
+    pub key_id: Uuid,
+    pub algorithm: KeyAlgorithm,
+    pub purposes: Vec<KeyPurpose>,
+    pub public_key: [u8; 32],
+    pub generated_by: IdentityId,
+    pub generated_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when an existing key is imported for an identity
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct KeyImported {
+    pub identity_id: IdentityId,
+    pub key_id: Uuid,
+    pub algorithm: KeyAlgorithm,
+    pub purposes: Vec<KeyPurpose>,
+    pub public_key: [u8; 32],
+    pub imported_by: IdentityId,
+    pub imported_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when a key is replaced by a newly generated one
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct KeyRotated {
+    pub identity_id: IdentityId,
+    pub previous_key_id: Uuid,
+    pub key_id: Uuid,
+    pub algorithm: KeyAlgorithm,
+    pub purposes: Vec<KeyPurpose>,
+    pub public_key: [u8; 32],
+    pub rotated_by: IdentityId,
+    pub rotated_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when a key is revoked
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct KeyRevoked {
+    pub identity_id: IdentityId,
+    pub key_id: Uuid,
+    pub reason: String,
+    pub revoked_by: IdentityId,
+    pub revoked_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when a signed identity token is issued
+///
+/// Carries the token for the requester and is not recorded by the event
+/// store; issuing a token does not change the identity.
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct IdentityTokenIssued {
+    pub identity_id: IdentityId,
+    pub token_id: Uuid,
+    pub kid: String,
+    pub token: String,
+    pub issued_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when a presented identity token is accepted
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct IdentityTokenValidated {
+    pub identity_id: IdentityId,
+    pub claims: crate::tokens::IdentityTokenClaims,
+    pub validated_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when a verified claim is exported as a Verifiable Credential
+///
+/// Carries the credential for the requester and is not recorded by the event
+/// store; exporting a claim does not change the identity.
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct CredentialExported {
+    pub identity_id: IdentityId,
+    pub credential: crate::credentials::VerifiableCredential,
+    pub exported_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when an externally issued credential becomes a verified claim
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct CredentialImported {
+    pub identity_id: IdentityId,
+    pub credential_id: String,
+    pub issuer_id: IdentityId,
+    pub claim_type: ClaimType,
+    pub value: String,
+    pub issued_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub imported_by: IdentityId,
+    pub imported_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when an identity publishes a DID document
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct DidPublished {
+    pub identity_id: IdentityId,
+    pub did: String,
+    pub method: DidMethod,
+    pub public_key_multibase: String,
+    pub services: Vec<DidService>,
+    pub published_by: IdentityId,
+    pub published_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
+}
+
+/// Event fired when the verification key of a DID is rotated
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct DidKeyRotated {
+    pub identity_id: IdentityId,
+    pub did: String,
+    pub retired_keys: Vec<String>,
+    pub public_key_multibase: String,
+    pub rotated_by: IdentityId,
+    pub rotated_at: DateTime<Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
 }
 
 /// Event fired when a projection is created
@@ -169,6 +855,8 @@ pub struct ProjectionCreated {
     pub target_domain: String,
     pub target_id: uuid::Uuid,
     pub created_at: chrono::DateTime<chrono::Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
 }
 
 /// Event fired when projections are synced
@@ -178,6 +866,8 @@ pub struct ProjectionsSynced {
     pub projections_synced: usize,
     pub sync_errors: usize,
     pub synced_at: chrono::DateTime<chrono::Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
 }
 
 /// Cross-domain event: Identity linked to person
@@ -186,6 +876,8 @@ pub struct IdentityLinkedToPerson {
     pub identity_id: IdentityId,
     pub person_id: uuid::Uuid,
     pub linked_at: chrono::DateTime<chrono::Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
 }
 
 /// Cross-domain event: Identity linked to organization
@@ -195,6 +887,8 @@ pub struct IdentityLinkedToOrganization {
     pub organization_id: uuid::Uuid,
     pub role: Option<String>,
     pub linked_at: chrono::DateTime<chrono::Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
 }
 
 /// Cross-domain event: Authentication requested
@@ -204,6 +898,8 @@ pub struct IdentityAuthenticationRequested {
     pub authentication_method: String,
     pub context: serde_json::Value,
     pub requested_at: chrono::DateTime<chrono::Utc>,
+    pub correlation_id: Uuid,
+    pub causation_id: Option<Uuid>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -213,3 +909,14 @@ pub enum WorkflowOutcome {
     Cancelled,
     Completed,
 }
+
+impl WorkflowOutcome {
+    /// Status of a workflow completed with this outcome
+    pub fn final_status(&self) -> WorkflowStatus {
+        match self {
+            WorkflowOutcome::Approved | WorkflowOutcome::Completed => WorkflowStatus::Completed,
+            WorkflowOutcome::Rejected => WorkflowStatus::Failed("Rejected".to_string()),
+            WorkflowOutcome::Cancelled => WorkflowStatus::Cancelled,
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! Rejection events for the Identity domain
+//!
+//! Every command handled by the identity systems has a matching `*Rejected`
+//! event. Rejections carry the originating command, its correlation ids and
+//! the `IdentityError` explaining why the command was not applied.
+
+use crate::commands::*;
+use crate::IdentityError;
+use bevy::ecs::prelude::*;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Event fired when a command is rejected by validation
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct CommandRejected<C: Send + Sync + 'static> {
+    pub command: C,
+    pub command_id: Uuid,
+    pub correlation_id: Uuid,
+    pub error: IdentityError,
+    pub rejected_at: DateTime<Utc>,
+}
+
+impl<C: Clone + Send + Sync + 'static> CommandRejected<C> {
+    /// Create a rejection for the given command envelope
+    pub fn new(envelope: &CommandEnvelope<C>, error: IdentityError) -> Self {
+        Self {
+            command: envelope.command.clone(),
+            command_id: envelope.command_id,
+            correlation_id: envelope.correlation_id,
+            error,
+            rejected_at: Utc::now(),
+        }
+    }
+}
+
+// Identity lifecycle rejections
+
+pub type IdentityCreationRejected = CommandRejected<CreateIdentityCommand>;
+pub type IdentityUpdateRejected = CommandRejected<UpdateIdentityCommand>;
+pub type IdentityMergeRejected = CommandRejected<MergeIdentitiesCommand>;
+pub type IdentityArchiveRejected = CommandRejected<ArchiveIdentityCommand>;
+
+// Claim rejections
+
+pub type ClaimAdditionRejected = CommandRejected<AddClaimCommand>;
+pub type ClaimUpdateRejected = CommandRejected<UpdateClaimCommand>;
+pub type ClaimRevocationRejected = CommandRejected<RevokeClaimCommand>;
+
+// Relationship rejections
+
+pub type RelationshipEstablishmentRejected = CommandRejected<EstablishRelationshipCommand>;
+pub type RelationshipValidationRejected = CommandRejected<ValidateRelationshipCommand>;
+pub type RelationshipRevocationRejected = CommandRejected<RevokeRelationshipCommand>;
+pub type RelationshipTraversalRejected = CommandRejected<TraverseRelationshipsCommand>;
+
+// Workflow rejections
+
+pub type WorkflowStartRejected = CommandRejected<StartWorkflowCommand>;
+pub type WorkflowStepRejected = CommandRejected<ProcessWorkflowStepCommand>;
+pub type WorkflowCompletionRejected = CommandRejected<CompleteWorkflowCommand>;
+pub type WorkflowTimeoutRejected = CommandRejected<TimeoutWorkflowCommand>;
+pub type WorkflowStepApprovalRejected = CommandRejected<ApproveWorkflowStepCommand>;
+pub type WorkflowStepRejectionRejected = CommandRejected<RejectWorkflowStepCommand>;
+pub type WorkflowPauseRejected = CommandRejected<PauseWorkflowCommand>;
+pub type WorkflowResumeRejected = CommandRejected<ResumeWorkflowCommand>;
+pub type WorkflowCancellationRejected = CommandRejected<CancelWorkflowCommand>;
+pub type WorkflowStepRetryRejected = CommandRejected<RetryWorkflowStepCommand>;
+
+// Verification rejections
+
+pub type VerificationStartRejected = CommandRejected<StartVerificationCommand>;
+pub type VerificationProcessingRejected = CommandRejected<ProcessVerificationCommand>;
+pub type VerificationCompletionRejected = CommandRejected<CompleteVerificationCommand>;
+pub type VerificationCodeSendRejected = CommandRejected<SendVerificationCodeCommand>;
+pub type VerificationCodeConfirmationRejected = CommandRejected<ConfirmVerificationCodeCommand>;
+
+// API key rejections
+
+pub type ApiKeyIssueRejected = CommandRejected<IssueApiKeyCommand>;
+pub type ApiKeyRotationRejected = CommandRejected<RotateApiKeyCommand>;
+pub type ApiKeyRevocationRejected = CommandRejected<RevokeApiKeyCommand>;
+pub type ApiKeyAuthenticationRejected = CommandRejected<AuthenticateApiKeyCommand>;
+
+// Authentication rejections
+
+pub type PasswordSetRejected = CommandRejected<SetPasswordCommand>;
+pub type PasswordAuthenticationRejected = CommandRejected<AuthenticatePasswordCommand>;
+pub type MfaEnableRejected = CommandRejected<EnableMfaCommand>;
+pub type MfaChallengeRejected = CommandRejected<IssueMfaChallengeCommand>;
+pub type MfaVerificationRejected = CommandRejected<VerifyMfaCommand>;
+pub type AuthenticationBeginRejected = CommandRejected<BeginAuthenticationCommand>;
+pub type AuthenticationFactorRejected = CommandRejected<SubmitAuthenticationFactorCommand>;
+
+// Session rejections
+
+pub type SessionStartRejected = CommandRejected<StartSessionCommand>;
+pub type SessionRefreshRejected = CommandRejected<RefreshSessionCommand>;
+pub type SessionRevocationRejected = CommandRejected<RevokeSessionCommand>;
+pub type SessionsRevocationRejected = CommandRejected<RevokeAllSessionsCommand>;
+
+// Key rejections
+
+pub type KeyGenerationRejected = CommandRejected<GenerateKeyCommand>;
+pub type KeyImportRejected = CommandRejected<ImportKeyCommand>;
+pub type KeyRotationRejected = CommandRejected<RotateKeyCommand>;
+pub type KeyRevocationRejected = CommandRejected<RevokeKeyCommand>;
+
+// Token rejections
+
+pub type IdentityTokenIssueRejected = CommandRejected<IssueIdentityTokenCommand>;
+pub type IdentityTokenValidationRejected = CommandRejected<ValidateIdentityTokenCommand>;
+
+// Credential rejections
+
+pub type CredentialExportRejected = CommandRejected<ExportCredentialCommand>;
+pub type CredentialImportRejected = CommandRejected<ImportCredentialCommand>;
+
+// DID rejections
+
+pub type DidPublicationRejected = CommandRejected<PublishDidCommand>;
+pub type DidKeyRotationRejected = CommandRejected<RotateDidKeyCommand>;
+
+// Projection rejections
+
+pub type ProjectionCreationRejected = CommandRejected<CreateProjectionCommand>;
+pub type ProjectionSyncRejected = CommandRejected<SyncProjectionsCommand>;
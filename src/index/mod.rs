@@ -0,0 +1,410 @@
+//! Lookup indexes for identity entities
+//!
+//! `IdentityIndex` maps identity, relationship and workflow ids to the
+//! entities holding them so systems can resolve commands in O(1) instead of
+//! scanning every `IdentityEntity`. The index is maintained by component hooks
+//! on `IdentityEntity`, `IdentityRelationship` and `IdentityWorkflow`, which
+//! keeps it consistent through spawns, component replacement and despawns.
+//! API keys are indexed by hooks on `IdentityApiKeys` when the component is
+//! inserted; keys added to an existing component and merges are recorded
+//! explicitly by the systems making those changes. Sessions are indexed the
+//! same way through `IdentitySessions`. Merge chains are collapsed as they
+//! are recorded, and a merge is forgotten once its source identity leaves the
+//! world.
+
+use crate::components::{
+    IdentityApiKeys, IdentityEntity, IdentityId, IdentityRelationship, IdentitySessions,
+    IdentityWorkflow,
+};
+use bevy::ecs::component::HookContext;
+use bevy::ecs::prelude::*;
+use bevy::ecs::world::DeferredWorld;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use uuid::Uuid;
+
+/// Index resource mapping domain ids to entities
+#[derive(Resource, Debug, Default, Clone)]
+pub struct IdentityIndex {
+    identities: HashMap<IdentityId, Entity>,
+    relationships: HashMap<Uuid, Entity>,
+    outgoing: HashMap<IdentityId, HashSet<Entity>>,
+    incoming: HashMap<IdentityId, HashSet<Entity>>,
+    workflows: HashMap<Uuid, Entity>,
+    workflows_by_identity: HashMap<IdentityId, HashSet<Entity>>,
+    merged_into: HashMap<IdentityId, IdentityId>,
+    merged_from: HashMap<IdentityId, HashSet<IdentityId>>,
+    api_keys: HashMap<Uuid, Entity>,
+    sessions: HashMap<Uuid, Entity>,
+}
+
+impl IdentityIndex {
+    /// Entity holding the identity with the given id
+    pub fn identity(&self, identity_id: IdentityId) -> Option<Entity> {
+        self.identities.get(&identity_id).copied()
+    }
+
+    /// Whether an identity with the given id exists
+    pub fn contains_identity(&self, identity_id: IdentityId) -> bool {
+        self.identities.contains_key(&identity_id)
+    }
+
+    /// Follow recorded merges to the identity that now represents `identity_id`
+    pub fn resolve(&self, identity_id: IdentityId) -> IdentityId {
+        let mut current = identity_id;
+        let mut seen = HashSet::new();
+        while let Some(target) = self.merged_into.get(&current) {
+            if !seen.insert(current) {
+                break;
+            }
+            current = *target;
+        }
+        current
+    }
+
+    /// Entity of the identity that now represents `identity_id` after merges
+    pub fn resolve_entity(&self, identity_id: IdentityId) -> Option<Entity> {
+        self.identity(self.resolve(identity_id))
+    }
+
+    /// Entity holding the relationship with the given id
+    pub fn relationship(&self, relationship_id: Uuid) -> Option<Entity> {
+        self.relationships.get(&relationship_id).copied()
+    }
+
+    /// Relationship entities whose source is the given identity
+    pub fn outgoing(&self, identity_id: IdentityId) -> impl Iterator<Item = Entity> + '_ {
+        self.outgoing.get(&identity_id).into_iter().flatten().copied()
+    }
+
+    /// Relationship entities whose target is the given identity
+    pub fn incoming(&self, identity_id: IdentityId) -> impl Iterator<Item = Entity> + '_ {
+        self.incoming.get(&identity_id).into_iter().flatten().copied()
+    }
+
+    /// Relationship entities with the given identity at either end
+    pub fn relationships_of(&self, identity_id: IdentityId) -> impl Iterator<Item = Entity> + '_ {
+        self.outgoing(identity_id).chain(self.incoming(identity_id))
+    }
+
+    /// Entity holding the workflow with the given id
+    pub fn workflow(&self, workflow_id: Uuid) -> Option<Entity> {
+        self.workflows.get(&workflow_id).copied()
+    }
+
+    /// Workflow entities belonging to the given identity
+    pub fn workflows_for(&self, identity_id: IdentityId) -> impl Iterator<Item = Entity> + '_ {
+        self.workflows_by_identity
+            .get(&identity_id)
+            .into_iter()
+            .flatten()
+            .copied()
+    }
+
+    /// Entity holding the API key with the given key id
+    pub fn api_key(&self, key_id: Uuid) -> Option<Entity> {
+        self.api_keys.get(&key_id).copied()
+    }
+
+    /// Record that `entity` holds the API key with the given key id
+    pub fn record_api_key(&mut self, key_id: Uuid, entity: Entity) {
+        self.api_keys.insert(key_id, entity);
+    }
+
+    /// Entity holding the session with the given id
+    pub fn session(&self, session_id: Uuid) -> Option<Entity> {
+        self.sessions.get(&session_id).copied()
+    }
+
+    /// Record that `entity` holds the session with the given id
+    pub fn record_session(&mut self, session_id: Uuid, entity: Entity) {
+        self.sessions.insert(session_id, entity);
+    }
+
+    /// Record that `source` has been merged into `target`
+    ///
+    /// Identities previously merged into `source` are redirected to `target`
+    /// directly, so resolving never walks a chain of merges.
+    pub fn record_merge(&mut self, source: IdentityId, target: IdentityId) {
+        if source == target {
+            return;
+        }
+        self.forget_merge(source);
+
+        let mut sources = self.merged_from.remove(&source).unwrap_or_default();
+        // A target previously merged into the source would otherwise form a cycle
+        if sources.remove(&target) {
+            self.merged_into.remove(&target);
+        }
+        sources.insert(source);
+        for merged in &sources {
+            self.merged_into.insert(*merged, target);
+        }
+        self.merged_from.entry(target).or_default().extend(sources);
+    }
+
+    /// Number of recorded merges
+    pub fn merge_count(&self) -> usize {
+        self.merged_into.len()
+    }
+
+    /// Number of indexed identities
+    pub fn identity_count(&self) -> usize {
+        self.identities.len()
+    }
+
+    /// Rebuild the index from the current contents of a world
+    pub fn rebuild(world: &mut World) -> Self {
+        let mut index = Self::default();
+
+        let mut identities = world.query::<(Entity, &IdentityEntity)>();
+        for (entity, identity) in identities.iter(world) {
+            index.insert_identity(identity, entity);
+        }
+
+        let mut relationships = world.query::<(Entity, &IdentityRelationship)>();
+        for (entity, relationship) in relationships.iter(world) {
+            index.insert_relationship(
+                relationship.relationship_id,
+                relationship.source_identity,
+                relationship.target_identity,
+                entity,
+            );
+        }
+
+        let mut workflows = world.query::<(Entity, &IdentityWorkflow)>();
+        for (entity, workflow) in workflows.iter(world) {
+            index.insert_workflow(workflow.workflow_id, workflow.identity_id, entity);
+        }
+
+        let mut api_keys = world.query::<(Entity, &IdentityApiKeys)>();
+        for (entity, keys) in api_keys.iter(world) {
+            for key in &keys.keys {
+                index.record_api_key(key.key_id, entity);
+            }
+        }
+
+        let mut sessions = world.query::<(Entity, &IdentitySessions)>();
+        for (entity, sessions) in sessions.iter(world) {
+            for session in &sessions.sessions {
+                index.record_session(session.session_id, entity);
+            }
+        }
+
+        index
+    }
+
+    fn insert_identity(&mut self, identity: &IdentityEntity, entity: Entity) {
+        self.identities.insert(identity.identity_id, entity);
+        if let crate::components::IdentityStatus::Merged { merged_into } = identity.status {
+            self.record_merge(identity.identity_id, merged_into);
+        }
+    }
+
+    fn remove_identity(&mut self, identity_id: IdentityId, entity: Entity) {
+        if self.identities.get(&identity_id) == Some(&entity) {
+            self.identities.remove(&identity_id);
+            self.forget_merge(identity_id);
+        }
+    }
+
+    fn forget_merge(&mut self, source: IdentityId) {
+        if let Some(target) = self.merged_into.remove(&source) {
+            remove_from_set(&mut self.merged_from, target, source);
+        }
+    }
+
+    fn insert_relationship(
+        &mut self,
+        relationship_id: Uuid,
+        source: IdentityId,
+        target: IdentityId,
+        entity: Entity,
+    ) {
+        self.relationships.insert(relationship_id, entity);
+        self.outgoing.entry(source).or_default().insert(entity);
+        self.incoming.entry(target).or_default().insert(entity);
+    }
+
+    fn remove_relationship(
+        &mut self,
+        relationship_id: Uuid,
+        source: IdentityId,
+        target: IdentityId,
+        entity: Entity,
+    ) {
+        if self.relationships.get(&relationship_id) == Some(&entity) {
+            self.relationships.remove(&relationship_id);
+        }
+        remove_from_set(&mut self.outgoing, source, entity);
+        remove_from_set(&mut self.incoming, target, entity);
+    }
+
+    fn insert_workflow(&mut self, workflow_id: Uuid, identity_id: IdentityId, entity: Entity) {
+        self.workflows.insert(workflow_id, entity);
+        self.workflows_by_identity
+            .entry(identity_id)
+            .or_default()
+            .insert(entity);
+    }
+
+    fn remove_workflow(&mut self, workflow_id: Uuid, identity_id: IdentityId, entity: Entity) {
+        if self.workflows.get(&workflow_id) == Some(&entity) {
+            self.workflows.remove(&workflow_id);
+        }
+        remove_from_set(&mut self.workflows_by_identity, identity_id, entity);
+    }
+}
+
+fn remove_from_set<T: Eq + Hash>(
+    map: &mut HashMap<IdentityId, HashSet<T>>,
+    key: IdentityId,
+    value: T,
+) {
+    if let Some(values) = map.get_mut(&key) {
+        values.remove(&value);
+        if values.is_empty() {
+            map.remove(&key);
+        }
+    }
+}
+
+// Component hooks keeping the index in sync. They are no-ops when the world
+// has no `IdentityIndex` resource.
+
+pub(crate) fn on_identity_inserted(mut world: DeferredWorld, context: HookContext) {
+    let Some(identity) = world.get::<IdentityEntity>(context.entity).cloned() else {
+        return;
+    };
+    if let Some(mut index) = world.get_resource_mut::<IdentityIndex>() {
+        index.insert_identity(&identity, context.entity);
+    }
+}
+
+pub(crate) fn on_identity_replaced(mut world: DeferredWorld, context: HookContext) {
+    let Some(identity_id) = world
+        .get::<IdentityEntity>(context.entity)
+        .map(|identity| identity.identity_id)
+    else {
+        return;
+    };
+    if let Some(mut index) = world.get_resource_mut::<IdentityIndex>() {
+        index.remove_identity(identity_id, context.entity);
+    }
+}
+
+pub(crate) fn on_relationship_inserted(mut world: DeferredWorld, context: HookContext) {
+    let Some((relationship_id, source, target)) = world
+        .get::<IdentityRelationship>(context.entity)
+        .map(|r| (r.relationship_id, r.source_identity, r.target_identity))
+    else {
+        return;
+    };
+    if let Some(mut index) = world.get_resource_mut::<IdentityIndex>() {
+        index.insert_relationship(relationship_id, source, target, context.entity);
+    }
+}
+
+pub(crate) fn on_relationship_replaced(mut world: DeferredWorld, context: HookContext) {
+    let Some((relationship_id, source, target)) = world
+        .get::<IdentityRelationship>(context.entity)
+        .map(|r| (r.relationship_id, r.source_identity, r.target_identity))
+    else {
+        return;
+    };
+    if let Some(mut index) = world.get_resource_mut::<IdentityIndex>() {
+        index.remove_relationship(relationship_id, source, target, context.entity);
+    }
+}
+
+pub(crate) fn on_workflow_inserted(mut world: DeferredWorld, context: HookContext) {
+    let Some((workflow_id, identity_id)) = world
+        .get::<IdentityWorkflow>(context.entity)
+        .map(|w| (w.workflow_id, w.identity_id))
+    else {
+        return;
+    };
+    if let Some(mut index) = world.get_resource_mut::<IdentityIndex>() {
+        index.insert_workflow(workflow_id, identity_id, context.entity);
+    }
+}
+
+pub(crate) fn on_workflow_replaced(mut world: DeferredWorld, context: HookContext) {
+    let Some((workflow_id, identity_id)) = world
+        .get::<IdentityWorkflow>(context.entity)
+        .map(|w| (w.workflow_id, w.identity_id))
+    else {
+        return;
+    };
+    if let Some(mut index) = world.get_resource_mut::<IdentityIndex>() {
+        index.remove_workflow(workflow_id, identity_id, context.entity);
+    }
+}
+
+pub(crate) fn on_api_keys_inserted(mut world: DeferredWorld, context: HookContext) {
+    let Some(key_ids) = world
+        .get::<IdentityApiKeys>(context.entity)
+        .map(|keys| keys.keys.iter().map(|key| key.key_id).collect::<Vec<_>>())
+    else {
+        return;
+    };
+    if let Some(mut index) = world.get_resource_mut::<IdentityIndex>() {
+        for key_id in key_ids {
+            index.record_api_key(key_id, context.entity);
+        }
+    }
+}
+
+pub(crate) fn on_api_keys_replaced(mut world: DeferredWorld, context: HookContext) {
+    let Some(key_ids) = world
+        .get::<IdentityApiKeys>(context.entity)
+        .map(|keys| keys.keys.iter().map(|key| key.key_id).collect::<Vec<_>>())
+    else {
+        return;
+    };
+    if let Some(mut index) = world.get_resource_mut::<IdentityIndex>() {
+        for key_id in key_ids {
+            if index.api_keys.get(&key_id) == Some(&context.entity) {
+                index.api_keys.remove(&key_id);
+            }
+        }
+    }
+}
+
+pub(crate) fn on_sessions_inserted(mut world: DeferredWorld, context: HookContext) {
+    let Some(session_ids) = world
+        .get::<IdentitySessions>(context.entity)
+        .map(session_ids)
+    else {
+        return;
+    };
+    if let Some(mut index) = world.get_resource_mut::<IdentityIndex>() {
+        for session_id in session_ids {
+            index.record_session(session_id, context.entity);
+        }
+    }
+}
+
+pub(crate) fn on_sessions_replaced(mut world: DeferredWorld, context: HookContext) {
+    let Some(session_ids) = world
+        .get::<IdentitySessions>(context.entity)
+        .map(session_ids)
+    else {
+        return;
+    };
+    if let Some(mut index) = world.get_resource_mut::<IdentityIndex>() {
+        for session_id in session_ids {
+            if index.sessions.get(&session_id) == Some(&context.entity) {
+                index.sessions.remove(&session_id);
+            }
+        }
+    }
+}
+
+fn session_ids(sessions: &IdentitySessions) -> Vec<Uuid> {
+    sessions
+        .sessions
+        .iter()
+        .map(|session| session.session_id)
+        .collect()
+}
@@ -0,0 +1,277 @@
+//! Storage for private keys of identities
+
+use super::KeySecret;
+use crate::components::KeyAlgorithm;
+use crate::{IdentityError, IdentityResult};
+use argon2::Argon2;
+use bevy::ecs::prelude::*;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use data_encoding::BASE64URL_NOPAD;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+/// Storage for private keys, addressed by key id
+///
+/// The store is used from ECS systems, so its methods are synchronous.
+pub trait KeyStore: Send + Sync {
+    /// Store a private key, replacing any key with the same id
+    fn store(&self, key_id: Uuid, secret: &KeySecret) -> IdentityResult<()>;
+
+    /// Load a private key
+    fn load(&self, key_id: Uuid) -> IdentityResult<KeySecret>;
+
+    /// Delete a private key
+    fn delete(&self, key_id: Uuid) -> IdentityResult<()>;
+}
+
+fn keystore_error(context: &str, error: impl std::fmt::Display) -> IdentityError {
+    IdentityError::KeyStoreError(format!("{context}: {error}"))
+}
+
+/// Key store keeping private keys in memory
+#[derive(Debug, Default)]
+pub struct InMemoryKeyStore {
+    secrets: Mutex<HashMap<Uuid, KeySecret>>,
+}
+
+impl InMemoryKeyStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl KeyStore for InMemoryKeyStore {
+    fn store(&self, key_id: Uuid, secret: &KeySecret) -> IdentityResult<()> {
+        self.secrets
+            .lock()
+            .map_err(|e| keystore_error("locking key store", e))?
+            .insert(key_id, secret.clone());
+        Ok(())
+    }
+
+    fn load(&self, key_id: Uuid) -> IdentityResult<KeySecret> {
+        self.secrets
+            .lock()
+            .map_err(|e| keystore_error("locking key store", e))?
+            .get(&key_id)
+            .cloned()
+            .ok_or(IdentityError::KeyNotFound(key_id))
+    }
+
+    fn delete(&self, key_id: Uuid) -> IdentityResult<()> {
+        self.secrets
+            .lock()
+            .map_err(|e| keystore_error("locking key store", e))?
+            .remove(&key_id)
+            .map(|_| ())
+            .ok_or(IdentityError::KeyNotFound(key_id))
+    }
+}
+
+const SALT_FILE: &str = "keystore.salt";
+const CHECK_FILE: &str = "keystore.check";
+const CHECK_PLAINTEXT: &[u8] = b"cim-domain-identity keystore";
+
+/// Private key encrypted on disk
+#[derive(Serialize, Deserialize)]
+struct EncryptedKey {
+    algorithm: KeyAlgorithm,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Key store writing each private key encrypted into its own file
+///
+/// Keys are encrypted with ChaCha20-Poly1305 under a key derived from a
+/// passphrase with argon2id. The key id and algorithm are authenticated with
+/// each key, so encrypted files cannot be swapped between key ids. A check
+/// file written on first use rejects a wrong passphrase when the store is
+/// opened again.
+pub struct EncryptedFileKeyStore {
+    directory: PathBuf,
+    cipher: ChaCha20Poly1305,
+}
+
+impl std::fmt::Debug for EncryptedFileKeyStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptedFileKeyStore")
+            .field("directory", &self.directory)
+            .finish_non_exhaustive()
+    }
+}
+
+impl EncryptedFileKeyStore {
+    /// Open the store in `directory`, creating it if needed
+    pub fn open(directory: impl AsRef<Path>, passphrase: &str) -> IdentityResult<Self> {
+        let directory = directory.as_ref().to_path_buf();
+        fs::create_dir_all(&directory)
+            .map_err(|e| keystore_error("creating key store directory", e))?;
+
+        let salt_path = directory.join(SALT_FILE);
+        let salt = match fs::read(&salt_path) {
+            Ok(salt) => salt,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let mut salt = vec![0u8; 16];
+                OsRng.fill_bytes(&mut salt);
+                write_atomically(&salt_path, &salt)?;
+                salt
+            }
+            Err(e) => return Err(keystore_error("reading key store salt", e)),
+        };
+
+        let mut key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| keystore_error("deriving key store key", e))?;
+        let store = Self {
+            directory,
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&key)),
+        };
+
+        let check_path = store.directory.join(CHECK_FILE);
+        if check_path.exists() {
+            let check = store.read_encrypted(&check_path, CHECK_FILE.as_bytes())?;
+            if check.1 != CHECK_PLAINTEXT {
+                return Err(IdentityError::KeyStoreError(
+                    "wrong key store passphrase".to_string(),
+                ));
+            }
+        } else {
+            store.write_encrypted(
+                &check_path,
+                KeyAlgorithm::X25519,
+                CHECK_FILE.as_bytes(),
+                CHECK_PLAINTEXT,
+            )?;
+        }
+
+        Ok(store)
+    }
+
+    fn key_path(&self, key_id: Uuid) -> PathBuf {
+        self.directory.join(format!("{key_id}.key"))
+    }
+
+    fn write_encrypted(
+        &self,
+        path: &Path,
+        algorithm: KeyAlgorithm,
+        aad: &[u8],
+        plaintext: &[u8],
+    ) -> IdentityResult<()> {
+        let mut nonce = [0u8; 12];
+        OsRng.fill_bytes(&mut nonce);
+        let aad = [aad, format!("{algorithm:?}").as_bytes()].concat();
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|e| keystore_error("encrypting key", e))?;
+
+        let data = serde_json::to_vec(&EncryptedKey {
+            algorithm,
+            nonce: BASE64URL_NOPAD.encode(&nonce),
+            ciphertext: BASE64URL_NOPAD.encode(&ciphertext),
+        })
+        .map_err(|e| keystore_error("serializing key", e))?;
+        write_atomically(path, &data)
+    }
+
+    fn read_encrypted(&self, path: &Path, aad: &[u8]) -> IdentityResult<(KeyAlgorithm, Vec<u8>)> {
+        let data = fs::read(path).map_err(|e| keystore_error("reading key", e))?;
+        let encrypted: EncryptedKey =
+            serde_json::from_slice(&data).map_err(|e| keystore_error("parsing key", e))?;
+        let nonce = BASE64URL_NOPAD
+            .decode(encrypted.nonce.as_bytes())
+            .ok()
+            .filter(|nonce| nonce.len() == 12)
+            .ok_or_else(|| IdentityError::KeyStoreError("invalid key nonce".to_string()))?;
+        let ciphertext = BASE64URL_NOPAD
+            .decode(encrypted.ciphertext.as_bytes())
+            .map_err(|e| keystore_error("decoding key", e))?;
+
+        let aad = [aad, format!("{:?}", encrypted.algorithm).as_bytes()].concat();
+        // A failed decryption means the passphrase is wrong or the file was altered
+        let plaintext = self
+            .cipher
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &ciphertext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| {
+                IdentityError::KeyStoreError(
+                    "key cannot be decrypted with this passphrase".to_string(),
+                )
+            })?;
+        Ok((encrypted.algorithm, plaintext))
+    }
+}
+
+fn write_atomically(path: &Path, data: &[u8]) -> IdentityResult<()> {
+    let temp = path.with_extension("tmp");
+    fs::write(&temp, data)
+        .and_then(|_| fs::rename(&temp, path))
+        .map_err(|e| keystore_error("writing key store file", e))
+}
+
+impl KeyStore for EncryptedFileKeyStore {
+    fn store(&self, key_id: Uuid, secret: &KeySecret) -> IdentityResult<()> {
+        self.write_encrypted(
+            &self.key_path(key_id),
+            secret.algorithm(),
+            key_id.as_bytes(),
+            secret.expose(),
+        )
+    }
+
+    fn load(&self, key_id: Uuid) -> IdentityResult<KeySecret> {
+        let path = self.key_path(key_id);
+        if !path.exists() {
+            return Err(IdentityError::KeyNotFound(key_id));
+        }
+
+        let (algorithm, plaintext) = self.read_encrypted(&path, key_id.as_bytes())?;
+        let bytes = <[u8; 32]>::try_from(plaintext.as_slice()).map_err(|_| {
+            IdentityError::KeyStoreError("private key must be 32 bytes".to_string())
+        })?;
+        Ok(KeySecret::from_bytes(algorithm, bytes))
+    }
+
+    fn delete(&self, key_id: Uuid) -> IdentityResult<()> {
+        match fs::remove_file(self.key_path(key_id)) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Err(IdentityError::KeyNotFound(key_id))
+            }
+            Err(e) => Err(keystore_error("deleting key", e)),
+        }
+    }
+}
+
+/// Key store used by the key systems
+///
+/// Defaults to an [`InMemoryKeyStore`]; insert an [`EncryptedFileKeyStore`]
+/// or another implementation before adding the plugin to keep keys on disk.
+#[derive(Resource, Clone)]
+pub struct IdentityKeyStore(pub Arc<dyn KeyStore>);
+
+impl Default for IdentityKeyStore {
+    fn default() -> Self {
+        Self(Arc::new(InMemoryKeyStore::new()))
+    }
+}
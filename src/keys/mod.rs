@@ -0,0 +1,166 @@
+//! Key material of identities
+//!
+//! Identities hold the public part of their Ed25519 and X25519 keys in
+//! [`IdentityKeys`]; the private keys stay in a [`KeyStore`]. Other modules
+//! sign, verify and agree on shared secrets through the functions here,
+//! which always use the current key of the requested purpose and refuse
+//! revoked keys.
+
+pub mod keystore;
+
+pub use keystore::{EncryptedFileKeyStore, IdentityKeyStore, InMemoryKeyStore, KeyStore};
+
+use crate::components::{IdentityKeys, KeyAlgorithm, KeyPurpose};
+use crate::{IdentityError, IdentityResult};
+use data_encoding::BASE64URL_NOPAD;
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use uuid::Uuid;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+/// Private key of an identity key
+///
+/// Never stored on the identity; handed to a [`KeyStore`] under its key id.
+#[derive(Clone, PartialEq, Eq)]
+pub struct KeySecret {
+    algorithm: KeyAlgorithm,
+    bytes: [u8; 32],
+}
+
+impl KeySecret {
+    /// Generate a random private key
+    pub fn generate(algorithm: KeyAlgorithm) -> Self {
+        let mut bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        KeySecret { algorithm, bytes }
+    }
+
+    /// Use an existing private key, such as an Ed25519 seed
+    pub fn from_bytes(algorithm: KeyAlgorithm, bytes: [u8; 32]) -> Self {
+        KeySecret { algorithm, bytes }
+    }
+
+    pub fn algorithm(&self) -> KeyAlgorithm {
+        self.algorithm
+    }
+
+    /// Public key of this private key
+    pub fn public_key(&self) -> [u8; 32] {
+        match self.algorithm {
+            KeyAlgorithm::Ed25519 => SigningKey::from_bytes(&self.bytes)
+                .verifying_key()
+                .to_bytes(),
+            KeyAlgorithm::X25519 => PublicKey::from(&StaticSecret::from(self.bytes)).to_bytes(),
+        }
+    }
+
+    pub(crate) fn expose(&self) -> &[u8; 32] {
+        &self.bytes
+    }
+}
+
+impl std::fmt::Debug for KeySecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeySecret")
+            .field("algorithm", &self.algorithm)
+            .field("bytes", &"<redacted>")
+            .finish()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct EncodedKeySecret {
+    algorithm: KeyAlgorithm,
+    key: String,
+}
+
+impl Serialize for KeySecret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        EncodedKeySecret {
+            algorithm: self.algorithm,
+            key: BASE64URL_NOPAD.encode(&self.bytes),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for KeySecret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let encoded = EncodedKeySecret::deserialize(deserializer)?;
+        let bytes = BASE64URL_NOPAD
+            .decode(encoded.key.as_bytes())
+            .ok()
+            .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+            .ok_or_else(|| serde::de::Error::custom("private key must be 32 bytes"))?;
+        Ok(KeySecret::from_bytes(encoded.algorithm, bytes))
+    }
+}
+
+/// Signature made with an identity key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeySignature {
+    pub key_id: Uuid,
+    pub signature: Signature,
+}
+
+/// Sign `message` with the current Ed25519 key of an identity serving `purpose`
+pub fn sign(
+    keys: &IdentityKeys,
+    store: &dyn KeyStore,
+    purpose: KeyPurpose,
+    message: &[u8],
+) -> IdentityResult<KeySignature> {
+    let key = keys
+        .current(purpose)
+        .filter(|key| key.algorithm == KeyAlgorithm::Ed25519)
+        .ok_or_else(|| IdentityError::InvalidOperation(format!("No current {purpose:?} key")))?;
+    let secret = store.load(key.key_id)?;
+
+    let signature = SigningKey::from_bytes(secret.expose()).sign(message);
+    Ok(KeySignature {
+        key_id: key.key_id,
+        signature,
+    })
+}
+
+/// Verify a signature made by one of an identity's keys
+///
+/// Signatures of rotated keys keep verifying; those of revoked keys do not.
+pub fn verify(keys: &IdentityKeys, message: &[u8], signature: &KeySignature) -> IdentityResult<()> {
+    let key = keys
+        .key(signature.key_id)
+        .ok_or(IdentityError::KeyNotFound(signature.key_id))?;
+    if key.revoked_at.is_some() {
+        return Err(IdentityError::KeyInactive(key.key_id));
+    }
+    if key.algorithm != KeyAlgorithm::Ed25519 {
+        return Err(IdentityError::InvalidOperation(
+            "Key cannot verify signatures".to_string(),
+        ));
+    }
+
+    VerifyingKey::from_bytes(&key.public_key)
+        .and_then(|verifying_key| verifying_key.verify_strict(message, &signature.signature))
+        .map_err(|_| IdentityError::VerificationFailed("signature mismatch".to_string()))
+}
+
+/// Shared secret between the current encryption key of an identity and a peer's X25519 key
+///
+/// Returns the id of the key used, so the peer can be told which key to use.
+pub fn shared_secret(
+    keys: &IdentityKeys,
+    store: &dyn KeyStore,
+    peer_public_key: &[u8; 32],
+) -> IdentityResult<(Uuid, [u8; 32])> {
+    let key = keys
+        .current(KeyPurpose::Encryption)
+        .filter(|key| key.algorithm == KeyAlgorithm::X25519)
+        .ok_or_else(|| IdentityError::InvalidOperation("No current Encryption key".to_string()))?;
+    let secret = store.load(key.key_id)?;
+
+    let shared =
+        StaticSecret::from(*secret.expose()).diffie_hellman(&PublicKey::from(*peer_public_key));
+    Ok((key.key_id, shared.to_bytes()))
+}
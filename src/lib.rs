@@ -4,19 +4,51 @@
 //! It serves as the orchestration layer for identity-related processes.
 
 pub mod aggregate;
+pub mod authentication;
 pub mod commands;
 pub mod components;
+pub mod credentials;
+pub mod did;
 pub mod events;
+pub mod index;
+pub mod keys;
+pub mod persistence;
+pub mod plugin;
 pub mod projections;
 pub mod queries;
 pub mod systems;
+pub mod tokens;
+pub mod verification;
+pub mod workflows;
 
 // Re-export key types
 pub use aggregate::*;
+pub use authentication::{
+    AuthFactor, AuthMethod, AuthenticationAttempt, AuthenticationChallenge, AuthenticationClock,
+    AuthenticationDecision, AuthenticationHistory, AuthenticationPolicy, Clock, FactorResponse,
+    LocationContext, LocationRisk, LockoutPolicy, LoginObservation, ManualClock, MfaChallenge,
+    MfaCode, MfaFactor, Password, PasswordHashParams, RefreshToken, RiskAssessment, RiskEngine,
+    RiskPolicy, RiskSignal, SessionDevice, SessionEndReason, SessionGrant, SessionPolicy,
+    SystemClock, TotpAlgorithm, TotpConfig, TotpSecret,
+};
 pub use commands::*;
 pub use components::*;
+pub use credentials::{CredentialIssuer, IssuerKeys, VerifiableCredential};
+pub use did::{DidDocument, DidResolver};
 pub use events::*;
+pub use index::IdentityIndex;
+pub use keys::{
+    EncryptedFileKeyStore, IdentityKeyStore, InMemoryKeyStore, KeySecret, KeySignature, KeyStore,
+};
+pub use plugin::{IdentityPlugin, IdentityPluginConfig, IdentitySet};
 pub use systems::*;
+pub use tokens::{IdentityTokenClaims, TokenClaim, TokenIssuer, TokenSigningKey, TokenVerifier};
+pub use verification::{
+    CodeDelivery, CodeHashKey, InMemoryNotifier, ProviderCheck, ReverificationReminders,
+    ThirdPartyVerificationAdapter, VerificationCodePolicy, VerificationCodes, VerificationNotifier,
+    VerificationProviders,
+};
+pub use workflows::{WorkflowDefinition, WorkflowDefinitions};
 // Don't re-export all from queries and projections to avoid conflicts
 pub use projections::{
     IdentityProjectionSystem, IdentityStatusProjection, RelationshipGraphProjection,
@@ -100,4 +132,88 @@ pub enum IdentityError {
 
     #[error("Invalid transition")]
     InvalidTransition,
+
+    #[error("Persistence error: {0}")]
+    PersistenceError(String),
+
+    #[error("Account is locked until {0}")]
+    AccountLocked(chrono::DateTime<chrono::Utc>),
+
+    #[error("Invalid credentials")]
+    InvalidCredentials,
+
+    #[error("Location risk is too high to authenticate: {0:?}")]
+    LocationRiskTooHigh(authentication::LocationRisk),
+
+    #[error("Authentication factor is not available: {0:?}")]
+    FactorUnavailable(authentication::AuthFactor),
+
+    #[error("Authentication attempt not found: {0}")]
+    AuthenticationAttemptNotFound(Uuid),
+
+    #[error("No authentication to start a session with")]
+    AuthenticationRequired,
+
+    #[error("Invalid API key")]
+    InvalidApiKey,
+
+    #[error("API key not found: {0}")]
+    ApiKeyNotFound(Uuid),
+
+    #[error("API key is revoked or expired: {0}")]
+    ApiKeyInactive(Uuid),
+
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+
+    #[error("Session not found: {0}")]
+    SessionNotFound(Uuid),
+
+    #[error("Session has ended: {0}")]
+    SessionEnded(Uuid),
+
+    #[error("Invalid refresh token")]
+    InvalidRefreshToken,
+
+    #[error("Invalid token: {0}")]
+    InvalidToken(String),
+
+    #[error("Token has expired")]
+    TokenExpired,
+
+    #[error("Verification code has expired")]
+    VerificationCodeExpired,
+
+    #[error("Too many wrong verification codes")]
+    VerificationAttemptsExceeded,
+
+    #[error("A new verification code can be sent at {0}")]
+    VerificationCodeThrottled(chrono::DateTime<chrono::Utc>),
+
+    #[error("Claim not found: {0:?}")]
+    ClaimNotFound(components::ClaimType),
+
+    #[error("Claim already held: {0:?}")]
+    ClaimAlreadyExists(components::ClaimType),
+
+    #[error("Invalid credential: {0}")]
+    InvalidCredential(String),
+
+    #[error("Credential issuer is not trusted: {0}")]
+    UntrustedIssuer(Uuid),
+
+    #[error("DID not found: {0}")]
+    DidNotFound(String),
+
+    #[error("Invalid DID: {0}")]
+    InvalidDid(String),
+
+    #[error("Key not found: {0}")]
+    KeyNotFound(Uuid),
+
+    #[error("Key is revoked or superseded: {0}")]
+    KeyInactive(Uuid),
+
+    #[error("Key store error: {0}")]
+    KeyStoreError(String),
 }
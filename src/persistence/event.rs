@@ -0,0 +1,154 @@
+//! Persisted form of identity domain events
+
+use crate::events::*;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Identity events that change the state of the world
+///
+/// Read-only events such as `RelationshipsTraversed` or projection events are
+/// not persisted; they can be derived from the state rebuilt by these.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event_type", content = "payload")]
+pub enum IdentityDomainEvent {
+    IdentityCreated(IdentityCreated),
+    IdentityUpdated(IdentityUpdated),
+    IdentitiesMerged(IdentitiesMerged),
+    IdentityArchived(IdentityArchived),
+    ClaimAdded(ClaimAdded),
+    ClaimUpdated(ClaimUpdated),
+    ClaimRevoked(ClaimRevoked),
+    ClaimExpired(ClaimExpired),
+    VerificationLevelDowngraded(VerificationLevelDowngraded),
+    RelationshipEstablished(RelationshipEstablished),
+    RelationshipValidated(RelationshipValidated),
+    RelationshipExpired(RelationshipExpired),
+    RelationshipRevoked(RelationshipRevoked),
+    ApiKeyIssued(ApiKeyIssued),
+    ApiKeyRotated(ApiKeyRotated),
+    ApiKeyRevoked(ApiKeyRevoked),
+    ApiKeyAuthenticated(ApiKeyAuthenticated),
+    PasswordSet(PasswordSet),
+    PasswordRehashed(PasswordRehashed),
+    AuthenticationSucceeded(AuthenticationSucceeded),
+    AuthenticationFailed(AuthenticationFailed),
+    AccountLocked(AccountLocked),
+    AccountUnlocked(AccountUnlocked),
+    MfaEnabled(MfaEnabled),
+    MfaChallengeIssued(MfaChallengeIssued),
+    MfaVerified(MfaVerified),
+    MfaFailed(MfaFailed),
+    AuthenticationAttemptStarted(AuthenticationAttemptStarted),
+    SuspiciousAuthenticationDetected(SuspiciousAuthenticationDetected),
+    AuthenticationChallengeIssued(AuthenticationChallengeIssued),
+    AuthenticationDecided(AuthenticationDecided),
+    SessionStarted(SessionStarted),
+    SessionRefreshed(SessionRefreshed),
+    SessionEnded(SessionEnded),
+    SessionsRevoked(SessionsRevoked),
+    KeyGenerated(KeyGenerated),
+    KeyImported(KeyImported),
+    KeyRotated(KeyRotated),
+    KeyRevoked(KeyRevoked),
+    CredentialImported(CredentialImported),
+    DidPublished(DidPublished),
+    DidKeyRotated(DidKeyRotated),
+    WorkflowStarted(WorkflowStarted),
+    WorkflowStepCompleted(WorkflowStepCompleted),
+    WorkflowCompleted(WorkflowCompleted),
+    WorkflowTimedOut(WorkflowTimedOut),
+    WorkflowPaused(WorkflowPaused),
+    WorkflowResumed(WorkflowResumed),
+    WorkflowCancelled(WorkflowCancelled),
+    WorkflowStepRetried(WorkflowStepRetried),
+    ApprovalRequested(ApprovalRequested),
+    ApprovalDecided(ApprovalDecided),
+    ApprovalEscalated(ApprovalEscalated),
+    VerificationStarted(VerificationStarted),
+    VerificationCodeSent(VerificationCodeSent),
+    VerificationCodeFailed(VerificationCodeFailed),
+    VerificationCompleted(VerificationCompleted),
+    ClaimVerified(ClaimVerified),
+}
+
+macro_rules! impl_domain_event {
+    ($($event:ident),* $(,)?) => {
+        $(
+            impl From<$event> for IdentityDomainEvent {
+                fn from(event: $event) -> Self {
+                    IdentityDomainEvent::$event(event)
+                }
+            }
+        )*
+    };
+}
+
+impl_domain_event!(
+    IdentityCreated,
+    IdentityUpdated,
+    IdentitiesMerged,
+    IdentityArchived,
+    ClaimAdded,
+    ClaimUpdated,
+    ClaimRevoked,
+    ClaimExpired,
+    VerificationLevelDowngraded,
+    RelationshipEstablished,
+    RelationshipValidated,
+    RelationshipExpired,
+    RelationshipRevoked,
+    ApiKeyIssued,
+    ApiKeyRotated,
+    ApiKeyRevoked,
+    ApiKeyAuthenticated,
+    PasswordSet,
+    PasswordRehashed,
+    AuthenticationSucceeded,
+    AuthenticationFailed,
+    AccountLocked,
+    AccountUnlocked,
+    MfaEnabled,
+    MfaChallengeIssued,
+    MfaVerified,
+    MfaFailed,
+    AuthenticationAttemptStarted,
+    SuspiciousAuthenticationDetected,
+    AuthenticationChallengeIssued,
+    AuthenticationDecided,
+    SessionStarted,
+    SessionRefreshed,
+    SessionEnded,
+    SessionsRevoked,
+    KeyGenerated,
+    KeyImported,
+    KeyRotated,
+    KeyRevoked,
+    CredentialImported,
+    DidPublished,
+    DidKeyRotated,
+    WorkflowStarted,
+    WorkflowStepCompleted,
+    WorkflowCompleted,
+    WorkflowTimedOut,
+    WorkflowPaused,
+    WorkflowResumed,
+    WorkflowCancelled,
+    WorkflowStepRetried,
+    ApprovalRequested,
+    ApprovalDecided,
+    ApprovalEscalated,
+    VerificationStarted,
+    VerificationCodeSent,
+    VerificationCodeFailed,
+    VerificationCompleted,
+    ClaimVerified,
+);
+
+/// An event as recorded in an event store
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredEvent {
+    /// Position in the store, starting at 1 and increasing without gaps
+    pub sequence: u64,
+    pub recorded_at: DateTime<Utc>,
+    pub event: IdentityDomainEvent,
+}
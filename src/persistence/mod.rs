@@ -0,0 +1,31 @@
+//! Event-sourced persistence for the identity ECS world
+//!
+//! Domain events emitted by the identity systems are recorded in an
+//! append-only [`EventStore`]. The world can be rebuilt by replaying those
+//! events into entities, and periodic [`WorldSnapshot`]s bound the amount of
+//! replay needed on a cold start.
+//!
+//! ```mermaid
+//! graph LR
+//!     A[Identity systems] -->|events| B[EventJournal]
+//!     B --> C[EventStore]
+//!     D[World] -->|every N events| E[SnapshotStore]
+//!     E -->|cold start| F[Restore snapshot]
+//!     C -->|events after snapshot| F
+//!     F --> D
+//! ```
+
+pub mod event;
+pub mod plugin;
+pub mod replay;
+pub mod snapshot;
+pub mod store;
+
+pub use event::{IdentityDomainEvent, StoredEvent};
+pub use plugin::{
+    DomainEventWriter, EventJournal, IdentityEventStore, IdentityPersistencePlugin,
+    IdentitySnapshotStore, PersistenceConfig, PersistenceState,
+};
+pub use replay::{apply_event, replay_events, restore_world};
+pub use snapshot::{FileSnapshotStore, IdentitySnapshot, SnapshotStore, WorldSnapshot};
+pub use store::{EventStore, FileEventStore, InMemoryEventStore};
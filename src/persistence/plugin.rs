@@ -0,0 +1,206 @@
+//! Plugin recording identity events and taking periodic snapshots
+
+use super::event::IdentityDomainEvent;
+use super::replay::restore_world;
+use super::snapshot::{SnapshotStore, WorldSnapshot};
+use super::store::EventStore;
+use crate::IdentitySet;
+use bevy::app::{App, Plugin, Update};
+use bevy::ecs::event::EventId;
+use bevy::ecs::prelude::*;
+use bevy::ecs::system::SystemParam;
+use std::sync::{Arc, Mutex};
+use tracing::error;
+
+/// Event store used by the persistence systems
+#[derive(Resource, Clone)]
+pub struct IdentityEventStore(pub Arc<dyn EventStore>);
+
+/// Snapshot store used by the persistence systems
+#[derive(Resource, Clone)]
+pub struct IdentitySnapshotStore(pub Arc<dyn SnapshotStore>);
+
+/// Configuration for event persistence
+#[derive(Resource, Debug, Clone, PartialEq, Eq)]
+pub struct PersistenceConfig {
+    /// Take a snapshot once this many events were stored since the last one;
+    /// 0 disables snapshots
+    pub snapshot_interval: u64,
+    /// Restore the world from the stores when the app is built
+    pub restore_on_startup: bool,
+}
+
+impl Default for PersistenceConfig {
+    fn default() -> Self {
+        Self {
+            snapshot_interval: 1_000,
+            restore_on_startup: true,
+        }
+    }
+}
+
+/// Sequence bookkeeping for the persistence systems
+#[derive(Resource, Debug, Clone, Default, PartialEq, Eq)]
+pub struct PersistenceState {
+    /// Sequence of the last event reflected in the world
+    pub last_sequence: u64,
+    /// Sequence covered by the most recent snapshot
+    pub last_snapshot_sequence: u64,
+}
+
+/// Events collected during the current frame, in emission order
+///
+/// The identity systems queue their state-changing events here through
+/// [`DomainEventWriter`] as they emit them, so the journal holds a single
+/// stream ordered across event types.
+#[derive(Resource, Debug, Default)]
+pub struct EventJournal {
+    pending: Mutex<Vec<IdentityDomainEvent>>,
+}
+
+impl EventJournal {
+    /// Queue an event for storage
+    pub fn record(&self, event: impl Into<IdentityDomainEvent>) {
+        self.pending.lock().unwrap().push(event.into());
+    }
+
+    /// Number of queued events
+    pub fn len(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    /// Whether no events are queued
+    pub fn is_empty(&self) -> bool {
+        self.pending.lock().unwrap().is_empty()
+    }
+}
+
+/// Event writer for state-changing identity events
+///
+/// Writes the event like an [`EventWriter`] and, when the persistence plugin
+/// is installed, queues it in the [`EventJournal`].
+#[derive(SystemParam)]
+pub struct DomainEventWriter<'w, E>
+where
+    E: Event + Clone + Into<IdentityDomainEvent>,
+{
+    events: EventWriter<'w, E>,
+    journal: Option<Res<'w, EventJournal>>,
+}
+
+impl<E> DomainEventWriter<'_, E>
+where
+    E: Event + Clone + Into<IdentityDomainEvent>,
+{
+    /// Write an event and queue it for storage
+    pub fn write(&mut self, event: E) -> EventId<E> {
+        if let Some(journal) = &self.journal {
+            journal.record(event.clone());
+        }
+        self.events.write(event)
+    }
+}
+
+/// Plugin persisting identity events to an event store
+///
+/// Add it after [`IdentityPlugin`](crate::IdentityPlugin). When the app is
+/// finished building, the world is restored from the latest snapshot plus
+/// the events stored after it; from then on every state-changing identity
+/// event is appended to the store at the end of the frame.
+#[derive(Clone)]
+pub struct IdentityPersistencePlugin {
+    pub events: Arc<dyn EventStore>,
+    pub snapshots: Arc<dyn SnapshotStore>,
+    pub config: PersistenceConfig,
+}
+
+impl IdentityPersistencePlugin {
+    /// Create a plugin with the default configuration
+    pub fn new(events: Arc<dyn EventStore>, snapshots: Arc<dyn SnapshotStore>) -> Self {
+        Self {
+            events,
+            snapshots,
+            config: PersistenceConfig::default(),
+        }
+    }
+
+    /// Replace the configuration
+    pub fn with_config(mut self, config: PersistenceConfig) -> Self {
+        self.config = config;
+        self
+    }
+}
+
+impl Plugin for IdentityPersistencePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(IdentityEventStore(self.events.clone()))
+            .insert_resource(IdentitySnapshotStore(self.snapshots.clone()))
+            .insert_resource(self.config.clone())
+            .init_resource::<PersistenceState>()
+            .init_resource::<EventJournal>();
+
+        app.add_systems(
+            Update,
+            (flush_event_journal_system, snapshot_system)
+                .chain()
+                .in_set(IdentitySet::Persistence),
+        );
+    }
+
+    fn finish(&self, app: &mut App) {
+        if !self.config.restore_on_startup {
+            return;
+        }
+
+        match restore_world(app.world_mut(), &*self.events, &*self.snapshots) {
+            Ok(sequence) => {
+                let mut state = app.world_mut().resource_mut::<PersistenceState>();
+                state.last_sequence = sequence;
+                state.last_snapshot_sequence = sequence;
+            }
+            Err(e) => error!("Failed to restore identity world: {}", e),
+        }
+    }
+}
+
+/// Append the queued events to the event store
+pub fn flush_event_journal_system(
+    journal: Res<EventJournal>,
+    store: Res<IdentityEventStore>,
+    mut state: ResMut<PersistenceState>,
+) {
+    let mut pending = journal.pending.lock().unwrap();
+    if pending.is_empty() {
+        return;
+    }
+
+    match store.0.append(&pending) {
+        Ok(sequence) => {
+            state.last_sequence = sequence;
+            pending.clear();
+        }
+        // Keep the events queued so the next frame retries the append
+        Err(e) => error!("Failed to persist {} identity events: {}", pending.len(), e),
+    }
+}
+
+/// Snapshot the world once enough events were stored since the last snapshot
+pub fn snapshot_system(world: &mut World) {
+    let interval = world.resource::<PersistenceConfig>().snapshot_interval;
+    let state = world.resource::<PersistenceState>().clone();
+    let since_snapshot = state
+        .last_sequence
+        .saturating_sub(state.last_snapshot_sequence);
+    if interval == 0 || since_snapshot < interval {
+        return;
+    }
+
+    let snapshot = WorldSnapshot::capture(world, state.last_sequence);
+    let store = world.resource::<IdentitySnapshotStore>().0.clone();
+    match store.save(&snapshot) {
+        Ok(()) => {
+            world.resource_mut::<PersistenceState>().last_snapshot_sequence = snapshot.sequence;
+        }
+        Err(e) => error!("Failed to snapshot identity world: {}", e),
+    }
+}
@@ -0,0 +1,805 @@
+//! Rebuilding the identity world from stored events
+
+use super::event::{IdentityDomainEvent, StoredEvent};
+use super::snapshot::SnapshotStore;
+use super::store::EventStore;
+use crate::components::*;
+use crate::systems::attempt::{
+    record_attempt_started, record_authentication_challenge_issued, record_authentication_decided,
+};
+use crate::systems::authentication::{
+    credentials_from, record_account_locked, record_account_unlocked,
+    record_authentication_failure, record_authentication_success, record_password_rehashed,
+    record_password_set,
+};
+use crate::systems::lifecycle::identity_from;
+use crate::systems::mfa::{
+    mfa_from, record_mfa_challenge_issued, record_mfa_failure, record_mfa_verified,
+};
+use crate::systems::session::{
+    record_session_ended, record_session_grant_used, record_session_refreshed,
+    record_session_started, record_sessions_revoked,
+};
+use crate::systems::verification::{
+    code_challenge, record_code_failed, record_code_sent, verification_workflow,
+};
+use crate::systems::workflow::{record_retry, record_step};
+use crate::verification::VerificationCodes;
+use crate::{IdentityIndex, IdentityResult};
+use bevy::ecs::prelude::*;
+
+/// Restore a world from the latest snapshot and the events recorded after it
+///
+/// Returns the sequence of the last event reflected in the world.
+pub fn restore_world(
+    world: &mut World,
+    events: &dyn EventStore,
+    snapshots: &dyn SnapshotStore,
+) -> IdentityResult<u64> {
+    ensure_index(world);
+
+    let mut sequence = 0;
+    if let Some(snapshot) = snapshots.load_latest()? {
+        snapshot.restore(world);
+        sequence = snapshot.sequence;
+    }
+
+    let remaining = events.read_from(sequence)?;
+    Ok(replay_events(world, &remaining).max(sequence))
+}
+
+/// Apply stored events to a world in order
+///
+/// Returns the sequence of the last applied event, or 0 if there were none.
+pub fn replay_events<'a>(
+    world: &mut World,
+    events: impl IntoIterator<Item = &'a StoredEvent>,
+) -> u64 {
+    ensure_index(world);
+
+    let mut last_sequence = 0;
+    for stored in events {
+        apply_event(world, &stored.event);
+        last_sequence = stored.sequence;
+    }
+    last_sequence
+}
+
+/// Apply a single event to a world
+///
+/// Events referring to entities that no longer exist are ignored, matching
+/// the behaviour of the systems that originally emitted them.
+pub fn apply_event(world: &mut World, event: &IdentityDomainEvent) {
+    ensure_index(world);
+
+    match event {
+        IdentityDomainEvent::IdentityCreated(event) => {
+            world.spawn((identity_from(event), IdentityClaims::default()));
+        }
+        IdentityDomainEvent::IdentityUpdated(event) => {
+            set_identity_status(world, event.identity_id, event.new_status, event.updated_at);
+        }
+        IdentityDomainEvent::IdentitiesMerged(event) => {
+            set_identity_status(
+                world,
+                event.source_identity,
+                IdentityStatus::Merged {
+                    merged_into: event.target_identity,
+                },
+                event.merged_at,
+            );
+            world
+                .resource_mut::<IdentityIndex>()
+                .record_merge(event.source_identity, event.target_identity);
+        }
+        IdentityDomainEvent::IdentityArchived(event) => {
+            set_identity_status(
+                world,
+                event.identity_id,
+                IdentityStatus::Archived,
+                event.archived_at,
+            );
+        }
+        IdentityDomainEvent::ClaimAdded(event) => {
+            if let Some(mut claims) = identity_claims(world, event.identity_id) {
+                claims.add(IdentityClaim {
+                    claim_type: event.claim_type.clone(),
+                    value: event.value.clone(),
+                    verified: false,
+                    issuer: event.issuer,
+                    issued_at: event.added_at,
+                    expires_at: event.expires_at,
+                });
+            }
+        }
+        IdentityDomainEvent::ClaimUpdated(event) => {
+            if let Some(mut claims) = identity_claims(world, event.identity_id) {
+                if let Some(claim) = claims.find_mut(&event.claim_type, &event.old_value) {
+                    claim.value = event.value.clone();
+                    claim.verified = event.verified;
+                    claim.expires_at = event.expires_at;
+                }
+            }
+        }
+        IdentityDomainEvent::ClaimRevoked(event) => {
+            if let Some(mut claims) = identity_claims(world, event.identity_id) {
+                claims.remove(&event.claim_type, &event.value);
+            }
+        }
+        IdentityDomainEvent::ClaimExpired(event) => {
+            if let Some(mut claims) = identity_claims(world, event.identity_id) {
+                claims.remove(&event.claim_type, &event.value);
+            }
+        }
+        IdentityDomainEvent::VerificationLevelDowngraded(event) => {
+            let entity = world.resource::<IdentityIndex>().identity(event.identity_id);
+            if let Some(mut verification) =
+                entity.and_then(|entity| world.get_mut::<IdentityVerification>(entity))
+            {
+                verification.verification_level = event.new_level;
+            }
+        }
+        IdentityDomainEvent::RelationshipEstablished(event) => {
+            world.spawn(IdentityRelationship {
+                relationship_id: event.relationship_id,
+                source_identity: event.from_identity,
+                target_identity: event.to_identity,
+                relationship_type: event.relationship_type.clone(),
+                rules: event.rules.clone(),
+                established_at: event.established_at,
+                established_by: Some(event.established_by),
+                expires_at: None,
+            });
+        }
+        IdentityDomainEvent::RelationshipValidated(event) => {
+            if !event.is_valid {
+                despawn_relationship(world, event.relationship_id);
+            }
+        }
+        IdentityDomainEvent::RelationshipExpired(event) => {
+            despawn_relationship(world, event.relationship_id);
+        }
+        IdentityDomainEvent::RelationshipRevoked(event) => {
+            despawn_relationship(world, event.relationship_id);
+        }
+        IdentityDomainEvent::ApiKeyIssued(event) => {
+            let Some(entity) = world.resource::<IdentityIndex>().identity(event.identity_id) else {
+                return;
+            };
+            add_api_key(
+                world,
+                entity,
+                ApiKeyRecord {
+                    key_id: event.key_id,
+                    name: event.name.clone(),
+                    key_hash: event.key_hash.clone(),
+                    permissions: event.permissions.clone(),
+                    created_at: event.issued_at,
+                    expires_at: event.expires_at,
+                    last_used: None,
+                    revoked_at: None,
+                },
+            );
+        }
+        IdentityDomainEvent::ApiKeyRotated(event) => {
+            let Some(entity) = world.resource::<IdentityIndex>().api_key(event.old_key_id) else {
+                return;
+            };
+            let Some(mut old_key) = api_key_mut(world, event.old_key_id) else {
+                return;
+            };
+            let original = old_key.clone();
+            if event.old_key_valid_until > event.rotated_at {
+                old_key.expires_at = Some(event.old_key_valid_until);
+            } else {
+                old_key.revoked_at = Some(event.rotated_at);
+            }
+
+            add_api_key(
+                world,
+                entity,
+                ApiKeyRecord {
+                    key_id: event.new_key_id,
+                    key_hash: event.new_key_hash.clone(),
+                    created_at: event.rotated_at,
+                    last_used: None,
+                    revoked_at: None,
+                    ..original
+                },
+            );
+        }
+        IdentityDomainEvent::ApiKeyRevoked(event) => {
+            if let Some(mut key) = api_key_mut(world, event.key_id) {
+                key.revoked_at = Some(event.revoked_at);
+            }
+        }
+        IdentityDomainEvent::ApiKeyAuthenticated(event) => {
+            if let Some(mut key) = api_key_mut(world, event.key_id) {
+                key.last_used = Some(event.authenticated_at);
+            }
+        }
+        IdentityDomainEvent::PasswordSet(event) => {
+            let Some(entity) = world
+                .resource::<IdentityIndex>()
+                .identity(event.identity_id)
+            else {
+                return;
+            };
+            if let Some(mut credentials) = world.get_mut::<IdentityCredentials>(entity) {
+                record_password_set(&mut credentials, event);
+            } else {
+                world.entity_mut(entity).insert(credentials_from(event));
+            }
+        }
+        IdentityDomainEvent::PasswordRehashed(event) => {
+            if let Some(mut credentials) = credentials_mut(world, event.identity_id) {
+                record_password_rehashed(&mut credentials, event);
+            }
+        }
+        IdentityDomainEvent::AuthenticationSucceeded(event) => {
+            if let Some(mut credentials) = credentials_mut(world, event.identity_id) {
+                record_authentication_success(&mut credentials, event);
+            }
+        }
+        IdentityDomainEvent::AuthenticationFailed(event) => {
+            if let Some(mut credentials) = credentials_mut(world, event.identity_id) {
+                record_authentication_failure(&mut credentials, event);
+            }
+        }
+        IdentityDomainEvent::AccountLocked(event) => {
+            if let Some(mut credentials) = credentials_mut(world, event.identity_id) {
+                record_account_locked(&mut credentials, event);
+            }
+        }
+        IdentityDomainEvent::AccountUnlocked(event) => {
+            if let Some(mut credentials) = credentials_mut(world, event.identity_id) {
+                record_account_unlocked(&mut credentials, event);
+            }
+        }
+        IdentityDomainEvent::MfaEnabled(event) => {
+            if let Some(entity) = world
+                .resource::<IdentityIndex>()
+                .identity(event.identity_id)
+            {
+                world.entity_mut(entity).insert(mfa_from(event));
+            }
+        }
+        IdentityDomainEvent::MfaChallengeIssued(event) => {
+            if let Some(entity) = world
+                .resource::<IdentityIndex>()
+                .identity(event.identity_id)
+            {
+                if let Some(mut mfa) = world.get_mut::<IdentityMfa>(entity) {
+                    record_mfa_challenge_issued(&mut mfa, event);
+                }
+            }
+        }
+        IdentityDomainEvent::MfaVerified(event) => {
+            let Some(entity) = world
+                .resource::<IdentityIndex>()
+                .identity(event.identity_id)
+            else {
+                return;
+            };
+            if let Ok((mut mfa, mut credentials)) = world
+                .query::<(&mut IdentityMfa, &mut IdentityCredentials)>()
+                .get_mut(world, entity)
+            {
+                record_mfa_verified(&mut mfa, &mut credentials, event);
+            }
+        }
+        IdentityDomainEvent::MfaFailed(event) => {
+            if let Some(mut credentials) = credentials_mut(world, event.identity_id) {
+                record_mfa_failure(&mut credentials, event);
+            }
+        }
+        IdentityDomainEvent::AuthenticationAttemptStarted(event) => {
+            if let Some(mut credentials) = credentials_mut(world, event.identity_id) {
+                record_attempt_started(&mut credentials, event);
+            }
+        }
+        IdentityDomainEvent::SuspiciousAuthenticationDetected(_) => {
+            // Reported only, the attempt carries the risk it was started with
+        }
+        IdentityDomainEvent::AuthenticationChallengeIssued(event) => {
+            if let Some(mut credentials) = credentials_mut(world, event.identity_id) {
+                record_authentication_challenge_issued(&mut credentials, event);
+            }
+        }
+        IdentityDomainEvent::AuthenticationDecided(event) => {
+            if let Some(mut credentials) = credentials_mut(world, event.identity_id) {
+                record_authentication_decided(&mut credentials, event);
+            }
+        }
+        IdentityDomainEvent::SessionStarted(event) => {
+            let Some(entity) = world
+                .resource::<IdentityIndex>()
+                .identity(event.identity_id)
+            else {
+                return;
+            };
+            if let Some(mut credentials) = world.get_mut::<IdentityCredentials>(entity) {
+                record_session_grant_used(&mut credentials, event);
+            }
+            if let Some(mut sessions) = world.get_mut::<IdentitySessions>(entity) {
+                record_session_started(&mut sessions, event);
+                world
+                    .resource_mut::<IdentityIndex>()
+                    .record_session(event.session_id, entity);
+            } else {
+                let mut sessions = IdentitySessions::default();
+                record_session_started(&mut sessions, event);
+                world.entity_mut(entity).insert(sessions);
+            }
+        }
+        IdentityDomainEvent::SessionRefreshed(event) => {
+            if let Some(mut sessions) = sessions_mut(world, event.session_id) {
+                record_session_refreshed(&mut sessions, event);
+            }
+        }
+        IdentityDomainEvent::SessionEnded(event) => {
+            if let Some(mut sessions) = sessions_mut(world, event.session_id) {
+                record_session_ended(&mut sessions, event);
+            }
+        }
+        IdentityDomainEvent::SessionsRevoked(event) => {
+            let entity = world
+                .resource::<IdentityIndex>()
+                .identity(event.identity_id);
+            if let Some(mut sessions) =
+                entity.and_then(|entity| world.get_mut::<IdentitySessions>(entity))
+            {
+                record_sessions_revoked(&mut sessions, event);
+            }
+        }
+        IdentityDomainEvent::KeyGenerated(event) => {
+            add_identity_key(
+                world,
+                event.identity_id,
+                IdentityKey {
+                    key_id: event.key_id,
+                    algorithm: event.algorithm,
+                    purposes: event.purposes.clone(),
+                    public_key: event.public_key,
+                    created_at: event.generated_at,
+                    superseded_by: None,
+                    revoked_at: None,
+                    revocation_reason: None,
+                },
+            );
+        }
+        IdentityDomainEvent::KeyImported(event) => {
+            add_identity_key(
+                world,
+                event.identity_id,
+                IdentityKey {
+                    key_id: event.key_id,
+                    algorithm: event.algorithm,
+                    purposes: event.purposes.clone(),
+                    public_key: event.public_key,
+                    created_at: event.imported_at,
+                    superseded_by: None,
+                    revoked_at: None,
+                    revocation_reason: None,
+                },
+            );
+        }
+        IdentityDomainEvent::KeyRotated(event) => {
+            if let Some(mut keys) = identity_keys(world, event.identity_id) {
+                if let Some(previous) = keys.key_mut(event.previous_key_id) {
+                    previous.superseded_by = Some(event.key_id);
+                }
+                keys.keys.push(IdentityKey {
+                    key_id: event.key_id,
+                    algorithm: event.algorithm,
+                    purposes: event.purposes.clone(),
+                    public_key: event.public_key,
+                    created_at: event.rotated_at,
+                    superseded_by: None,
+                    revoked_at: None,
+                    revocation_reason: None,
+                });
+            }
+        }
+        IdentityDomainEvent::KeyRevoked(event) => {
+            if let Some(mut keys) = identity_keys(world, event.identity_id) {
+                if let Some(key) = keys.key_mut(event.key_id) {
+                    key.revoked_at = Some(event.revoked_at);
+                    key.revocation_reason = Some(event.reason.clone());
+                }
+            }
+        }
+        IdentityDomainEvent::CredentialImported(event) => {
+            if let Some(mut claims) = identity_claims(world, event.identity_id) {
+                claims.add(IdentityClaim {
+                    claim_type: event.claim_type.clone(),
+                    value: event.value.clone(),
+                    verified: true,
+                    issuer: Some(event.issuer_id),
+                    issued_at: event.issued_at,
+                    expires_at: event.expires_at,
+                });
+            }
+        }
+        IdentityDomainEvent::DidPublished(event) => {
+            let entity = world.resource::<IdentityIndex>().identity(event.identity_id);
+            if let Some(entity) = entity {
+                world.entity_mut(entity).insert(IdentityDid {
+                    did: event.did.clone(),
+                    method: event.method.clone(),
+                    keys: vec![DidKey {
+                        public_key_multibase: event.public_key_multibase.clone(),
+                        added_at: event.published_at,
+                        retired_at: None,
+                    }],
+                    services: event.services.clone(),
+                    updated_at: event.published_at,
+                });
+            }
+        }
+        IdentityDomainEvent::DidKeyRotated(event) => {
+            let entity = world.resource::<IdentityIndex>().identity(event.identity_id);
+            if let Some(mut identity_did) =
+                entity.and_then(|entity| world.get_mut::<IdentityDid>(entity))
+            {
+                identity_did.rotate(event.public_key_multibase.clone(), event.rotated_at);
+            }
+        }
+        IdentityDomainEvent::WorkflowStarted(event) => {
+            let active = event.steps.iter().find(|s| s.status == StepStatus::Active);
+            world.spawn((
+                IdentityWorkflow {
+                    workflow_id: event.workflow_id,
+                    identity_id: event.identity_id,
+                    workflow_type: event.workflow_type.clone(),
+                    status: active
+                        .map_or(WorkflowStatus::NotStarted, |s| s.step_type.waiting_status()),
+                    current_step: active.map(|s| s.step_id.clone()),
+                    steps: event.steps.clone(),
+                    transitions: event.transitions.clone(),
+                    context: event.context.clone(),
+                    started_at: Some(event.started_at),
+                    completed_at: None,
+                    paused_at: None,
+                },
+                WorkflowHistory::new(event.workflow_id),
+            ));
+        }
+        IdentityDomainEvent::WorkflowStepCompleted(event) => {
+            let Some(entity) = world
+                .resource::<IdentityIndex>()
+                .workflow(event.workflow_id)
+            else {
+                return;
+            };
+            let Some(mut workflow) = world.get_mut::<IdentityWorkflow>(entity) else {
+                return;
+            };
+            workflow.apply_step_outcome(
+                &event.step_id,
+                &event.outcome,
+                event.next_step.as_deref(),
+                event.completed_at,
+            );
+            let workflow = workflow.clone();
+            if let Some(mut history) = world.get_mut::<WorkflowHistory>(entity) {
+                record_step(&mut history, &workflow, event);
+            }
+            if let Some(mut verification) = world.get_mut::<VerificationWorkflow>(entity) {
+                let step_type = workflow
+                    .steps
+                    .iter()
+                    .find(|s| s.step_id == event.step_id)
+                    .map(|s| s.step_type.clone());
+                if let (Some(StepType::Approval), Some(processed_by)) =
+                    (step_type, event.processed_by)
+                {
+                    verification.verified_by = processed_by;
+                }
+                if let Some(command_id) = event.causation_id {
+                    verification.last_command_id = command_id;
+                }
+            }
+        }
+        IdentityDomainEvent::ApprovalRequested(event) => {
+            let entity = world
+                .resource::<IdentityIndex>()
+                .workflow(event.workflow_id);
+            if let Some(entity) = entity {
+                world.entity_mut(entity).insert(ApprovalTask {
+                    workflow_id: event.workflow_id,
+                    identity_id: event.identity_id,
+                    step_id: event.step_id.clone(),
+                    approvers: event.approvers.clone(),
+                    quorum: event.quorum,
+                    decisions: Vec::new(),
+                    requested_at: event.requested_at,
+                    escalated_at: None,
+                });
+            }
+        }
+        IdentityDomainEvent::ApprovalDecided(event) => {
+            let Some(entity) = world
+                .resource::<IdentityIndex>()
+                .workflow(event.workflow_id)
+            else {
+                return;
+            };
+            let decision = ApprovalDecision {
+                step_id: event.step_id.clone(),
+                approver: event.approver,
+                approved: event.approved,
+                comment: event.comment.clone(),
+                decided_at: event.decided_at,
+            };
+            if let Some(mut task) = world.get_mut::<ApprovalTask>(entity) {
+                if task.step_id == event.step_id {
+                    task.decisions.push(decision.clone());
+                }
+            }
+            if let Some(mut history) = world.get_mut::<WorkflowHistory>(entity) {
+                history.approvals.push(decision);
+            }
+        }
+        IdentityDomainEvent::ApprovalEscalated(event) => {
+            let entity = world
+                .resource::<IdentityIndex>()
+                .workflow(event.workflow_id);
+            if let Some(mut task) = entity.and_then(|entity| world.get_mut::<ApprovalTask>(entity))
+            {
+                task.approvers.extend(event.added_approvers.iter().copied());
+                task.escalated_at = Some(event.escalated_at);
+            }
+        }
+        IdentityDomainEvent::WorkflowCompleted(event) => {
+            let Some(entity) = world
+                .resource::<IdentityIndex>()
+                .workflow(event.workflow_id)
+            else {
+                return;
+            };
+            // Finished verification workflows keep only their workflow and history
+            world.entity_mut(entity).remove::<VerificationWorkflow>();
+            if let Some(mut workflow) = world.get_mut::<IdentityWorkflow>(entity) {
+                workflow.status = event.final_status.clone();
+                workflow.paused_at = None;
+                workflow.completed_at = Some(event.completed_at);
+            }
+        }
+        IdentityDomainEvent::WorkflowPaused(event) => {
+            if let Some(mut workflow) = workflow_mut(world, event.workflow_id) {
+                workflow.pause(event.paused_at);
+            }
+        }
+        IdentityDomainEvent::WorkflowResumed(event) => {
+            let Some(entity) = world
+                .resource::<IdentityIndex>()
+                .workflow(event.workflow_id)
+            else {
+                return;
+            };
+            let Some(mut workflow) = world.get_mut::<IdentityWorkflow>(entity) else {
+                return;
+            };
+            let paused_for = workflow.resume(event.resumed_at);
+            if let Some(mut task) = world.get_mut::<ApprovalTask>(entity) {
+                task.requested_at += paused_for;
+            }
+        }
+        IdentityDomainEvent::WorkflowCancelled(event) => {
+            if let Some(mut workflow) = workflow_mut(world, event.workflow_id) {
+                workflow.cancel(event.cancelled_at);
+            }
+        }
+        IdentityDomainEvent::WorkflowStepRetried(event) => {
+            let Some(entity) = world
+                .resource::<IdentityIndex>()
+                .workflow(event.workflow_id)
+            else {
+                return;
+            };
+            if let Some(mut workflow) = world.get_mut::<IdentityWorkflow>(entity) {
+                workflow.retry_step(&event.step_id, event.retried_at);
+            }
+            if let Some(mut history) = world.get_mut::<WorkflowHistory>(entity) {
+                record_retry(&mut history, event);
+            }
+        }
+        IdentityDomainEvent::WorkflowTimedOut(event) => {
+            if let Some(mut workflow) = workflow_mut(world, event.workflow_id) {
+                if let Some(step) = workflow
+                    .steps
+                    .iter_mut()
+                    .find(|s| s.step_id == event.step_id)
+                {
+                    step.status = StepStatus::Failed;
+                    step.completed_at = Some(event.timed_out_at);
+                }
+                workflow.status = WorkflowStatus::Failed("Step timeout".to_string());
+                workflow.completed_at = Some(event.timed_out_at);
+            }
+        }
+        IdentityDomainEvent::VerificationStarted(event) => {
+            let mut workflow = verification_workflow(
+                event.workflow_id,
+                event.identity_id,
+                &event.verification_method,
+                event.started_at,
+            );
+            // The provider check was requested when the workflow started
+            if event.provider_reference.is_some() {
+                workflow.complete_active_step(event.started_at);
+            }
+            world.spawn((
+                workflow,
+                WorkflowHistory::new(event.workflow_id),
+                VerificationWorkflow {
+                    verification_method: event.verification_method.clone(),
+                    target_level: event.target_level,
+                    initiated_by: event.initiated_by,
+                    verified_by: event.initiated_by,
+                    code_claim: None,
+                    provider_reference: event.provider_reference.clone(),
+                    correlation_id: event.correlation_id,
+                    last_command_id: event.causation_id.unwrap_or_default(),
+                },
+            ));
+        }
+        IdentityDomainEvent::VerificationCodeSent(event) => {
+            // Put the pending challenge back where codes are configured
+            if let Some(mut codes) = world.get_resource_mut::<VerificationCodes>() {
+                codes.restore_challenge(code_challenge(event));
+            }
+            // Codes sent by a starting verification complete its sending step
+            let Some(entity) = event
+                .workflow_id
+                .and_then(|workflow_id| world.resource::<IdentityIndex>().workflow(workflow_id))
+            else {
+                return;
+            };
+            let mut query = world.query::<(&mut IdentityWorkflow, &mut VerificationWorkflow)>();
+            if let Ok((mut workflow, mut verification)) = query.get_mut(world, entity) {
+                record_code_sent(
+                    &mut workflow,
+                    &mut verification,
+                    (event.claim_type.clone(), event.value.clone()),
+                    event.expires_at,
+                    event.sent_at,
+                );
+            }
+        }
+        IdentityDomainEvent::VerificationCodeFailed(event) => {
+            if let Some(mut codes) = world.get_resource_mut::<VerificationCodes>() {
+                record_code_failed(&mut codes, event);
+            }
+        }
+        IdentityDomainEvent::VerificationCompleted(event) => {
+            if !event.verification_successful {
+                return;
+            }
+            let entity = world.resource::<IdentityIndex>().identity(event.identity_id);
+            if let Some(mut verification) =
+                entity.and_then(|entity| world.get_mut::<IdentityVerification>(entity))
+            {
+                verification.verification_level = event.new_verification_level;
+                verification.verified_at = Some(event.completed_at);
+                verification.verified_by = Some(event.verified_by);
+                verification.verification_method = Some(event.verification_method.clone());
+            }
+            if let Some((claim_type, value)) = &event.verified_claim {
+                discard_challenge(world, event.identity_id, claim_type, value);
+            }
+        }
+        IdentityDomainEvent::ClaimVerified(event) => {
+            if let Some(mut claims) = identity_claims(world, event.identity_id) {
+                claims.verify(&event.claim_type, &event.value);
+            }
+            discard_challenge(world, event.identity_id, &event.claim_type, &event.value);
+        }
+    }
+}
+
+fn ensure_index(world: &mut World) {
+    if !world.contains_resource::<IdentityIndex>() {
+        let index = IdentityIndex::rebuild(world);
+        world.insert_resource(index);
+    }
+}
+
+/// Drop the challenge whose code has been confirmed
+fn discard_challenge(
+    world: &mut World,
+    identity_id: IdentityId,
+    claim_type: &ClaimType,
+    value: &str,
+) {
+    if let Some(mut codes) = world.get_resource_mut::<VerificationCodes>() {
+        codes.discard_challenge(identity_id, claim_type, value);
+    }
+}
+
+fn identity_claims(world: &mut World, identity_id: IdentityId) -> Option<Mut<'_, IdentityClaims>> {
+    let entity = world.resource::<IdentityIndex>().identity(identity_id)?;
+    world.get_mut::<IdentityClaims>(entity)
+}
+
+fn identity_keys(world: &mut World, identity_id: IdentityId) -> Option<Mut<'_, IdentityKeys>> {
+    let entity = world.resource::<IdentityIndex>().identity(identity_id)?;
+    world.get_mut::<IdentityKeys>(entity)
+}
+
+fn add_identity_key(world: &mut World, identity_id: IdentityId, key: IdentityKey) {
+    let Some(entity) = world.resource::<IdentityIndex>().identity(identity_id) else {
+        return;
+    };
+    match world.get_mut::<IdentityKeys>(entity) {
+        Some(mut keys) => keys.keys.push(key),
+        None => {
+            world
+                .entity_mut(entity)
+                .insert(IdentityKeys { keys: vec![key] });
+        }
+    }
+}
+
+fn set_identity_status(
+    world: &mut World,
+    identity_id: IdentityId,
+    status: IdentityStatus,
+    at: chrono::DateTime<chrono::Utc>,
+) {
+    let Some(entity) = world.resource::<IdentityIndex>().identity(identity_id) else {
+        return;
+    };
+
+    if let Some(mut identity) = world.get_mut::<IdentityEntity>(entity) {
+        identity.status = status;
+    }
+    if let Some(mut metadata) = world.get_mut::<IdentityMetadata>(entity) {
+        metadata.updated_at = at;
+        metadata.version += 1;
+    }
+}
+
+fn despawn_relationship(world: &mut World, relationship_id: RelationshipId) {
+    let entity = world.resource::<IdentityIndex>().relationship(relationship_id);
+    if let Some(entity) = entity {
+        world.despawn(entity);
+    }
+}
+
+fn workflow_mut(world: &mut World, workflow_id: WorkflowId) -> Option<Mut<'_, IdentityWorkflow>> {
+    let entity = world.resource::<IdentityIndex>().workflow(workflow_id)?;
+    world.get_mut::<IdentityWorkflow>(entity)
+}
+
+fn add_api_key(world: &mut World, entity: Entity, key: ApiKeyRecord) {
+    let key_id = key.key_id;
+    if let Some(mut keys) = world.get_mut::<IdentityApiKeys>(entity) {
+        keys.keys.push(key);
+        world
+            .resource_mut::<IdentityIndex>()
+            .record_api_key(key_id, entity);
+    } else if let Ok(mut entity) = world.get_entity_mut(entity) {
+        entity.insert(IdentityApiKeys { keys: vec![key] });
+    }
+}
+
+fn api_key_mut(world: &mut World, key_id: uuid::Uuid) -> Option<Mut<'_, ApiKeyRecord>> {
+    let entity = world.resource::<IdentityIndex>().api_key(key_id)?;
+    let keys = world.get_mut::<IdentityApiKeys>(entity)?;
+    keys.filter_map_unchanged(|keys| keys.key_mut(key_id))
+}
+
+fn sessions_mut(world: &mut World, session_id: uuid::Uuid) -> Option<Mut<'_, IdentitySessions>> {
+    let entity = world.resource::<IdentityIndex>().session(session_id)?;
+    world.get_mut::<IdentitySessions>(entity)
+}
+
+fn credentials_mut(
+    world: &mut World,
+    identity_id: IdentityId,
+) -> Option<Mut<'_, IdentityCredentials>> {
+    let entity = world.resource::<IdentityIndex>().identity(identity_id)?;
+    world.get_mut::<IdentityCredentials>(entity)
+}
@@ -0,0 +1,320 @@
+//! Snapshots of the identity world
+
+use crate::components::{
+    ApprovalTask, IdentityApiKeys, IdentityClaims, IdentityCredentials, IdentityDid,
+    IdentityEntity, IdentityKeys, IdentityMetadata, IdentityMfa, IdentityRelationship,
+    IdentitySessions, IdentityVerification, IdentityWorkflow, VerificationWorkflow,
+    WorkflowHistory, WorkflowId,
+};
+use crate::verification::{CodeChallenge, VerificationCodes};
+use crate::{IdentityError, IdentityResult};
+use bevy::ecs::prelude::*;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Identity components captured in a snapshot
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdentitySnapshot {
+    pub identity: IdentityEntity,
+    pub metadata: Option<IdentityMetadata>,
+    pub verification: Option<IdentityVerification>,
+    #[serde(default)]
+    pub api_keys: Option<IdentityApiKeys>,
+    #[serde(default)]
+    pub claims: Option<IdentityClaims>,
+    #[serde(default)]
+    pub did: Option<IdentityDid>,
+    #[serde(default)]
+    pub keys: Option<IdentityKeys>,
+    #[serde(default)]
+    pub credentials: Option<IdentityCredentials>,
+    #[serde(default)]
+    pub mfa: Option<IdentityMfa>,
+    #[serde(default)]
+    pub sessions: Option<IdentitySessions>,
+}
+
+/// State of the identity world after the event at `sequence` was applied
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorldSnapshot {
+    pub sequence: u64,
+    pub taken_at: DateTime<Utc>,
+    pub identities: Vec<IdentitySnapshot>,
+    pub relationships: Vec<IdentityRelationship>,
+    pub workflows: Vec<IdentityWorkflow>,
+    #[serde(default)]
+    pub workflow_histories: Vec<WorkflowHistory>,
+    #[serde(default)]
+    pub approval_tasks: Vec<ApprovalTask>,
+    /// Verification state of the verification workflows in progress
+    #[serde(default)]
+    pub verification_workflows: Vec<(WorkflowId, VerificationWorkflow)>,
+    /// Verification codes waiting to be confirmed
+    #[serde(default)]
+    pub code_challenges: Vec<CodeChallenge>,
+}
+
+impl WorldSnapshot {
+    /// Capture the identity, relationship and workflow components of a world
+    /// along with its pending verification codes
+    pub fn capture(world: &mut World, sequence: u64) -> Self {
+        let identities = world
+            .query::<(
+                &IdentityEntity,
+                Option<&IdentityMetadata>,
+                Option<&IdentityVerification>,
+                Option<&IdentityApiKeys>,
+                Option<&IdentityClaims>,
+                Option<&IdentityDid>,
+                Option<&IdentityKeys>,
+                Option<&IdentityCredentials>,
+                Option<&IdentityMfa>,
+                Option<&IdentitySessions>,
+            )>()
+            .iter(world)
+            .map(
+                |(
+                    identity,
+                    metadata,
+                    verification,
+                    api_keys,
+                    claims,
+                    did,
+                    keys,
+                    credentials,
+                    mfa,
+                    sessions,
+                )| {
+                    IdentitySnapshot {
+                        identity: identity.clone(),
+                        metadata: metadata.cloned(),
+                        verification: verification.cloned(),
+                        api_keys: api_keys.cloned(),
+                        claims: claims.cloned(),
+                        did: did.cloned(),
+                        keys: keys.cloned(),
+                        credentials: credentials.cloned(),
+                        mfa: mfa.cloned(),
+                        sessions: sessions.cloned(),
+                    }
+                },
+            )
+            .collect();
+
+        let relationships = world
+            .query::<&IdentityRelationship>()
+            .iter(world)
+            .cloned()
+            .collect();
+
+        let workflows = world
+            .query::<&IdentityWorkflow>()
+            .iter(world)
+            .cloned()
+            .collect();
+
+        let workflow_histories = world
+            .query::<&WorkflowHistory>()
+            .iter(world)
+            .cloned()
+            .collect();
+
+        let approval_tasks = world
+            .query::<&ApprovalTask>()
+            .iter(world)
+            .cloned()
+            .collect();
+
+        let verification_workflows = world
+            .query::<(&IdentityWorkflow, &VerificationWorkflow)>()
+            .iter(world)
+            .map(|(workflow, verification)| (workflow.workflow_id, verification.clone()))
+            .collect();
+
+        let code_challenges = world
+            .get_resource::<VerificationCodes>()
+            .map(|codes| codes.challenges().cloned().collect())
+            .unwrap_or_default();
+
+        Self {
+            sequence,
+            taken_at: chrono::Utc::now(),
+            identities,
+            relationships,
+            workflows,
+            workflow_histories,
+            approval_tasks,
+            verification_workflows,
+            code_challenges,
+        }
+    }
+
+    /// Spawn the captured entities into a world and put back pending codes
+    pub fn restore(&self, world: &mut World) {
+        if let Some(mut codes) = world.get_resource_mut::<VerificationCodes>() {
+            for challenge in &self.code_challenges {
+                codes.restore_challenge(challenge.clone());
+            }
+        }
+
+        for snapshot in &self.identities {
+            let mut entity = world.spawn(snapshot.identity.clone());
+            if let Some(metadata) = &snapshot.metadata {
+                entity.insert(metadata.clone());
+            }
+            if let Some(verification) = &snapshot.verification {
+                entity.insert(verification.clone());
+            }
+            if let Some(api_keys) = &snapshot.api_keys {
+                entity.insert(api_keys.clone());
+            }
+            if let Some(claims) = &snapshot.claims {
+                entity.insert(claims.clone());
+            }
+            if let Some(did) = &snapshot.did {
+                entity.insert(did.clone());
+            }
+            if let Some(keys) = &snapshot.keys {
+                entity.insert(keys.clone());
+            }
+            if let Some(credentials) = &snapshot.credentials {
+                entity.insert(credentials.clone());
+            }
+            if let Some(mfa) = &snapshot.mfa {
+                entity.insert(mfa.clone());
+            }
+            if let Some(sessions) = &snapshot.sessions {
+                entity.insert(sessions.clone());
+            }
+        }
+
+        world.spawn_batch(self.relationships.clone());
+
+        let histories: HashMap<WorkflowId, &WorkflowHistory> = self
+            .workflow_histories
+            .iter()
+            .map(|history| (history.workflow_id, history))
+            .collect();
+        let approval_tasks: HashMap<WorkflowId, &ApprovalTask> = self
+            .approval_tasks
+            .iter()
+            .map(|task| (task.workflow_id, task))
+            .collect();
+        let verifications: HashMap<WorkflowId, &VerificationWorkflow> = self
+            .verification_workflows
+            .iter()
+            .map(|(workflow_id, verification)| (*workflow_id, verification))
+            .collect();
+        for workflow in &self.workflows {
+            let mut entity = world.spawn(workflow.clone());
+            if let Some(history) = histories.get(&workflow.workflow_id) {
+                entity.insert((*history).clone());
+            }
+            if let Some(task) = approval_tasks.get(&workflow.workflow_id) {
+                entity.insert((*task).clone());
+            }
+            if let Some(verification) = verifications.get(&workflow.workflow_id) {
+                entity.insert((*verification).clone());
+            }
+        }
+    }
+}
+
+/// Storage for world snapshots
+pub trait SnapshotStore: Send + Sync {
+    /// Persist a snapshot
+    fn save(&self, snapshot: &WorldSnapshot) -> IdentityResult<()>;
+
+    /// Load the most recent readable snapshot, if any
+    fn load_latest(&self) -> IdentityResult<Option<WorldSnapshot>>;
+}
+
+/// Snapshot store writing one JSON file per snapshot into a directory
+///
+/// Snapshots are written to a temporary file and renamed into place, so a
+/// crash never leaves a partially written snapshot behind. Only the most
+/// recent `retain` snapshots are kept.
+#[derive(Debug, Clone)]
+pub struct FileSnapshotStore {
+    directory: PathBuf,
+    retain: usize,
+}
+
+impl FileSnapshotStore {
+    /// Open the store in `directory`, creating it if needed
+    pub fn open(directory: impl AsRef<Path>) -> IdentityResult<Self> {
+        let directory = directory.as_ref().to_path_buf();
+        fs::create_dir_all(&directory).map_err(|e| {
+            IdentityError::PersistenceError(format!("creating snapshot directory: {e}"))
+        })?;
+        Ok(Self {
+            directory,
+            retain: 2,
+        })
+    }
+
+    /// Number of snapshots to keep on disk, at least one
+    pub fn with_retain(mut self, retain: usize) -> Self {
+        self.retain = retain.max(1);
+        self
+    }
+
+    fn snapshot_files(&self) -> IdentityResult<Vec<PathBuf>> {
+        let entries = fs::read_dir(&self.directory).map_err(|e| {
+            IdentityError::PersistenceError(format!("reading snapshot directory: {e}"))
+        })?;
+
+        let mut files: Vec<_> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with("snapshot-") && name.ends_with(".json"))
+            })
+            .collect();
+        // Sequence numbers are zero padded, so lexical order is sequence order
+        files.sort();
+        Ok(files)
+    }
+}
+
+impl SnapshotStore for FileSnapshotStore {
+    fn save(&self, snapshot: &WorldSnapshot) -> IdentityResult<()> {
+        let name = format!("snapshot-{:020}.json", snapshot.sequence);
+        let path = self.directory.join(&name);
+        let temp = self.directory.join(format!("{name}.tmp"));
+
+        let data = serde_json::to_vec(snapshot)
+            .map_err(|e| IdentityError::PersistenceError(format!("serializing snapshot: {e}")))?;
+        fs::write(&temp, data)
+            .and_then(|_| fs::rename(&temp, &path))
+            .map_err(|e| IdentityError::PersistenceError(format!("writing snapshot: {e}")))?;
+
+        let files = self.snapshot_files()?;
+        let excess = files.len().saturating_sub(self.retain);
+        for old in &files[..excess] {
+            if let Err(e) = fs::remove_file(old) {
+                warn!("Failed to remove old snapshot {}: {}", old.display(), e);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn load_latest(&self) -> IdentityResult<Option<WorldSnapshot>> {
+        for path in self.snapshot_files()?.iter().rev() {
+            match fs::read(path)
+                .map_err(|e| e.to_string())
+                .and_then(|data| serde_json::from_slice(&data).map_err(|e| e.to_string()))
+            {
+                Ok(snapshot) => return Ok(Some(snapshot)),
+                Err(e) => warn!("Skipping unreadable snapshot {}: {}", path.display(), e),
+            }
+        }
+        Ok(None)
+    }
+}
@@ -0,0 +1,214 @@
+//! Event store abstraction and implementations
+
+use super::event::{IdentityDomainEvent, StoredEvent};
+use crate::{IdentityError, IdentityResult};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::warn;
+
+/// Append-only storage for identity domain events
+///
+/// Implementations assign consecutive sequence numbers starting at 1. The
+/// store is used from ECS systems, so its methods are synchronous.
+pub trait EventStore: Send + Sync {
+    /// Append events in order and return the sequence of the last one
+    fn append(&self, events: &[IdentityDomainEvent]) -> IdentityResult<u64>;
+
+    /// Read every event with a sequence greater than `after`
+    fn read_from(&self, after: u64) -> IdentityResult<Vec<StoredEvent>>;
+
+    /// Sequence of the most recently appended event, 0 when empty
+    fn last_sequence(&self) -> IdentityResult<u64>;
+}
+
+fn persistence_error(context: &str, error: impl std::fmt::Display) -> IdentityError {
+    IdentityError::PersistenceError(format!("{context}: {error}"))
+}
+
+/// Event store keeping events in memory
+#[derive(Debug, Default)]
+pub struct InMemoryEventStore {
+    events: Mutex<Vec<StoredEvent>>,
+}
+
+impl InMemoryEventStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl EventStore for InMemoryEventStore {
+    fn append(&self, events: &[IdentityDomainEvent]) -> IdentityResult<u64> {
+        let mut stored = self.events.lock().unwrap();
+        let recorded_at = chrono::Utc::now();
+        for event in events {
+            let sequence = stored.len() as u64 + 1;
+            stored.push(StoredEvent {
+                sequence,
+                recorded_at,
+                event: event.clone(),
+            });
+        }
+        Ok(stored.len() as u64)
+    }
+
+    fn read_from(&self, after: u64) -> IdentityResult<Vec<StoredEvent>> {
+        let stored = self.events.lock().unwrap();
+        Ok(stored.iter().skip(after as usize).cloned().collect())
+    }
+
+    fn last_sequence(&self) -> IdentityResult<u64> {
+        Ok(self.events.lock().unwrap().len() as u64)
+    }
+}
+
+/// Append-only event store backed by a JSON-lines file
+///
+/// Each line holds one [`StoredEvent`]. Appends are flushed and synced before
+/// returning. A partially written trailing line left by a crash is truncated
+/// when the store is opened; corruption anywhere else is reported as an error.
+#[derive(Debug)]
+pub struct FileEventStore {
+    path: PathBuf,
+    state: Mutex<FileState>,
+}
+
+#[derive(Debug)]
+struct FileState {
+    file: File,
+    last_sequence: u64,
+}
+
+impl FileEventStore {
+    /// Open the store at `path`, creating the file and its directory if needed
+    pub fn open(path: impl AsRef<Path>) -> IdentityResult<Self> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)
+                .map_err(|e| persistence_error("creating event store directory", e))?;
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| persistence_error("opening event store", e))?;
+
+        let (last_sequence, valid_len) = Self::scan(&path)?;
+        let file_len = file
+            .metadata()
+            .map_err(|e| persistence_error("reading event store metadata", e))?
+            .len();
+        if valid_len < file_len {
+            warn!(
+                "Truncating {} bytes of incomplete data from event store {}",
+                file_len - valid_len,
+                path.display()
+            );
+            file.set_len(valid_len)
+                .map_err(|e| persistence_error("truncating event store", e))?;
+        }
+
+        Ok(Self {
+            path,
+            state: Mutex::new(FileState {
+                file,
+                last_sequence,
+            }),
+        })
+    }
+
+    /// Path of the underlying file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Return the last sequence and the length of the valid prefix of the file
+    fn scan(path: &Path) -> IdentityResult<(u64, u64)> {
+        let data = fs::read(path).map_err(|e| persistence_error("reading event store", e))?;
+
+        let mut last_sequence = 0;
+        let mut valid_len = 0;
+        let mut offset = 0;
+        while offset < data.len() {
+            let Some(newline) = data[offset..].iter().position(|b| *b == b'\n') else {
+                // Trailing bytes without a newline were never fully written
+                break;
+            };
+            let line = &data[offset..offset + newline];
+            offset += newline + 1;
+
+            if !line.iter().all(u8::is_ascii_whitespace) {
+                let stored: StoredEvent = serde_json::from_slice(line).map_err(|e| {
+                    persistence_error(&format!("corrupt event after sequence {last_sequence}"), e)
+                })?;
+                last_sequence = stored.sequence;
+            }
+            valid_len = offset as u64;
+        }
+
+        Ok((last_sequence, valid_len))
+    }
+}
+
+impl EventStore for FileEventStore {
+    fn append(&self, events: &[IdentityDomainEvent]) -> IdentityResult<u64> {
+        let mut state = self.state.lock().unwrap();
+        if events.is_empty() {
+            return Ok(state.last_sequence);
+        }
+
+        let recorded_at = chrono::Utc::now();
+        let mut buffer = Vec::new();
+        let mut sequence = state.last_sequence;
+        for event in events {
+            sequence += 1;
+            let stored = StoredEvent {
+                sequence,
+                recorded_at,
+                event: event.clone(),
+            };
+            serde_json::to_writer(&mut buffer, &stored)
+                .map_err(|e| persistence_error("serializing event", e))?;
+            buffer.push(b'\n');
+        }
+
+        state
+            .file
+            .write_all(&buffer)
+            .and_then(|_| state.file.flush())
+            .and_then(|_| state.file.sync_data())
+            .map_err(|e| persistence_error("writing event store", e))?;
+        state.last_sequence = sequence;
+
+        Ok(sequence)
+    }
+
+    fn read_from(&self, after: u64) -> IdentityResult<Vec<StoredEvent>> {
+        // Hold the lock so a concurrent append cannot be observed half written
+        let _state = self.state.lock().unwrap();
+        let file = File::open(&self.path).map_err(|e| persistence_error("opening event store", e))?;
+
+        let mut events = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|e| persistence_error("reading event store", e))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let stored: StoredEvent = serde_json::from_str(&line)
+                .map_err(|e| persistence_error("deserializing event", e))?;
+            if stored.sequence > after {
+                events.push(stored);
+            }
+        }
+
+        Ok(events)
+    }
+
+    fn last_sequence(&self) -> IdentityResult<u64> {
+        Ok(self.state.lock().unwrap().last_sequence)
+    }
+}
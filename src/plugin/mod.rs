@@ -0,0 +1,583 @@
+//! Bevy plugin for the Identity domain
+//!
+//! `IdentityPlugin` registers every command and event type of the domain and
+//! schedules all identity systems in explicit, ordered system sets so an
+//! application gets the whole domain by adding a single plugin.
+
+use crate::authentication::{AuthenticationClock, AuthenticationPolicy};
+use crate::credentials::{CredentialIssuer, IssuerKeys};
+use crate::did::DidResolver;
+use crate::keys::IdentityKeyStore;
+use crate::verification::{ReverificationReminders, VerificationCodes, VerificationProviders};
+use crate::workflows::WorkflowDefinitions;
+use crate::{commands::*, events::*, projections, systems::*, tokens::TokenIssuer, IdentityIndex};
+use bevy::app::{App, Plugin, Update};
+use bevy::ecs::prelude::*;
+
+/// System sets used by the identity domain
+///
+/// The sets run in the order
+/// `Validation → Mutation → Projection → Expiry → Persistence` within the
+/// configured schedule.
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IdentitySet {
+    /// Validates existing state before new commands are applied
+    Validation,
+    /// Applies commands to identities, relationships, API keys, passwords, MFA, sessions,
+    /// identity keys, tokens, credentials, DIDs, workflows and verifications
+    Mutation,
+    /// Maintains projections, read models, type markers and the DID resolver
+    Projection,
+    /// Expires relationships and claims, reminds of expiring claims, escalates approvals
+    /// and times out workflows
+    Expiry,
+    /// Records the events of the frame, see [`crate::persistence`]
+    Persistence,
+}
+
+/// Configuration for the identity plugin
+#[derive(Resource, Debug, Clone, PartialEq, Eq)]
+pub struct IdentityPluginConfig {
+    /// Register the projection systems and the `projections` read models
+    pub enable_projections: bool,
+    /// Register the identity and location type marker systems
+    pub enable_markers: bool,
+    /// Register the relationship and claim expiry, re-verification reminder,
+    /// approval escalation and workflow timeout systems
+    pub enable_expiry: bool,
+}
+
+impl Default for IdentityPluginConfig {
+    fn default() -> Self {
+        Self {
+            enable_projections: true,
+            enable_markers: true,
+            enable_expiry: true,
+        }
+    }
+}
+
+/// Plugin wiring the identity domain into a Bevy `App`
+#[derive(Debug, Clone, Default)]
+pub struct IdentityPlugin {
+    pub config: IdentityPluginConfig,
+}
+
+impl IdentityPlugin {
+    /// Create a plugin with the given configuration
+    pub fn new(config: IdentityPluginConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl Plugin for IdentityPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.config.clone())
+            .init_resource::<IdentityIndex>()
+            // Keeps an outcome channel the application inserted with its own timeout
+            .init_resource::<CommandOutcomes>()
+            // Keeps an authentication policy the application inserted
+            .init_resource::<AuthenticationPolicy>()
+            // Keeps a clock the application inserted, such as a manual clock in tests
+            .init_resource::<AuthenticationClock>()
+            // Keeps a token issuer the application inserted with its own keys
+            .init_resource::<TokenIssuer>()
+            // Keeps a credential issuer the application inserted with its own key
+            .init_resource::<CredentialIssuer>()
+            .init_resource::<IssuerKeys>()
+            .init_resource::<DidResolver>()
+            // Keeps a key store the application inserted, such as an encrypted file store
+            .init_resource::<IdentityKeyStore>()
+            .init_resource::<VerificationProviders>()
+            // Keeps verification codes the application inserted with its own notifier
+            .init_resource::<VerificationCodes>()
+            .init_resource::<ReverificationReminders>()
+            // Keeps workflow definitions the application registered or loaded
+            .init_resource::<WorkflowDefinitions>();
+
+        register_commands(app);
+        register_events(app);
+        register_rejections(app);
+
+        app.configure_sets(
+            Update,
+            (
+                IdentitySet::Validation,
+                IdentitySet::Mutation,
+                IdentitySet::Projection,
+                IdentitySet::Expiry,
+                IdentitySet::Persistence,
+            )
+                .chain(),
+        );
+
+        // Validation
+        app.add_systems(
+            Update,
+            (validate_relationships_system, validate_projection_system)
+                .chain()
+                .in_set(IdentitySet::Validation),
+        );
+
+        // Mutation: lifecycle first so that identities created this frame
+        // are visible to claim, relationship, workflow and verification commands,
+        // and relationships before credentials are imported against them
+        app.add_systems(
+            Update,
+            (
+                (
+                    create_identity_system,
+                    update_identity_system,
+                    merge_identities_system,
+                    archive_identity_system,
+                )
+                    .chain(),
+                (add_claim_system, update_claim_system, revoke_claim_system).chain(),
+                (
+                    establish_relationship_system,
+                    revoke_relationship_system,
+                    traverse_relationships_system,
+                )
+                    .chain(),
+                (
+                    issue_api_key_system,
+                    rotate_api_key_system,
+                    revoke_api_key_system,
+                    authenticate_api_key_system,
+                )
+                    .chain(),
+                (
+                    set_password_system,
+                    authenticate_password_system,
+                    enable_mfa_system,
+                    issue_mfa_challenge_system,
+                    verify_mfa_system,
+                    begin_authentication_system,
+                    submit_authentication_factor_system,
+                )
+                    .chain(),
+                (
+                    start_session_system,
+                    refresh_session_system,
+                    revoke_session_system,
+                    revoke_all_sessions_system,
+                )
+                    .chain(),
+                (
+                    generate_key_system,
+                    import_key_system,
+                    rotate_key_system,
+                    revoke_key_system,
+                )
+                    .chain(),
+                (issue_identity_token_system, validate_identity_token_system).chain(),
+                (export_credential_system, import_credential_system).chain(),
+                (publish_did_system, rotate_did_key_system).chain(),
+                (
+                    start_workflow_system,
+                    pause_workflow_system,
+                    resume_workflow_system,
+                    cancel_workflow_system,
+                    retry_workflow_step_system,
+                    process_workflow_step_system,
+                    decide_approval_system,
+                    assign_approvals_system,
+                    complete_workflow_system,
+                )
+                    .chain(),
+                (
+                    start_verification_system,
+                    advance_verification_system,
+                    poll_verification_providers_system,
+                    complete_verification_system,
+                    process_verification_system,
+                    send_verification_code_system,
+                    confirm_verification_code_system,
+                    update_verification_claims_system,
+                )
+                    .chain(),
+                (create_projection_system, refresh_projections_system).chain(),
+            )
+                .chain()
+                .in_set(IdentitySet::Mutation),
+        );
+
+        // Projection
+        if self.config.enable_projections {
+            app.add_systems(
+                Update,
+                (
+                    sync_projections_system,
+                    projections::update_identity_projections,
+                    projections::update_relationship_graph,
+                    projections::update_identity_status_projection,
+                    projections::update_workflow_status_projection,
+                )
+                    .chain()
+                    .in_set(IdentitySet::Projection),
+            );
+        }
+
+        // DIDs resolve whether or not projections are enabled
+        app.add_systems(
+            Update,
+            sync_did_resolver_system.in_set(IdentitySet::Projection),
+        );
+
+        if self.config.enable_markers {
+            app.add_systems(
+                Update,
+                (add_identity_markers_system, add_location_markers_system)
+                    .in_set(IdentitySet::Projection),
+            );
+        }
+
+        // Expiry
+        if self.config.enable_expiry {
+            if !app.world().contains_resource::<bevy::time::Time>() {
+                app.init_resource::<bevy::time::Time>();
+            }
+
+            app.add_systems(
+                Update,
+                (
+                    expire_relationships_system,
+                    expire_claims_system,
+                    remind_claim_reverification_system,
+                    escalate_approvals_system,
+                    timeout_workflows_system,
+                )
+                    .chain()
+                    .in_set(IdentitySet::Expiry),
+            );
+        }
+
+        // Outcomes are resolved once every set has emitted its events; earlier
+        // groups resolve before later ones so that a command producing several
+        // events, such as a creation with initial claims or a session start
+        // superseding another session, resolves to its primary event. Approval
+        // decisions resolve before the steps they complete, and authentication
+        // challenges and decisions before the factor checks behind them.
+        // Subscriptions left unresolved time out last.
+        app.add_systems(
+            Update,
+            (
+                (
+                    resolve_command_outcomes_system::<ApprovalDecided>,
+                    (
+                        resolve_command_outcomes_system::<AuthenticationChallengeIssued>,
+                        resolve_command_outcomes_system::<AuthenticationDecided>,
+                    ),
+                    (
+                        resolve_command_outcomes_system::<IdentityCreated>,
+                        resolve_command_outcomes_system::<IdentityUpdated>,
+                        resolve_command_outcomes_system::<IdentitiesMerged>,
+                        resolve_command_outcomes_system::<IdentityArchived>,
+                        resolve_command_outcomes_system::<RelationshipEstablished>,
+                        resolve_command_outcomes_system::<RelationshipValidated>,
+                        resolve_command_outcomes_system::<RelationshipRevoked>,
+                        resolve_command_outcomes_system::<RelationshipsTraversed>,
+                        resolve_command_outcomes_system::<WorkflowStarted>,
+                        resolve_command_outcomes_system::<WorkflowStepCompleted>,
+                        resolve_command_outcomes_system::<WorkflowCompleted>,
+                        resolve_command_outcomes_system::<WorkflowPaused>,
+                        resolve_command_outcomes_system::<WorkflowResumed>,
+                        resolve_command_outcomes_system::<WorkflowCancelled>,
+                        resolve_command_outcomes_system::<WorkflowStepRetried>,
+                        resolve_command_outcomes_system::<CredentialExported>,
+                        resolve_command_outcomes_system::<CredentialImported>,
+                        resolve_command_outcomes_system::<DidPublished>,
+                        resolve_command_outcomes_system::<DidKeyRotated>,
+                        resolve_command_outcomes_system::<SessionStarted>,
+                    ),
+                    (
+                        resolve_command_outcomes_system::<ClaimAdded>,
+                        resolve_command_outcomes_system::<ClaimUpdated>,
+                        resolve_command_outcomes_system::<ClaimRevoked>,
+                        resolve_command_outcomes_system::<VerificationStarted>,
+                        resolve_command_outcomes_system::<VerificationCompleted>,
+                        resolve_command_outcomes_system::<VerificationCodeSent>,
+                        resolve_command_outcomes_system::<ClaimVerified>,
+                        resolve_command_outcomes_system::<ApiKeyIssued>,
+                        resolve_command_outcomes_system::<ApiKeyRotated>,
+                        resolve_command_outcomes_system::<ApiKeyRevoked>,
+                        resolve_command_outcomes_system::<ApiKeyAuthenticated>,
+                        resolve_command_outcomes_system::<KeyGenerated>,
+                        resolve_command_outcomes_system::<KeyImported>,
+                        resolve_command_outcomes_system::<KeyRotated>,
+                        resolve_command_outcomes_system::<KeyRevoked>,
+                        resolve_command_outcomes_system::<IdentityTokenIssued>,
+                        resolve_command_outcomes_system::<IdentityTokenValidated>,
+                        resolve_command_outcomes_system::<ProjectionCreated>,
+                        resolve_command_outcomes_system::<ProjectionsSynced>,
+                    ),
+                    (
+                        resolve_command_outcomes_system::<PasswordSet>,
+                        resolve_command_outcomes_system::<AuthenticationSucceeded>,
+                        resolve_command_outcomes_system::<MfaEnabled>,
+                        resolve_command_outcomes_system::<MfaChallengeIssued>,
+                        resolve_command_outcomes_system::<MfaVerified>,
+                        resolve_command_outcomes_system::<AuthenticationAttemptStarted>,
+                        resolve_command_outcomes_system::<SuspiciousAuthenticationDetected>,
+                        resolve_command_outcomes_system::<SessionRefreshed>,
+                        resolve_command_outcomes_system::<SessionEnded>,
+                        resolve_command_outcomes_system::<SessionsRevoked>,
+                    ),
+                )
+                    .chain(),
+                (
+                    (
+                        resolve_command_rejections_system::<CreateIdentityCommand>,
+                        resolve_command_rejections_system::<UpdateIdentityCommand>,
+                        resolve_command_rejections_system::<MergeIdentitiesCommand>,
+                        resolve_command_rejections_system::<ArchiveIdentityCommand>,
+                        resolve_command_rejections_system::<AddClaimCommand>,
+                        resolve_command_rejections_system::<UpdateClaimCommand>,
+                        resolve_command_rejections_system::<RevokeClaimCommand>,
+                        resolve_command_rejections_system::<EstablishRelationshipCommand>,
+                        resolve_command_rejections_system::<ValidateRelationshipCommand>,
+                        resolve_command_rejections_system::<RevokeRelationshipCommand>,
+                        resolve_command_rejections_system::<TraverseRelationshipsCommand>,
+                    ),
+                    (
+                        resolve_command_rejections_system::<StartWorkflowCommand>,
+                        resolve_command_rejections_system::<ProcessWorkflowStepCommand>,
+                        resolve_command_rejections_system::<CompleteWorkflowCommand>,
+                        resolve_command_rejections_system::<TimeoutWorkflowCommand>,
+                        resolve_command_rejections_system::<ApproveWorkflowStepCommand>,
+                        resolve_command_rejections_system::<RejectWorkflowStepCommand>,
+                        resolve_command_rejections_system::<PauseWorkflowCommand>,
+                        resolve_command_rejections_system::<ResumeWorkflowCommand>,
+                        resolve_command_rejections_system::<CancelWorkflowCommand>,
+                        resolve_command_rejections_system::<RetryWorkflowStepCommand>,
+                        resolve_command_rejections_system::<StartVerificationCommand>,
+                        resolve_command_rejections_system::<ProcessVerificationCommand>,
+                        resolve_command_rejections_system::<CompleteVerificationCommand>,
+                        resolve_command_rejections_system::<SendVerificationCodeCommand>,
+                        resolve_command_rejections_system::<ConfirmVerificationCodeCommand>,
+                    ),
+                    (
+                        resolve_command_rejections_system::<IssueApiKeyCommand>,
+                        resolve_command_rejections_system::<RotateApiKeyCommand>,
+                        resolve_command_rejections_system::<RevokeApiKeyCommand>,
+                        resolve_command_rejections_system::<AuthenticateApiKeyCommand>,
+                        resolve_command_rejections_system::<GenerateKeyCommand>,
+                        resolve_command_rejections_system::<ImportKeyCommand>,
+                        resolve_command_rejections_system::<RotateKeyCommand>,
+                        resolve_command_rejections_system::<RevokeKeyCommand>,
+                        resolve_command_rejections_system::<IssueIdentityTokenCommand>,
+                        resolve_command_rejections_system::<ValidateIdentityTokenCommand>,
+                        resolve_command_rejections_system::<ExportCredentialCommand>,
+                        resolve_command_rejections_system::<ImportCredentialCommand>,
+                        resolve_command_rejections_system::<PublishDidCommand>,
+                        resolve_command_rejections_system::<RotateDidKeyCommand>,
+                        resolve_command_rejections_system::<CreateProjectionCommand>,
+                        resolve_command_rejections_system::<SyncProjectionsCommand>,
+                    ),
+                    (
+                        resolve_command_rejections_system::<SetPasswordCommand>,
+                        resolve_command_rejections_system::<AuthenticatePasswordCommand>,
+                        resolve_command_rejections_system::<EnableMfaCommand>,
+                        resolve_command_rejections_system::<IssueMfaChallengeCommand>,
+                        resolve_command_rejections_system::<VerifyMfaCommand>,
+                        resolve_command_rejections_system::<BeginAuthenticationCommand>,
+                        resolve_command_rejections_system::<SubmitAuthenticationFactorCommand>,
+                        resolve_command_rejections_system::<StartSessionCommand>,
+                        resolve_command_rejections_system::<RefreshSessionCommand>,
+                        resolve_command_rejections_system::<RevokeSessionCommand>,
+                        resolve_command_rejections_system::<RevokeAllSessionsCommand>,
+                    ),
+                ),
+                expire_command_outcomes_system,
+            )
+                .chain()
+                .after(IdentitySet::Expiry),
+        );
+    }
+}
+
+/// Register the envelope of every identity command
+fn register_commands(app: &mut App) {
+    app.add_event::<CommandEnvelope<CreateIdentityCommand>>()
+        .add_event::<CommandEnvelope<UpdateIdentityCommand>>()
+        .add_event::<CommandEnvelope<MergeIdentitiesCommand>>()
+        .add_event::<CommandEnvelope<ArchiveIdentityCommand>>()
+        .add_event::<CommandEnvelope<AddClaimCommand>>()
+        .add_event::<CommandEnvelope<UpdateClaimCommand>>()
+        .add_event::<CommandEnvelope<RevokeClaimCommand>>()
+        .add_event::<CommandEnvelope<EstablishRelationshipCommand>>()
+        .add_event::<CommandEnvelope<ValidateRelationshipCommand>>()
+        .add_event::<CommandEnvelope<RevokeRelationshipCommand>>()
+        .add_event::<CommandEnvelope<TraverseRelationshipsCommand>>()
+        .add_event::<CommandEnvelope<StartWorkflowCommand>>()
+        .add_event::<CommandEnvelope<ProcessWorkflowStepCommand>>()
+        .add_event::<CommandEnvelope<CompleteWorkflowCommand>>()
+        .add_event::<CommandEnvelope<TimeoutWorkflowCommand>>()
+        .add_event::<CommandEnvelope<ApproveWorkflowStepCommand>>()
+        .add_event::<CommandEnvelope<RejectWorkflowStepCommand>>()
+        .add_event::<CommandEnvelope<PauseWorkflowCommand>>()
+        .add_event::<CommandEnvelope<ResumeWorkflowCommand>>()
+        .add_event::<CommandEnvelope<CancelWorkflowCommand>>()
+        .add_event::<CommandEnvelope<RetryWorkflowStepCommand>>()
+        .add_event::<CommandEnvelope<StartVerificationCommand>>()
+        .add_event::<CommandEnvelope<ProcessVerificationCommand>>()
+        .add_event::<CommandEnvelope<CompleteVerificationCommand>>()
+        .add_event::<CommandEnvelope<SendVerificationCodeCommand>>()
+        .add_event::<CommandEnvelope<ConfirmVerificationCodeCommand>>()
+        .add_event::<CommandEnvelope<IssueApiKeyCommand>>()
+        .add_event::<CommandEnvelope<RotateApiKeyCommand>>()
+        .add_event::<CommandEnvelope<RevokeApiKeyCommand>>()
+        .add_event::<CommandEnvelope<AuthenticateApiKeyCommand>>()
+        .add_event::<CommandEnvelope<SetPasswordCommand>>()
+        .add_event::<CommandEnvelope<AuthenticatePasswordCommand>>()
+        .add_event::<CommandEnvelope<EnableMfaCommand>>()
+        .add_event::<CommandEnvelope<IssueMfaChallengeCommand>>()
+        .add_event::<CommandEnvelope<VerifyMfaCommand>>()
+        .add_event::<CommandEnvelope<BeginAuthenticationCommand>>()
+        .add_event::<CommandEnvelope<SubmitAuthenticationFactorCommand>>()
+        .add_event::<CommandEnvelope<StartSessionCommand>>()
+        .add_event::<CommandEnvelope<RefreshSessionCommand>>()
+        .add_event::<CommandEnvelope<RevokeSessionCommand>>()
+        .add_event::<CommandEnvelope<RevokeAllSessionsCommand>>()
+        .add_event::<CommandEnvelope<GenerateKeyCommand>>()
+        .add_event::<CommandEnvelope<ImportKeyCommand>>()
+        .add_event::<CommandEnvelope<RotateKeyCommand>>()
+        .add_event::<CommandEnvelope<RevokeKeyCommand>>()
+        .add_event::<CommandEnvelope<IssueIdentityTokenCommand>>()
+        .add_event::<CommandEnvelope<ValidateIdentityTokenCommand>>()
+        .add_event::<CommandEnvelope<ExportCredentialCommand>>()
+        .add_event::<CommandEnvelope<ImportCredentialCommand>>()
+        .add_event::<CommandEnvelope<PublishDidCommand>>()
+        .add_event::<CommandEnvelope<RotateDidKeyCommand>>()
+        .add_event::<CommandEnvelope<CreateProjectionCommand>>()
+        .add_event::<CommandEnvelope<SyncProjectionsCommand>>();
+}
+
+/// Register every event type of the identity domain
+fn register_events(app: &mut App) {
+    app.add_event::<IdentityCreated>()
+        .add_event::<IdentityUpdated>()
+        .add_event::<IdentitiesMerged>()
+        .add_event::<IdentityArchived>()
+        .add_event::<ClaimAdded>()
+        .add_event::<ClaimUpdated>()
+        .add_event::<ClaimRevoked>()
+        .add_event::<ClaimExpired>()
+        .add_event::<VerificationLevelDowngraded>()
+        .add_event::<RelationshipEstablished>()
+        .add_event::<RelationshipValidated>()
+        .add_event::<RelationshipExpired>()
+        .add_event::<RelationshipsTraversed>()
+        .add_event::<RelationshipRevoked>()
+        .add_event::<WorkflowStarted>()
+        .add_event::<WorkflowStepCompleted>()
+        .add_event::<WorkflowCompleted>()
+        .add_event::<WorkflowTimedOut>()
+        .add_event::<WorkflowPaused>()
+        .add_event::<WorkflowResumed>()
+        .add_event::<WorkflowCancelled>()
+        .add_event::<WorkflowStepRetried>()
+        .add_event::<ApprovalRequested>()
+        .add_event::<ApprovalDecided>()
+        .add_event::<ApprovalEscalated>()
+        .add_event::<VerificationStarted>()
+        .add_event::<VerificationCompleted>()
+        .add_event::<VerificationCodeClaimConfirmed>()
+        .add_event::<VerificationCodeSent>()
+        .add_event::<VerificationCodeFailed>()
+        .add_event::<ClaimVerified>()
+        .add_event::<ApiKeyIssued>()
+        .add_event::<ApiKeyRotated>()
+        .add_event::<ApiKeyRevoked>()
+        .add_event::<ApiKeyAuthenticated>()
+        .add_event::<PasswordSet>()
+        .add_event::<PasswordRehashed>()
+        .add_event::<AuthenticationSucceeded>()
+        .add_event::<AuthenticationFailed>()
+        .add_event::<AccountLocked>()
+        .add_event::<AccountUnlocked>()
+        .add_event::<MfaEnabled>()
+        .add_event::<MfaChallengeIssued>()
+        .add_event::<MfaVerified>()
+        .add_event::<MfaFailed>()
+        .add_event::<AuthenticationAttemptStarted>()
+        .add_event::<SuspiciousAuthenticationDetected>()
+        .add_event::<AuthenticationChallengeIssued>()
+        .add_event::<AuthenticationDecided>()
+        .add_event::<SessionStarted>()
+        .add_event::<SessionRefreshed>()
+        .add_event::<SessionEnded>()
+        .add_event::<SessionsRevoked>()
+        .add_event::<KeyGenerated>()
+        .add_event::<KeyImported>()
+        .add_event::<KeyRotated>()
+        .add_event::<KeyRevoked>()
+        .add_event::<IdentityTokenIssued>()
+        .add_event::<IdentityTokenValidated>()
+        .add_event::<CredentialExported>()
+        .add_event::<CredentialImported>()
+        .add_event::<DidPublished>()
+        .add_event::<DidKeyRotated>()
+        .add_event::<ProjectionCreated>()
+        .add_event::<ProjectionsSynced>()
+        .add_event::<IdentityLinkedToPerson>()
+        .add_event::<IdentityLinkedToOrganization>()
+        .add_event::<IdentityAuthenticationRequested>();
+}
+
+/// Register the rejection event of every identity command
+fn register_rejections(app: &mut App) {
+    app.add_event::<IdentityCreationRejected>()
+        .add_event::<IdentityUpdateRejected>()
+        .add_event::<IdentityMergeRejected>()
+        .add_event::<IdentityArchiveRejected>()
+        .add_event::<ClaimAdditionRejected>()
+        .add_event::<ClaimUpdateRejected>()
+        .add_event::<ClaimRevocationRejected>()
+        .add_event::<RelationshipEstablishmentRejected>()
+        .add_event::<RelationshipValidationRejected>()
+        .add_event::<RelationshipRevocationRejected>()
+        .add_event::<RelationshipTraversalRejected>()
+        .add_event::<WorkflowStartRejected>()
+        .add_event::<WorkflowStepRejected>()
+        .add_event::<WorkflowCompletionRejected>()
+        .add_event::<WorkflowTimeoutRejected>()
+        .add_event::<WorkflowStepApprovalRejected>()
+        .add_event::<WorkflowStepRejectionRejected>()
+        .add_event::<WorkflowPauseRejected>()
+        .add_event::<WorkflowResumeRejected>()
+        .add_event::<WorkflowCancellationRejected>()
+        .add_event::<WorkflowStepRetryRejected>()
+        .add_event::<VerificationStartRejected>()
+        .add_event::<VerificationProcessingRejected>()
+        .add_event::<VerificationCompletionRejected>()
+        .add_event::<VerificationCodeSendRejected>()
+        .add_event::<VerificationCodeConfirmationRejected>()
+        .add_event::<ApiKeyIssueRejected>()
+        .add_event::<ApiKeyRotationRejected>()
+        .add_event::<ApiKeyRevocationRejected>()
+        .add_event::<ApiKeyAuthenticationRejected>()
+        .add_event::<PasswordSetRejected>()
+        .add_event::<PasswordAuthenticationRejected>()
+        .add_event::<MfaEnableRejected>()
+        .add_event::<MfaChallengeRejected>()
+        .add_event::<MfaVerificationRejected>()
+        .add_event::<AuthenticationBeginRejected>()
+        .add_event::<AuthenticationFactorRejected>()
+        .add_event::<SessionStartRejected>()
+        .add_event::<SessionRefreshRejected>()
+        .add_event::<SessionRevocationRejected>()
+        .add_event::<SessionsRevocationRejected>()
+        .add_event::<KeyGenerationRejected>()
+        .add_event::<KeyImportRejected>()
+        .add_event::<KeyRotationRejected>()
+        .add_event::<KeyRevocationRejected>()
+        .add_event::<IdentityTokenIssueRejected>()
+        .add_event::<IdentityTokenValidationRejected>()
+        .add_event::<CredentialExportRejected>()
+        .add_event::<CredentialImportRejected>()
+        .add_event::<DidPublicationRejected>()
+        .add_event::<DidKeyRotationRejected>()
+        .add_event::<ProjectionCreationRejected>()
+        .add_event::<ProjectionSyncRejected>();
+}
@@ -9,12 +9,14 @@ use uuid::Uuid;
 
 use crate::{
     aggregate::{AggregateState, IdentityAggregate},
+    authentication::{AuthenticationClock, AuthenticationPolicy},
     components::{
-        ClaimType, IdentityClaim, IdentityEntity, IdentityId, IdentityMetadata,
-        IdentityRelationship, IdentityStatus, IdentityType, IdentityVerification, IdentityWorkflow,
-        ProjectionType, RelationshipId, RelationshipType, VerificationLevel,
-        WorkflowStatus, WorkflowType,
+        ApprovalTask, ClaimType, IdentityClaims, IdentityEntity, IdentityId, IdentityMetadata,
+        IdentityRelationship, IdentitySessions, IdentityStatus, IdentityType,
+        IdentityVerification, IdentityWorkflow, ProjectionType, RelationshipId, RelationshipType,
+        SessionRecord, VerificationLevel, WorkflowStatus, WorkflowType,
     },
+    IdentityIndex,
 };
 
 /// Query to find an identity by ID
@@ -153,6 +155,67 @@ pub fn find_active_workflows_for_identity(
     results
 }
 
+/// Query to find the approval steps awaiting a decision by an approver
+///
+/// Steps of paused workflows are left out until the workflow resumes.
+pub fn find_pending_approvals(world: &mut World, approver: IdentityId) -> Vec<ApprovalTask> {
+    let mut query = world.query::<(&IdentityWorkflow, &ApprovalTask)>();
+
+    query
+        .iter(world)
+        .filter(|(workflow, task)| {
+            workflow.status != WorkflowStatus::Paused
+                && workflow.running_step().as_ref() == Some(&task.step_id)
+                && task.awaits(approver)
+        })
+        .map(|(_, task)| task.clone())
+        .collect()
+}
+
+/// Query to list the live sessions of an identity, most recently used first
+///
+/// Sessions past their idle or absolute deadline are left out, measured by
+/// the world's `AuthenticationClock` and `AuthenticationPolicy`.
+pub fn find_active_sessions(world: &mut World, identity_id: IdentityId) -> Vec<SessionRecord> {
+    let now = world
+        .get_resource::<AuthenticationClock>()
+        .cloned()
+        .unwrap_or_default()
+        .now();
+    let policy = world
+        .get_resource::<AuthenticationPolicy>()
+        .map(|policy| policy.session)
+        .unwrap_or_default();
+
+    let Some(sessions) = world
+        .get_resource::<IdentityIndex>()
+        .and_then(|index| index.identity(identity_id))
+        .and_then(|entity| world.get::<IdentitySessions>(entity))
+    else {
+        return Vec::new();
+    };
+
+    let mut active: Vec<SessionRecord> = sessions
+        .sessions
+        .iter()
+        .filter(|session| session.is_active(now, &policy))
+        .cloned()
+        .collect();
+    active.sort_by(|a, b| b.last_seen_at.cmp(&a.last_seen_at));
+    active
+}
+
+/// Query to find the live session of an identity on a device
+pub fn find_device_session(
+    world: &mut World,
+    identity_id: IdentityId,
+    device_id: &str,
+) -> Option<SessionRecord> {
+    find_active_sessions(world, identity_id)
+        .into_iter()
+        .find(|session| session.device.device_id.as_deref() == Some(device_id))
+}
+
 /// Query to get aggregate state for an identity
 pub fn get_aggregate_state(world: &mut World, identity_id: IdentityId) -> Option<AggregateState> {
     // Find identity
@@ -219,10 +282,10 @@ pub fn find_identities_by_claim(
     value: &str,
 ) -> Vec<IdentityId> {
     let mut results = Vec::new();
-    let mut query = world.query::<(&IdentityEntity, &IdentityClaim)>();
+    let mut query = world.query::<(&IdentityEntity, &IdentityClaims)>();
 
-    for (identity, claim) in query.iter(world) {
-        if claim.claim_type == claim_type && claim.value == value {
+    for (identity, claims) in query.iter(world) {
+        if claims.find(&claim_type, value).is_some() {
             results.push(identity.identity_id);
         }
     }
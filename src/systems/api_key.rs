@@ -0,0 +1,287 @@
+//! API key systems for service and system identities
+
+use crate::{
+    aggregate::IdentityAggregate, commands::*, components::*, events::*,
+    persistence::DomainEventWriter, IdentityError, IdentityIndex,
+};
+use bevy::ecs::prelude::*;
+use std::collections::HashMap;
+
+/// System to issue API keys
+pub fn issue_api_key_system(
+    mut commands: Commands,
+    mut events: EventReader<CommandEnvelope<IssueApiKeyCommand>>,
+    mut issued_events: DomainEventWriter<ApiKeyIssued>,
+    mut rejected_events: EventWriter<ApiKeyIssueRejected>,
+    mut identities: Query<(&IdentityEntity, Option<&mut IdentityApiKeys>)>,
+    mut index: ResMut<IdentityIndex>,
+) {
+    // Key components inserted this frame, so several keys issued to a new
+    // holder in the same frame are all kept
+    let mut new_holders: HashMap<Entity, IdentityApiKeys> = HashMap::new();
+
+    for envelope in events.read() {
+        let event = &envelope.command;
+
+        let Some((entity, (identity, keys))) = index
+            .identity(event.identity_id)
+            .and_then(|entity| identities.get_mut(entity).ok().map(|found| (entity, found)))
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::IdentityNotFound(event.identity_id),
+            ));
+            continue;
+        };
+
+        if let Err(e) = IdentityAggregate::validate_api_key_holder(identity) {
+            rejected_events.write(CommandRejected::new(envelope, e));
+            continue;
+        }
+
+        let Some(key_id) = event.key.key_id() else {
+            rejected_events.write(CommandRejected::new(envelope, IdentityError::InvalidApiKey));
+            continue;
+        };
+        if index.api_key(key_id).is_some()
+            || new_holders.values().any(|keys| keys.key(key_id).is_some())
+        {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::InvalidOperation(format!("API key id already in use: {key_id}")),
+            ));
+            continue;
+        }
+
+        let now = chrono::Utc::now();
+        let record = ApiKeyRecord {
+            key_id,
+            name: event.name.clone(),
+            key_hash: event.key.hash(),
+            permissions: event.permissions.clone(),
+            created_at: now,
+            expires_at: event.expires_at,
+            last_used: None,
+            revoked_at: None,
+        };
+
+        match keys {
+            Some(mut keys) => {
+                keys.keys.push(record.clone());
+                index.record_api_key(key_id, entity);
+            }
+            None => new_holders.entry(entity).or_default().keys.push(record.clone()),
+        }
+
+        issued_events.write(ApiKeyIssued {
+            identity_id: event.identity_id,
+            key_id,
+            name: record.name,
+            key_hash: record.key_hash,
+            permissions: record.permissions,
+            expires_at: record.expires_at,
+            issued_by: event.issued_by,
+            issued_at: now,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        });
+    }
+
+    for (entity, keys) in new_holders {
+        commands.entity(entity).insert(keys);
+    }
+}
+
+/// System to replace API keys with new ones
+pub fn rotate_api_key_system(
+    mut events: EventReader<CommandEnvelope<RotateApiKeyCommand>>,
+    mut rotated_events: DomainEventWriter<ApiKeyRotated>,
+    mut rejected_events: EventWriter<ApiKeyRotationRejected>,
+    mut identities: Query<(&IdentityEntity, &mut IdentityApiKeys)>,
+    mut index: ResMut<IdentityIndex>,
+) {
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = chrono::Utc::now();
+
+        let Some((entity, (identity, mut keys))) = index
+            .identity(event.identity_id)
+            .and_then(|entity| identities.get_mut(entity).ok().map(|found| (entity, found)))
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::ApiKeyNotFound(event.key_id),
+            ));
+            continue;
+        };
+
+        if let Err(e) = IdentityAggregate::validate_api_key_holder(identity) {
+            rejected_events.write(CommandRejected::new(envelope, e));
+            continue;
+        }
+
+        let Some(old_key) = keys.key(event.key_id).cloned() else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::ApiKeyNotFound(event.key_id),
+            ));
+            continue;
+        };
+        if !old_key.is_usable(now) {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::ApiKeyInactive(event.key_id),
+            ));
+            continue;
+        }
+
+        let Some(new_key_id) = event.new_key.key_id() else {
+            rejected_events.write(CommandRejected::new(envelope, IdentityError::InvalidApiKey));
+            continue;
+        };
+        if index.api_key(new_key_id).is_some() {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::InvalidOperation(format!(
+                    "API key id already in use: {new_key_id}"
+                )),
+            ));
+            continue;
+        }
+
+        // The old key either stops working now or at the end of the grace period
+        let old_key_valid_until = match event.old_key_valid_until {
+            Some(until) if until > now => old_key.expires_at.map_or(until, |e| e.min(until)),
+            _ => now,
+        };
+        if let Some(old) = keys.key_mut(event.key_id) {
+            if old_key_valid_until > now {
+                old.expires_at = Some(old_key_valid_until);
+            } else {
+                old.revoked_at = Some(now);
+            }
+        }
+
+        let new_record = ApiKeyRecord {
+            key_id: new_key_id,
+            key_hash: event.new_key.hash(),
+            created_at: now,
+            last_used: None,
+            revoked_at: None,
+            ..old_key
+        };
+        keys.keys.push(new_record.clone());
+        index.record_api_key(new_key_id, entity);
+
+        rotated_events.write(ApiKeyRotated {
+            identity_id: event.identity_id,
+            old_key_id: event.key_id,
+            new_key_id,
+            new_key_hash: new_record.key_hash,
+            old_key_valid_until,
+            rotated_by: event.rotated_by,
+            rotated_at: now,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        });
+    }
+}
+
+/// System to revoke API keys
+pub fn revoke_api_key_system(
+    mut events: EventReader<CommandEnvelope<RevokeApiKeyCommand>>,
+    mut revoked_events: DomainEventWriter<ApiKeyRevoked>,
+    mut rejected_events: EventWriter<ApiKeyRevocationRejected>,
+    mut identities: Query<&mut IdentityApiKeys>,
+    index: Res<IdentityIndex>,
+) {
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = chrono::Utc::now();
+
+        let Some(mut keys) = index
+            .identity(event.identity_id)
+            .and_then(|entity| identities.get_mut(entity).ok())
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::ApiKeyNotFound(event.key_id),
+            ));
+            continue;
+        };
+
+        let Some(key) = keys.key_mut(event.key_id) else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::ApiKeyNotFound(event.key_id),
+            ));
+            continue;
+        };
+        if key.revoked_at.is_some() {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::ApiKeyInactive(event.key_id),
+            ));
+            continue;
+        }
+
+        key.revoked_at = Some(now);
+
+        revoked_events.write(ApiKeyRevoked {
+            identity_id: event.identity_id,
+            key_id: event.key_id,
+            revoked_by: event.revoked_by,
+            revoked_at: now,
+            reason: event.reason.clone(),
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        });
+    }
+}
+
+/// System to authenticate callers by API key
+pub fn authenticate_api_key_system(
+    mut events: EventReader<CommandEnvelope<AuthenticateApiKeyCommand>>,
+    mut authenticated_events: DomainEventWriter<ApiKeyAuthenticated>,
+    mut rejected_events: EventWriter<ApiKeyAuthenticationRejected>,
+    mut identities: Query<(&IdentityEntity, &mut IdentityApiKeys)>,
+    index: Res<IdentityIndex>,
+) {
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = chrono::Utc::now();
+
+        // Unknown, malformed and mismatching keys are indistinguishable to the caller
+        let Some((key_id, (identity, mut keys))) = event.key.key_id().and_then(|key_id| {
+            index
+                .api_key(key_id)
+                .and_then(|entity| identities.get_mut(entity).ok())
+                .map(|found| (key_id, found))
+        }) else {
+            rejected_events.write(CommandRejected::new(envelope, IdentityError::InvalidApiKey));
+            continue;
+        };
+        let Some(key) = keys.key_mut(key_id).filter(|key| key.matches(&event.key)) else {
+            rejected_events.write(CommandRejected::new(envelope, IdentityError::InvalidApiKey));
+            continue;
+        };
+
+        if let Err(e) =
+            IdentityAggregate::validate_api_key_use(identity, key, &event.required_permissions, now)
+        {
+            rejected_events.write(CommandRejected::new(envelope, e));
+            continue;
+        }
+
+        key.last_used = Some(now);
+
+        authenticated_events.write(ApiKeyAuthenticated {
+            identity_id: identity.identity_id,
+            key_id,
+            granted_permissions: event.required_permissions.clone(),
+            authenticated_at: now,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        });
+    }
+}
@@ -0,0 +1,336 @@
+//! Workflow approval systems
+
+use super::workflow::record_step;
+use crate::{
+    commands::*, components::*, events::*, persistence::DomainEventWriter,
+    workflows::identity_scope, IdentityError, IdentityIndex, IdentityResult,
+};
+use bevy::ecs::prelude::*;
+use std::collections::HashMap;
+use tracing::trace;
+use uuid::Uuid;
+
+/// System to assign approvers to approval steps as they become active
+///
+/// Approvers named by relationship are resolved once, when the step
+/// activates. The workflow's own identity is never an approver.
+pub fn assign_approvals_system(
+    mut commands: Commands,
+    workflows: Query<(Entity, &IdentityWorkflow, Option<&ApprovalTask>), Changed<IdentityWorkflow>>,
+    relationships: Query<&IdentityRelationship>,
+    index: Res<IdentityIndex>,
+    mut requested_events: DomainEventWriter<ApprovalRequested>,
+) {
+    let now = chrono::Utc::now();
+
+    for (entity, workflow, task) in workflows.iter() {
+        if workflow.is_finished() {
+            continue;
+        }
+        let Some(step) = workflow.active_step() else {
+            continue;
+        };
+        let (Some(policy), Some(started_at)) = (&step.approval, step.started_at) else {
+            continue;
+        };
+        // The task of this activation was assigned already
+        if task.is_some_and(|t| t.step_id == step.step_id && t.requested_at >= started_at) {
+            continue;
+        }
+
+        let approvers = resolve_approvers(
+            &policy.approvers,
+            workflow.identity_id,
+            &index,
+            &relationships,
+        );
+        trace!(
+            "Step {} of workflow {} assigned to {} approvers",
+            step.step_id,
+            workflow.workflow_id,
+            approvers.len()
+        );
+
+        requested_events.write(ApprovalRequested {
+            workflow_id: workflow.workflow_id,
+            identity_id: workflow.identity_id,
+            step_id: step.step_id.clone(),
+            approvers: approvers.clone(),
+            quorum: policy.quorum,
+            requested_at: now,
+            correlation_id: Uuid::new_v4(),
+            causation_id: None,
+        });
+        commands.entity(entity).insert(ApprovalTask {
+            workflow_id: workflow.workflow_id,
+            identity_id: workflow.identity_id,
+            step_id: step.step_id.clone(),
+            approvers,
+            quorum: policy.quorum,
+            decisions: Vec::new(),
+            requested_at: now,
+            escalated_at: None,
+        });
+    }
+}
+
+/// System to record approval decisions and advance decided approval steps
+///
+/// Each assigned approver decides once. The step completes once the quorum
+/// approves it and fails once the quorum can no longer be reached; the
+/// workflow then moves on along its transitions with
+/// `{"approved": bool, "decisions": [..]}` as the step output. Every decision
+/// is kept in the workflow's history.
+#[allow(clippy::too_many_arguments)]
+pub fn decide_approval_system(
+    mut commands: Commands,
+    mut approvals: EventReader<CommandEnvelope<ApproveWorkflowStepCommand>>,
+    mut rejections: EventReader<CommandEnvelope<RejectWorkflowStepCommand>>,
+    mut workflows: Query<(
+        &mut IdentityWorkflow,
+        Option<&mut ApprovalTask>,
+        Option<&mut WorkflowHistory>,
+    )>,
+    identities: Query<(
+        &IdentityEntity,
+        Option<&IdentityVerification>,
+        Option<&IdentityClaims>,
+    )>,
+    index: Res<IdentityIndex>,
+    mut decided_events: DomainEventWriter<ApprovalDecided>,
+    mut step_events: DomainEventWriter<WorkflowStepCompleted>,
+    mut approval_rejected: EventWriter<WorkflowStepApprovalRejected>,
+    mut rejection_rejected: EventWriter<WorkflowStepRejectionRejected>,
+) {
+    // Histories inserted this frame for workflows started without one
+    let mut new_histories: HashMap<Entity, WorkflowHistory> = HashMap::new();
+
+    let mut decide = |workflow_id: Uuid,
+                      decision: ApprovalDecision,
+                      correlation_id: Uuid,
+                      command_id: Uuid|
+     -> IdentityResult<()> {
+        let Some((entity, (mut workflow, task, history))) = index
+            .workflow(workflow_id)
+            .and_then(|entity| workflows.get_mut(entity).ok().map(|found| (entity, found)))
+        else {
+            return Err(IdentityError::WorkflowError(format!(
+                "Workflow not found: {workflow_id}"
+            )));
+        };
+        if workflow.status == WorkflowStatus::Paused {
+            return Err(IdentityError::WorkflowError(
+                "Workflow is paused".to_string(),
+            ));
+        }
+        let awaiting = workflow.running_step().as_ref() == Some(&decision.step_id);
+        let Some(mut task) = task.filter(|t| awaiting && t.step_id == decision.step_id) else {
+            return Err(IdentityError::WorkflowError(format!(
+                "Step {} is not awaiting approval",
+                decision.step_id
+            )));
+        };
+        if !task.approvers.contains(&decision.approver) {
+            return Err(IdentityError::PermissionDenied(format!(
+                "{} is not an approver of step {}",
+                decision.approver, decision.step_id
+            )));
+        }
+        if !task.awaits(decision.approver) {
+            return Err(IdentityError::InvalidOperation(format!(
+                "{} has already decided step {}",
+                decision.approver, decision.step_id
+            )));
+        }
+
+        let mut decided = task.clone();
+        decided.decisions.push(decision.clone());
+        let status = decided.status();
+        let completed = if status == ApprovalStatus::Pending {
+            None
+        } else {
+            let outcome = match status {
+                ApprovalStatus::Approved => StepOutcome::Completed,
+                _ => StepOutcome::Failed("Approval rejected".to_string()),
+            };
+            let output = serde_json::json!({
+                "approved": status == ApprovalStatus::Approved,
+                "decisions": decided.decisions,
+            });
+            let identity = index
+                .identity(workflow.identity_id)
+                .and_then(|entity| identities.get(entity).ok())
+                .map_or(
+                    serde_json::Value::Null,
+                    |(identity, verification, claims)| {
+                        identity_scope(identity, verification, claims)
+                    },
+                );
+            let next_step =
+                workflow.finish_active_step(&outcome, &output, &identity, decision.decided_at)?;
+
+            Some(WorkflowStepCompleted {
+                workflow_id,
+                identity_id: workflow.identity_id,
+                workflow_type: workflow.workflow_type.clone(),
+                step_id: decision.step_id.clone(),
+                outcome,
+                next_step,
+                processed_by: Some(decision.approver),
+                step_data: output,
+                completed_at: decision.decided_at,
+                correlation_id,
+                causation_id: Some(command_id),
+            })
+        };
+        *task = decided;
+
+        decided_events.write(ApprovalDecided {
+            workflow_id,
+            identity_id: workflow.identity_id,
+            step_id: decision.step_id.clone(),
+            approver: decision.approver,
+            approved: decision.approved,
+            comment: decision.comment.clone(),
+            decided_at: decision.decided_at,
+            correlation_id,
+            causation_id: Some(command_id),
+        });
+
+        let history = match history {
+            Some(history) => history.into_inner(),
+            None => new_histories
+                .entry(entity)
+                .or_insert_with(|| WorkflowHistory::new(workflow_id)),
+        };
+        history.approvals.push(decision);
+        if let Some(completed) = completed {
+            record_step(history, &workflow, &completed);
+            step_events.write(completed);
+        }
+        Ok(())
+    };
+
+    for envelope in approvals.read() {
+        let event = &envelope.command;
+        let decision = ApprovalDecision {
+            step_id: event.step_name.clone(),
+            approver: event.approver,
+            approved: true,
+            comment: event.comment.clone(),
+            decided_at: chrono::Utc::now(),
+        };
+        if let Err(e) = decide(
+            *event.workflow_id.as_uuid(),
+            decision,
+            envelope.correlation_id,
+            envelope.command_id,
+        ) {
+            approval_rejected.write(CommandRejected::new(envelope, e));
+        }
+    }
+
+    for envelope in rejections.read() {
+        let event = &envelope.command;
+        let decision = ApprovalDecision {
+            step_id: event.step_name.clone(),
+            approver: event.approver,
+            approved: false,
+            comment: event.comment.clone(),
+            decided_at: chrono::Utc::now(),
+        };
+        if let Err(e) = decide(
+            *event.workflow_id.as_uuid(),
+            decision,
+            envelope.correlation_id,
+            envelope.command_id,
+        ) {
+            rejection_rejected.write(CommandRejected::new(envelope, e));
+        }
+    }
+
+    for (entity, history) in new_histories {
+        commands.entity(entity).insert(history);
+    }
+}
+
+/// System to escalate approval steps left undecided past their policy's deadline
+///
+/// Each task is escalated once, adding the approvers of the policy's
+/// `escalate_to` rules to those already assigned.
+pub fn escalate_approvals_system(
+    mut tasks: Query<(&IdentityWorkflow, &mut ApprovalTask)>,
+    relationships: Query<&IdentityRelationship>,
+    index: Res<IdentityIndex>,
+    mut escalated_events: DomainEventWriter<ApprovalEscalated>,
+) {
+    let now = chrono::Utc::now();
+
+    for (workflow, mut task) in tasks.iter_mut() {
+        if task.escalated_at.is_some()
+            || workflow.status == WorkflowStatus::Paused
+            || task.status() != ApprovalStatus::Pending
+            || workflow.running_step().as_ref() != Some(&task.step_id)
+        {
+            continue;
+        }
+        let Some(policy) = workflow.active_step().and_then(|s| s.approval.as_ref()) else {
+            continue;
+        };
+        let Some(escalate_after) = policy.escalate_after_seconds else {
+            continue;
+        };
+        if (now - task.requested_at).num_seconds() < escalate_after as i64 {
+            continue;
+        }
+
+        let added_approvers: Vec<IdentityId> = resolve_approvers(
+            &policy.escalate_to,
+            workflow.identity_id,
+            &index,
+            &relationships,
+        )
+        .into_iter()
+        .filter(|approver| !task.approvers.contains(approver))
+        .collect();
+        task.approvers.extend(added_approvers.iter().copied());
+        task.escalated_at = Some(now);
+
+        escalated_events.write(ApprovalEscalated {
+            workflow_id: workflow.workflow_id,
+            identity_id: workflow.identity_id,
+            step_id: task.step_id.clone(),
+            added_approvers,
+            escalated_at: now,
+            correlation_id: Uuid::new_v4(),
+            causation_id: None,
+        });
+    }
+}
+
+/// Identities assigned by `rules` to approve a step of `subject`'s workflow
+fn resolve_approvers(
+    rules: &[ApproverRule],
+    subject: IdentityId,
+    index: &IdentityIndex,
+    relationships: &Query<&IdentityRelationship>,
+) -> Vec<IdentityId> {
+    let mut approvers = Vec::new();
+    for rule in rules {
+        let assigned: Vec<IdentityId> = match rule {
+            ApproverRule::Identity(identity_id) => vec![*identity_id],
+            ApproverRule::Relationship(relationship_type) => index
+                .incoming(subject)
+                .filter_map(|entity| relationships.get(entity).ok())
+                .filter(|r| &r.relationship_type == relationship_type)
+                .map(|r| r.source_identity)
+                .collect(),
+        };
+        for approver in assigned {
+            if approver != subject && !approvers.contains(&approver) {
+                approvers.push(approver);
+            }
+        }
+    }
+    approvers
+}
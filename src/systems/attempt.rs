@@ -0,0 +1,359 @@
+//! Multi-factor authentication attempts for person identities
+//!
+//! The open attempt of an identity is held in [`IdentityCredentials`] and is
+//! only changed through the `record_*` functions below, which replay calls
+//! with the same events. Factors are checked like the single-factor
+//! commands, so wrong answers count towards the same lockout.
+//!
+//! The location of each attempt is scored by the [`RiskEngine`] of the
+//! [`AuthenticationPolicy`] against the identity's earlier granted attempts.
+
+use super::authentication::{
+    release_expired_lock, succeed_authentication, verify_password_factor, PasswordEventWriters,
+};
+use super::mfa::{check_mfa_code, MfaEventWriters};
+use crate::{
+    aggregate::IdentityAggregate, authentication::*, commands::*, components::*, events::*,
+    persistence::DomainEventWriter, IdentityError, IdentityIndex,
+};
+use bevy::ecs::prelude::*;
+use uuid::Uuid;
+
+/// System to start authentication attempts and challenge their first factor
+///
+/// Attempts scoring above `LocationRisk::Low` are reported as suspicious.
+/// Attempts of inactive or locked identities, and attempts needing a factor
+/// the identity has not enrolled or the client cannot present, are denied
+/// right away.
+#[allow(clippy::too_many_arguments)]
+pub fn begin_authentication_system(
+    mut events: EventReader<CommandEnvelope<BeginAuthenticationCommand>>,
+    mut started_events: DomainEventWriter<AuthenticationAttemptStarted>,
+    mut suspicious_events: DomainEventWriter<SuspiciousAuthenticationDetected>,
+    mut challenge_events: DomainEventWriter<AuthenticationChallengeIssued>,
+    mut decided_events: DomainEventWriter<AuthenticationDecided>,
+    mut rejected_events: EventWriter<AuthenticationBeginRejected>,
+    mut identities: Query<(
+        &IdentityEntity,
+        &mut IdentityCredentials,
+        Has<IdentityMfa>,
+        Option<&IdentityVerification>,
+    )>,
+    index: Res<IdentityIndex>,
+    policy: Res<AuthenticationPolicy>,
+    clock: Res<AuthenticationClock>,
+) {
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = clock.now();
+
+        // Unknown identities and identities without a password look alike
+        let Some((identity, mut credentials, mfa_enabled, verification)) = index
+            .identity(event.identity_id)
+            .and_then(|entity| identities.get_mut(entity).ok())
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::InvalidCredentials,
+            ));
+            continue;
+        };
+
+        let attempt_id = Uuid::new_v4();
+        let assessment = policy.risk.assess(
+            &credentials.history,
+            &LoginObservation::new(&event.location, now),
+        );
+        let location_risk = assessment.level;
+        if assessment.is_suspicious() {
+            suspicious_events.write(SuspiciousAuthenticationDetected {
+                identity_id: event.identity_id,
+                attempt_id,
+                assessment,
+                location: event.location.clone(),
+                detected_at: now,
+                correlation_id: envelope.correlation_id,
+                causation_id: Some(envelope.command_id),
+            });
+        }
+
+        let required = IdentityAggregate::validate_authentication(identity)
+            .and_then(|_| match credentials.locked_until {
+                Some(until) if now < until => Err(IdentityError::AccountLocked(until)),
+                _ => Ok(()),
+            })
+            .and_then(|_| {
+                let verification_level =
+                    verification.map_or(VerificationLevel::Unverified, |v| v.verification_level);
+                required_factors(verification_level, mfa_enabled, location_risk)
+            })
+            .and_then(|required| {
+                let missing = required
+                    .iter()
+                    .copied()
+                    .find(|factor| !event.available_factors.contains(factor));
+                match missing {
+                    Some(missing) => Err(IdentityError::FactorUnavailable(missing)),
+                    None => Ok(required),
+                }
+            });
+        let required = match required {
+            Ok(required) => required,
+            Err(e) => {
+                let decided = AuthenticationDecided {
+                    identity_id: event.identity_id,
+                    attempt_id,
+                    decision: AuthenticationDecision::Denied(e),
+                    factors_used: Vec::new(),
+                    location_risk,
+                    decided_at: now,
+                    correlation_id: envelope.correlation_id,
+                    causation_id: Some(envelope.command_id),
+                };
+                record_authentication_decided(&mut credentials, &decided);
+                decided_events.write(decided);
+                continue;
+            }
+        };
+
+        let issued = AuthenticationChallengeIssued {
+            identity_id: event.identity_id,
+            attempt_id,
+            challenge: AuthenticationChallenge::issue(required[0], now),
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        };
+        let started = AuthenticationAttemptStarted {
+            identity_id: event.identity_id,
+            attempt_id,
+            required_factors: required,
+            location_risk,
+            location: event.location.clone(),
+            started_at: now,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        };
+        record_attempt_started(&mut credentials, &started);
+        started_events.write(started);
+        record_authentication_challenge_issued(&mut credentials, &issued);
+        challenge_events.write(issued);
+    }
+}
+
+/// System to check the factors presented for authentication attempts
+///
+/// A right answer challenges the next factor or grants the attempt; a wrong
+/// answer or an expired challenge denies it. Failures count towards the
+/// lockout until an attempt is granted, so one right factor does not reset
+/// the count for the next.
+#[allow(clippy::too_many_arguments)]
+pub fn submit_authentication_factor_system(
+    mut events: EventReader<CommandEnvelope<SubmitAuthenticationFactorCommand>>,
+    mut challenge_events: DomainEventWriter<AuthenticationChallengeIssued>,
+    mut decided_events: DomainEventWriter<AuthenticationDecided>,
+    mut password_events: PasswordEventWriters,
+    mut mfa_events: MfaEventWriters,
+    mut rejected_events: EventWriter<AuthenticationFactorRejected>,
+    mut identities: Query<(
+        &IdentityEntity,
+        &mut IdentityCredentials,
+        Option<&mut IdentityMfa>,
+    )>,
+    index: Res<IdentityIndex>,
+    policy: Res<AuthenticationPolicy>,
+    clock: Res<AuthenticationClock>,
+) {
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = clock.now();
+        let ids = (envelope.correlation_id, Some(envelope.command_id));
+
+        let Some((identity, mut credentials, mut mfa)) = index
+            .identity(event.identity_id)
+            .and_then(|entity| identities.get_mut(entity).ok())
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::AuthenticationAttemptNotFound(event.attempt_id),
+            ));
+            continue;
+        };
+        let Some(attempt) = credentials
+            .attempt
+            .clone()
+            .filter(|attempt| attempt.attempt_id == event.attempt_id)
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::AuthenticationAttemptNotFound(event.attempt_id),
+            ));
+            continue;
+        };
+        let Some(challenge) = attempt
+            .challenge
+            .clone()
+            .filter(|challenge| challenge.challenge_id == event.challenge_id)
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::VerificationFailed(
+                    "Authentication challenge is not open".to_string(),
+                ),
+            ));
+            continue;
+        };
+        if event.response.factor() != challenge.factor {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::InvalidOperation(format!(
+                    "Challenge expects {:?}",
+                    challenge.factor
+                )),
+            ));
+            continue;
+        }
+
+        let result = IdentityAggregate::validate_authentication(identity)
+            .and_then(|_| {
+                if challenge.is_open(now) {
+                    Ok(())
+                } else {
+                    Err(IdentityError::VerificationFailed(
+                        "Authentication challenge has expired".to_string(),
+                    ))
+                }
+            })
+            .and_then(|_| {
+                release_expired_lock(
+                    &mut credentials,
+                    identity,
+                    now,
+                    ids,
+                    &mut password_events.lockout,
+                )
+            })
+            .and_then(|_| match (&event.response, mfa.as_deref_mut()) {
+                (FactorResponse::Password(password), _) => verify_password_factor(
+                    &mut credentials,
+                    identity,
+                    password,
+                    &policy,
+                    now,
+                    ids,
+                    &mut password_events,
+                ),
+                (FactorResponse::Code(code), Some(mfa)) => check_mfa_code(
+                    mfa,
+                    &mut credentials,
+                    identity,
+                    challenge.challenge_id,
+                    code,
+                    &policy,
+                    now,
+                    ids,
+                    &mut mfa_events,
+                    &mut password_events.lockout,
+                ),
+                (FactorResponse::Code(_), None) => {
+                    Err(IdentityError::FactorUnavailable(AuthFactor::Totp))
+                }
+            });
+
+        let mut factors_used = attempt.completed_factors.clone();
+        let decision = match result {
+            Ok(()) => {
+                factors_used.push(challenge.factor);
+                if let Some(factor) = attempt.factor_after_current() {
+                    let issued = AuthenticationChallengeIssued {
+                        identity_id: event.identity_id,
+                        attempt_id: attempt.attempt_id,
+                        challenge: AuthenticationChallenge::issue(factor, now),
+                        correlation_id: envelope.correlation_id,
+                        causation_id: Some(envelope.command_id),
+                    };
+                    record_authentication_challenge_issued(&mut credentials, &issued);
+                    challenge_events.write(issued);
+                    continue;
+                }
+                AuthenticationDecision::Granted
+            }
+            Err(e) => AuthenticationDecision::Denied(e),
+        };
+
+        let decided = AuthenticationDecided {
+            identity_id: event.identity_id,
+            attempt_id: attempt.attempt_id,
+            decision,
+            factors_used,
+            location_risk: attempt.location_risk,
+            decided_at: now,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        };
+        let granted = decided.decision == AuthenticationDecision::Granted;
+        record_authentication_decided(&mut credentials, &decided);
+        decided_events.write(decided);
+        // Failures are only cleared once every factor has been answered
+        if granted {
+            succeed_authentication(
+                &mut credentials,
+                identity,
+                Some(attempt.attempt_id),
+                now,
+                ids,
+                &mut password_events,
+            );
+        }
+    }
+}
+
+/// Open an attempt, replacing any earlier one
+pub(crate) fn record_attempt_started(
+    credentials: &mut IdentityCredentials,
+    event: &AuthenticationAttemptStarted,
+) {
+    credentials.attempt = Some(AuthenticationAttempt {
+        attempt_id: event.attempt_id,
+        required_factors: event.required_factors.clone(),
+        completed_factors: Vec::new(),
+        location_risk: event.location_risk,
+        location: event.location.clone(),
+        started_at: event.started_at,
+        challenge: None,
+    });
+}
+
+/// Challenge the next factor of an attempt, completing the one challenged before
+pub(crate) fn record_authentication_challenge_issued(
+    credentials: &mut IdentityCredentials,
+    event: &AuthenticationChallengeIssued,
+) {
+    let Some(attempt) = credentials
+        .attempt
+        .as_mut()
+        .filter(|attempt| attempt.attempt_id == event.attempt_id)
+    else {
+        return;
+    };
+    if let Some(previous) = attempt.challenge.replace(event.challenge.clone()) {
+        attempt.completed_factors.push(previous.factor);
+    }
+}
+
+/// Close a decided attempt, adding its location to the history if granted
+pub(crate) fn record_authentication_decided(
+    credentials: &mut IdentityCredentials,
+    event: &AuthenticationDecided,
+) {
+    let Some(attempt) = credentials
+        .attempt
+        .take_if(|attempt| attempt.attempt_id == event.attempt_id)
+    else {
+        return;
+    };
+    if event.decision == AuthenticationDecision::Granted {
+        credentials
+            .history
+            .record(LoginObservation::new(&attempt.location, event.decided_at));
+    }
+}
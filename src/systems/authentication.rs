@@ -0,0 +1,358 @@
+//! Password authentication systems for person identities
+//!
+//! Credentials are only changed through the `record_*` functions below,
+//! which replay calls with the same events.
+
+use crate::{
+    aggregate::IdentityAggregate, authentication::*, commands::*, components::*, events::*,
+    persistence::DomainEventWriter, IdentityError, IdentityIndex, IdentityResult,
+};
+use bevy::ecs::prelude::*;
+use chrono::{DateTime, Utc};
+use tracing::warn;
+use uuid::Uuid;
+
+/// System to set the passwords of person identities
+#[allow(clippy::too_many_arguments)]
+pub fn set_password_system(
+    mut commands: Commands,
+    mut events: EventReader<CommandEnvelope<SetPasswordCommand>>,
+    mut set_events: DomainEventWriter<PasswordSet>,
+    mut rejected_events: EventWriter<PasswordSetRejected>,
+    mut identities: Query<(&IdentityEntity, Option<&mut IdentityCredentials>)>,
+    index: Res<IdentityIndex>,
+    policy: Res<AuthenticationPolicy>,
+    clock: Res<AuthenticationClock>,
+) {
+    for envelope in events.read() {
+        let event = &envelope.command;
+
+        let Some((entity, (identity, credentials))) = index
+            .identity(event.identity_id)
+            .and_then(|entity| identities.get_mut(entity).ok().map(|found| (entity, found)))
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::IdentityNotFound(event.identity_id),
+            ));
+            continue;
+        };
+
+        if let Err(e) = IdentityAggregate::validate_password_holder(identity) {
+            rejected_events.write(CommandRejected::new(envelope, e));
+            continue;
+        }
+
+        let password_hash = match hash_password(&event.password, &policy.password) {
+            Ok(password_hash) => password_hash,
+            Err(e) => {
+                rejected_events.write(CommandRejected::new(envelope, e));
+                continue;
+            }
+        };
+
+        let set = PasswordSet {
+            identity_id: event.identity_id,
+            password_hash,
+            set_by: event.set_by,
+            set_at: clock.now(),
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        };
+        match credentials {
+            Some(mut credentials) => record_password_set(&mut credentials, &set),
+            None => {
+                commands.entity(entity).insert(credentials_from(&set));
+            }
+        }
+
+        set_events.write(set);
+    }
+}
+
+/// System to authenticate person identities by password
+///
+/// Wrong passwords count towards the [`LockoutPolicy`]; once it locks the
+/// account every attempt is refused until the lock runs out.
+pub fn authenticate_password_system(
+    mut events: EventReader<CommandEnvelope<AuthenticatePasswordCommand>>,
+    mut password_events: PasswordEventWriters,
+    mut rejected_events: EventWriter<PasswordAuthenticationRejected>,
+    mut identities: Query<(&IdentityEntity, &mut IdentityCredentials)>,
+    index: Res<IdentityIndex>,
+    policy: Res<AuthenticationPolicy>,
+    clock: Res<AuthenticationClock>,
+) {
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = clock.now();
+        let ids = (envelope.correlation_id, Some(envelope.command_id));
+
+        // Unknown identities and identities without a password look like wrong passwords
+        let Some((identity, mut credentials)) = index
+            .identity(event.identity_id)
+            .and_then(|entity| identities.get_mut(entity).ok())
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::InvalidCredentials,
+            ));
+            continue;
+        };
+
+        if let Err(e) = IdentityAggregate::validate_authentication(identity)
+            .and_then(|_| {
+                release_expired_lock(
+                    &mut credentials,
+                    identity,
+                    now,
+                    ids,
+                    &mut password_events.lockout,
+                )
+            })
+            .and_then(|_| {
+                check_password(
+                    &mut credentials,
+                    identity,
+                    &event.password,
+                    &policy,
+                    now,
+                    ids,
+                    &mut password_events,
+                )
+            })
+        {
+            rejected_events.write(CommandRejected::new(envelope, e));
+        }
+    }
+}
+
+/// Writers for the events of password checks
+#[derive(bevy::ecs::system::SystemParam)]
+pub struct PasswordEventWriters<'w> {
+    succeeded: DomainEventWriter<'w, AuthenticationSucceeded>,
+    failed: DomainEventWriter<'w, AuthenticationFailed>,
+    rehashed: DomainEventWriter<'w, PasswordRehashed>,
+    pub(crate) lockout: LockoutEventWriters<'w>,
+}
+
+/// Check the password of an identity whose account is not locked
+///
+/// A wrong password counts towards the lockout policy and may lock the
+/// account. A right one clears the failures and upgrades a hash produced
+/// with outdated parameters.
+pub(crate) fn check_password(
+    credentials: &mut IdentityCredentials,
+    identity: &IdentityEntity,
+    password: &Password,
+    policy: &AuthenticationPolicy,
+    now: DateTime<Utc>,
+    ids: (Uuid, Option<Uuid>),
+    events: &mut PasswordEventWriters,
+) -> IdentityResult<()> {
+    verify_password_factor(credentials, identity, password, policy, now, ids, events)?;
+    succeed_authentication(credentials, identity, None, now, ids, events);
+    Ok(())
+}
+
+/// Check the password of an identity whose account is not locked, leaving
+/// the failures for the caller to clear
+///
+/// Used for the password factor of attempts, which only clear the failures
+/// once every factor has been answered.
+pub(crate) fn verify_password_factor(
+    credentials: &mut IdentityCredentials,
+    identity: &IdentityEntity,
+    password: &Password,
+    policy: &AuthenticationPolicy,
+    now: DateTime<Utc>,
+    (correlation_id, causation_id): (Uuid, Option<Uuid>),
+    events: &mut PasswordEventWriters,
+) -> IdentityResult<()> {
+    if !verify_password(password, &credentials.password_hash) {
+        let failed = AuthenticationFailed {
+            identity_id: identity.identity_id,
+            failed_attempts: credentials.failed_attempts + 1,
+            failed_at: now,
+            correlation_id,
+            causation_id,
+        };
+        record_authentication_failure(credentials, &failed);
+        events.failed.write(failed);
+        lock_if_due(
+            credentials,
+            identity,
+            &policy.lockout,
+            now,
+            (correlation_id, causation_id),
+            &mut events.lockout,
+        );
+        return Err(IdentityError::InvalidCredentials);
+    }
+
+    // Upgrade hashes produced with outdated parameters
+    if needs_rehash(&credentials.password_hash, &policy.password) {
+        match hash_password(password, &policy.password) {
+            Ok(password_hash) => {
+                let rehashed = PasswordRehashed {
+                    identity_id: identity.identity_id,
+                    password_hash,
+                    rehashed_at: now,
+                    correlation_id,
+                    causation_id,
+                };
+                record_password_rehashed(credentials, &rehashed);
+                events.rehashed.write(rehashed);
+            }
+            Err(e) => warn!(
+                "Failed to rehash password of identity {}: {}",
+                identity.identity_id, e
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Record that an identity authenticated, clearing its failures
+pub(crate) fn succeed_authentication(
+    credentials: &mut IdentityCredentials,
+    identity: &IdentityEntity,
+    attempt_id: Option<Uuid>,
+    now: DateTime<Utc>,
+    (correlation_id, causation_id): (Uuid, Option<Uuid>),
+    events: &mut PasswordEventWriters,
+) {
+    let succeeded = AuthenticationSucceeded {
+        identity_id: identity.identity_id,
+        attempt_id,
+        authenticated_at: now,
+        correlation_id,
+        causation_id,
+    };
+    record_authentication_success(credentials, &succeeded);
+    events.succeeded.write(succeeded);
+}
+
+/// Writers for the events of the lockout policy
+#[derive(bevy::ecs::system::SystemParam)]
+pub struct LockoutEventWriters<'w> {
+    locked: DomainEventWriter<'w, AccountLocked>,
+    unlocked: DomainEventWriter<'w, AccountUnlocked>,
+}
+
+/// Refuse attempts while the account is locked and release a lock that has run out
+pub(crate) fn release_expired_lock(
+    credentials: &mut IdentityCredentials,
+    identity: &IdentityEntity,
+    now: DateTime<Utc>,
+    (correlation_id, causation_id): (Uuid, Option<Uuid>),
+    events: &mut LockoutEventWriters,
+) -> IdentityResult<()> {
+    match credentials.locked_until {
+        Some(until) if now < until => Err(IdentityError::AccountLocked(until)),
+        Some(_) => {
+            let unlocked = AccountUnlocked {
+                identity_id: identity.identity_id,
+                unlocked_at: now,
+                correlation_id,
+                causation_id,
+            };
+            record_account_unlocked(credentials, &unlocked);
+            events.unlocked.write(unlocked);
+            Ok(())
+        }
+        None => Ok(()),
+    }
+}
+
+/// Lock the account if the failures so far reach the policy threshold
+pub(crate) fn lock_if_due(
+    credentials: &mut IdentityCredentials,
+    identity: &IdentityEntity,
+    policy: &LockoutPolicy,
+    now: DateTime<Utc>,
+    (correlation_id, causation_id): (Uuid, Option<Uuid>),
+    events: &mut LockoutEventWriters,
+) {
+    if !policy.should_lock(credentials.failed_attempts) {
+        return;
+    }
+
+    let locked = AccountLocked {
+        identity_id: identity.identity_id,
+        locked_until: now + policy.lockout_duration(credentials.lockout_count),
+        lockout_count: credentials.lockout_count + 1,
+        locked_at: now,
+        correlation_id,
+        causation_id,
+    };
+    record_account_locked(credentials, &locked);
+    events.locked.write(locked);
+}
+
+/// Credentials of an identity whose first password was set
+pub(crate) fn credentials_from(event: &PasswordSet) -> IdentityCredentials {
+    IdentityCredentials {
+        password_hash: event.password_hash.clone(),
+        password_changed_at: event.set_at,
+        last_authenticated_at: None,
+        failed_attempts: 0,
+        lockout_count: 0,
+        locked_until: None,
+        attempt: None,
+        history: AuthenticationHistory::default(),
+        session_grant: None,
+    }
+}
+
+/// Replace the password of an identity
+pub(crate) fn record_password_set(credentials: &mut IdentityCredentials, event: &PasswordSet) {
+    credentials.password_hash = event.password_hash.clone();
+    credentials.password_changed_at = event.set_at;
+}
+
+/// Replace a password hash with its upgraded form
+pub(crate) fn record_password_rehashed(
+    credentials: &mut IdentityCredentials,
+    event: &PasswordRehashed,
+) {
+    credentials.password_hash = event.password_hash.clone();
+}
+
+/// Record a successful authentication, which clears the lockout backoff and
+/// may start one session
+pub(crate) fn record_authentication_success(
+    credentials: &mut IdentityCredentials,
+    event: &AuthenticationSucceeded,
+) {
+    credentials.last_authenticated_at = Some(event.authenticated_at);
+    credentials.failed_attempts = 0;
+    credentials.lockout_count = 0;
+    credentials.session_grant = Some(SessionGrant {
+        attempt_id: event.attempt_id,
+        authenticated_at: event.authenticated_at,
+    });
+}
+
+/// Record a failed attempt
+pub(crate) fn record_authentication_failure(
+    credentials: &mut IdentityCredentials,
+    event: &AuthenticationFailed,
+) {
+    credentials.failed_attempts = event.failed_attempts;
+}
+
+/// Lock an account
+pub(crate) fn record_account_locked(credentials: &mut IdentityCredentials, event: &AccountLocked) {
+    credentials.locked_until = Some(event.locked_until);
+    credentials.lockout_count = event.lockout_count;
+}
+
+/// Release an account whose lock ran out, starting a new count of failures
+pub(crate) fn record_account_unlocked(
+    credentials: &mut IdentityCredentials,
+    _event: &AccountUnlocked,
+) {
+    credentials.locked_until = None;
+    credentials.failed_attempts = 0;
+}
@@ -0,0 +1,345 @@
+//! Claim lifecycle systems
+
+use crate::{
+    aggregate::IdentityAggregate, commands::*, components::*, events::*,
+    persistence::DomainEventWriter, verification::*, IdentityError, IdentityIndex,
+};
+use bevy::ecs::prelude::*;
+use serde_json::json;
+use uuid::Uuid;
+
+/// System to add claims to identities
+pub fn add_claim_system(
+    mut events: EventReader<CommandEnvelope<AddClaimCommand>>,
+    mut added_events: DomainEventWriter<ClaimAdded>,
+    mut rejected_events: EventWriter<ClaimAdditionRejected>,
+    mut identities: Query<(&IdentityEntity, &mut IdentityClaims)>,
+    index: Res<IdentityIndex>,
+) {
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = chrono::Utc::now();
+
+        let Some((identity, mut claims)) = index
+            .identity(event.identity_id)
+            .and_then(|entity| identities.get_mut(entity).ok())
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::IdentityNotFound(event.identity_id),
+            ));
+            continue;
+        };
+
+        if let Err(e) = IdentityAggregate::validate_claim_change(identity, event.expires_at, now) {
+            rejected_events.write(CommandRejected::new(envelope, e));
+            continue;
+        }
+
+        if claims.find(&event.claim_type, &event.value).is_some() {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::ClaimAlreadyExists(event.claim_type.clone()),
+            ));
+            continue;
+        }
+
+        claims.add(IdentityClaim {
+            claim_type: event.claim_type.clone(),
+            value: event.value.clone(),
+            verified: false,
+            issuer: event.issuer,
+            issued_at: now,
+            expires_at: event.expires_at,
+        });
+
+        added_events.write(ClaimAdded {
+            identity_id: event.identity_id,
+            claim_type: event.claim_type.clone(),
+            value: event.value.clone(),
+            issuer: event.issuer,
+            expires_at: event.expires_at,
+            added_by: event.added_by,
+            added_at: now,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        });
+    }
+}
+
+/// System to change the value or expiry of claims
+///
+/// Changing the value of a verified claim lowers the verification level it
+/// supported.
+#[allow(clippy::too_many_arguments)]
+pub fn update_claim_system(
+    mut events: EventReader<CommandEnvelope<UpdateClaimCommand>>,
+    mut updated_events: DomainEventWriter<ClaimUpdated>,
+    mut downgraded_events: DomainEventWriter<VerificationLevelDowngraded>,
+    mut rejected_events: EventWriter<ClaimUpdateRejected>,
+    mut identities: Query<(
+        &IdentityEntity,
+        &mut IdentityClaims,
+        &mut IdentityVerification,
+    )>,
+    mut reminders: ResMut<ReverificationReminders>,
+    index: Res<IdentityIndex>,
+) {
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = chrono::Utc::now();
+
+        let Some((identity, mut claims, mut verification)) = index
+            .identity(event.identity_id)
+            .and_then(|entity| identities.get_mut(entity).ok())
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::IdentityNotFound(event.identity_id),
+            ));
+            continue;
+        };
+
+        if let Err(e) = IdentityAggregate::validate_claim_change(identity, event.expires_at, now) {
+            rejected_events.write(CommandRejected::new(envelope, e));
+            continue;
+        }
+
+        let Some(previous) = claims.find(&event.claim_type, &event.value).cloned() else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::ClaimNotFound(event.claim_type.clone()),
+            ));
+            continue;
+        };
+
+        let new_value = event.new_value.as_ref().unwrap_or(&event.value);
+        let value_changed = *new_value != previous.value;
+        if value_changed && claims.find(&event.claim_type, new_value).is_some() {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::ClaimAlreadyExists(event.claim_type.clone()),
+            ));
+            continue;
+        }
+
+        let claim = claims
+            .find_mut(&event.claim_type, &event.value)
+            .expect("claim is held");
+        claim.value = new_value.clone();
+        claim.verified = previous.verified && !value_changed;
+        claim.expires_at = event.expires_at.or(previous.expires_at);
+        let updated = claim.clone();
+        reminders.forget(event.identity_id, &event.claim_type, &event.value);
+
+        updated_events.write(ClaimUpdated {
+            identity_id: event.identity_id,
+            claim_type: event.claim_type.clone(),
+            old_value: previous.value.clone(),
+            value: updated.value,
+            verified: updated.verified,
+            expires_at: updated.expires_at,
+            updated_by: event.updated_by,
+            updated_at: now,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        });
+
+        if value_changed {
+            if let Some(downgraded) = downgrade_for_lapse(
+                event.identity_id,
+                &mut verification,
+                &claims,
+                &previous,
+                envelope.correlation_id,
+                Some(envelope.command_id),
+            ) {
+                downgraded_events.write(downgraded);
+            }
+        }
+    }
+}
+
+/// System to revoke claims
+#[allow(clippy::too_many_arguments)]
+pub fn revoke_claim_system(
+    mut events: EventReader<CommandEnvelope<RevokeClaimCommand>>,
+    mut revoked_events: DomainEventWriter<ClaimRevoked>,
+    mut downgraded_events: DomainEventWriter<VerificationLevelDowngraded>,
+    mut rejected_events: EventWriter<ClaimRevocationRejected>,
+    mut identities: Query<(
+        &IdentityEntity,
+        &mut IdentityClaims,
+        &mut IdentityVerification,
+    )>,
+    mut reminders: ResMut<ReverificationReminders>,
+    index: Res<IdentityIndex>,
+) {
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = chrono::Utc::now();
+
+        let Some((identity, mut claims, mut verification)) = index
+            .identity(event.identity_id)
+            .and_then(|entity| identities.get_mut(entity).ok())
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::IdentityNotFound(event.identity_id),
+            ));
+            continue;
+        };
+
+        if let Err(e) = IdentityAggregate::validate_claim_change(identity, None, now) {
+            rejected_events.write(CommandRejected::new(envelope, e));
+            continue;
+        }
+
+        let Some(revoked) = claims.remove(&event.claim_type, &event.value) else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::ClaimNotFound(event.claim_type.clone()),
+            ));
+            continue;
+        };
+        reminders.forget(event.identity_id, &event.claim_type, &event.value);
+
+        revoked_events.write(ClaimRevoked {
+            identity_id: event.identity_id,
+            claim_type: event.claim_type.clone(),
+            value: event.value.clone(),
+            revoked_by: event.revoked_by,
+            revoked_at: now,
+            reason: event.reason.clone(),
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        });
+
+        if let Some(downgraded) = downgrade_for_lapse(
+            event.identity_id,
+            &mut verification,
+            &claims,
+            &revoked,
+            envelope.correlation_id,
+            Some(envelope.command_id),
+        ) {
+            downgraded_events.write(downgraded);
+        }
+    }
+}
+
+/// System to expire claims
+pub fn expire_claims_system(
+    mut expired_events: DomainEventWriter<ClaimExpired>,
+    mut downgraded_events: DomainEventWriter<VerificationLevelDowngraded>,
+    mut identities: Query<(
+        &IdentityEntity,
+        &mut IdentityClaims,
+        &mut IdentityVerification,
+    )>,
+    mut reminders: ResMut<ReverificationReminders>,
+) {
+    let now = chrono::Utc::now();
+
+    for (identity, mut claims, mut verification) in identities.iter_mut() {
+        let has_expired = claims
+            .iter()
+            .any(|claim| claim.expires_at.is_some_and(|expires_at| expires_at < now));
+        if !has_expired {
+            continue;
+        }
+
+        for expired in claims.remove_expired(now) {
+            let correlation_id = Uuid::new_v4();
+            reminders.forget(identity.identity_id, &expired.claim_type, &expired.value);
+
+            expired_events.write(ClaimExpired {
+                identity_id: identity.identity_id,
+                claim_type: expired.claim_type.clone(),
+                value: expired.value.clone(),
+                expired_at: expired.expires_at.unwrap_or(now),
+                correlation_id,
+                causation_id: None,
+            });
+
+            if let Some(downgraded) = downgrade_for_lapse(
+                identity.identity_id,
+                &mut verification,
+                &claims,
+                &expired,
+                correlation_id,
+                None,
+            ) {
+                downgraded_events.write(downgraded);
+            }
+        }
+    }
+}
+
+/// System to remind identities to re-verify claims close to their expiry
+///
+/// Each reminder starts a `Verification` workflow naming the claim in its
+/// context.
+pub fn remind_claim_reverification_system(
+    mut start_commands: EventWriter<CommandEnvelope<StartWorkflowCommand>>,
+    identities: Query<(&IdentityEntity, &IdentityClaims)>,
+    mut reminders: ResMut<ReverificationReminders>,
+) {
+    let now = chrono::Utc::now();
+
+    for (identity, claims) in identities.iter() {
+        if !matches!(
+            identity.status,
+            IdentityStatus::Active | IdentityStatus::Pending
+        ) {
+            continue;
+        }
+
+        for claim in claims.iter() {
+            if !reminders.is_due(identity.identity_id, claim, now) {
+                continue;
+            }
+
+            start_commands.write(CommandEnvelope::new(StartWorkflowCommand {
+                identity_id: identity.identity_id,
+                workflow_type: WorkflowType::Verification,
+                started_by: identity.identity_id,
+                context: json!({
+                    "reason": "claim_reverification",
+                    "claim_type": claim.claim_type,
+                    "value": claim.value,
+                    "expires_at": claim.expires_at,
+                }),
+            }));
+            reminders.mark_reminded(identity.identity_id, claim);
+        }
+    }
+}
+
+/// Lower the verification level if the lapsed claim supported it
+fn downgrade_for_lapse(
+    identity_id: IdentityId,
+    verification: &mut Mut<IdentityVerification>,
+    claims: &IdentityClaims,
+    lapsed: &IdentityClaim,
+    correlation_id: Uuid,
+    causation_id: Option<Uuid>,
+) -> Option<VerificationLevelDowngraded> {
+    let previous_level = verification.verification_level;
+    let new_level = level_after_claim_lapse(previous_level, claims, lapsed);
+    if new_level == previous_level {
+        return None;
+    }
+
+    verification.verification_level = new_level;
+    Some(VerificationLevelDowngraded {
+        identity_id,
+        previous_level,
+        new_level,
+        claim_type: lapsed.claim_type.clone(),
+        value: lapsed.value.clone(),
+        downgraded_at: chrono::Utc::now(),
+        correlation_id,
+        causation_id,
+    })
+}
@@ -0,0 +1,167 @@
+//! Verifiable Credential systems
+
+use crate::{
+    aggregate::IdentityAggregate, commands::*, components::*, credentials::*, events::*,
+    persistence::DomainEventWriter, IdentityError, IdentityIndex,
+};
+use bevy::ecs::prelude::*;
+
+/// System to export verified claims as signed Verifiable Credentials
+pub fn export_credential_system(
+    mut events: EventReader<CommandEnvelope<ExportCredentialCommand>>,
+    mut exported_events: EventWriter<CredentialExported>,
+    mut rejected_events: EventWriter<CredentialExportRejected>,
+    identities: Query<(&IdentityEntity, &IdentityClaims)>,
+    index: Res<IdentityIndex>,
+    issuer: Res<CredentialIssuer>,
+) {
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = chrono::Utc::now();
+
+        let Some((identity, claims)) = index
+            .identity(event.identity_id)
+            .and_then(|entity| identities.get(entity).ok())
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::IdentityNotFound(event.identity_id),
+            ));
+            continue;
+        };
+
+        if let Err(e) = IdentityAggregate::validate_token_subject(identity) {
+            rejected_events.write(CommandRejected::new(envelope, e));
+            continue;
+        }
+
+        let Some(claim) = claims.find(&event.claim_type, &event.value) else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::ClaimNotFound(event.claim_type.clone()),
+            ));
+            continue;
+        };
+
+        // Business rule: Only claims that are verified and current are attested
+        if !claim.verified || claim.expires_at.is_some_and(|expires_at| expires_at <= now) {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::InvalidOperation("Claim is not verified".to_string()),
+            ));
+            continue;
+        }
+
+        let credential = match issuer.issue(identity.identity_id, claim, now) {
+            Ok(credential) => credential,
+            Err(e) => {
+                rejected_events.write(CommandRejected::new(envelope, e));
+                continue;
+            }
+        };
+
+        exported_events.write(CredentialExported {
+            identity_id: identity.identity_id,
+            credential,
+            exported_at: now,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        });
+    }
+}
+
+/// System to import externally issued credentials as verified claims
+///
+/// The proof is checked offline against [`IssuerKeys`]; the issuer must then
+/// be the target of an active `Trusts` relationship of the importing identity.
+#[allow(clippy::too_many_arguments)]
+pub fn import_credential_system(
+    mut events: EventReader<CommandEnvelope<ImportCredentialCommand>>,
+    mut imported_events: DomainEventWriter<CredentialImported>,
+    mut rejected_events: EventWriter<CredentialImportRejected>,
+    mut identities: Query<(&IdentityEntity, &mut IdentityClaims)>,
+    relationships: Query<&IdentityRelationship>,
+    index: Res<IdentityIndex>,
+    issuer_keys: Res<IssuerKeys>,
+) {
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = chrono::Utc::now();
+
+        let Some((identity, mut claims)) = index
+            .identity(event.identity_id)
+            .and_then(|entity| identities.get_mut(entity).ok())
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::IdentityNotFound(event.identity_id),
+            ));
+            continue;
+        };
+
+        if let Err(e) = IdentityAggregate::validate_claim_change(identity, None, now) {
+            rejected_events.write(CommandRejected::new(envelope, e));
+            continue;
+        }
+
+        let (issuer_id, credential) = match issuer_keys.verify(&event.credential, now) {
+            Ok(verified) => verified,
+            Err(e) => {
+                rejected_events.write(CommandRejected::new(envelope, e));
+                continue;
+            }
+        };
+
+        if credential.subject_identity() != Some(identity.identity_id) {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::InvalidCredential(
+                    "credential is about another identity".to_string(),
+                ),
+            ));
+            continue;
+        }
+
+        let trusted = index
+            .outgoing(event.imported_by)
+            .filter_map(|entity| relationships.get(entity).ok())
+            .any(|relationship| {
+                relationship.target_identity == issuer_id
+                    && relationship.relationship_type == RelationshipType::Trusts
+                    && relationship
+                        .expires_at
+                        .is_none_or(|expires_at| expires_at > now)
+            });
+        if !trusted {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::UntrustedIssuer(issuer_id),
+            ));
+            continue;
+        }
+
+        let subject = credential.credential_subject;
+        claims.add(IdentityClaim {
+            claim_type: subject.claim_type.clone(),
+            value: subject.value.clone(),
+            verified: true,
+            issuer: Some(issuer_id),
+            issued_at: credential.issuance_date,
+            expires_at: credential.expiration_date,
+        });
+
+        imported_events.write(CredentialImported {
+            identity_id: identity.identity_id,
+            credential_id: credential.id,
+            issuer_id,
+            claim_type: subject.claim_type,
+            value: subject.value,
+            issued_at: credential.issuance_date,
+            expires_at: credential.expiration_date,
+            imported_by: event.imported_by,
+            imported_at: now,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        });
+    }
+}
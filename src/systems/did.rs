@@ -0,0 +1,168 @@
+//! DID document systems
+
+use crate::{
+    aggregate::IdentityAggregate, commands::*, components::*, did::*, events::*,
+    persistence::DomainEventWriter, IdentityError, IdentityIndex,
+};
+use bevy::ecs::prelude::*;
+use ed25519_dalek::VerifyingKey;
+
+/// System to publish DID documents for identities
+#[allow(clippy::too_many_arguments)]
+pub fn publish_did_system(
+    mut commands: Commands,
+    mut events: EventReader<CommandEnvelope<PublishDidCommand>>,
+    mut published_events: DomainEventWriter<DidPublished>,
+    mut rejected_events: EventWriter<DidPublicationRejected>,
+    identities: Query<(&IdentityEntity, Option<&IdentityDid>)>,
+    index: Res<IdentityIndex>,
+    resolver: Res<DidResolver>,
+) {
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = chrono::Utc::now();
+
+        let Some((entity, (identity, current))) = index
+            .identity(event.identity_id)
+            .and_then(|entity| identities.get(entity).ok().map(|found| (entity, found)))
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::IdentityNotFound(event.identity_id),
+            ));
+            continue;
+        };
+
+        if let Err(e) = IdentityAggregate::validate_did_change(identity, current, false) {
+            rejected_events.write(CommandRejected::new(envelope, e));
+            continue;
+        }
+
+        let Ok(key) = VerifyingKey::from_bytes(&event.public_key) else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::InvalidDid("invalid Ed25519 public key".to_string()),
+            ));
+            continue;
+        };
+
+        let did = did_for(&event.method, identity.identity_id, &key);
+        if resolver
+            .identity(&did)
+            .is_some_and(|owner| owner != identity.identity_id)
+        {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::InvalidOperation("DID is published by another identity".to_string()),
+            ));
+            continue;
+        }
+
+        let public_key_multibase = ed25519_multikey(&key);
+        commands.entity(entity).insert(IdentityDid {
+            did: did.clone(),
+            method: event.method.clone(),
+            keys: vec![DidKey {
+                public_key_multibase: public_key_multibase.clone(),
+                added_at: now,
+                retired_at: None,
+            }],
+            services: event.services.clone(),
+            updated_at: now,
+        });
+
+        published_events.write(DidPublished {
+            identity_id: identity.identity_id,
+            did,
+            method: event.method.clone(),
+            public_key_multibase,
+            services: event.services.clone(),
+            published_by: event.published_by,
+            published_at: now,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        });
+    }
+}
+
+/// System to rotate the verification key of published DIDs
+///
+/// The retired keys stay in the identity's [`IdentityDid`] as history but are
+/// no longer listed in its document.
+pub fn rotate_did_key_system(
+    mut events: EventReader<CommandEnvelope<RotateDidKeyCommand>>,
+    mut rotated_events: DomainEventWriter<DidKeyRotated>,
+    mut rejected_events: EventWriter<DidKeyRotationRejected>,
+    mut identities: Query<(&IdentityEntity, Option<&mut IdentityDid>)>,
+    index: Res<IdentityIndex>,
+) {
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = chrono::Utc::now();
+
+        let Some((identity, current)) = index
+            .identity(event.identity_id)
+            .and_then(|entity| identities.get_mut(entity).ok())
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::IdentityNotFound(event.identity_id),
+            ));
+            continue;
+        };
+
+        if let Err(e) = IdentityAggregate::validate_did_change(identity, current.as_deref(), true) {
+            rejected_events.write(CommandRejected::new(envelope, e));
+            continue;
+        }
+        let mut identity_did = current.expect("DID is published");
+
+        let Ok(key) = VerifyingKey::from_bytes(&event.public_key) else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::InvalidDid("invalid Ed25519 public key".to_string()),
+            ));
+            continue;
+        };
+
+        // Business rule: Retired keys are never brought back
+        let public_key_multibase = ed25519_multikey(&key);
+        if identity_did
+            .keys
+            .iter()
+            .any(|key| key.public_key_multibase == public_key_multibase)
+        {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::InvalidOperation("Key was already used by this DID".to_string()),
+            ));
+            continue;
+        }
+
+        let retired_keys = identity_did.rotate(public_key_multibase.clone(), now);
+
+        rotated_events.write(DidKeyRotated {
+            identity_id: identity.identity_id,
+            did: identity_did.did.clone(),
+            retired_keys,
+            public_key_multibase,
+            rotated_by: event.rotated_by,
+            rotated_at: now,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        });
+    }
+}
+
+/// System to keep the [`DidResolver`] in step with published DIDs
+pub fn sync_did_resolver_system(
+    mut resolver: ResMut<DidResolver>,
+    dids: Query<(&IdentityEntity, &IdentityDid), Changed<IdentityDid>>,
+) {
+    for (identity, identity_did) in dids.iter() {
+        resolver.publish(
+            identity.identity_id,
+            DidDocument::from_identity_did(identity_did),
+        );
+    }
+}
@@ -0,0 +1,329 @@
+//! Cryptographic key systems for device, service and system identities
+
+use crate::{
+    aggregate::IdentityAggregate, commands::*, components::*, events::*, keys::*,
+    persistence::DomainEventWriter, IdentityError, IdentityIndex,
+};
+use bevy::ecs::prelude::*;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Add a key to the identity's keys, or to the keys inserted at the end of the frame
+fn hold_key(
+    keys: Option<Mut<IdentityKeys>>,
+    new_holders: &mut HashMap<Entity, IdentityKeys>,
+    entity: Entity,
+    key: IdentityKey,
+) {
+    match keys {
+        Some(mut keys) => keys.keys.push(key),
+        None => new_holders.entry(entity).or_default().keys.push(key),
+    }
+}
+
+/// System to generate keys for identities
+pub fn generate_key_system(
+    mut commands: Commands,
+    mut events: EventReader<CommandEnvelope<GenerateKeyCommand>>,
+    mut generated_events: DomainEventWriter<KeyGenerated>,
+    mut rejected_events: EventWriter<KeyGenerationRejected>,
+    mut identities: Query<(&IdentityEntity, Option<&mut IdentityKeys>)>,
+    index: Res<IdentityIndex>,
+    store: Res<IdentityKeyStore>,
+) {
+    // Key components inserted this frame, so several keys generated for a new
+    // holder in the same frame are all kept
+    let mut new_holders: HashMap<Entity, IdentityKeys> = HashMap::new();
+
+    for envelope in events.read() {
+        let event = &envelope.command;
+
+        let Some((entity, (identity, keys))) = index
+            .identity(event.identity_id)
+            .and_then(|entity| identities.get_mut(entity).ok().map(|found| (entity, found)))
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::IdentityNotFound(event.identity_id),
+            ));
+            continue;
+        };
+
+        if let Err(e) = IdentityAggregate::validate_key_holder(identity).and_then(|_| {
+            IdentityAggregate::validate_key_purposes(event.algorithm, &event.purposes)
+        }) {
+            rejected_events.write(CommandRejected::new(envelope, e));
+            continue;
+        }
+
+        let key_id = Uuid::new_v4();
+        let secret = KeySecret::generate(event.algorithm);
+        if let Err(e) = store.0.store(key_id, &secret) {
+            rejected_events.write(CommandRejected::new(envelope, e));
+            continue;
+        }
+
+        let now = chrono::Utc::now();
+        let key = IdentityKey {
+            key_id,
+            algorithm: event.algorithm,
+            purposes: event.purposes.clone(),
+            public_key: secret.public_key(),
+            created_at: now,
+            superseded_by: None,
+            revoked_at: None,
+            revocation_reason: None,
+        };
+        hold_key(keys, &mut new_holders, entity, key.clone());
+
+        generated_events.write(KeyGenerated {
+            identity_id: event.identity_id,
+            key_id,
+            algorithm: key.algorithm,
+            purposes: key.purposes,
+            public_key: key.public_key,
+            generated_by: event.generated_by,
+            generated_at: now,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        });
+    }
+
+    for (entity, keys) in new_holders {
+        commands.entity(entity).insert(keys);
+    }
+}
+
+/// System to import existing private keys for identities
+pub fn import_key_system(
+    mut commands: Commands,
+    mut events: EventReader<CommandEnvelope<ImportKeyCommand>>,
+    mut imported_events: DomainEventWriter<KeyImported>,
+    mut rejected_events: EventWriter<KeyImportRejected>,
+    mut identities: Query<(&IdentityEntity, Option<&mut IdentityKeys>)>,
+    index: Res<IdentityIndex>,
+    store: Res<IdentityKeyStore>,
+) {
+    let mut new_holders: HashMap<Entity, IdentityKeys> = HashMap::new();
+
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let algorithm = event.secret.algorithm();
+
+        let Some((entity, (identity, keys))) = index
+            .identity(event.identity_id)
+            .and_then(|entity| identities.get_mut(entity).ok().map(|found| (entity, found)))
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::IdentityNotFound(event.identity_id),
+            ));
+            continue;
+        };
+
+        if let Err(e) = IdentityAggregate::validate_key_holder(identity)
+            .and_then(|_| IdentityAggregate::validate_key_purposes(algorithm, &event.purposes))
+        {
+            rejected_events.write(CommandRejected::new(envelope, e));
+            continue;
+        }
+
+        // Business rule: A key is imported once; a revoked key stays revoked
+        let public_key = event.secret.public_key();
+        if keys
+            .as_deref()
+            .or(new_holders.get(&entity))
+            .is_some_and(|keys| keys.holds_public_key(&public_key))
+        {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::InvalidOperation("Key is already held".to_string()),
+            ));
+            continue;
+        }
+
+        let key_id = Uuid::new_v4();
+        if let Err(e) = store.0.store(key_id, &event.secret) {
+            rejected_events.write(CommandRejected::new(envelope, e));
+            continue;
+        }
+
+        let now = chrono::Utc::now();
+        hold_key(
+            keys,
+            &mut new_holders,
+            entity,
+            IdentityKey {
+                key_id,
+                algorithm,
+                purposes: event.purposes.clone(),
+                public_key,
+                created_at: now,
+                superseded_by: None,
+                revoked_at: None,
+                revocation_reason: None,
+            },
+        );
+
+        imported_events.write(KeyImported {
+            identity_id: event.identity_id,
+            key_id,
+            algorithm,
+            purposes: event.purposes.clone(),
+            public_key,
+            imported_by: event.imported_by,
+            imported_at: now,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        });
+    }
+
+    for (entity, keys) in new_holders {
+        commands.entity(entity).insert(keys);
+    }
+}
+
+/// System to replace keys with newly generated ones
+///
+/// The replaced key stays listed as superseded so its signatures keep
+/// verifying, and its private key stays in the store.
+pub fn rotate_key_system(
+    mut events: EventReader<CommandEnvelope<RotateKeyCommand>>,
+    mut rotated_events: DomainEventWriter<KeyRotated>,
+    mut rejected_events: EventWriter<KeyRotationRejected>,
+    mut identities: Query<(&IdentityEntity, &mut IdentityKeys)>,
+    index: Res<IdentityIndex>,
+    store: Res<IdentityKeyStore>,
+) {
+    for envelope in events.read() {
+        let event = &envelope.command;
+
+        let Some((identity, mut keys)) = index
+            .identity(event.identity_id)
+            .and_then(|entity| identities.get_mut(entity).ok())
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::KeyNotFound(event.key_id),
+            ));
+            continue;
+        };
+
+        if let Err(e) = IdentityAggregate::validate_key_holder(identity) {
+            rejected_events.write(CommandRejected::new(envelope, e));
+            continue;
+        }
+
+        let Some(old_key) = keys.key(event.key_id).cloned() else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::KeyNotFound(event.key_id),
+            ));
+            continue;
+        };
+        if !old_key.is_current() {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::KeyInactive(event.key_id),
+            ));
+            continue;
+        }
+
+        let key_id = Uuid::new_v4();
+        let secret = KeySecret::generate(old_key.algorithm);
+        if let Err(e) = store.0.store(key_id, &secret) {
+            rejected_events.write(CommandRejected::new(envelope, e));
+            continue;
+        }
+
+        let now = chrono::Utc::now();
+        if let Some(old) = keys.key_mut(event.key_id) {
+            old.superseded_by = Some(key_id);
+        }
+        let new_key = IdentityKey {
+            key_id,
+            public_key: secret.public_key(),
+            created_at: now,
+            ..old_key
+        };
+        keys.keys.push(new_key.clone());
+
+        rotated_events.write(KeyRotated {
+            identity_id: event.identity_id,
+            previous_key_id: event.key_id,
+            key_id,
+            algorithm: new_key.algorithm,
+            purposes: new_key.purposes,
+            public_key: new_key.public_key,
+            rotated_by: event.rotated_by,
+            rotated_at: now,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        });
+    }
+}
+
+/// System to revoke keys
+///
+/// The private key of a revoked key is deleted from the store; signatures
+/// made with it no longer verify.
+pub fn revoke_key_system(
+    mut events: EventReader<CommandEnvelope<RevokeKeyCommand>>,
+    mut revoked_events: DomainEventWriter<KeyRevoked>,
+    mut rejected_events: EventWriter<KeyRevocationRejected>,
+    mut identities: Query<&mut IdentityKeys>,
+    index: Res<IdentityIndex>,
+    store: Res<IdentityKeyStore>,
+) {
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = chrono::Utc::now();
+
+        let Some(mut keys) = index
+            .identity(event.identity_id)
+            .and_then(|entity| identities.get_mut(entity).ok())
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::KeyNotFound(event.key_id),
+            ));
+            continue;
+        };
+
+        let Some(key) = keys.key_mut(event.key_id) else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::KeyNotFound(event.key_id),
+            ));
+            continue;
+        };
+        if key.revoked_at.is_some() {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::KeyInactive(event.key_id),
+            ));
+            continue;
+        }
+
+        match store.0.delete(event.key_id) {
+            Ok(()) | Err(IdentityError::KeyNotFound(_)) => {}
+            Err(e) => {
+                rejected_events.write(CommandRejected::new(envelope, e));
+                continue;
+            }
+        }
+
+        key.revoked_at = Some(now);
+        key.revocation_reason = Some(event.reason.clone());
+
+        revoked_events.write(KeyRevoked {
+            identity_id: event.identity_id,
+            key_id: event.key_id,
+            reason: event.reason.clone(),
+            revoked_by: event.revoked_by,
+            revoked_at: now,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        });
+    }
+}
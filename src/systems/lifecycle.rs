@@ -1,239 +1,285 @@
 //! Identity lifecycle systems
 
-use crate::{aggregate::IdentityAggregate, commands::*, components::*, events::*};
+use crate::{
+    aggregate::IdentityAggregate, commands::*, components::*, events::*,
+    persistence::DomainEventWriter, IdentityError, IdentityIndex,
+};
 use bevy::ecs::prelude::*;
 use uuid::Uuid;
 
 /// System to create new identities
 pub fn create_identity_system(
     mut commands: Commands,
-    mut events: EventReader<CreateIdentityCommand>,
-    mut created_events: EventWriter<IdentityCreated>,
-    existing_identities: Query<&IdentityEntity>,
+    mut events: EventReader<CommandEnvelope<CreateIdentityCommand>>,
+    mut created_events: DomainEventWriter<IdentityCreated>,
+    mut claim_events: DomainEventWriter<ClaimAdded>,
+    mut rejected_events: EventWriter<IdentityCreationRejected>,
+    index: Res<IdentityIndex>,
 ) {
-    for event in events.read() {
-        // Collect existing identities for validation
-        let existing: Vec<_> = existing_identities.iter().cloned().collect();
+    for envelope in events.read() {
+        let event = &envelope.command;
 
         // Validate through aggregate
-        match IdentityAggregate::validate_create(event, &existing) {
+        match IdentityAggregate::validate_create(event, &index) {
             Ok(_) => {
                 let identity_id = Uuid::new_v4();
+                let now = chrono::Utc::now();
 
-                // Spawn the identity entity with components
-                let entity = commands
-                    .spawn((
-                        IdentityEntity {
-                            identity_id,
-                            identity_type: event.identity_type,
-                            status: IdentityStatus::Pending,
-                        },
-                        IdentityMetadata::default(),
-                        IdentityVerification {
-                            verification_level: VerificationLevel::Unverified,
-                            verified_at: None,
-                            verified_by: None,
-                            verification_method: None,
-                        },
-                    ))
-                    .id();
-
-                // Add initial claims if provided
-                if let Some(claims) = &event.initial_claims {
-                    for (claim_type, value) in claims {
-                        commands.entity(entity).insert(IdentityClaim {
-                            claim_type: claim_type.clone(),
-                            value: value.clone(),
-                            verified: false,
-                            issuer: Some(event.created_by),
-                            issued_at: chrono::Utc::now(),
-                            expires_at: None,
-                        });
-                    }
-                }
-
-                // Emit created event
-                created_events.write(IdentityCreated {
+                // Spawn the identity entity with its initial claims
+                let claims: Vec<IdentityClaim> = event
+                    .initial_claims
+                    .iter()
+                    .flatten()
+                    .map(|(claim_type, value)| IdentityClaim {
+                        claim_type: claim_type.clone(),
+                        value: value.clone(),
+                        verified: false,
+                        issuer: Some(event.created_by),
+                        issued_at: now,
+                        expires_at: None,
+                    })
+                    .collect();
+                let created = IdentityCreated {
                     identity_id,
                     identity_type: event.identity_type,
                     created_by: Some(event.created_by),
-                    created_at: chrono::Utc::now(),
+                    created_at: now,
                     external_reference: event.external_reference.clone(),
-                });
+                    correlation_id: envelope.correlation_id,
+                    causation_id: Some(envelope.command_id),
+                };
+                commands.spawn((identity_from(&created), IdentityClaims::new(claims.clone())));
+
+                // Emit created event, followed by one event per initial claim
+                created_events.write(created);
+                for claim in claims {
+                    claim_events.write(ClaimAdded {
+                        identity_id,
+                        claim_type: claim.claim_type,
+                        value: claim.value,
+                        issuer: claim.issuer,
+                        expires_at: claim.expires_at,
+                        added_by: event.created_by,
+                        added_at: now,
+                        correlation_id: envelope.correlation_id,
+                        causation_id: Some(envelope.command_id),
+                    });
+                }
             }
             Err(e) => {
-                // In production, would emit error event
-                eprintln!("Failed to create identity: {e}");
+                rejected_events.write(CommandRejected::new(envelope, e));
             }
         }
     }
 }
 
+/// Components of a newly created identity, without its claims
+pub(crate) fn identity_from(
+    event: &IdentityCreated,
+) -> (IdentityEntity, IdentityMetadata, IdentityVerification) {
+    (
+        IdentityEntity {
+            identity_id: event.identity_id,
+            identity_type: event.identity_type,
+            status: IdentityStatus::Pending,
+        },
+        IdentityMetadata {
+            created_at: event.created_at,
+            updated_at: event.created_at,
+            created_by: event.created_by,
+            ..Default::default()
+        },
+        IdentityVerification {
+            verification_level: VerificationLevel::Unverified,
+            verified_at: None,
+            verified_by: None,
+            verification_method: None,
+        },
+    )
+}
+
 /// System to update identity status
 pub fn update_identity_system(
-    mut events: EventReader<UpdateIdentityCommand>,
-    mut updated_events: EventWriter<IdentityUpdated>,
+    mut events: EventReader<CommandEnvelope<UpdateIdentityCommand>>,
+    mut updated_events: DomainEventWriter<IdentityUpdated>,
+    mut rejected_events: EventWriter<IdentityUpdateRejected>,
     mut identities: Query<(&mut IdentityEntity, &mut IdentityMetadata)>,
+    index: Res<IdentityIndex>,
 ) {
-    for event in events.read() {
-        for (mut identity, mut metadata) in identities.iter_mut() {
-            if identity.identity_id == event.identity_id {
-                // Validate through aggregate
-                match IdentityAggregate::validate_update(&identity, event) {
-                    Ok(_) => {
-                        // Update status if provided
-                        if let Some(new_status) = event.new_status {
-                            let old_status = identity.status;
-                            identity.status = new_status;
-
-                            // Update metadata
-                            metadata.updated_at = chrono::Utc::now();
-                            metadata.version += 1;
-
-                            // Emit updated event
-                            updated_events.write(IdentityUpdated {
-                                identity_id: event.identity_id,
-                                old_status,
-                                new_status,
-                                updated_by: event.updated_by,
-                                updated_at: chrono::Utc::now(),
-                            });
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("Failed to update identity: {e}");
-                    }
-                }
-            }
+    for envelope in events.read() {
+        let event = &envelope.command;
+
+        let Some((mut identity, mut metadata)) = index
+            .identity(event.identity_id)
+            .and_then(|entity| identities.get_mut(entity).ok())
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::IdentityNotFound(event.identity_id),
+            ));
+            continue;
+        };
+
+        // Validate through aggregate
+        if let Err(e) = IdentityAggregate::validate_update(&identity, event) {
+            rejected_events.write(CommandRejected::new(envelope, e));
+            continue;
         }
+
+        // Update status if provided
+        let Some(new_status) = event.new_status else {
+            continue;
+        };
+
+        let old_status = identity.status;
+        identity.status = new_status;
+
+        // Update metadata
+        metadata.updated_at = chrono::Utc::now();
+        metadata.version += 1;
+
+        // Emit updated event
+        updated_events.write(IdentityUpdated {
+            identity_id: event.identity_id,
+            old_status,
+            new_status,
+            updated_by: event.updated_by,
+            updated_at: chrono::Utc::now(),
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        });
     }
 }
 
 /// System to merge duplicate identities
 pub fn merge_identities_system(
-    mut events: EventReader<MergeIdentitiesCommand>,
-    mut merged_events: EventWriter<IdentitiesMerged>,
+    mut events: EventReader<CommandEnvelope<MergeIdentitiesCommand>>,
+    mut merged_events: DomainEventWriter<IdentitiesMerged>,
+    mut rejected_events: EventWriter<IdentityMergeRejected>,
     mut identities: Query<(&mut IdentityEntity, &IdentityVerification)>,
-    relationships: Query<&IdentityRelationship>,
-    workflows: Query<&IdentityWorkflow>,
+    mut index: ResMut<IdentityIndex>,
 ) {
-    for event in events.read() {
+    for envelope in events.read() {
+        let event = &envelope.command;
+
         // Find source and target identities
-        let mut source_data = None;
-        let mut target_data = None;
+        let source_entity = index.identity(event.source_identity);
+        let source_data = source_entity
+            .and_then(|entity| identities.get(entity).ok())
+            .map(|(identity, verification)| (identity.clone(), verification.clone()));
+        let target_data = index
+            .identity(event.target_identity)
+            .and_then(|entity| identities.get(entity).ok())
+            .map(|(identity, verification)| (identity.clone(), verification.clone()));
 
-        // Collect identity data for validation
-        for (identity, verification) in identities.iter() {
-            if identity.identity_id == event.source_identity {
-                source_data = Some((identity.clone(), verification.clone()));
-            }
-            if identity.identity_id == event.target_identity {
-                target_data = Some((identity.clone(), verification.clone()));
-            }
+        let (Some(source_entity), Some((source_identity, source_verification))) =
+            (source_entity, source_data)
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::IdentityNotFound(event.source_identity),
+            ));
+            continue;
+        };
+
+        let Some((target_identity, target_verification)) = target_data else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::IdentityNotFound(event.target_identity),
+            ));
+            continue;
+        };
+
+        // Validate through aggregate
+        if let Err(e) = IdentityAggregate::validate_merge(
+            &source_identity,
+            &target_identity,
+            &source_verification,
+            &target_verification,
+        ) {
+            rejected_events.write(CommandRejected::new(envelope, e));
+            continue;
         }
 
-        if let (
-            Some((source_identity, source_verification)),
-            Some((target_identity, target_verification)),
-        ) = (source_data, target_data)
-        {
-            // Validate through aggregate
-            match IdentityAggregate::validate_merge(
-                &source_identity,
-                &target_identity,
-                &source_verification,
-                &target_verification,
-            ) {
-                Ok(_) => {
-                    // Update source identity status
-                    for (mut identity, _) in identities.iter_mut() {
-                        if identity.identity_id == event.source_identity {
-                            identity.status = IdentityStatus::Merged {
-                                merged_into: event.target_identity,
-                            };
-                        }
-                    }
-
-                    // Count migrated relationships and workflows
-                    let migrated_relationships = relationships
-                        .iter()
-                        .filter(|r| r.source_identity == event.source_identity)
-                        .count();
-
-                    let migrated_workflows = workflows
-                        .iter()
-                        .filter(|w| w.identity_id == event.source_identity)
-                        .count();
-
-                    // Emit merged event
-                    merged_events.write(IdentitiesMerged {
-                        source_identity: event.source_identity,
-                        target_identity: event.target_identity,
-                        merged_by: event.merged_by,
-                        merged_at: chrono::Utc::now(),
-                        migrated_relationships,
-                        migrated_workflows,
-                        retained_verification_level: source_verification
-                            .verification_level
-                            .max(target_verification.verification_level),
-                    });
-                }
-                Err(e) => {
-                    eprintln!("Failed to merge identities: {e}");
-                }
-            }
+        // Update source identity status
+        if let Ok((mut identity, _)) = identities.get_mut(source_entity) {
+            identity.status = IdentityStatus::Merged {
+                merged_into: event.target_identity,
+            };
         }
+        index.record_merge(event.source_identity, event.target_identity);
+
+        // Count migrated relationships and workflows
+        let migrated_relationships = index.outgoing(event.source_identity).count();
+        let migrated_workflows = index.workflows_for(event.source_identity).count();
+
+        // Emit merged event
+        merged_events.write(IdentitiesMerged {
+            source_identity: event.source_identity,
+            target_identity: event.target_identity,
+            merged_by: event.merged_by,
+            merged_at: chrono::Utc::now(),
+            migrated_relationships,
+            migrated_workflows,
+            retained_verification_level: source_verification
+                .verification_level
+                .max(target_verification.verification_level),
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        });
     }
 }
 
 /// System to archive identities
 pub fn archive_identity_system(
-    mut events: EventReader<ArchiveIdentityCommand>,
-    mut archived_events: EventWriter<IdentityArchived>,
+    mut events: EventReader<CommandEnvelope<ArchiveIdentityCommand>>,
+    mut archived_events: DomainEventWriter<IdentityArchived>,
+    mut rejected_events: EventWriter<IdentityArchiveRejected>,
     mut identities: Query<(&mut IdentityEntity, &mut IdentityMetadata)>,
-    relationships: Query<&IdentityRelationship>,
+    index: Res<IdentityIndex>,
 ) {
-    for event in events.read() {
-        for (mut identity, mut metadata) in identities.iter_mut() {
-            if identity.identity_id == event.identity_id {
-                // Count active relationships
-                let active_relationships = relationships
-                    .iter()
-                    .filter(|r| {
-                        r.source_identity == event.identity_id
-                            || r.target_identity == event.identity_id
-                    })
-                    .count();
-
-                // Validate through aggregate
-                match IdentityAggregate::validate_archive(
-                    &identity,
-                    active_relationships,
-                    event.force,
-                ) {
-                    Ok(_) => {
-                        // Update status
-                        let old_status = identity.status;
-                        identity.status = IdentityStatus::Archived;
-
-                        // Update metadata
-                        metadata.updated_at = chrono::Utc::now();
-                        metadata.version += 1;
-
-                        // Emit archived event
-                        archived_events.write(IdentityArchived {
-                            identity_id: event.identity_id,
-                            previous_status: old_status,
-                            archived_by: event.archived_by,
-                            archived_at: chrono::Utc::now(),
-                            reason: event.reason.clone(),
-                        });
-                    }
-                    Err(e) => {
-                        eprintln!("Failed to archive identity: {e}");
-                    }
-                }
-            }
+    for envelope in events.read() {
+        let event = &envelope.command;
+
+        let Some((mut identity, mut metadata)) = index
+            .identity(event.identity_id)
+            .and_then(|entity| identities.get_mut(entity).ok())
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::IdentityNotFound(event.identity_id),
+            ));
+            continue;
+        };
+
+        // Count active relationships
+        let active_relationships = index.relationships_of(event.identity_id).count();
+
+        // Validate through aggregate
+        if let Err(e) =
+            IdentityAggregate::validate_archive(&identity, active_relationships, event.force)
+        {
+            rejected_events.write(CommandRejected::new(envelope, e));
+            continue;
         }
+
+        // Update status
+        let old_status = identity.status;
+        identity.status = IdentityStatus::Archived;
+
+        // Update metadata
+        metadata.updated_at = chrono::Utc::now();
+        metadata.version += 1;
+
+        // Emit archived event
+        archived_events.write(IdentityArchived {
+            identity_id: event.identity_id,
+            previous_status: old_status,
+            archived_by: event.archived_by,
+            archived_at: chrono::Utc::now(),
+            reason: event.reason.clone(),
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        });
     }
 }
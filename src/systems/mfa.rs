@@ -0,0 +1,323 @@
+//! TOTP multi-factor authentication systems for person identities
+//!
+//! [`IdentityMfa`] is only changed through the `record_*` functions below,
+//! which replay calls with the same events. Wrong codes are counted in
+//! [`IdentityCredentials`] and lock the account like wrong passwords.
+
+use super::authentication::{lock_if_due, release_expired_lock, LockoutEventWriters};
+use crate::{
+    aggregate::IdentityAggregate, authentication::*, commands::*, components::*, events::*,
+    persistence::DomainEventWriter, IdentityError, IdentityIndex, IdentityResult,
+};
+use bevy::ecs::prelude::*;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+/// System to enable TOTP MFA for person identities
+#[allow(clippy::too_many_arguments)]
+pub fn enable_mfa_system(
+    mut commands: Commands,
+    mut events: EventReader<CommandEnvelope<EnableMfaCommand>>,
+    mut enabled_events: DomainEventWriter<MfaEnabled>,
+    mut rejected_events: EventWriter<MfaEnableRejected>,
+    identities: Query<(&IdentityEntity, Has<IdentityCredentials>, Has<IdentityMfa>)>,
+    index: Res<IdentityIndex>,
+    policy: Res<AuthenticationPolicy>,
+    clock: Res<AuthenticationClock>,
+) {
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = clock.now();
+
+        let Some((entity, (identity, has_password, has_mfa))) = index
+            .identity(event.identity_id)
+            .and_then(|entity| identities.get(entity).ok().map(|found| (entity, found)))
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::IdentityNotFound(event.identity_id),
+            ));
+            continue;
+        };
+
+        let result = IdentityAggregate::validate_authentication(identity).and_then(|_| {
+            if !has_password {
+                Err(IdentityError::InvalidOperation(
+                    "A password must be set before enabling MFA".to_string(),
+                ))
+            } else if has_mfa {
+                Err(IdentityError::InvalidOperation(
+                    "MFA is already enabled".to_string(),
+                ))
+            } else {
+                // The authenticator app must produce a code for the secret
+                verify_totp(
+                    &event.secret,
+                    event.confirmation_code.expose(),
+                    now,
+                    &policy.totp,
+                    None,
+                )
+                .ok_or_else(|| {
+                    IdentityError::VerificationFailed("Invalid MFA confirmation code".to_string())
+                })
+            }
+        });
+        let confirmed_step = match result {
+            Ok(step) => step,
+            Err(e) => {
+                rejected_events.write(CommandRejected::new(envelope, e));
+                continue;
+            }
+        };
+
+        let enabled = MfaEnabled {
+            identity_id: event.identity_id,
+            secret: event.secret.clone(),
+            backup_code_hashes: event
+                .backup_codes
+                .iter()
+                .map(|code| hash_backup_code(code.expose()))
+                .collect(),
+            confirmed_step,
+            enabled_at: now,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        };
+        commands.entity(entity).insert(mfa_from(&enabled));
+        enabled_events.write(enabled);
+    }
+}
+
+/// System to ask identities with MFA enabled for a second factor
+pub fn issue_mfa_challenge_system(
+    mut events: EventReader<CommandEnvelope<IssueMfaChallengeCommand>>,
+    mut issued_events: DomainEventWriter<MfaChallengeIssued>,
+    mut rejected_events: EventWriter<MfaChallengeRejected>,
+    mut identities: Query<(&IdentityEntity, Option<&mut IdentityMfa>)>,
+    index: Res<IdentityIndex>,
+    clock: Res<AuthenticationClock>,
+) {
+    for envelope in events.read() {
+        let event = &envelope.command;
+
+        let Some((identity, mfa)) = index
+            .identity(event.identity_id)
+            .and_then(|entity| identities.get_mut(entity).ok())
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::IdentityNotFound(event.identity_id),
+            ));
+            continue;
+        };
+
+        if let Err(e) = IdentityAggregate::validate_authentication(identity) {
+            rejected_events.write(CommandRejected::new(envelope, e));
+            continue;
+        }
+        let Some(mut mfa) = mfa else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::InvalidOperation("MFA is not enabled".to_string()),
+            ));
+            continue;
+        };
+
+        let issued = MfaChallengeIssued {
+            identity_id: event.identity_id,
+            challenge: MfaChallenge::issue(clock.now()),
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        };
+        record_mfa_challenge_issued(&mut mfa, &issued);
+        issued_events.write(issued);
+    }
+}
+
+/// System to verify answers to MFA challenges
+///
+/// A TOTP code is accepted once within the drift window of the
+/// [`TotpConfig`]; otherwise an unused backup code is consumed.
+#[allow(clippy::too_many_arguments)]
+pub fn verify_mfa_system(
+    mut events: EventReader<CommandEnvelope<VerifyMfaCommand>>,
+    mut mfa_events: MfaEventWriters,
+    mut lockout_events: LockoutEventWriters,
+    mut rejected_events: EventWriter<MfaVerificationRejected>,
+    mut identities: Query<(&IdentityEntity, &mut IdentityMfa, &mut IdentityCredentials)>,
+    index: Res<IdentityIndex>,
+    policy: Res<AuthenticationPolicy>,
+    clock: Res<AuthenticationClock>,
+) {
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = clock.now();
+        let ids = (envelope.correlation_id, Some(envelope.command_id));
+
+        let Some((identity, mut mfa, mut credentials)) = index
+            .identity(event.identity_id)
+            .and_then(|entity| identities.get_mut(entity).ok())
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::InvalidCredentials,
+            ));
+            continue;
+        };
+
+        if let Err(e) = IdentityAggregate::validate_authentication(identity).and_then(|_| {
+            release_expired_lock(&mut credentials, identity, now, ids, &mut lockout_events)
+        }) {
+            rejected_events.write(CommandRejected::new(envelope, e));
+            continue;
+        }
+
+        let open = mfa
+            .challenge
+            .as_ref()
+            .is_some_and(|c| c.challenge_id == event.challenge_id && c.is_open(now));
+        if !open {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::VerificationFailed("MFA challenge is not open".to_string()),
+            ));
+            continue;
+        }
+
+        if let Err(e) = check_mfa_code(
+            &mut mfa,
+            &mut credentials,
+            identity,
+            event.challenge_id,
+            &event.code,
+            &policy,
+            now,
+            ids,
+            &mut mfa_events,
+            &mut lockout_events,
+        ) {
+            rejected_events.write(CommandRejected::new(envelope, e));
+        }
+    }
+}
+
+/// Writers for the events of MFA code checks
+#[derive(bevy::ecs::system::SystemParam)]
+pub struct MfaEventWriters<'w> {
+    verified: DomainEventWriter<'w, MfaVerified>,
+    failed: DomainEventWriter<'w, MfaFailed>,
+}
+
+/// Check a TOTP or backup code of an identity whose account is not locked
+///
+/// A wrong code counts towards the lockout policy like a wrong password. A
+/// right answer to a challenge of [`IdentityMfa`] clears the failures; one
+/// to the challenge of an attempt leaves them to the attempt's decision.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn check_mfa_code(
+    mfa: &mut IdentityMfa,
+    credentials: &mut IdentityCredentials,
+    identity: &IdentityEntity,
+    challenge_id: Uuid,
+    code: &MfaCode,
+    policy: &AuthenticationPolicy,
+    now: DateTime<Utc>,
+    (correlation_id, causation_id): (Uuid, Option<Uuid>),
+    events: &mut MfaEventWriters,
+    lockout_events: &mut LockoutEventWriters,
+) -> IdentityResult<()> {
+    let factor = verify_totp(
+        &mfa.secret,
+        code.expose(),
+        now,
+        &policy.totp,
+        mfa.last_totp_step,
+    )
+    .map(|step| MfaFactor::Totp { step })
+    .or_else(|| {
+        match_backup_code(&mfa.backup_code_hashes, code.expose())
+            .map(|code_hash| MfaFactor::BackupCode { code_hash })
+    });
+
+    let Some(factor) = factor else {
+        let failed = MfaFailed {
+            identity_id: identity.identity_id,
+            challenge_id,
+            failed_attempts: credentials.failed_attempts + 1,
+            failed_at: now,
+            correlation_id,
+            causation_id,
+        };
+        record_mfa_failure(credentials, &failed);
+        events.failed.write(failed);
+        lock_if_due(
+            credentials,
+            identity,
+            &policy.lockout,
+            now,
+            (correlation_id, causation_id),
+            lockout_events,
+        );
+        return Err(IdentityError::InvalidCredentials);
+    };
+
+    let verified = MfaVerified {
+        identity_id: identity.identity_id,
+        challenge_id,
+        factor,
+        verified_at: now,
+        correlation_id,
+        causation_id,
+    };
+    record_mfa_verified(mfa, credentials, &verified);
+    events.verified.write(verified);
+    Ok(())
+}
+
+/// MFA state of an identity that enabled it
+pub(crate) fn mfa_from(event: &MfaEnabled) -> IdentityMfa {
+    IdentityMfa {
+        secret: event.secret.clone(),
+        backup_code_hashes: event.backup_code_hashes.clone(),
+        last_totp_step: Some(event.confirmed_step),
+        enabled_at: event.enabled_at,
+        last_verified_at: None,
+        challenge: None,
+    }
+}
+
+/// Open a challenge, replacing any earlier one
+pub(crate) fn record_mfa_challenge_issued(mfa: &mut IdentityMfa, event: &MfaChallengeIssued) {
+    mfa.challenge = Some(event.challenge.clone());
+}
+
+/// Close the answered challenge and use up its factor
+pub(crate) fn record_mfa_verified(
+    mfa: &mut IdentityMfa,
+    credentials: &mut IdentityCredentials,
+    event: &MfaVerified,
+) {
+    match &event.factor {
+        MfaFactor::Totp { step } => mfa.last_totp_step = Some(*step),
+        MfaFactor::BackupCode { code_hash } => {
+            mfa.backup_code_hashes.retain(|hash| hash != code_hash)
+        }
+    }
+    // Answers to an authentication attempt leave other challenges open, and
+    // leave the failures until the attempt is granted
+    if mfa
+        .challenge
+        .as_ref()
+        .is_some_and(|challenge| challenge.challenge_id == event.challenge_id)
+    {
+        mfa.challenge = None;
+        credentials.failed_attempts = 0;
+    }
+    mfa.last_verified_at = Some(event.verified_at);
+}
+
+/// Record a wrong code
+pub(crate) fn record_mfa_failure(credentials: &mut IdentityCredentials, event: &MfaFailed) {
+    credentials.failed_attempts = event.failed_attempts;
+}
@@ -3,12 +3,24 @@
 //! This module contains all systems that operate on identity components.
 //! Systems implement the behavior and business logic of the domain.
 
+pub mod api_key;
+pub mod approval;
+pub mod attempt;
+pub mod authentication;
+pub mod claims;
+pub mod credential;
+pub mod did;
+pub mod keys;
 pub mod lifecycle;
+pub mod mfa;
 pub mod projection;
 pub mod relationship;
+pub mod session;
+pub mod token;
 pub mod verification;
 pub mod workflow;
 pub mod markers;
+pub mod outcomes;
 
 // Re-export key systems
 pub use lifecycle::{
@@ -16,22 +28,62 @@ pub use lifecycle::{
     update_identity_system,
 };
 
+pub use claims::{
+    add_claim_system, expire_claims_system, remind_claim_reverification_system,
+    revoke_claim_system, update_claim_system,
+};
+
+pub use api_key::{
+    authenticate_api_key_system, issue_api_key_system, revoke_api_key_system,
+    rotate_api_key_system,
+};
+
+pub use approval::{assign_approvals_system, decide_approval_system, escalate_approvals_system};
+
+pub use authentication::{authenticate_password_system, set_password_system};
+
+pub use mfa::{enable_mfa_system, issue_mfa_challenge_system, verify_mfa_system};
+
+pub use attempt::{begin_authentication_system, submit_authentication_factor_system};
+
+pub use session::{
+    refresh_session_system, revoke_all_sessions_system, revoke_session_system,
+    start_session_system,
+};
+
+pub use keys::{generate_key_system, import_key_system, revoke_key_system, rotate_key_system};
+
+pub use credential::{export_credential_system, import_credential_system};
+
+pub use did::{publish_did_system, rotate_did_key_system, sync_did_resolver_system};
+
+pub use token::{issue_identity_token_system, validate_identity_token_system};
+
 pub use relationship::{
-    establish_relationship_system, expire_relationships_system, traverse_relationships_system,
-    validate_relationships_system,
+    establish_relationship_system, expire_relationships_system, revoke_relationship_system,
+    traverse_relationships_system, validate_relationships_system,
 };
 
 pub use workflow::{
-    complete_workflow_system, process_workflow_step_system, start_workflow_system,
-    timeout_workflows_system,
+    cancel_workflow_system, complete_workflow_system, pause_workflow_system,
+    process_workflow_step_system, resume_workflow_system, retry_workflow_step_system,
+    start_workflow_system, timeout_workflows_system,
 };
 
 pub use verification::{
-    complete_verification_system, process_verification_system, start_verification_system,
+    advance_verification_system, complete_verification_system, confirm_verification_code_system,
+    poll_verification_providers_system, process_verification_system,
+    send_verification_code_system, start_verification_system,
 };
 
 pub use projection::{
-    create_projection_system, sync_projections_system, validate_projection_system,
+    create_projection_system, refresh_projections_system, sync_projections_system,
+    validate_projection_system,
+};
+
+pub use outcomes::{
+    expire_command_outcomes_system, resolve_command_outcomes_system,
+    resolve_command_rejections_system,
 };
 
 // Re-export all systems
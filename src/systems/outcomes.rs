@@ -0,0 +1,48 @@
+//! Command outcome resolution systems
+
+use crate::{commands::*, events::CommandRejected};
+use bevy::ecs::prelude::*;
+
+/// System resolving command outcomes from the events they caused
+pub fn resolve_command_outcomes_system<E: CorrelatedEvent>(
+    mut events: EventReader<E>,
+    outcomes: Res<CommandOutcomes>,
+) {
+    for event in events.read() {
+        let Some(command_id) = event.causation_id() else {
+            continue;
+        };
+
+        outcomes.resolve(
+            command_id,
+            CommandOutcome::Accepted {
+                command_id,
+                correlation_id: event.correlation_id(),
+                event_type: E::EVENT_TYPE.to_string(),
+                event: serde_json::to_value(event).unwrap_or(serde_json::Value::Null),
+            },
+        );
+    }
+}
+
+/// System resolving command outcomes from rejections
+pub fn resolve_command_rejections_system<C: Clone + Send + Sync + 'static>(
+    mut events: EventReader<CommandRejected<C>>,
+    outcomes: Res<CommandOutcomes>,
+) {
+    for event in events.read() {
+        outcomes.resolve(
+            event.command_id,
+            CommandOutcome::Rejected {
+                command_id: event.command_id,
+                correlation_id: event.correlation_id,
+                error: event.error.clone(),
+            },
+        );
+    }
+}
+
+/// System timing out outcome subscriptions that no event or rejection resolved
+pub fn expire_command_outcomes_system(outcomes: Res<CommandOutcomes>) {
+    outcomes.expire(std::time::Instant::now());
+}
@@ -4,23 +4,24 @@ use bevy::ecs::prelude::*;
 use uuid::Uuid;
 
 use crate::{
-    commands::CreateProjectionCommand,
-    components::{
-        IdentityEntity, IdentityProjection, IdentityVerification,
-        ProjectionSyncStatus, ProjectionType,
-    },
+    commands::{CommandEnvelope, CreateProjectionCommand, SyncProjectionsCommand},
+    components::{IdentityProjection, ProjectionSyncStatus, ProjectionType},
     events::{
-        IdentityCreated, IdentityLinkedToOrganization, IdentityLinkedToPerson, ProjectionCreated,
+        CommandRejected, IdentityCreated, IdentityLinkedToOrganization, IdentityLinkedToPerson,
+        ProjectionCreated, ProjectionSyncRejected, ProjectionsSynced,
     },
+    IdentityError, IdentityIndex,
 };
 
 /// System to create projections
 pub fn create_projection_system(
     mut commands: Commands,
-    mut events: EventReader<CreateProjectionCommand>,
+    mut events: EventReader<CommandEnvelope<CreateProjectionCommand>>,
     mut created_events: EventWriter<ProjectionCreated>,
 ) {
-    for event in events.read() {
+    for envelope in events.read() {
+        let event = &envelope.command;
+
         // Create the projection entity
         commands.spawn(IdentityProjection {
             identity_id: event.identity_id,
@@ -39,6 +40,8 @@ pub fn create_projection_system(
             target_domain: event.target_domain.clone(),
             target_id: Uuid::new_v4(),
             created_at: chrono::Utc::now(),
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
         });
     }
 }
@@ -71,6 +74,8 @@ pub fn sync_projections_system(
                             identity_id: event.identity_id,
                             person_id: target_id,
                             linked_at: chrono::Utc::now(),
+                            correlation_id: event.correlation_id,
+                            causation_id: event.causation_id,
                         });
                     }
                 }
@@ -81,6 +86,8 @@ pub fn sync_projections_system(
                             organization_id: target_id,
                             role: None,
                             linked_at: chrono::Utc::now(),
+                            correlation_id: event.correlation_id,
+                            causation_id: event.causation_id,
                         });
                     }
                 }
@@ -90,18 +97,80 @@ pub fn sync_projections_system(
     }
 }
 
+/// System to re-synchronize projections on request
+///
+/// Projections matching the command's identity and type are marked synced
+/// while their source identity exists and failed otherwise. Projections that
+/// are already synced are skipped unless the command forces a sync.
+pub fn refresh_projections_system(
+    mut events: EventReader<CommandEnvelope<SyncProjectionsCommand>>,
+    mut synced_events: EventWriter<ProjectionsSynced>,
+    mut rejected_events: EventWriter<ProjectionSyncRejected>,
+    mut projections: Query<&mut IdentityProjection>,
+    index: Res<IdentityIndex>,
+) {
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = chrono::Utc::now();
+
+        if let Some(identity_id) = event.identity_id {
+            if !index.contains_identity(identity_id) {
+                rejected_events.write(CommandRejected::new(
+                    envelope,
+                    IdentityError::IdentityNotFound(identity_id),
+                ));
+                continue;
+            }
+        }
+
+        let mut projections_synced = 0;
+        let mut sync_errors = 0;
+        for mut projection in projections.iter_mut() {
+            let selected = event
+                .identity_id
+                .is_none_or(|id| id == projection.identity_id)
+                && event
+                    .projection_type
+                    .as_ref()
+                    .is_none_or(|t| *t == projection.projection_type);
+            if !selected || (!event.force && projection.sync_status == ProjectionSyncStatus::Synced)
+            {
+                continue;
+            }
+
+            projection.last_sync = now;
+            if index.contains_identity(projection.identity_id) {
+                projection.sync_status = ProjectionSyncStatus::Synced;
+                projection.last_synced = now;
+                projections_synced += 1;
+            } else {
+                projection.sync_status =
+                    ProjectionSyncStatus::Failed("Source identity not found".to_string());
+                sync_errors += 1;
+            }
+        }
+
+        synced_events.write(ProjectionsSynced {
+            identity_id: event.identity_id,
+            projections_synced,
+            sync_errors,
+            synced_at: now,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        });
+    }
+}
+
 /// System to validate projections
 pub fn validate_projection_system(
     mut _commands: Commands,
-    identities: Query<(&IdentityEntity, &IdentityVerification)>,
     projections: Query<&IdentityProjection>,
+    index: Res<IdentityIndex>,
 ) {
     // Basic validation logic
     for projection in projections.iter() {
         // Check if source identity exists
-        let _identity_valid = identities
-            .iter()
-            .any(|(e, _)| e.identity_id == projection.identity_id);
+        let _identity_valid = index.contains_identity(projection.identity_id);
 
         // In a real implementation, would validate against target domain
         // and emit validation events
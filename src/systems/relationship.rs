@@ -1,40 +1,51 @@
 //! Identity relationship systems
 
-use crate::{aggregate::IdentityAggregate, commands::*, components::*, events::*};
+use crate::{
+    aggregate::IdentityAggregate, commands::*, components::*, events::*,
+    persistence::DomainEventWriter, IdentityError, IdentityIndex,
+};
 use bevy::ecs::prelude::*;
 use uuid::Uuid;
 
 /// System to establish relationships between identities
 pub fn establish_relationship_system(
     mut commands: Commands,
-    mut events: EventReader<EstablishRelationshipCommand>,
-    mut established_events: EventWriter<RelationshipEstablished>,
-    identities: Query<&IdentityEntity>,
+    mut events: EventReader<CommandEnvelope<EstablishRelationshipCommand>>,
+    mut established_events: DomainEventWriter<RelationshipEstablished>,
+    mut rejected_events: EventWriter<RelationshipEstablishmentRejected>,
     existing_relationships: Query<&IdentityRelationship>,
+    index: Res<IdentityIndex>,
 ) {
-    for event in events.read() {
+    for envelope in events.read() {
+        let event = &envelope.command;
+
         // Validate identities exist
-        let from_exists = identities
-            .iter()
-            .any(|i| i.identity_id == event.from_identity);
-        let to_exists = identities
-            .iter()
-            .any(|i| i.identity_id == event.to_identity);
-
-        if !from_exists || !to_exists {
-            eprintln!("Cannot establish relationship: one or both identities don't exist");
+        let missing_identity = [event.from_identity, event.to_identity]
+            .into_iter()
+            .find(|id| !index.contains_identity(*id));
+
+        if let Some(identity_id) = missing_identity {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::IdentityNotFound(identity_id),
+            ));
             continue;
         }
 
         // Check for duplicate relationships
-        let duplicate = existing_relationships.iter().any(|r| {
-            r.source_identity == event.from_identity
-                && r.target_identity == event.to_identity
-                && r.relationship_type == event.relationship_type
-        });
+        let duplicate = index
+            .outgoing(event.from_identity)
+            .filter_map(|entity| existing_relationships.get(entity).ok())
+            .any(|r| {
+                r.target_identity == event.to_identity
+                    && r.relationship_type == event.relationship_type
+            });
 
         if duplicate {
-            eprintln!("Relationship already exists");
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::RelationshipConflict("Relationship already exists".to_string()),
+            ));
             continue;
         }
 
@@ -49,7 +60,7 @@ pub fn establish_relationship_system(
 
                 // Spawn the relationship entity
                 commands.spawn((IdentityRelationship {
-                    relationship_id: Uuid::new_v4(),
+                    relationship_id,
                     source_identity: event.from_identity,
                     target_identity: event.to_identity,
                     relationship_type: event.relationship_type.clone(),
@@ -65,12 +76,15 @@ pub fn establish_relationship_system(
                     from_identity: event.from_identity,
                     to_identity: event.to_identity,
                     relationship_type: event.relationship_type.clone(),
+                    rules: event.rules.clone(),
                     established_by: event.established_by,
                     established_at: chrono::Utc::now(),
+                    correlation_id: envelope.correlation_id,
+                    causation_id: Some(envelope.command_id),
                 });
             }
             Err(e) => {
-                eprintln!("Failed to establish relationship: {e}");
+                rejected_events.write(CommandRejected::new(envelope, e));
             }
         }
     }
@@ -79,69 +93,133 @@ pub fn establish_relationship_system(
 /// System to validate relationships
 pub fn validate_relationships_system(
     mut commands: Commands,
-    mut events: EventReader<ValidateRelationshipCommand>,
-    mut validated_events: EventWriter<RelationshipValidated>,
-    relationships: Query<(&IdentityRelationship, Entity)>,
+    mut events: EventReader<CommandEnvelope<ValidateRelationshipCommand>>,
+    mut validated_events: DomainEventWriter<RelationshipValidated>,
+    mut rejected_events: EventWriter<RelationshipValidationRejected>,
+    relationships: Query<&IdentityRelationship>,
     identities: Query<&IdentityEntity>,
+    index: Res<IdentityIndex>,
 ) {
-    for event in events.read() {
-        for (relationship, entity) in relationships.iter() {
-            if relationship.relationship_id == event.relationship_id {
-                // Check if both identities still exist and are active
-                let from_active = identities
-                    .iter()
-                    .find(|i| i.identity_id == relationship.source_identity)
-                    .map(|i| matches!(i.status, IdentityStatus::Active))
-                    .unwrap_or(false);
-
-                let to_active = identities
-                    .iter()
-                    .find(|i| i.identity_id == relationship.target_identity)
-                    .map(|i| matches!(i.status, IdentityStatus::Active))
-                    .unwrap_or(false);
-
-                let is_valid = from_active && to_active;
-
-                // Check expiration
-                let expired = relationship
-                    .expires_at
-                    .map(|exp| exp < chrono::Utc::now())
-                    .unwrap_or(false);
-
-                if !is_valid || expired {
-                    // Remove invalid relationship
-                    commands.entity(entity).despawn();
-
-                    validated_events.write(RelationshipValidated {
-                        relationship_id: event.relationship_id,
-                        is_valid: false,
-                        reason: if !is_valid {
-                            "One or both identities are not active".to_string()
-                        } else {
-                            "Relationship has expired".to_string()
-                        },
-                        validated_at: chrono::Utc::now(),
-                    });
+    for envelope in events.read() {
+        let event = &envelope.command;
+
+        let Some((relationship, entity)) = index
+            .relationship(event.relationship_id)
+            .and_then(|entity| relationships.get(entity).ok().map(|r| (r, entity)))
+        else {
+            rejected_events.write(CommandRejected::new(envelope, IdentityError::NotFound));
+            continue;
+        };
+
+        let is_active = |identity_id| {
+            index
+                .identity(identity_id)
+                .and_then(|entity| identities.get(entity).ok())
+                .map(|i| matches!(i.status, IdentityStatus::Active))
+                .unwrap_or(false)
+        };
+
+        // Check if both identities still exist and are active
+        let from_active = is_active(relationship.source_identity);
+        let to_active = is_active(relationship.target_identity);
+
+        let is_valid = from_active && to_active;
+
+        // Check expiration
+        let expired = relationship
+            .expires_at
+            .map(|exp| exp < chrono::Utc::now())
+            .unwrap_or(false);
+
+        if !is_valid || expired {
+            // Remove invalid relationship
+            commands.entity(entity).despawn();
+
+            validated_events.write(RelationshipValidated {
+                relationship_id: event.relationship_id,
+                is_valid: false,
+                reason: if !is_valid {
+                    "One or both identities are not active".to_string()
                 } else {
-                    validated_events.write(RelationshipValidated {
-                        relationship_id: event.relationship_id,
-                        is_valid: true,
-                        reason: "Relationship is valid".to_string(),
-                        validated_at: chrono::Utc::now(),
-                    });
-                }
-            }
+                    "Relationship has expired".to_string()
+                },
+                validated_at: chrono::Utc::now(),
+                correlation_id: envelope.correlation_id,
+                causation_id: Some(envelope.command_id),
+            });
+        } else {
+            validated_events.write(RelationshipValidated {
+                relationship_id: event.relationship_id,
+                is_valid: true,
+                reason: "Relationship is valid".to_string(),
+                validated_at: chrono::Utc::now(),
+                correlation_id: envelope.correlation_id,
+                causation_id: Some(envelope.command_id),
+            });
         }
     }
 }
 
+/// System to revoke relationships
+///
+/// Either end of a relationship or the identity that established it may
+/// revoke it.
+pub fn revoke_relationship_system(
+    mut commands: Commands,
+    mut events: EventReader<CommandEnvelope<RevokeRelationshipCommand>>,
+    mut revoked_events: DomainEventWriter<RelationshipRevoked>,
+    mut rejected_events: EventWriter<RelationshipRevocationRejected>,
+    relationships: Query<&IdentityRelationship>,
+    index: Res<IdentityIndex>,
+) {
+    for envelope in events.read() {
+        let event = &envelope.command;
+
+        let Some((relationship, entity)) = index
+            .relationship(event.relationship_id)
+            .and_then(|entity| relationships.get(entity).ok().map(|r| (r, entity)))
+        else {
+            rejected_events.write(CommandRejected::new(envelope, IdentityError::NotFound));
+            continue;
+        };
+
+        let may_revoke = event.revoked_by == relationship.source_identity
+            || event.revoked_by == relationship.target_identity
+            || Some(event.revoked_by) == relationship.established_by;
+        if !may_revoke {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::PermissionDenied(format!(
+                    "{} is not a party to relationship {}",
+                    event.revoked_by, event.relationship_id
+                )),
+            ));
+            continue;
+        }
+
+        commands.entity(entity).despawn();
+
+        revoked_events.write(RelationshipRevoked {
+            relationship_id: event.relationship_id,
+            revoked_by: event.revoked_by,
+            revoked_at: chrono::Utc::now(),
+            reason: Some(event.reason.clone()),
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        });
+    }
+}
+
 /// System to traverse relationship graphs
 pub fn traverse_relationships_system(
-    mut events: EventReader<TraverseRelationshipsCommand>,
+    mut events: EventReader<CommandEnvelope<TraverseRelationshipsCommand>>,
     mut traversed_events: EventWriter<RelationshipsTraversed>,
     relationships: Query<&IdentityRelationship>,
+    index: Res<IdentityIndex>,
 ) {
-    for event in events.read() {
+    for envelope in events.read() {
+        let event = &envelope.command;
+
         let mut visited = std::collections::HashSet::new();
         let mut paths = Vec::new();
         let mut queue = std::collections::VecDeque::new();
@@ -159,39 +237,40 @@ pub fn traverse_relationships_system(
             }
 
             // Find relationships from current identity
-            for relationship in relationships.iter() {
-                if relationship.source_identity == current {
-                    // Check if relationship type matches filter
-                    if let Some(filter) = &event.relationship_filter {
-                        let type_matches = filter.iter().any(|t| {
-                            std::mem::discriminant(t)
-                                == std::mem::discriminant(&relationship.relationship_type)
-                        });
-                        if !type_matches {
-                            continue;
-                        }
+            for relationship in index
+                .outgoing(current)
+                .filter_map(|entity| relationships.get(entity).ok())
+            {
+                // Check if relationship type matches filter
+                if let Some(filter) = &event.relationship_filter {
+                    let type_matches = filter.iter().any(|t| {
+                        std::mem::discriminant(t)
+                            == std::mem::discriminant(&relationship.relationship_type)
+                    });
+                    if !type_matches {
+                        continue;
                     }
+                }
 
-                    let next = relationship.target_identity;
-
-                    // Check if we've visited this identity
-                    if !visited.contains(&next) {
-                        visited.insert(next);
+                let next = relationship.target_identity;
 
-                        let mut new_path = path.clone();
-                        new_path.push(next);
+                // Check if we've visited this identity
+                if !visited.contains(&next) {
+                    visited.insert(next);
 
-                        let mut new_rels = rels.clone();
-                        new_rels.push(relationship.relationship_id);
+                    let mut new_path = path.clone();
+                    new_path.push(next);
 
-                        // If this is the target, save the path
-                        if Some(next) == event.to_identity {
-                            paths.push((new_path.clone(), new_rels.clone()));
-                        }
+                    let mut new_rels = rels.clone();
+                    new_rels.push(relationship.relationship_id);
 
-                        // Continue traversal
-                        queue.push_back((next, new_path, new_rels, depth + 1));
+                    // If this is the target, save the path
+                    if Some(next) == event.to_identity {
+                        paths.push((new_path.clone(), new_rels.clone()));
                     }
+
+                    // Continue traversal
+                    queue.push_back((next, new_path, new_rels, depth + 1));
                 }
             }
         }
@@ -203,6 +282,8 @@ pub fn traverse_relationships_system(
             paths,
             total_identities_visited: visited.len(),
             traversed_at: chrono::Utc::now(),
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
         });
     }
 }
@@ -210,7 +291,7 @@ pub fn traverse_relationships_system(
 /// System to expire relationships
 pub fn expire_relationships_system(
     mut commands: Commands,
-    mut expired_events: EventWriter<RelationshipExpired>,
+    mut expired_events: DomainEventWriter<RelationshipExpired>,
     relationships: Query<(&IdentityRelationship, Entity)>,
 ) {
     let now = chrono::Utc::now();
@@ -226,6 +307,8 @@ pub fn expire_relationships_system(
                     to_identity: relationship.target_identity,
                     relationship_type: relationship.relationship_type.clone(),
                     expired_at: expires_at,
+                    correlation_id: Uuid::new_v4(),
+                    causation_id: None,
                 });
             }
         }
@@ -0,0 +1,407 @@
+//! Session systems for authenticated identities
+//!
+//! [`IdentitySessions`] is only changed through the `record_*` functions
+//! below, which replay calls with the same events. Timeouts are applied
+//! lazily: a session past its idle or absolute deadline is ended when it is
+//! next refreshed, and is left out of revocations and session queries.
+
+use crate::{
+    aggregate::IdentityAggregate, authentication::*, commands::*, components::*, events::*,
+    persistence::DomainEventWriter, IdentityError, IdentityIndex,
+};
+use bevy::ecs::prelude::*;
+use std::collections::HashMap;
+
+/// System to start sessions for identities that have authenticated
+///
+/// Each session uses up the [`SessionGrant`] of the authentication it was
+/// started for, so one authentication starts one session.
+#[allow(clippy::too_many_arguments)]
+pub fn start_session_system(
+    mut commands: Commands,
+    mut events: EventReader<CommandEnvelope<StartSessionCommand>>,
+    mut started_events: DomainEventWriter<SessionStarted>,
+    mut ended_events: DomainEventWriter<SessionEnded>,
+    mut rejected_events: EventWriter<SessionStartRejected>,
+    mut identities: Query<(
+        &IdentityEntity,
+        Option<&mut IdentityCredentials>,
+        Option<&mut IdentitySessions>,
+    )>,
+    mut index: ResMut<IdentityIndex>,
+    policy: Res<AuthenticationPolicy>,
+    clock: Res<AuthenticationClock>,
+) {
+    // Session components inserted this frame, so several sessions started
+    // for the same identity in one frame are all kept
+    let mut new_holders: HashMap<Entity, IdentitySessions> = HashMap::new();
+
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = clock.now();
+
+        let Some((entity, (identity, mut credentials, sessions))) = index
+            .identity(event.identity_id)
+            .and_then(|entity| identities.get_mut(entity).ok().map(|found| (entity, found)))
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::IdentityNotFound(event.identity_id),
+            ));
+            continue;
+        };
+
+        if let Err(e) = IdentityAggregate::validate_authentication(identity) {
+            rejected_events.write(CommandRejected::new(envelope, e));
+            continue;
+        }
+        if let Some(until) = credentials
+            .as_ref()
+            .and_then(|credentials| credentials.locked_until)
+            .filter(|until| now < *until)
+        {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::AccountLocked(until),
+            ));
+            continue;
+        }
+        let granted = credentials
+            .as_ref()
+            .and_then(|credentials| credentials.session_grant)
+            .is_some_and(|grant| grant.allows(event.attempt_id, now, &policy.session));
+        if !granted {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::AuthenticationRequired,
+            ));
+            continue;
+        }
+
+        let Some(session_id) = event.refresh_token.session_id() else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::InvalidRefreshToken,
+            ));
+            continue;
+        };
+        if index.session(session_id).is_some()
+            || new_holders
+                .values()
+                .any(|sessions| sessions.session(session_id).is_some())
+        {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::InvalidOperation(format!("Session id already in use: {session_id}")),
+            ));
+            continue;
+        }
+
+        let started = SessionStarted {
+            identity_id: event.identity_id,
+            session_id,
+            device: SessionDevice::from(&event.location),
+            auth_method: event.auth_method,
+            refresh_token_hash: event.refresh_token.hash(),
+            started_at: now,
+            expires_at: now + policy.session.absolute_timeout,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        };
+
+        let sessions = match sessions {
+            Some(sessions) => sessions.into_inner(),
+            None => new_holders.entry(entity).or_default(),
+        };
+
+        // One live session per device
+        if let Some(device_id) = &started.device.device_id {
+            let superseded: Vec<_> = sessions
+                .sessions
+                .iter()
+                .filter(|session| {
+                    session.device.device_id.as_ref() == Some(device_id)
+                        && session.is_active(now, &policy.session)
+                })
+                .map(|session| session.session_id)
+                .collect();
+            for session_id in superseded {
+                let ended = SessionEnded {
+                    identity_id: event.identity_id,
+                    session_id,
+                    reason: SessionEndReason::Superseded,
+                    ended_at: now,
+                    correlation_id: envelope.correlation_id,
+                    causation_id: Some(envelope.command_id),
+                };
+                record_session_ended(sessions, &ended);
+                ended_events.write(ended);
+            }
+        }
+
+        record_session_started(sessions, &started);
+        if let Some(credentials) = credentials.as_deref_mut() {
+            record_session_grant_used(credentials, &started);
+        }
+        if !new_holders.contains_key(&entity) {
+            index.record_session(session_id, entity);
+        }
+        started_events.write(started);
+    }
+
+    for (entity, sessions) in new_holders {
+        commands.entity(entity).insert(sessions);
+    }
+}
+
+/// System to exchange refresh tokens of live sessions
+///
+/// Unknown, malformed and outdated tokens are indistinguishable to the caller.
+#[allow(clippy::too_many_arguments)]
+pub fn refresh_session_system(
+    mut events: EventReader<CommandEnvelope<RefreshSessionCommand>>,
+    mut refreshed_events: DomainEventWriter<SessionRefreshed>,
+    mut ended_events: DomainEventWriter<SessionEnded>,
+    mut rejected_events: EventWriter<SessionRefreshRejected>,
+    mut identities: Query<(&IdentityEntity, &mut IdentitySessions)>,
+    index: Res<IdentityIndex>,
+    policy: Res<AuthenticationPolicy>,
+    clock: Res<AuthenticationClock>,
+) {
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = clock.now();
+
+        let Some((session_id, (identity, mut sessions))) =
+            event.refresh_token.session_id().and_then(|session_id| {
+                index
+                    .session(session_id)
+                    .and_then(|entity| identities.get_mut(entity).ok())
+                    .map(|found| (session_id, found))
+            })
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::InvalidRefreshToken,
+            ));
+            continue;
+        };
+        let Some(session) = sessions
+            .session(session_id)
+            .filter(|session| session.refresh_token_matches(&event.refresh_token))
+            .cloned()
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::InvalidRefreshToken,
+            ));
+            continue;
+        };
+
+        if let Err(e) = IdentityAggregate::validate_authentication(identity) {
+            rejected_events.write(CommandRejected::new(envelope, e));
+            continue;
+        }
+
+        if let Some(reason) = session.end_reason_at(now, &policy.session) {
+            if session.end_reason.is_none() {
+                // Ended by time rather than by this command
+                let ended_at = match reason {
+                    SessionEndReason::AbsoluteTimeout => session.expires_at,
+                    _ => session.idle_deadline(&policy.session),
+                };
+                let ended = SessionEnded {
+                    identity_id: identity.identity_id,
+                    session_id,
+                    reason,
+                    ended_at,
+                    correlation_id: envelope.correlation_id,
+                    causation_id: None,
+                };
+                record_session_ended(&mut sessions, &ended);
+                ended_events.write(ended);
+            }
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::SessionEnded(session_id),
+            ));
+            continue;
+        }
+
+        if event.new_refresh_token.session_id() != Some(session_id)
+            || event.new_refresh_token == event.refresh_token
+        {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::InvalidRefreshToken,
+            ));
+            continue;
+        }
+
+        let refreshed = SessionRefreshed {
+            identity_id: identity.identity_id,
+            session_id,
+            refresh_token_hash: event.new_refresh_token.hash(),
+            refreshed_at: now,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        };
+        record_session_refreshed(&mut sessions, &refreshed);
+        refreshed_events.write(refreshed);
+    }
+}
+
+/// System to revoke single sessions
+pub fn revoke_session_system(
+    mut events: EventReader<CommandEnvelope<RevokeSessionCommand>>,
+    mut ended_events: DomainEventWriter<SessionEnded>,
+    mut rejected_events: EventWriter<SessionRevocationRejected>,
+    mut identities: Query<&mut IdentitySessions>,
+    index: Res<IdentityIndex>,
+    policy: Res<AuthenticationPolicy>,
+    clock: Res<AuthenticationClock>,
+) {
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = clock.now();
+
+        let Some(mut sessions) = index
+            .identity(event.identity_id)
+            .and_then(|entity| identities.get_mut(entity).ok())
+            .filter(|sessions| sessions.session(event.session_id).is_some())
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::SessionNotFound(event.session_id),
+            ));
+            continue;
+        };
+
+        let active = sessions
+            .session(event.session_id)
+            .is_some_and(|session| session.is_active(now, &policy.session));
+        if !active {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::SessionEnded(event.session_id),
+            ));
+            continue;
+        }
+
+        let ended = SessionEnded {
+            identity_id: event.identity_id,
+            session_id: event.session_id,
+            reason: SessionEndReason::Revoked(event.reason.clone()),
+            ended_at: now,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        };
+        record_session_ended(&mut sessions, &ended);
+        ended_events.write(ended);
+    }
+}
+
+/// System to revoke every live session of an identity
+pub fn revoke_all_sessions_system(
+    mut events: EventReader<CommandEnvelope<RevokeAllSessionsCommand>>,
+    mut revoked_events: DomainEventWriter<SessionsRevoked>,
+    mut rejected_events: EventWriter<SessionsRevocationRejected>,
+    mut identities: Query<Option<&mut IdentitySessions>, With<IdentityEntity>>,
+    index: Res<IdentityIndex>,
+    policy: Res<AuthenticationPolicy>,
+    clock: Res<AuthenticationClock>,
+) {
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = clock.now();
+
+        let Some(sessions) = index
+            .identity(event.identity_id)
+            .and_then(|entity| identities.get_mut(entity).ok())
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::IdentityNotFound(event.identity_id),
+            ));
+            continue;
+        };
+
+        let session_ids: Vec<_> = sessions
+            .as_ref()
+            .map(|sessions| {
+                sessions
+                    .sessions
+                    .iter()
+                    .filter(|session| {
+                        Some(session.session_id) != event.keep
+                            && session.is_active(now, &policy.session)
+                    })
+                    .map(|session| session.session_id)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let revoked = SessionsRevoked {
+            identity_id: event.identity_id,
+            session_ids,
+            revoked_by: event.revoked_by,
+            reason: event.reason.clone(),
+            revoked_at: now,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        };
+        if let Some(mut sessions) = sessions {
+            record_sessions_revoked(&mut sessions, &revoked);
+        }
+        revoked_events.write(revoked);
+    }
+}
+
+/// Add a started session
+pub(crate) fn record_session_started(sessions: &mut IdentitySessions, event: &SessionStarted) {
+    sessions.sessions.push(SessionRecord {
+        session_id: event.session_id,
+        device: event.device.clone(),
+        auth_method: event.auth_method,
+        started_at: event.started_at,
+        last_seen_at: event.started_at,
+        expires_at: event.expires_at,
+        refresh_token_hash: event.refresh_token_hash.clone(),
+        ended_at: None,
+        end_reason: None,
+    });
+}
+
+/// Use up the authentication a session was started with
+pub(crate) fn record_session_grant_used(
+    credentials: &mut IdentityCredentials,
+    _event: &SessionStarted,
+) {
+    credentials.session_grant = None;
+}
+
+/// Replace the refresh token of a session and record the activity
+pub(crate) fn record_session_refreshed(sessions: &mut IdentitySessions, event: &SessionRefreshed) {
+    if let Some(session) = sessions.session_mut(event.session_id) {
+        session.refresh_token_hash = event.refresh_token_hash.clone();
+        session.last_seen_at = event.refreshed_at;
+    }
+}
+
+/// End a session
+pub(crate) fn record_session_ended(sessions: &mut IdentitySessions, event: &SessionEnded) {
+    if let Some(session) = sessions.session_mut(event.session_id) {
+        session.ended_at = Some(event.ended_at);
+        session.end_reason = Some(event.reason.clone());
+    }
+}
+
+/// End every revoked session
+pub(crate) fn record_sessions_revoked(sessions: &mut IdentitySessions, event: &SessionsRevoked) {
+    for session_id in &event.session_ids {
+        if let Some(session) = sessions.session_mut(*session_id) {
+            session.ended_at = Some(event.revoked_at);
+            session.end_reason = Some(SessionEndReason::Revoked(event.reason.clone()));
+        }
+    }
+}
@@ -0,0 +1,141 @@
+//! Signed identity token systems
+
+use crate::{
+    aggregate::IdentityAggregate, commands::*, components::*, events::*, tokens::*,
+    IdentityError, IdentityIndex,
+};
+use bevy::ecs::prelude::*;
+use uuid::Uuid;
+
+/// System to issue signed identity tokens
+pub fn issue_identity_token_system(
+    mut events: EventReader<CommandEnvelope<IssueIdentityTokenCommand>>,
+    mut issued_events: EventWriter<IdentityTokenIssued>,
+    mut rejected_events: EventWriter<IdentityTokenIssueRejected>,
+    identities: Query<(
+        &IdentityEntity,
+        &IdentityVerification,
+        Option<&IdentityClaims>,
+    )>,
+    index: Res<IdentityIndex>,
+    issuer: Res<TokenIssuer>,
+) {
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = chrono::Utc::now();
+
+        let Some((identity, verification, held_claims)) = index
+            .identity(event.identity_id)
+            .and_then(|entity| identities.get(entity).ok())
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::IdentityNotFound(event.identity_id),
+            ));
+            continue;
+        };
+
+        if let Err(e) = IdentityAggregate::validate_token_subject(identity) {
+            rejected_events.write(CommandRejected::new(envelope, e));
+            continue;
+        }
+
+        let expires_at = event.expires_at.unwrap_or(now + issuer.lifetime());
+        if expires_at <= now {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::InvalidOperation("Token expiry is in the past".to_string()),
+            ));
+            continue;
+        }
+
+        // Only verified claims of the requested types are asserted
+        let claims = held_claims
+            .into_iter()
+            .flat_map(IdentityClaims::iter)
+            .filter(|claim| event.claim_types.contains(&claim.claim_type))
+            .filter_map(|claim| TokenClaim::from_verified(claim, now))
+            .collect();
+
+        let token_claims = IdentityTokenClaims {
+            iss: issuer.issuer().to_string(),
+            sub: identity.identity_id,
+            jti: Uuid::new_v4(),
+            iat: now.timestamp(),
+            exp: expires_at.timestamp(),
+            identity_type: identity.identity_type,
+            verification_level: verification.verification_level,
+            claims,
+        };
+        let token = match issuer.sign(&token_claims) {
+            Ok(token) => token,
+            Err(e) => {
+                rejected_events.write(CommandRejected::new(envelope, e));
+                continue;
+            }
+        };
+
+        issued_events.write(IdentityTokenIssued {
+            identity_id: identity.identity_id,
+            token_id: token_claims.jti,
+            kid: issuer.active_kid().to_string(),
+            token,
+            issued_at: now,
+            expires_at,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        });
+    }
+}
+
+/// System to validate presented identity tokens
+///
+/// Checks the token offline first, then rejects it if its identity has since
+/// been suspended, archived or merged.
+pub fn validate_identity_token_system(
+    mut events: EventReader<CommandEnvelope<ValidateIdentityTokenCommand>>,
+    mut validated_events: EventWriter<IdentityTokenValidated>,
+    mut rejected_events: EventWriter<IdentityTokenValidationRejected>,
+    identities: Query<&IdentityEntity>,
+    index: Res<IdentityIndex>,
+    issuer: Res<TokenIssuer>,
+) {
+    let verifier = issuer.verifier();
+
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = chrono::Utc::now();
+
+        let claims = match verifier.verify(&event.token, now) {
+            Ok(claims) => claims,
+            Err(e) => {
+                rejected_events.write(CommandRejected::new(envelope, e));
+                continue;
+            }
+        };
+
+        let Some(identity) = index
+            .identity(claims.sub)
+            .and_then(|entity| identities.get(entity).ok())
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::IdentityNotFound(claims.sub),
+            ));
+            continue;
+        };
+
+        if let Err(e) = IdentityAggregate::validate_token_subject(identity) {
+            rejected_events.write(CommandRejected::new(envelope, e));
+            continue;
+        }
+
+        validated_events.write(IdentityTokenValidated {
+            identity_id: identity.identity_id,
+            claims,
+            validated_at: now,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        });
+    }
+}
@@ -1,175 +1,826 @@
 //! Identity verification systems
 
-use crate::{commands::*, components::*, events::*};
+use super::workflow::record_step;
+use crate::{
+    aggregate::IdentityAggregate, commands::*, components::*, events::*,
+    persistence::DomainEventWriter, verification::*, IdentityError, IdentityIndex, IdentityResult,
+};
 use bevy::ecs::prelude::*;
-use tracing::info;
+use std::collections::HashMap;
+use tracing::{info, warn};
+use uuid::Uuid;
 
 /// System to start identity verification
+///
+/// Spawns the verification workflow of the requested method and runs its
+/// automated first step: sending the email code or phone OTP to the claim
+/// named by the command, or requesting the third-party check. Email and phone
+/// verification is refused unless the identity holds that claim.
+#[allow(clippy::too_many_arguments)]
 pub fn start_verification_system(
-    mut events: EventReader<StartVerificationCommand>,
-    mut started_events: EventWriter<VerificationStarted>,
-    identities: Query<(&IdentityEntity, &IdentityVerification)>,
+    mut commands: Commands,
+    mut events: EventReader<CommandEnvelope<StartVerificationCommand>>,
+    mut started_events: DomainEventWriter<VerificationStarted>,
+    mut sent_events: DomainEventWriter<VerificationCodeSent>,
+    mut rejected_events: EventWriter<VerificationStartRejected>,
+    identities: Query<(
+        &IdentityEntity,
+        &IdentityVerification,
+        Option<&IdentityClaims>,
+    )>,
+    workflows: Query<&IdentityWorkflow, With<VerificationWorkflow>>,
+    index: Res<IdentityIndex>,
+    providers: Res<VerificationProviders>,
+    mut codes: ResMut<VerificationCodes>,
 ) {
-    for event in events.read() {
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = chrono::Utc::now();
+
         // Find identity to verify
-        let identity_data = identities
-            .iter()
-            .find(|(e, _)| e.identity_id == event.identity_id);
+        let Some((identity, current_verification, claims)) = index
+            .identity(event.identity_id)
+            .and_then(|entity| identities.get(entity).ok())
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::IdentityNotFound(event.identity_id),
+            ));
+            continue;
+        };
 
-        if let Some((_identity, current_verification)) = identity_data {
-            // Check if verification is already at max level
-            if current_verification.verification_level == VerificationLevel::Full {
-                continue;
-            }
+        if let Err(e) =
+            IdentityAggregate::validate_workflow_start(identity, &WorkflowType::Verification)
+        {
+            rejected_events.write(CommandRejected::new(envelope, e));
+            continue;
+        }
 
-            // Start verification workflow based on method
-            match &event.verification_method {
-                VerificationMethod::Email => {
-                    // Would trigger email verification workflow
-                }
-                VerificationMethod::Phone => {
-                    // Would trigger phone verification workflow
-                }
-                VerificationMethod::Document => {
-                    // Would trigger document verification workflow
-                }
-                VerificationMethod::Biometric => {
-                    // Would trigger biometric verification workflow
+        // Check if verification is already at max level
+        let Some(target_level) = next_verification_level(current_verification.verification_level)
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::VerificationFailed("Identity is already fully verified".to_string()),
+            ));
+            continue;
+        };
+
+        let in_progress = index
+            .workflows_for(event.identity_id)
+            .filter_map(|entity| workflows.get(entity).ok())
+            .any(|w| !w.is_finished());
+        if in_progress {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::WorkflowInProgress,
+            ));
+            continue;
+        }
+
+        let workflow_id = Uuid::new_v4();
+        let mut workflow = verification_workflow(
+            workflow_id,
+            event.identity_id,
+            &event.verification_method,
+            now,
+        );
+        let mut verification = VerificationWorkflow {
+            verification_method: event.verification_method.clone(),
+            target_level,
+            initiated_by: event.initiated_by,
+            verified_by: event.initiated_by,
+            code_claim: None,
+            provider_reference: None,
+            correlation_id: envelope.correlation_id,
+            last_command_id: envelope.command_id,
+        };
+
+        // Run the automated first step of the method
+        let mut code_sent = None;
+        match &event.verification_method {
+            VerificationMethod::Email | VerificationMethod::Phone => {
+                let claim_type = if event.verification_method == VerificationMethod::Email {
+                    ClaimType::Email
+                } else {
+                    ClaimType::Phone
+                };
+                let claim = event
+                    .claim_value
+                    .as_deref()
+                    .and_then(|value| claims.and_then(|claims| claims.find(&claim_type, value)));
+                let Some(claim) = claim else {
+                    rejected_events.write(CommandRejected::new(
+                        envelope,
+                        IdentityError::ClaimNotFound(claim_type),
+                    ));
+                    continue;
+                };
+                let challenge =
+                    match codes.send_code(event.identity_id, &claim_type, &claim.value, now) {
+                        Ok(challenge) => challenge,
+                        Err(e) => {
+                            rejected_events.write(CommandRejected::new(envelope, e));
+                            continue;
+                        }
+                    };
+
+                record_code_sent(
+                    &mut workflow,
+                    &mut verification,
+                    (claim_type, claim.value.clone()),
+                    challenge.expires_at,
+                    challenge.sent_at,
+                );
+                code_sent = Some(VerificationCodeSent {
+                    identity_id: event.identity_id,
+                    workflow_id: Some(workflow_id),
+                    claim_type: challenge.claim_type,
+                    value: challenge.value,
+                    code_hash: challenge.code_hash,
+                    sent_at: challenge.sent_at,
+                    expires_at: challenge.expires_at,
+                    failed_attempts: challenge.failed_attempts,
+                    correlation_id: envelope.correlation_id,
+                    causation_id: Some(envelope.command_id),
+                });
+            }
+            VerificationMethod::ThirdParty { provider } => {
+                let Some(adapter) = providers.get(provider) else {
+                    rejected_events.write(CommandRejected::new(
+                        envelope,
+                        IdentityError::VerificationFailed(format!(
+                            "Unknown verification provider: {provider}"
+                        )),
+                    ));
+                    continue;
+                };
+                match adapter.request_check(event.identity_id, workflow_id) {
+                    Ok(reference) => {
+                        info!("Requested third-party verification from: {}", provider);
+                        verification.provider_reference = Some(reference);
+                        workflow.complete_active_step(now);
+                    }
+                    Err(e) => {
+                        rejected_events.write(CommandRejected::new(envelope, e));
+                        continue;
+                    }
                 }
-                VerificationMethod::InPerson => {
-                    // Would trigger manual verification workflow
+            }
+            VerificationMethod::Document
+            | VerificationMethod::Biometric
+            | VerificationMethod::InPerson => {
+                // The first step waits for a submission
+            }
+        }
+
+        // Emit started event ahead of the code so replay finds the workflow
+        started_events.write(VerificationStarted {
+            identity_id: event.identity_id,
+            workflow_id,
+            verification_method: event.verification_method.clone(),
+            target_level,
+            initiated_by: event.initiated_by,
+            provider_reference: verification.provider_reference.clone(),
+            started_at: now,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        });
+        if let Some(sent) = code_sent {
+            sent_events.write(sent);
+        }
+        commands.spawn((workflow, verification, WorkflowHistory::new(workflow_id)));
+    }
+}
+
+/// Workflow verifying `identity_id` with `method`, with its first step active
+pub(crate) fn verification_workflow(
+    workflow_id: WorkflowId,
+    identity_id: IdentityId,
+    method: &VerificationMethod,
+    now: chrono::DateTime<chrono::Utc>,
+) -> IdentityWorkflow {
+    let mut workflow = IdentityWorkflow {
+        workflow_id,
+        identity_id,
+        workflow_type: WorkflowType::Verification,
+        status: WorkflowStatus::NotStarted,
+        current_step: None,
+        steps: verification_steps(method),
+        transitions: Vec::new(),
+        context: serde_json::Value::Null,
+        started_at: Some(now),
+        completed_at: None,
+        paused_at: None,
+    };
+    workflow.activate_next_step(now);
+    workflow
+}
+
+/// Complete the sending step of an email or phone verification
+///
+/// The confirmation step lasts as long as the code.
+pub(crate) fn record_code_sent(
+    workflow: &mut IdentityWorkflow,
+    verification: &mut VerificationWorkflow,
+    code_claim: (ClaimType, String),
+    expires_at: chrono::DateTime<chrono::Utc>,
+    now: chrono::DateTime<chrono::Utc>,
+) {
+    workflow.complete_active_step(now);
+    if let Some(step) = workflow.active_step_mut() {
+        step.timeout_seconds = Some((expires_at - now).num_seconds().max(0) as u64);
+    }
+    verification.code_claim = Some(code_claim);
+}
+
+/// Pending challenge recorded by a sent code
+pub(crate) fn code_challenge(event: &VerificationCodeSent) -> CodeChallenge {
+    CodeChallenge {
+        identity_id: event.identity_id,
+        claim_type: event.claim_type.clone(),
+        value: event.value.clone(),
+        code_hash: event.code_hash.clone(),
+        sent_at: event.sent_at,
+        expires_at: event.expires_at,
+        failed_attempts: event.failed_attempts,
+    }
+}
+
+/// System to answer the active step of a verification workflow
+///
+/// `verification_data` depends on the step: `{"code": ".."}` confirms an
+/// email code or phone OTP, `{"matched": bool}` reports a biometric match and
+/// `{"approved": bool, "reason": ".."}` decides an approval step. Manual
+/// steps accept any submission. An expired or exhausted code fails the
+/// verification; a confirmed one verifies its claim once the workflow
+/// completes.
+#[allow(clippy::too_many_arguments)]
+pub fn advance_verification_system(
+    mut events: EventReader<CommandEnvelope<ProcessVerificationCommand>>,
+    mut step_events: DomainEventWriter<WorkflowStepCompleted>,
+    mut failed_events: DomainEventWriter<VerificationCodeFailed>,
+    mut rejected_events: EventWriter<VerificationProcessingRejected>,
+    mut workflows: Query<(
+        &mut IdentityWorkflow,
+        &mut VerificationWorkflow,
+        Option<&mut WorkflowHistory>,
+    )>,
+    mut codes: ResMut<VerificationCodes>,
+    index: Res<IdentityIndex>,
+) {
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = chrono::Utc::now();
+
+        let active = index.workflows_for(event.identity_id).find(|entity| {
+            workflows
+                .get(*entity)
+                .is_ok_and(|(workflow, _, _)| !workflow.is_finished())
+        });
+        let Some((mut workflow, mut verification, history)) =
+            active.and_then(|entity| workflows.get_mut(entity).ok())
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::WorkflowError(format!(
+                    "No verification in progress for identity {}",
+                    event.identity_id
+                )),
+            ));
+            continue;
+        };
+
+        let Some(step) = workflow.active_step().cloned() else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::WorkflowError("Verification has no active step".to_string()),
+            ));
+            continue;
+        };
+
+        // Ok(None) completes the step, Ok(Some(reason)) fails the verification
+        let data = &event.verification_data;
+        let result = match step.step_type {
+            StepType::Manual => Ok(None),
+            StepType::Approval if event.processed_by == workflow.identity_id => {
+                Err(IdentityError::InvalidOperation(
+                    "Identities cannot approve their own verification".to_string(),
+                ))
+            }
+            StepType::Approval => match data.get("approved").and_then(|v| v.as_bool()) {
+                Some(true) => Ok(None),
+                Some(false) => Ok(Some(
+                    data.get("reason")
+                        .and_then(|v| v.as_str())
+                        .unwrap_or("Verification was not approved")
+                        .to_string(),
+                )),
+                None => Err(IdentityError::InvalidOperation(
+                    "Approval requires an `approved` flag".to_string(),
+                )),
+            },
+            StepType::Verification => {
+                match (&verification.code_claim, &verification.provider_reference) {
+                    (Some((claim_type, value)), _) => {
+                        match data.get("code").and_then(|v| v.as_str()) {
+                            Some(code) => confirm_code(
+                                &mut codes,
+                                &mut failed_events,
+                                envelope,
+                                workflow.identity_id,
+                                claim_type,
+                                value,
+                                code,
+                                now,
+                            )
+                            .map(|_| None),
+                            None => Err(IdentityError::InvalidOperation(
+                                "Verification requires a `code`".to_string(),
+                            )),
+                        }
+                    }
+                    (None, Some(_)) => Err(IdentityError::WorkflowError(
+                        "Step is answered by the verification provider".to_string(),
+                    )),
+                    (None, None) => match data.get("matched").and_then(|v| v.as_bool()) {
+                        Some(true) => Ok(None),
+                        Some(false) => Ok(Some("Biometric sample did not match".to_string())),
+                        None => Err(IdentityError::InvalidOperation(
+                            "Verification requires a `matched` flag".to_string(),
+                        )),
+                    },
                 }
-                VerificationMethod::ThirdParty { provider } => {
-                    // Would integrate with external service
-                    info!("Starting third-party verification with provider: {}", provider);
+            }
+            StepType::Automated | StepType::Notification => Err(IdentityError::WorkflowError(
+                "Step runs automatically".to_string(),
+            )),
+        };
+
+        let outcome = match result {
+            Ok(None) => {
+                workflow.complete_active_step(now);
+                StepOutcome::Completed
+            }
+            Ok(Some(reason)) => {
+                workflow.fail_active_step(reason.clone(), now);
+                StepOutcome::Failed(reason)
+            }
+            Err(e) => {
+                let reason = e.to_string();
+                let unusable = matches!(
+                    e,
+                    IdentityError::VerificationCodeExpired
+                        | IdentityError::VerificationAttemptsExceeded
+                );
+                rejected_events.write(CommandRejected::new(envelope, e));
+                if !unusable {
+                    continue;
                 }
+                // The code can no longer be confirmed, so the verification fails
+                workflow.fail_active_step(reason.clone(), now);
+                StepOutcome::Failed(reason)
             }
+        };
+        if step.step_type == StepType::Approval {
+            verification.verified_by = event.processed_by;
+        }
+        verification.last_command_id = envelope.command_id;
 
-            // Emit started event
-            started_events.write(VerificationStarted {
-                identity_id: event.identity_id,
-                verification_method: event.verification_method.clone(),
-                initiated_by: event.initiated_by,
-                started_at: chrono::Utc::now(),
-            });
+        let completed = WorkflowStepCompleted {
+            workflow_id: workflow.workflow_id,
+            identity_id: workflow.identity_id,
+            workflow_type: workflow.workflow_type.clone(),
+            step_id: step.step_id,
+            outcome,
+            next_step: workflow.running_step(),
+            processed_by: Some(event.processed_by),
+            step_data: serde_json::Value::Null,
+            completed_at: now,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        };
+        if let Some(mut history) = history {
+            record_step(&mut history, &workflow, &completed);
         }
+        step_events.write(completed);
+    }
+}
+
+/// System to send one-time codes to email and phone claims
+pub fn send_verification_code_system(
+    mut events: EventReader<CommandEnvelope<SendVerificationCodeCommand>>,
+    mut sent_events: DomainEventWriter<VerificationCodeSent>,
+    mut rejected_events: EventWriter<VerificationCodeSendRejected>,
+    claims: Query<&IdentityClaims>,
+    mut codes: ResMut<VerificationCodes>,
+    index: Res<IdentityIndex>,
+) {
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = chrono::Utc::now();
+
+        let Some(entity) = index.identity(event.identity_id) else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::IdentityNotFound(event.identity_id),
+            ));
+            continue;
+        };
+
+        let holds_claim = claims
+            .get(entity)
+            .is_ok_and(|claims| claims.find(&event.claim_type, &event.value).is_some());
+        if !holds_claim {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::ClaimNotFound(event.claim_type.clone()),
+            ));
+            continue;
+        }
+
+        match codes.send_code(event.identity_id, &event.claim_type, &event.value, now) {
+            Ok(challenge) => {
+                sent_events.write(VerificationCodeSent {
+                    identity_id: event.identity_id,
+                    workflow_id: None,
+                    claim_type: challenge.claim_type,
+                    value: challenge.value,
+                    code_hash: challenge.code_hash,
+                    sent_at: challenge.sent_at,
+                    expires_at: challenge.expires_at,
+                    failed_attempts: challenge.failed_attempts,
+                    correlation_id: envelope.correlation_id,
+                    causation_id: Some(envelope.command_id),
+                });
+            }
+            Err(e) => {
+                rejected_events.write(CommandRejected::new(envelope, e));
+            }
+        }
+    }
+}
+
+/// System to confirm one-time codes, verifying only the claim they were sent to
+pub fn confirm_verification_code_system(
+    mut events: EventReader<CommandEnvelope<ConfirmVerificationCodeCommand>>,
+    mut verified_events: DomainEventWriter<ClaimVerified>,
+    mut failed_events: DomainEventWriter<VerificationCodeFailed>,
+    mut rejected_events: EventWriter<VerificationCodeConfirmationRejected>,
+    mut claims: Query<&mut IdentityClaims>,
+    mut codes: ResMut<VerificationCodes>,
+    index: Res<IdentityIndex>,
+) {
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = chrono::Utc::now();
+
+        if let Err(e) = confirm_code(
+            &mut codes,
+            &mut failed_events,
+            envelope,
+            event.identity_id,
+            &event.claim_type,
+            &event.value,
+            &event.code,
+            now,
+        ) {
+            rejected_events.write(CommandRejected::new(envelope, e));
+            continue;
+        }
+
+        if !mark_claim_verified(
+            &mut claims,
+            &index,
+            event.identity_id,
+            &event.claim_type,
+            &event.value,
+        ) {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::ClaimNotFound(event.claim_type.clone()),
+            ));
+            continue;
+        }
+
+        verified_events.write(ClaimVerified {
+            identity_id: event.identity_id,
+            claim_type: event.claim_type.clone(),
+            value: event.value.clone(),
+            verified_at: now,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        });
+    }
+}
+
+/// Check a presented code, recording why a pending code was not confirmed
+#[allow(clippy::too_many_arguments)]
+fn confirm_code<C>(
+    codes: &mut VerificationCodes,
+    failed_events: &mut DomainEventWriter<VerificationCodeFailed>,
+    envelope: &CommandEnvelope<C>,
+    identity_id: IdentityId,
+    claim_type: &ClaimType,
+    value: &str,
+    code: &str,
+    now: chrono::DateTime<chrono::Utc>,
+) -> IdentityResult<CodeChallenge> {
+    let pending = codes.challenge(identity_id, claim_type, value).cloned();
+    let error = match codes.confirm_code(identity_id, claim_type, value, code, now) {
+        Ok(challenge) => return Ok(challenge),
+        Err(error) => error,
+    };
+    if let Some(pending) = pending {
+        let (failed_attempts, dropped) = match error {
+            IdentityError::VerificationCodeExpired => (pending.failed_attempts, true),
+            IdentityError::VerificationAttemptsExceeded => (pending.failed_attempts + 1, true),
+            _ => (pending.failed_attempts + 1, false),
+        };
+        failed_events.write(VerificationCodeFailed {
+            identity_id,
+            claim_type: claim_type.clone(),
+            value: value.to_string(),
+            failed_attempts,
+            dropped,
+            failed_at: now,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        });
+    }
+    Err(error)
+}
+
+/// Apply a recorded code failure to the pending codes
+pub(crate) fn record_code_failed(codes: &mut VerificationCodes, event: &VerificationCodeFailed) {
+    if event.dropped {
+        codes.discard_challenge(event.identity_id, &event.claim_type, &event.value);
+    } else {
+        codes.record_failed_attempts(
+            event.identity_id,
+            &event.claim_type,
+            &event.value,
+            event.failed_attempts,
+        );
+    }
+}
+
+/// Mark the identity's claim with the given type and value as verified
+///
+/// Returns false if the identity no longer holds the claim.
+fn mark_claim_verified(
+    claims: &mut Query<&mut IdentityClaims>,
+    index: &IdentityIndex,
+    identity_id: IdentityId,
+    claim_type: &ClaimType,
+    value: &str,
+) -> bool {
+    index
+        .identity(identity_id)
+        .and_then(|entity| claims.get_mut(entity).ok())
+        .is_some_and(|mut claims| claims.verify(claim_type, value))
+}
+
+/// System to collect third-party verification results
+pub fn poll_verification_providers_system(
+    mut step_events: DomainEventWriter<WorkflowStepCompleted>,
+    mut workflows: Query<(
+        &mut IdentityWorkflow,
+        &VerificationWorkflow,
+        Option<&mut WorkflowHistory>,
+    )>,
+    providers: Res<VerificationProviders>,
+) {
+    let now = chrono::Utc::now();
+
+    for (mut workflow, verification, history) in workflows.iter_mut() {
+        let (VerificationMethod::ThirdParty { provider }, Some(reference)) = (
+            &verification.verification_method,
+            &verification.provider_reference,
+        ) else {
+            continue;
+        };
+        if workflow.is_finished() {
+            continue;
+        }
+        let Some(step_id) = workflow.current_step.clone() else {
+            continue;
+        };
+
+        let check = match providers.get(provider).map(|a| a.check_result(reference)) {
+            Some(Ok(check)) => check,
+            Some(Err(e)) => {
+                warn!("Verification provider {} failed: {}", provider, e);
+                continue;
+            }
+            None => ProviderCheck::Rejected(format!(
+                "Verification provider is no longer registered: {provider}"
+            )),
+        };
+        let outcome = match check {
+            ProviderCheck::Pending => continue,
+            ProviderCheck::Verified => {
+                workflow.complete_active_step(now);
+                StepOutcome::Completed
+            }
+            ProviderCheck::Rejected(reason) => {
+                workflow.fail_active_step(reason.clone(), now);
+                StepOutcome::Failed(reason)
+            }
+        };
+
+        let completed = WorkflowStepCompleted {
+            workflow_id: workflow.workflow_id,
+            identity_id: workflow.identity_id,
+            workflow_type: workflow.workflow_type.clone(),
+            step_id,
+            outcome,
+            next_step: workflow.running_step(),
+            processed_by: None,
+            step_data: serde_json::Value::Null,
+            completed_at: now,
+            correlation_id: verification.correlation_id,
+            causation_id: None,
+        };
+        if let Some(mut history) = history {
+            record_step(&mut history, &workflow, &completed);
+        }
+        step_events.write(completed);
     }
 }
 
 /// System to process verification results
+///
+/// A claim is only reported as verified for completions written by
+/// `complete_verification_system` for a workflow that confirmed its code.
 pub fn process_verification_system(
-    mut events: EventReader<CompleteVerificationCommand>,
-    mut completed_events: EventWriter<VerificationCompleted>,
+    mut events: EventReader<CommandEnvelope<CompleteVerificationCommand>>,
+    mut confirmed_claims: EventReader<VerificationCodeClaimConfirmed>,
+    mut completed_events: DomainEventWriter<VerificationCompleted>,
+    mut rejected_events: EventWriter<VerificationCompletionRejected>,
     mut identities: Query<(&IdentityEntity, &mut IdentityVerification)>,
+    index: Res<IdentityIndex>,
 ) {
-    for event in events.read() {
-        for (identity, mut verification) in identities.iter_mut() {
-            if identity.identity_id == event.identity_id {
-                if event.verification_result {
-                    // Update verification level
-                    let old_level = verification.verification_level;
-                    verification.verification_level = event.verification_level;
-                    verification.verified_at = Some(chrono::Utc::now());
-                    verification.verified_by = Some(event.verified_by);
-                    verification.verification_method = Some(event.verification_method.clone());
-
-                    // Update identity status if pending
-                    if matches!(identity.status, IdentityStatus::Pending) {
-                        // Would update to Active status
-                    }
+    let mut confirmed_claims: HashMap<Uuid, (ClaimType, String)> = confirmed_claims
+        .read()
+        .map(|confirmed| (confirmed.command_id, confirmed.claim.clone()))
+        .collect();
 
-                    // Log provider if third-party verification
-                    if let VerificationMethod::ThirdParty { provider } = &event.verification_method {
-                        info!("Verification completed via third-party provider: {}", provider);
-                    }
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = chrono::Utc::now();
 
-                    // Emit completed event
-                    completed_events.write(VerificationCompleted {
-                        identity_id: event.identity_id,
-                        verification_successful: true,
-                        new_verification_level: event.verification_level,
-                        verified_by: event.verified_by,
-                        completed_at: chrono::Utc::now(),
-                    });
-                } else {
-                    // Verification failed
-                    completed_events.write(VerificationCompleted {
-                        identity_id: event.identity_id,
-                        verification_successful: false,
-                        new_verification_level: verification.verification_level,
-                        verified_by: event.verified_by,
-                        completed_at: chrono::Utc::now(),
-                    });
-                }
+        let Some((identity, mut verification)) = index
+            .identity(event.identity_id)
+            .and_then(|entity| identities.get_mut(entity).ok())
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::IdentityNotFound(event.identity_id),
+            ));
+            continue;
+        };
+
+        if event.verification_result {
+            // Validate level transition through aggregate
+            if let Err(e) = IdentityAggregate::validate_verification_transition(
+                verification.verification_level,
+                event.verification_level,
+            ) {
+                rejected_events.write(CommandRejected::new(envelope, e));
+                continue;
+            }
+
+            // Update verification level
+            let old_level = verification.verification_level;
+            verification.verification_level = event.verification_level;
+            verification.verified_at = Some(now);
+            verification.verified_by = Some(event.verified_by);
+            verification.verification_method = Some(event.verification_method.clone());
+
+            // Update identity status if pending
+            if matches!(identity.status, IdentityStatus::Pending) {
+                // Would update to Active status
             }
+
+            // Log provider if third-party verification
+            if let VerificationMethod::ThirdParty { provider } = &event.verification_method {
+                info!("Verification completed via third-party provider: {}", provider);
+            }
+
+            // Emit completed event
+            completed_events.write(VerificationCompleted {
+                identity_id: event.identity_id,
+                verification_successful: true,
+                new_verification_level: event.verification_level,
+                verification_method: event.verification_method.clone(),
+                verified_by: event.verified_by,
+                verified_claim: confirmed_claims.remove(&envelope.command_id),
+                completed_at: now,
+                correlation_id: envelope.correlation_id,
+                causation_id: Some(envelope.command_id),
+            });
+        } else {
+            // Verification failed
+            completed_events.write(VerificationCompleted {
+                identity_id: event.identity_id,
+                verification_successful: false,
+                new_verification_level: verification.verification_level,
+                verification_method: event.verification_method.clone(),
+                verified_by: event.verified_by,
+                verified_claim: None,
+                completed_at: now,
+                correlation_id: envelope.correlation_id,
+                causation_id: Some(envelope.command_id),
+            });
         }
     }
 }
 
 /// System to complete verification workflows
+///
+/// Turns every finished verification workflow into the matching
+/// `CompleteVerificationCommand`, which `process_verification_system` applies.
+/// The workflow is kept with its history like other finished workflows; only
+/// its `VerificationWorkflow` state is removed, so it is completed once.
 pub fn complete_verification_system(
-    mut _commands: Commands,
-    verifications: Query<(&IdentityEntity, &IdentityVerification, &IdentityWorkflow)>,
-    mut workflow_events: EventWriter<WorkflowCompleted>,
+    mut commands: Commands,
+    mut workflows: Query<(Entity, &mut IdentityWorkflow, &VerificationWorkflow)>,
+    mut complete_commands: EventWriter<CommandEnvelope<CompleteVerificationCommand>>,
+    mut confirmed_claims: EventWriter<VerificationCodeClaimConfirmed>,
+    mut workflow_events: DomainEventWriter<WorkflowCompleted>,
 ) {
-    for (identity, _verification, workflow) in verifications.iter() {
-        // Check if verification workflow is complete
-        if matches!(workflow.workflow_type, WorkflowType::Verification) {
-            match &workflow.status {
-                WorkflowStatus::Completed => {
-                    // Verification workflow completed successfully
-                    workflow_events.write(WorkflowCompleted {
-                        workflow_id: workflow.workflow_id,
-                        identity_id: identity.identity_id,
-                        workflow_type: workflow.workflow_type.clone(),
-                        final_status: WorkflowStatus::Completed,
-                        completed_at: chrono::Utc::now(),
-                    });
-                }
-                WorkflowStatus::Failed(_) | WorkflowStatus::Cancelled => {
-                    // Verification workflow failed
-                    workflow_events.write(WorkflowCompleted {
-                        workflow_id: workflow.workflow_id,
-                        identity_id: identity.identity_id,
-                        workflow_type: workflow.workflow_type.clone(),
-                        final_status: workflow.status.clone(),
-                        completed_at: chrono::Utc::now(),
-                    });
-                }
-                _ => {}
-            }
+    for (entity, mut workflow, verification) in workflows.iter_mut() {
+        if !workflow.is_finished() {
+            continue;
+        }
+        let completed_at = *workflow.completed_at.get_or_insert_with(chrono::Utc::now);
+        workflow.paused_at = None;
+
+        let complete = CommandEnvelope::caused_by(
+            CompleteVerificationCommand {
+                identity_id: workflow.identity_id,
+                verification_result: workflow.status == WorkflowStatus::Completed,
+                verification_level: verification.target_level,
+                verification_method: verification.verification_method.clone(),
+                verified_by: verification.verified_by,
+            },
+            verification.correlation_id,
+            verification.last_command_id,
+        );
+        if let (WorkflowStatus::Completed, Some(claim)) =
+            (&workflow.status, &verification.code_claim)
+        {
+            confirmed_claims.write(VerificationCodeClaimConfirmed {
+                command_id: complete.command_id,
+                claim: claim.clone(),
+            });
         }
+
+        workflow_events.write(WorkflowCompleted {
+            workflow_id: workflow.workflow_id,
+            identity_id: workflow.identity_id,
+            workflow_type: workflow.workflow_type.clone(),
+            final_status: workflow.status.clone(),
+            completed_at,
+            correlation_id: verification.correlation_id,
+            causation_id: Some(complete.command_id),
+        });
+        complete_commands.write(complete);
+
+        commands.entity(entity).remove::<VerificationWorkflow>();
     }
 }
 
 /// System to handle verification claim updates
+///
+/// Only the claim whose one-time code a successful verification confirmed is
+/// marked verified; other methods raise the verification level without
+/// verifying any claim. The claim is reported with a `ClaimVerified` event.
 pub fn update_verification_claims_system(
-    mut _commands: Commands,
-    verifications: Query<(&IdentityEntity, &IdentityVerification)>,
-    mut claims: Query<&mut IdentityClaim>,
+    mut completed_events: EventReader<VerificationCompleted>,
+    mut verified_events: DomainEventWriter<ClaimVerified>,
+    mut claims: Query<&mut IdentityClaims>,
+    index: Res<IdentityIndex>,
 ) {
-    for (_identity, verification) in verifications.iter() {
-        // Update claim verification status based on verification level
-        for mut claim in claims.iter_mut() {
-            match verification.verification_level {
-                VerificationLevel::Basic => {
-                    if matches!(claim.claim_type, ClaimType::Email) {
-                        claim.verified = true;
-                    }
-                }
-                VerificationLevel::Enhanced => {
-                    if matches!(claim.claim_type, ClaimType::Email | ClaimType::Phone) {
-                        claim.verified = true;
-                    }
-                }
-                VerificationLevel::Full => {
-                    // All claims verified
-                    claim.verified = true;
-                }
-                VerificationLevel::Unverified => {
-                    // No claims verified
-                }
-            }
+    for event in completed_events.read() {
+        if !event.verification_successful {
+            continue;
+        }
+        let Some((claim_type, value)) = &event.verified_claim else {
+            continue;
+        };
+        if !mark_claim_verified(&mut claims, &index, event.identity_id, claim_type, value) {
+            continue;
         }
+
+        verified_events.write(ClaimVerified {
+            identity_id: event.identity_id,
+            claim_type: claim_type.clone(),
+            value: value.clone(),
+            verified_at: event.completed_at,
+            correlation_id: event.correlation_id,
+            causation_id: event.causation_id,
+        });
     }
 }
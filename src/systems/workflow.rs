@@ -1,60 +1,107 @@
 //! Identity workflow systems
 
-use crate::{commands::*, components::*, events::*};
+use crate::{
+    aggregate::IdentityAggregate,
+    commands::*,
+    components::*,
+    events::*,
+    persistence::DomainEventWriter,
+    workflows::{identity_scope, WorkflowDefinitions},
+    IdentityError, IdentityIndex, IdentityResult,
+};
 use bevy::ecs::prelude::*;
+use std::collections::HashMap;
 use tracing::trace;
 
 /// System to start identity workflows
+#[allow(clippy::too_many_arguments)]
 pub fn start_workflow_system(
     mut commands: Commands,
-    mut events: EventReader<StartWorkflowCommand>,
-    mut started_events: EventWriter<WorkflowStarted>,
+    mut events: EventReader<CommandEnvelope<StartWorkflowCommand>>,
+    mut started_events: DomainEventWriter<WorkflowStarted>,
+    mut rejected_events: EventWriter<WorkflowStartRejected>,
     identities: Query<&IdentityEntity>,
     workflows: Query<&IdentityWorkflow>,
+    index: Res<IdentityIndex>,
+    definitions: Res<WorkflowDefinitions>,
 ) {
-    for event in events.read() {
+    for envelope in events.read() {
+        let event = &envelope.command;
+
         // Validate identity exists
-        let identity_exists = identities
-            .iter()
-            .any(|e| e.identity_id == event.identity_id);
+        let Some(identity) = index
+            .identity(event.identity_id)
+            .and_then(|entity| identities.get(entity).ok())
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::IdentityNotFound(event.identity_id),
+            ));
+            continue;
+        };
 
-        if !identity_exists {
+        // Validate through aggregate
+        if let Err(e) = IdentityAggregate::validate_workflow_start(identity, &event.workflow_type) {
+            rejected_events.write(CommandRejected::new(envelope, e));
             continue;
         }
 
         // Check for existing workflows of same type
-        let existing_workflow = workflows.iter().any(|w| {
-            w.identity_id == event.identity_id
-                && w.workflow_type == event.workflow_type
-                && matches!(
-                    w.status,
-                    WorkflowStatus::InProgress
-                        | WorkflowStatus::WaitingForInput
-                        | WorkflowStatus::WaitingForApproval
-                )
-        });
+        let existing_workflow = index
+            .workflows_for(event.identity_id)
+            .filter_map(|entity| workflows.get(entity).ok())
+            .any(|w| {
+                w.workflow_type == event.workflow_type
+                    && matches!(
+                        w.status,
+                        WorkflowStatus::InProgress
+                            | WorkflowStatus::Paused
+                            | WorkflowStatus::WaitingForInput
+                            | WorkflowStatus::WaitingForApproval
+                    )
+            });
 
         if existing_workflow {
             // Cannot start duplicate workflow
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::WorkflowInProgress,
+            ));
             continue;
         }
 
+        let Some(definition) = definitions.get(&event.workflow_type) else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::WorkflowError(format!(
+                    "No workflow definition for {:?}",
+                    event.workflow_type
+                )),
+            ));
+            continue;
+        };
+
         let workflow_id = uuid::Uuid::new_v4();
+        let now = chrono::Utc::now();
 
-        // Create new workflow
-        let workflow = IdentityWorkflow {
+        // Create new workflow from its definition
+        let workflow = match definition.instantiate(
             workflow_id,
-            identity_id: event.identity_id,
-            workflow_type: event.workflow_type.clone(),
-            status: WorkflowStatus::NotStarted,
-            current_step: None,
-            steps: Vec::new(), // Workflow steps should be initialized based on workflow type
-            started_at: Some(chrono::Utc::now()),
-            completed_at: None,
+            event.identity_id,
+            event.context.clone(),
+            now,
+        ) {
+            Ok(workflow) => workflow,
+            Err(e) => {
+                rejected_events.write(CommandRejected::new(envelope, e));
+                continue;
+            }
         };
+        let steps = workflow.steps.clone();
+        let transitions = workflow.transitions.clone();
 
         // Spawn workflow entity
-        commands.spawn((workflow,));
+        commands.spawn((workflow, WorkflowHistory::new(workflow_id)));
 
         // Emit started event
         started_events.write(WorkflowStarted {
@@ -62,84 +109,438 @@ pub fn start_workflow_system(
             identity_id: event.identity_id,
             workflow_type: event.workflow_type.clone(),
             started_by: event.started_by,
-            started_at: chrono::Utc::now(),
+            started_at: now,
             context: event.context.clone(),
+            steps,
+            transitions,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
         });
     }
 }
 
 /// System to process workflow steps
+///
+/// The active step ends with the command's outcome and the workflow moves on
+/// along the first transition whose condition holds for it, with expression
+/// conditions reading the attributes of the workflow's identity. Each move is
+/// recorded in the workflow's history. Verification workflows advance through
+/// `ProcessVerificationCommand` and approval steps with an approval policy
+/// through approval decisions instead.
+#[allow(clippy::too_many_arguments)]
 pub fn process_workflow_step_system(
-    mut events: EventReader<CompleteWorkflowCommand>,
-    mut workflows: Query<(Entity, &mut IdentityWorkflow)>,
-    mut writer: EventWriter<WorkflowStepCompleted>,
+    mut commands: Commands,
+    mut events: EventReader<CommandEnvelope<ProcessWorkflowStepCommand>>,
+    mut workflows: Query<(
+        &mut IdentityWorkflow,
+        Option<&mut WorkflowHistory>,
+        Has<VerificationWorkflow>,
+    )>,
+    mut writer: DomainEventWriter<WorkflowStepCompleted>,
+    mut rejected_events: EventWriter<WorkflowStepRejected>,
+    identities: Query<(
+        &IdentityEntity,
+        Option<&IdentityVerification>,
+        Option<&IdentityClaims>,
+    )>,
+    index: Res<IdentityIndex>,
 ) {
-    for event in events.read() {
-        for (_entity, mut workflow) in workflows.iter_mut() {
-            if workflow.workflow_id == *event.workflow_id.as_uuid() {
-                // Find and update the current step
-                let current_step_id = workflow.current_step.clone();
-                
-                // Update workflow status
-                workflow.status = WorkflowStatus::InProgress;
-                
-                // Log current step progress
-                if let Some(step_id) = current_step_id {
-                    trace!("Processing workflow step: {:?}", step_id);
+    // Histories inserted this frame for workflows started without one
+    let mut new_histories: HashMap<Entity, WorkflowHistory> = HashMap::new();
+
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = chrono::Utc::now();
+
+        let Some((entity, (mut workflow, history, is_verification))) = index
+            .workflow(*event.workflow_id.as_uuid())
+            .and_then(|entity| workflows.get_mut(entity).ok().map(|found| (entity, found)))
+        else {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::WorkflowError(format!(
+                    "Workflow not found: {}",
+                    event.workflow_id.as_uuid()
+                )),
+            ));
+            continue;
+        };
+
+        let rejection = if is_verification {
+            Some("Verification workflows advance through verification commands".to_string())
+        } else if workflow.is_finished() {
+            Some("Workflow has already finished".to_string())
+        } else if workflow.status == WorkflowStatus::Paused {
+            Some("Workflow is paused".to_string())
+        } else if workflow.current_step.as_ref() != Some(&event.step_name) {
+            Some(format!("Step {} is not active", event.step_name))
+        } else if workflow.active_step().is_some_and(|s| s.approval.is_some()) {
+            Some(format!(
+                "Step {} is decided by its approvers",
+                event.step_name
+            ))
+        } else {
+            None
+        };
+        if let Some(reason) = rejection {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::WorkflowError(reason),
+            ));
+            continue;
+        }
+
+        let identity = index
+            .identity(workflow.identity_id)
+            .and_then(|entity| identities.get(entity).ok())
+            .map_or(
+                serde_json::Value::Null,
+                |(identity, verification, claims)| identity_scope(identity, verification, claims),
+            );
+        let next_step =
+            match workflow.finish_active_step(&event.outcome, &event.step_data, &identity, now) {
+                Ok(next_step) => next_step,
+                Err(e) => {
+                    rejected_events.write(CommandRejected::new(envelope, e));
+                    continue;
                 }
-                
-                // Emit step completed event
-                writer.write(WorkflowStepCompleted {
-                    workflow_id: workflow.workflow_id,
-                    identity_id: workflow.identity_id,
-                    workflow_type: workflow.workflow_type.clone(),
-                    step_id: workflow.current_step.clone().unwrap_or_default(),
-                    completed_at: chrono::Utc::now(),
-                });
-            }
+            };
+        trace!(
+            "Workflow {} moved from {} to {:?}",
+            workflow.workflow_id,
+            event.step_name,
+            next_step
+        );
+
+        let completed = WorkflowStepCompleted {
+            workflow_id: workflow.workflow_id,
+            identity_id: workflow.identity_id,
+            workflow_type: workflow.workflow_type.clone(),
+            step_id: event.step_name.clone(),
+            outcome: event.outcome.clone(),
+            next_step,
+            processed_by: Some(event.processed_by),
+            step_data: event.step_data.clone(),
+            completed_at: now,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        };
+        match history {
+            Some(mut history) => record_step(&mut history, &workflow, &completed),
+            None => record_step(
+                new_histories
+                    .entry(entity)
+                    .or_insert_with(|| WorkflowHistory::new(workflow.workflow_id)),
+                &workflow,
+                &completed,
+            ),
         }
+
+        writer.write(completed);
+    }
+
+    for (entity, history) in new_histories {
+        commands.entity(entity).insert(history);
+    }
+}
+
+/// Add a processed step to a workflow's history
+pub(crate) fn record_step(
+    history: &mut WorkflowHistory,
+    workflow: &IdentityWorkflow,
+    completed: &WorkflowStepCompleted,
+) {
+    if let Some(next_step) = &completed.next_step {
+        history.step_transitions.push(StepTransition {
+            from_step: completed.step_id.clone(),
+            to_step: next_step.clone(),
+            transitioned_at: completed.completed_at,
+            transitioned_by: completed.processed_by,
+            reason: completed.outcome.to_string(),
+            data: completed.step_data.clone(),
+        });
+    }
+    if workflow.is_finished() {
+        history.total_duration = workflow
+            .started_at
+            .map(|started_at| completed.completed_at - started_at);
+        history.completion_data = Some(completed.step_data.clone());
     }
 }
 
 /// System to complete workflows
+///
+/// The workflow ends with the status of the command's outcome and is kept,
+/// together with its history. Verification workflows are completed by their
+/// own steps.
 pub fn complete_workflow_system(
-    mut commands: Commands,
-    mut completed_events: EventWriter<WorkflowCompleted>,
-    mut workflows: Query<(Entity, &mut IdentityWorkflow)>,
-    mut events: EventReader<CompleteWorkflowCommand>,
+    mut completed_events: DomainEventWriter<WorkflowCompleted>,
+    mut rejected_events: EventWriter<WorkflowCompletionRejected>,
+    mut workflows: Query<(&mut IdentityWorkflow, Has<VerificationWorkflow>)>,
+    mut events: EventReader<CommandEnvelope<CompleteWorkflowCommand>>,
+    index: Res<IdentityIndex>,
+) {
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = chrono::Utc::now();
+
+        let mut workflow =
+            match controlled_workflow(&mut workflows, &index, *event.workflow_id.as_uuid()) {
+                Ok(workflow) => workflow,
+                Err(e) => {
+                    rejected_events.write(CommandRejected::new(envelope, e));
+                    continue;
+                }
+            };
+
+        // Check if workflow can be completed
+        if matches!(
+            workflow.status,
+            WorkflowStatus::Completed | WorkflowStatus::Failed(_) | WorkflowStatus::Cancelled
+        ) {
+            rejected_events.write(CommandRejected::new(
+                envelope,
+                IdentityError::WorkflowError("Workflow has already finished".to_string()),
+            ));
+            continue;
+        }
+
+        workflow.status = event.outcome.final_status();
+        workflow.paused_at = None;
+        workflow.completed_at = Some(now);
+
+        // Emit completed event
+        completed_events.write(WorkflowCompleted {
+            workflow_id: workflow.workflow_id,
+            identity_id: workflow.identity_id,
+            workflow_type: workflow.workflow_type.clone(),
+            final_status: workflow.status.clone(),
+            completed_at: now,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        });
+    }
+}
+
+/// System to pause workflows
+///
+/// A paused workflow stays on its active step and neither advances, times out
+/// nor escalates until it is resumed.
+pub fn pause_workflow_system(
+    mut events: EventReader<CommandEnvelope<PauseWorkflowCommand>>,
+    mut workflows: Query<(&mut IdentityWorkflow, Has<VerificationWorkflow>)>,
+    index: Res<IdentityIndex>,
+    mut paused_events: DomainEventWriter<WorkflowPaused>,
+    mut rejected_events: EventWriter<WorkflowPauseRejected>,
 ) {
-    for event in events.read() {
-        for (entity, workflow) in workflows.iter_mut() {
-            if workflow.workflow_id == *event.workflow_id.as_uuid() {
-                // Check if workflow can be completed
-                if matches!(
-                    workflow.status,
-                    WorkflowStatus::Completed
-                        | WorkflowStatus::Failed(_)
-                        | WorkflowStatus::Cancelled
-                ) {
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = chrono::Utc::now();
+
+        let mut workflow =
+            match controlled_workflow(&mut workflows, &index, *event.workflow_id.as_uuid())
+                .and_then(|w| IdentityAggregate::validate_workflow_pause(&w).map(|_| w))
+            {
+                Ok(workflow) => workflow,
+                Err(e) => {
+                    rejected_events.write(CommandRejected::new(envelope, e));
                     continue;
                 }
+            };
+        workflow.pause(now);
+
+        paused_events.write(WorkflowPaused {
+            workflow_id: workflow.workflow_id,
+            identity_id: workflow.identity_id,
+            paused_by: event.paused_by,
+            reason: event.reason.clone(),
+            paused_at: now,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        });
+    }
+}
+
+/// System to resume paused workflows
+///
+/// The active step's timeout, and the escalation of its approval, do not
+/// count the time the workflow was paused.
+pub fn resume_workflow_system(
+    mut events: EventReader<CommandEnvelope<ResumeWorkflowCommand>>,
+    mut workflows: Query<(&mut IdentityWorkflow, Has<VerificationWorkflow>)>,
+    mut tasks: Query<&mut ApprovalTask>,
+    index: Res<IdentityIndex>,
+    mut resumed_events: DomainEventWriter<WorkflowResumed>,
+    mut rejected_events: EventWriter<WorkflowResumeRejected>,
+) {
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = chrono::Utc::now();
+        let workflow_id = *event.workflow_id.as_uuid();
 
-                // Emit completed event
-                completed_events.write(WorkflowCompleted {
-                    workflow_id: workflow.workflow_id,
-                    identity_id: workflow.identity_id,
-                    workflow_type: workflow.workflow_type.clone(),
-                    final_status: workflow.status.clone(),
-                    completed_at: chrono::Utc::now(),
-                });
-
-                // Remove active workflow
-                commands.entity(entity).despawn();
+        let mut workflow = match controlled_workflow(&mut workflows, &index, workflow_id)
+            .and_then(|w| IdentityAggregate::validate_workflow_resume(&w).map(|_| w))
+        {
+            Ok(workflow) => workflow,
+            Err(e) => {
+                rejected_events.write(CommandRejected::new(envelope, e));
+                continue;
             }
+        };
+        let paused_for = workflow.resume(now);
+        if let Some(mut task) = index
+            .workflow(workflow_id)
+            .and_then(|entity| tasks.get_mut(entity).ok())
+        {
+            task.requested_at += paused_for;
         }
+
+        resumed_events.write(WorkflowResumed {
+            workflow_id,
+            identity_id: workflow.identity_id,
+            resumed_by: event.resumed_by,
+            resumed_at: now,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        });
+    }
+}
+
+/// System to cancel workflows
+///
+/// Cancelled workflows are kept, together with their history.
+pub fn cancel_workflow_system(
+    mut events: EventReader<CommandEnvelope<CancelWorkflowCommand>>,
+    mut workflows: Query<(&mut IdentityWorkflow, Has<VerificationWorkflow>)>,
+    index: Res<IdentityIndex>,
+    mut cancelled_events: DomainEventWriter<WorkflowCancelled>,
+    mut rejected_events: EventWriter<WorkflowCancellationRejected>,
+) {
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = chrono::Utc::now();
+
+        let mut workflow =
+            match controlled_workflow(&mut workflows, &index, *event.workflow_id.as_uuid())
+                .and_then(|w| IdentityAggregate::validate_workflow_cancel(&w).map(|_| w))
+            {
+                Ok(workflow) => workflow,
+                Err(e) => {
+                    rejected_events.write(CommandRejected::new(envelope, e));
+                    continue;
+                }
+            };
+        workflow.cancel(now);
+
+        cancelled_events.write(WorkflowCancelled {
+            workflow_id: workflow.workflow_id,
+            identity_id: workflow.identity_id,
+            cancelled_by: event.cancelled_by,
+            reason: event.reason.clone(),
+            cancelled_at: now,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        });
     }
 }
 
+/// System to retry the step a workflow failed on
+///
+/// The step becomes active again once its retry policy's backoff has passed,
+/// reopening the workflow, and the retry is recorded in the workflow's
+/// history as a transition from the step to itself.
+pub fn retry_workflow_step_system(
+    mut events: EventReader<CommandEnvelope<RetryWorkflowStepCommand>>,
+    mut workflows: Query<(&mut IdentityWorkflow, Has<VerificationWorkflow>)>,
+    mut histories: Query<&mut WorkflowHistory>,
+    index: Res<IdentityIndex>,
+    mut retried_events: DomainEventWriter<WorkflowStepRetried>,
+    mut rejected_events: EventWriter<WorkflowStepRetryRejected>,
+) {
+    for envelope in events.read() {
+        let event = &envelope.command;
+        let now = chrono::Utc::now();
+        let workflow_id = *event.workflow_id.as_uuid();
+
+        let mut workflow =
+            match controlled_workflow(&mut workflows, &index, workflow_id).and_then(|w| {
+                IdentityAggregate::validate_step_retry(&w, &event.step_name, now).map(|_| w)
+            }) {
+                Ok(workflow) => workflow,
+                Err(e) => {
+                    rejected_events.write(CommandRejected::new(envelope, e));
+                    continue;
+                }
+            };
+        let Some(attempt) = workflow.retry_step(&event.step_name, now) else {
+            continue;
+        };
+        trace!(
+            "Workflow {} retries step {} (attempt {})",
+            workflow_id,
+            event.step_name,
+            attempt
+        );
+
+        let retried = WorkflowStepRetried {
+            workflow_id,
+            identity_id: workflow.identity_id,
+            step_id: event.step_name.clone(),
+            attempt,
+            retried_by: event.retried_by,
+            retried_at: now,
+            correlation_id: envelope.correlation_id,
+            causation_id: Some(envelope.command_id),
+        };
+        if let Some(mut history) = index
+            .workflow(workflow_id)
+            .and_then(|entity| histories.get_mut(entity).ok())
+        {
+            record_retry(&mut history, &retried);
+        }
+
+        retried_events.write(retried);
+    }
+}
+
+/// Add a retried step to a workflow's history
+pub(crate) fn record_retry(history: &mut WorkflowHistory, retried: &WorkflowStepRetried) {
+    history.step_transitions.push(StepTransition {
+        from_step: retried.step_id.clone(),
+        to_step: retried.step_id.clone(),
+        transitioned_at: retried.retried_at,
+        transitioned_by: Some(retried.retried_by),
+        reason: "retry".to_string(),
+        data: serde_json::json!({ "attempt": retried.attempt }),
+    });
+}
+
+/// The workflow `workflow_id`, unless it is a verification workflow
+fn controlled_workflow<'w>(
+    workflows: &'w mut Query<(&mut IdentityWorkflow, Has<VerificationWorkflow>)>,
+    index: &IdentityIndex,
+    workflow_id: uuid::Uuid,
+) -> IdentityResult<Mut<'w, IdentityWorkflow>> {
+    let Some((workflow, is_verification)) = index
+        .workflow(workflow_id)
+        .and_then(|entity| workflows.get_mut(entity).ok())
+    else {
+        return Err(IdentityError::WorkflowError(format!(
+            "Workflow not found: {workflow_id}"
+        )));
+    };
+    if is_verification {
+        return Err(IdentityError::WorkflowError(
+            "Verification workflows advance through verification commands".to_string(),
+        ));
+    }
+    Ok(workflow)
+}
+
 /// System to handle workflow timeouts
 pub fn timeout_workflows_system(
     mut workflows: Query<&mut IdentityWorkflow>,
+    mut timed_out_events: DomainEventWriter<WorkflowTimedOut>,
     time: Res<bevy::time::Time>,
 ) {
     // Use the time resource to get elapsed time since startup
@@ -155,8 +556,13 @@ pub fn timeout_workflows_system(
     }
 
     for mut workflow in workflows.iter_mut() {
-        // Skip if not in progress
-        if !matches!(workflow.status, WorkflowStatus::InProgress) {
+        // Skip if not running or waiting on its current step
+        if !matches!(
+            workflow.status,
+            WorkflowStatus::InProgress
+                | WorkflowStatus::WaitingForInput
+                | WorkflowStatus::WaitingForApproval
+        ) {
             continue;
         }
 
@@ -174,7 +580,19 @@ pub fn timeout_workflows_system(
                         if elapsed.num_seconds() > timeout_seconds as i64 {
                             // Timeout occurred
                             step.status = StepStatus::Failed;
+                            step.completed_at = Some(current_time);
                             workflow.status = WorkflowStatus::Failed("Step timeout".to_string());
+                            workflow.completed_at = Some(current_time);
+
+                            timed_out_events.write(WorkflowTimedOut {
+                                workflow_id: workflow.workflow_id,
+                                identity_id: workflow.identity_id,
+                                workflow_type: workflow.workflow_type.clone(),
+                                step_id: step_id.clone(),
+                                timed_out_at: current_time,
+                                correlation_id: uuid::Uuid::new_v4(),
+                                causation_id: None,
+                            });
                         }
                     }
                 }
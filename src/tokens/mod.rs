@@ -0,0 +1,312 @@
+//! Signed identity tokens
+//!
+//! Tokens are compact JWS strings (`header.payload.signature`, base64url)
+//! signed with Ed25519 (`"alg": "EdDSA"`), so any JWT library can read them.
+//! The header names the signing key by `kid`; after a rotation the previous
+//! public keys stay with the [`TokenIssuer`] so tokens signed before the
+//! rotation keep verifying until they expire or their key is retired.
+//!
+//! Downstream domains verify tokens offline with a [`TokenVerifier`] built
+//! from the published public keys. Rejecting tokens of identities that have
+//! since been suspended, archived or merged needs the current identity state
+//! and is done by `validate_identity_token_system`.
+
+use crate::components::{ClaimType, IdentityClaim, IdentityId, IdentityType, VerificationLevel};
+use crate::{IdentityError, IdentityResult};
+use bevy::ecs::prelude::*;
+use chrono::{DateTime, Duration, Utc};
+use data_encoding::BASE64URL_NOPAD;
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+const TOKEN_ALGORITHM: &str = "EdDSA";
+const TOKEN_TYPE: &str = "JWT";
+
+/// Claims carried by an identity token
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdentityTokenClaims {
+    /// Issuer
+    pub iss: String,
+    /// Identity the token was issued to
+    pub sub: IdentityId,
+    /// Token id
+    pub jti: Uuid,
+    /// Issued at, seconds since the Unix epoch
+    pub iat: i64,
+    /// Expires at, seconds since the Unix epoch
+    pub exp: i64,
+    pub identity_type: IdentityType,
+    pub verification_level: VerificationLevel,
+    /// Verified claims selected at issuance
+    #[serde(default)]
+    pub claims: Vec<TokenClaim>,
+}
+
+impl IdentityTokenClaims {
+    /// Expiry as a timestamp
+    pub fn expires_at(&self) -> DateTime<Utc> {
+        DateTime::from_timestamp(self.exp, 0).unwrap_or(DateTime::<Utc>::MIN_UTC)
+    }
+
+    /// Value of the first carried claim of the given type
+    pub fn claim(&self, claim_type: &ClaimType) -> Option<&str> {
+        self.claims
+            .iter()
+            .find(|claim| &claim.claim_type == claim_type)
+            .map(|claim| claim.value.as_str())
+    }
+}
+
+/// Verified identity claim embedded in a token
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenClaim {
+    pub claim_type: ClaimType,
+    pub value: String,
+}
+
+impl TokenClaim {
+    /// The claim if it is verified and unexpired at `now`
+    pub fn from_verified(claim: &IdentityClaim, now: DateTime<Utc>) -> Option<Self> {
+        (claim.verified && claim.expires_at.is_none_or(|expires| now < expires)).then(|| {
+            TokenClaim {
+                claim_type: claim.claim_type.clone(),
+                value: claim.value.clone(),
+            }
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TokenHeader {
+    alg: String,
+    typ: String,
+    kid: String,
+}
+
+/// Ed25519 key signing identity tokens
+pub struct TokenSigningKey {
+    kid: String,
+    key: SigningKey,
+}
+
+impl TokenSigningKey {
+    /// Generate a key with a fresh key id
+    pub fn generate() -> Self {
+        Self::from_signing_key(
+            Uuid::new_v4().simple().to_string(),
+            SigningKey::generate(&mut OsRng),
+        )
+    }
+
+    /// Use an existing key under the given key id
+    pub fn from_signing_key(kid: impl Into<String>, key: SigningKey) -> Self {
+        TokenSigningKey {
+            kid: kid.into(),
+            key,
+        }
+    }
+
+    pub fn kid(&self) -> &str {
+        &self.kid
+    }
+
+    pub fn verifying_key(&self) -> VerifyingKey {
+        self.key.verifying_key()
+    }
+
+    /// Sign claims into a compact token
+    pub fn sign(&self, claims: &IdentityTokenClaims) -> IdentityResult<String> {
+        let header = TokenHeader {
+            alg: TOKEN_ALGORITHM.to_string(),
+            typ: TOKEN_TYPE.to_string(),
+            kid: self.kid.clone(),
+        };
+        let signing_input = format!("{}.{}", encode_json(&header)?, encode_json(claims)?);
+        let signature = self.key.sign(signing_input.as_bytes());
+        Ok(format!(
+            "{signing_input}.{}",
+            BASE64URL_NOPAD.encode(&signature.to_bytes())
+        ))
+    }
+
+    /// Sign raw bytes, such as the hash data of a credential proof
+    pub(crate) fn sign_message(&self, message: &[u8]) -> Signature {
+        self.key.sign(message)
+    }
+}
+
+impl std::fmt::Debug for TokenSigningKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenSigningKey")
+            .field("kid", &self.kid)
+            .field("key", &"<redacted>")
+            .finish()
+    }
+}
+
+/// Offline verifier for identity tokens
+///
+/// Holds only public keys, so it can be handed to any domain consuming
+/// identity tokens.
+#[derive(Debug, Clone)]
+pub struct TokenVerifier {
+    issuer: String,
+    keys: HashMap<String, VerifyingKey>,
+}
+
+impl TokenVerifier {
+    pub fn new(issuer: impl Into<String>) -> Self {
+        TokenVerifier {
+            issuer: issuer.into(),
+            keys: HashMap::new(),
+        }
+    }
+
+    /// Trust tokens signed by the key with the given id
+    pub fn with_key(mut self, kid: impl Into<String>, key: VerifyingKey) -> Self {
+        self.keys.insert(kid.into(), key);
+        self
+    }
+
+    /// Check the signature, issuer and expiry of a token and return its claims
+    pub fn verify(&self, token: &str, now: DateTime<Utc>) -> IdentityResult<IdentityTokenClaims> {
+        let Some((signing_input, signature)) = token.rsplit_once('.') else {
+            return Err(invalid_token("malformed token"));
+        };
+        let Some((header, payload)) = signing_input
+            .split_once('.')
+            .filter(|(_, payload)| !payload.contains('.'))
+        else {
+            return Err(invalid_token("malformed token"));
+        };
+
+        let header: TokenHeader = decode_json(header)?;
+        if header.alg != TOKEN_ALGORITHM {
+            return Err(invalid_token("unsupported algorithm"));
+        }
+        let key = self
+            .keys
+            .get(&header.kid)
+            .ok_or_else(|| invalid_token("unknown key id"))?;
+
+        let signature = BASE64URL_NOPAD
+            .decode(signature.as_bytes())
+            .ok()
+            .and_then(|bytes| Signature::from_slice(&bytes).ok())
+            .ok_or_else(|| invalid_token("malformed signature"))?;
+        key.verify_strict(signing_input.as_bytes(), &signature)
+            .map_err(|_| invalid_token("signature mismatch"))?;
+
+        let claims: IdentityTokenClaims = decode_json(payload)?;
+        if claims.iss != self.issuer {
+            return Err(invalid_token("unexpected issuer"));
+        }
+        if now.timestamp() >= claims.exp {
+            return Err(IdentityError::TokenExpired);
+        }
+
+        Ok(claims)
+    }
+}
+
+/// Resource issuing identity tokens
+///
+/// Inserted by `IdentityPlugin` with a freshly generated key unless the
+/// application inserts its own first.
+#[derive(Resource, Debug)]
+pub struct TokenIssuer {
+    issuer: String,
+    lifetime: Duration,
+    active: TokenSigningKey,
+    previous: HashMap<String, VerifyingKey>,
+}
+
+impl TokenIssuer {
+    /// Default lifetime of issued tokens
+    pub const DEFAULT_LIFETIME_MINUTES: i64 = 15;
+
+    pub fn new(issuer: impl Into<String>, key: TokenSigningKey) -> Self {
+        TokenIssuer {
+            issuer: issuer.into(),
+            lifetime: Duration::minutes(Self::DEFAULT_LIFETIME_MINUTES),
+            active: key,
+            previous: HashMap::new(),
+        }
+    }
+
+    /// Set the lifetime of tokens issued without an explicit expiry
+    pub fn with_lifetime(mut self, lifetime: Duration) -> Self {
+        self.lifetime = lifetime;
+        self
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    pub fn lifetime(&self) -> Duration {
+        self.lifetime
+    }
+
+    /// Id of the key signing new tokens
+    pub fn active_kid(&self) -> &str {
+        self.active.kid()
+    }
+
+    /// Sign new tokens with `key`, keeping the current key for verification
+    pub fn rotate(&mut self, key: TokenSigningKey) {
+        let old = std::mem::replace(&mut self.active, key);
+        self.previous.insert(old.kid, old.key.verifying_key());
+    }
+
+    /// Stop accepting tokens signed by a previous key
+    pub fn retire(&mut self, kid: &str) -> bool {
+        self.previous.remove(kid).is_some()
+    }
+
+    /// Public keys accepted for verification, by key id
+    pub fn public_keys(&self) -> impl Iterator<Item = (&str, VerifyingKey)> + '_ {
+        std::iter::once((self.active.kid(), self.active.verifying_key()))
+            .chain(self.previous.iter().map(|(kid, key)| (kid.as_str(), *key)))
+    }
+
+    /// Verifier trusting the active and previous keys
+    pub fn verifier(&self) -> TokenVerifier {
+        self.public_keys()
+            .fold(TokenVerifier::new(self.issuer.clone()), |verifier, (kid, key)| {
+                verifier.with_key(kid, key)
+            })
+    }
+
+    /// Sign claims with the active key
+    pub fn sign(&self, claims: &IdentityTokenClaims) -> IdentityResult<String> {
+        self.active.sign(claims)
+    }
+}
+
+impl Default for TokenIssuer {
+    fn default() -> Self {
+        TokenIssuer::new("cim-domain-identity", TokenSigningKey::generate())
+    }
+}
+
+fn invalid_token(reason: &str) -> IdentityError {
+    IdentityError::InvalidToken(reason.to_string())
+}
+
+fn encode_json<T: Serialize>(value: &T) -> IdentityResult<String> {
+    serde_json::to_vec(value)
+        .map(|json| BASE64URL_NOPAD.encode(&json))
+        .map_err(|e| IdentityError::InvalidOperation(format!("Token encoding failed: {e}")))
+}
+
+fn decode_json<T: for<'de> Deserialize<'de>>(part: &str) -> IdentityResult<T> {
+    BASE64URL_NOPAD
+        .decode(part.as_bytes())
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .ok_or_else(|| invalid_token("malformed token"))
+}
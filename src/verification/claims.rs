@@ -0,0 +1,112 @@
+//! Verification levels supported by claims, and re-verification reminders
+//!
+//! A verification level is kept only while the identity holds the verified
+//! claims supporting it: an email for `Basic`, an email and a phone for
+//! `Enhanced` and `Full`. When such a claim is revoked, replaced or expires
+//! and no other verified claim of its type remains, the level drops to the
+//! highest level the claim was not needed for.
+//!
+//! Verified claims close to their expiry raise a reminder once per expiry
+//! date, as a `Verification` workflow started for the identity.
+
+use crate::components::{ClaimType, IdentityClaim, IdentityClaims, IdentityId, VerificationLevel};
+use bevy::ecs::prelude::*;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+
+/// Whether a verified claim of this type is needed to keep `level`
+pub fn claim_supports_level(claim_type: &ClaimType, level: VerificationLevel) -> bool {
+    match level {
+        VerificationLevel::Unverified => false,
+        VerificationLevel::Basic => *claim_type == ClaimType::Email,
+        VerificationLevel::Enhanced | VerificationLevel::Full => {
+            matches!(claim_type, ClaimType::Email | ClaimType::Phone)
+        }
+    }
+}
+
+/// Level left once `lapsed` is no longer among the identity's `claims`
+pub fn level_after_claim_lapse(
+    level: VerificationLevel,
+    claims: &IdentityClaims,
+    lapsed: &IdentityClaim,
+) -> VerificationLevel {
+    let still_supported = claims
+        .of_type(&lapsed.claim_type)
+        .any(|claim| claim.verified);
+    if !lapsed.verified || still_supported {
+        return level;
+    }
+
+    [
+        VerificationLevel::Full,
+        VerificationLevel::Enhanced,
+        VerificationLevel::Basic,
+    ]
+    .into_iter()
+    .filter(|candidate| *candidate <= level)
+    .find(|candidate| !claim_supports_level(&lapsed.claim_type, *candidate))
+    .unwrap_or(VerificationLevel::Unverified)
+}
+
+type ReminderKey = (IdentityId, ClaimType, String);
+
+/// Claims already reminded about, and how long before expiry reminders go out
+#[derive(Resource, Debug, Clone)]
+pub struct ReverificationReminders {
+    notice: Duration,
+    /// Expiry each claim was last reminded about
+    reminded: HashMap<ReminderKey, DateTime<Utc>>,
+}
+
+impl Default for ReverificationReminders {
+    fn default() -> Self {
+        Self::new(Duration::days(30))
+    }
+}
+
+impl ReverificationReminders {
+    pub fn new(notice: Duration) -> Self {
+        Self {
+            notice,
+            reminded: HashMap::new(),
+        }
+    }
+
+    pub fn notice(&self) -> Duration {
+        self.notice
+    }
+
+    /// Whether a verified claim expires within the notice period and has not
+    /// been reminded about for its current expiry
+    pub fn is_due(
+        &self,
+        identity_id: IdentityId,
+        claim: &IdentityClaim,
+        now: DateTime<Utc>,
+    ) -> bool {
+        let Some(expires_at) = claim.expires_at else {
+            return false;
+        };
+        let key = (identity_id, claim.claim_type.clone(), claim.value.clone());
+        claim.verified
+            && expires_at - now <= self.notice
+            && self.reminded.get(&key) != Some(&expires_at)
+    }
+
+    /// Remember that a claim was reminded about for its current expiry
+    pub fn mark_reminded(&mut self, identity_id: IdentityId, claim: &IdentityClaim) {
+        if let Some(expires_at) = claim.expires_at {
+            self.reminded.insert(
+                (identity_id, claim.claim_type.clone(), claim.value.clone()),
+                expires_at,
+            );
+        }
+    }
+
+    /// Drop the reminder of a claim that is no longer held
+    pub fn forget(&mut self, identity_id: IdentityId, claim_type: &ClaimType, value: &str) {
+        self.reminded
+            .remove(&(identity_id, claim_type.clone(), value.to_string()));
+    }
+}
@@ -0,0 +1,376 @@
+//! One-time codes for email and phone claims
+//!
+//! [`VerificationCodes`] keeps one challenge per claim. Only an HMAC-SHA256 of
+//! a code, keyed by the server's [`CodeHashKey`], is stored, next to its
+//! expiry and the number of failed attempts.
+//! Sending a new code replaces the previous one but is throttled, and a
+//! challenge is dropped once it is confirmed, expires or runs out of attempts.
+//! The throttle keeps counting from the last code sent even once its
+//! challenge is dropped. Challenges are recorded in `VerificationCodeSent`
+//! events and wrong codes in `VerificationCodeFailed` events, so replay can
+//! put them back as long as the same key is configured.
+//! Codes reach the identity through a [`VerificationNotifier`].
+
+use crate::components::{ClaimType, IdentityId};
+use crate::{IdentityError, IdentityResult};
+use bevy::ecs::prelude::*;
+use chrono::{DateTime, Duration, Utc};
+use data_encoding::HEXLOWER;
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use subtle::ConstantTimeEq;
+
+/// Limits applied to verification codes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerificationCodePolicy {
+    pub email_code_ttl: Duration,
+    pub phone_code_ttl: Duration,
+    /// Wrong codes accepted before the challenge is dropped
+    pub max_attempts: u32,
+    /// Minimum time between two codes sent for the same claim
+    pub resend_interval: Duration,
+}
+
+impl Default for VerificationCodePolicy {
+    fn default() -> Self {
+        Self {
+            email_code_ttl: Duration::minutes(15),
+            phone_code_ttl: Duration::minutes(5),
+            max_attempts: 5,
+            resend_interval: Duration::seconds(60),
+        }
+    }
+}
+
+impl VerificationCodePolicy {
+    /// How long a code for the given claim type stays valid
+    pub fn code_ttl(&self, claim_type: &ClaimType) -> Duration {
+        match claim_type {
+            ClaimType::Phone => self.phone_code_ttl,
+            _ => self.email_code_ttl,
+        }
+    }
+}
+
+/// A code to deliver to the holder of a claim
+#[derive(Clone, PartialEq, Eq)]
+pub struct CodeDelivery {
+    pub identity_id: IdentityId,
+    pub claim_type: ClaimType,
+    /// Email address or phone number the code is sent to
+    pub destination: String,
+    pub code: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl fmt::Debug for CodeDelivery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CodeDelivery")
+            .field("identity_id", &self.identity_id)
+            .field("claim_type", &self.claim_type)
+            .field("destination", &self.destination)
+            .field("code", &"<redacted>")
+            .field("expires_at", &self.expires_at)
+            .finish()
+    }
+}
+
+/// Outbound channel delivering verification codes by email or SMS
+pub trait VerificationNotifier: Send + Sync {
+    fn send_code(&self, delivery: &CodeDelivery) -> IdentityResult<()>;
+}
+
+/// Notifier keeping delivered codes in memory, for tests and development
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryNotifier {
+    sent: Arc<Mutex<Vec<CodeDelivery>>>,
+}
+
+impl InMemoryNotifier {
+    /// Every delivery so far, oldest first
+    pub fn sent(&self) -> Vec<CodeDelivery> {
+        self.sent.lock().unwrap().clone()
+    }
+
+    /// Latest code sent to a destination
+    pub fn last_code_for(&self, destination: &str) -> Option<String> {
+        self.sent
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|delivery| delivery.destination == destination)
+            .map(|delivery| delivery.code.clone())
+    }
+}
+
+impl VerificationNotifier for InMemoryNotifier {
+    fn send_code(&self, delivery: &CodeDelivery) -> IdentityResult<()> {
+        self.sent.lock().unwrap().push(delivery.clone());
+        Ok(())
+    }
+}
+
+/// Server secret keying the hashes of verification codes
+///
+/// Six digit codes are easily guessed from a plain hash, so a stored hash is
+/// only useful together with this key. Left out of `Debug` output.
+#[derive(Clone, PartialEq, Eq)]
+pub struct CodeHashKey(Vec<u8>);
+
+impl CodeHashKey {
+    /// Generate a new random key
+    pub fn generate() -> Self {
+        let mut bytes = vec![0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        CodeHashKey(bytes)
+    }
+
+    /// Wrap raw key bytes, such as a secret loaded from configuration
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        CodeHashKey(bytes)
+    }
+
+    fn hash(&self, code: &str) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.0).expect("HMAC accepts keys of any length");
+        mac.update(code.trim().as_bytes());
+        HEXLOWER.encode(&mac.finalize().into_bytes())
+    }
+}
+
+impl fmt::Debug for CodeHashKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CodeHashKey(<redacted>)")
+    }
+}
+
+/// Pending code for one claim
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CodeChallenge {
+    pub identity_id: IdentityId,
+    pub claim_type: ClaimType,
+    pub value: String,
+    /// HMAC of the code under the server's [`CodeHashKey`]
+    pub code_hash: String,
+    pub sent_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub failed_attempts: u32,
+}
+
+type ChallengeKey = (IdentityId, ClaimType, String);
+
+/// Pending verification codes and the notifier delivering them
+///
+/// Defaults to an [`InMemoryNotifier`] and a key generated at startup;
+/// applications insert their own `VerificationCodes` before adding the
+/// `IdentityPlugin`.
+#[derive(Resource, Clone)]
+pub struct VerificationCodes {
+    policy: VerificationCodePolicy,
+    notifier: Arc<dyn VerificationNotifier>,
+    key: CodeHashKey,
+    challenges: HashMap<ChallengeKey, CodeChallenge>,
+    /// When the last code was sent for each claim, kept for the resend throttle
+    last_sent: HashMap<ChallengeKey, DateTime<Utc>>,
+}
+
+impl Default for VerificationCodes {
+    fn default() -> Self {
+        Self::new(
+            VerificationCodePolicy::default(),
+            Arc::new(InMemoryNotifier::default()),
+        )
+    }
+}
+
+impl fmt::Debug for VerificationCodes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VerificationCodes")
+            .field("policy", &self.policy)
+            .field("pending", &self.challenges.len())
+            .finish()
+    }
+}
+
+impl VerificationCodes {
+    pub fn new(policy: VerificationCodePolicy, notifier: Arc<dyn VerificationNotifier>) -> Self {
+        Self {
+            policy,
+            notifier,
+            key: CodeHashKey::generate(),
+            challenges: HashMap::new(),
+            last_sent: HashMap::new(),
+        }
+    }
+
+    /// Key code hashes with a configured secret instead of a generated one
+    pub fn with_key(mut self, key: CodeHashKey) -> Self {
+        self.key = key;
+        self
+    }
+
+    pub fn policy(&self) -> &VerificationCodePolicy {
+        &self.policy
+    }
+
+    /// Pending challenge for a claim
+    pub fn challenge(
+        &self,
+        identity_id: IdentityId,
+        claim_type: &ClaimType,
+        value: &str,
+    ) -> Option<&CodeChallenge> {
+        self.challenges
+            .get(&(identity_id, claim_type.clone(), value.to_string()))
+    }
+
+    /// Every pending challenge
+    pub fn challenges(&self) -> impl Iterator<Item = &CodeChallenge> {
+        self.challenges.values()
+    }
+
+    /// Put back a challenge recorded when its code was sent
+    pub fn restore_challenge(&mut self, challenge: CodeChallenge) {
+        let key = (
+            challenge.identity_id,
+            challenge.claim_type.clone(),
+            challenge.value.clone(),
+        );
+        let last_sent = self
+            .last_sent
+            .entry(key.clone())
+            .or_insert(challenge.sent_at);
+        *last_sent = (*last_sent).max(challenge.sent_at);
+        self.challenges.insert(key, challenge);
+    }
+
+    /// Set the wrong codes presented for a claim's pending code, as recorded
+    pub fn record_failed_attempts(
+        &mut self,
+        identity_id: IdentityId,
+        claim_type: &ClaimType,
+        value: &str,
+        failed_attempts: u32,
+    ) {
+        if let Some(challenge) =
+            self.challenges
+                .get_mut(&(identity_id, claim_type.clone(), value.to_string()))
+        {
+            challenge.failed_attempts = failed_attempts;
+        }
+    }
+
+    /// Drop the pending challenge for a claim, as confirming its code does
+    pub fn discard_challenge(
+        &mut self,
+        identity_id: IdentityId,
+        claim_type: &ClaimType,
+        value: &str,
+    ) {
+        self.challenges
+            .remove(&(identity_id, claim_type.clone(), value.to_string()));
+    }
+
+    /// Generate a code for a claim and deliver it, replacing any pending code
+    ///
+    /// Returns the new challenge.
+    pub fn send_code(
+        &mut self,
+        identity_id: IdentityId,
+        claim_type: &ClaimType,
+        value: &str,
+        now: DateTime<Utc>,
+    ) -> IdentityResult<CodeChallenge> {
+        if !matches!(claim_type, ClaimType::Email | ClaimType::Phone) {
+            return Err(IdentityError::InvalidOperation(
+                "Only email and phone claims are verified with codes".to_string(),
+            ));
+        }
+
+        let key = (identity_id, claim_type.clone(), value.to_string());
+        let resend_interval = self.policy.resend_interval;
+        if let Some(last_sent) = self.last_sent.get(&key) {
+            let retry_at = *last_sent + resend_interval;
+            if now < retry_at {
+                return Err(IdentityError::VerificationCodeThrottled(retry_at));
+            }
+        }
+        self.last_sent
+            .retain(|_, last_sent| now < *last_sent + resend_interval);
+
+        let code = generate_code();
+        let expires_at = now + self.policy.code_ttl(claim_type);
+        self.notifier.send_code(&CodeDelivery {
+            identity_id,
+            claim_type: claim_type.clone(),
+            destination: value.to_string(),
+            code: code.clone(),
+            expires_at,
+        })?;
+
+        let challenge = CodeChallenge {
+            identity_id,
+            claim_type: claim_type.clone(),
+            value: value.to_string(),
+            code_hash: self.key.hash(&code),
+            sent_at: now,
+            expires_at,
+            failed_attempts: 0,
+        };
+        self.last_sent.insert(key.clone(), now);
+        self.challenges.insert(key, challenge.clone());
+        Ok(challenge)
+    }
+
+    /// Check a presented code, consuming the challenge when it matches
+    pub fn confirm_code(
+        &mut self,
+        identity_id: IdentityId,
+        claim_type: &ClaimType,
+        value: &str,
+        code: &str,
+        now: DateTime<Utc>,
+    ) -> IdentityResult<CodeChallenge> {
+        let key = (identity_id, claim_type.clone(), value.to_string());
+        let Some(challenge) = self.challenges.get_mut(&key) else {
+            return Err(IdentityError::VerificationFailed(
+                "No verification code is pending".to_string(),
+            ));
+        };
+
+        if now >= challenge.expires_at {
+            self.challenges.remove(&key);
+            return Err(IdentityError::VerificationCodeExpired);
+        }
+
+        let matches: bool = challenge
+            .code_hash
+            .as_bytes()
+            .ct_eq(self.key.hash(code).as_bytes())
+            .into();
+        if !matches {
+            challenge.failed_attempts += 1;
+            if challenge.failed_attempts >= self.policy.max_attempts {
+                self.challenges.remove(&key);
+                return Err(IdentityError::VerificationAttemptsExceeded);
+            }
+            return Err(IdentityError::VerificationFailed(
+                "Invalid verification code".to_string(),
+            ));
+        }
+
+        Ok(self.challenges.remove(&key).expect("challenge is pending"))
+    }
+}
+
+/// Generate a six digit code
+fn generate_code() -> String {
+    format!("{:06}", OsRng.gen_range(0..1_000_000))
+}
@@ -0,0 +1,199 @@
+//! Verification workflows
+//!
+//! Every `VerificationMethod` runs as an `IdentityWorkflow` whose steps come
+//! from [`verification_steps`]:
+//!
+//! | Method       | Steps                                                 |
+//! |--------------|-------------------------------------------------------|
+//! | `Email`      | `generate_email_code` → `confirm_email_code`          |
+//! | `Phone`      | `send_phone_otp` → `confirm_phone_otp`                |
+//! | `Document`   | `submit_document` → `review_document` (approval)      |
+//! | `Biometric`  | `capture_biometric` → `match_biometric`               |
+//! | `InPerson`   | `attend_in_person` → `approve_in_person` (approval)   |
+//! | `ThirdParty` | `request_provider_check` → `await_provider_result`    |
+//!
+//! Automated steps run when the workflow starts; email and phone steps send
+//! and confirm a one-time code for the identity's claim through
+//! [`VerificationCodes`]. Manual, verification and approval steps are
+//! answered with `ProcessVerificationCommand`s, and third-party results are
+//! polled from the [`ThirdPartyVerificationAdapter`] registered for the
+//! provider in [`VerificationProviders`].
+//!
+//! Levels are lowered again when the claims supporting them lapse, see
+//! [`level_after_claim_lapse`].
+
+pub mod claims;
+pub mod codes;
+
+pub use claims::*;
+pub use codes::*;
+
+use crate::components::{
+    IdentityId, StepStatus, StepType, VerificationLevel, VerificationMethod, WorkflowId,
+    WorkflowStep,
+};
+use crate::IdentityResult;
+use bevy::ecs::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// Seconds a third-party provider has to report a result
+pub const PROVIDER_TIMEOUT_SECONDS: u64 = 24 * 60 * 60;
+
+/// Steps of the workflow verifying an identity with `method`
+pub fn verification_steps(method: &VerificationMethod) -> Vec<WorkflowStep> {
+    match method {
+        VerificationMethod::Email => vec![
+            step(
+                "generate_email_code",
+                "Send email code",
+                StepType::Automated,
+            ),
+            step(
+                "confirm_email_code",
+                "Confirm email code",
+                StepType::Verification,
+            ),
+        ],
+        VerificationMethod::Phone => vec![
+            step("send_phone_otp", "Send phone OTP", StepType::Automated),
+            step(
+                "confirm_phone_otp",
+                "Confirm phone OTP",
+                StepType::Verification,
+            ),
+        ],
+        VerificationMethod::Document => vec![
+            step(
+                "submit_document",
+                "Submit identity document",
+                StepType::Manual,
+            ),
+            step(
+                "review_document",
+                "Review identity document",
+                StepType::Approval,
+            ),
+        ],
+        VerificationMethod::Biometric => vec![
+            step(
+                "capture_biometric",
+                "Capture biometric sample",
+                StepType::Manual,
+            ),
+            step(
+                "match_biometric",
+                "Match biometric sample",
+                StepType::Verification,
+            ),
+        ],
+        VerificationMethod::InPerson => vec![
+            step(
+                "attend_in_person",
+                "Attend in-person check",
+                StepType::Manual,
+            ),
+            step(
+                "approve_in_person",
+                "Approve in-person check",
+                StepType::Approval,
+            ),
+        ],
+        VerificationMethod::ThirdParty { .. } => vec![
+            step(
+                "request_provider_check",
+                "Request provider check",
+                StepType::Automated,
+            ),
+            WorkflowStep {
+                timeout_seconds: Some(PROVIDER_TIMEOUT_SECONDS),
+                ..step(
+                    "await_provider_result",
+                    "Await provider result",
+                    StepType::Verification,
+                )
+            },
+        ],
+    }
+}
+
+fn step(step_id: &str, name: &str, step_type: StepType) -> WorkflowStep {
+    WorkflowStep {
+        step_id: step_id.to_string(),
+        step_type,
+        status: StepStatus::Pending,
+        name: name.to_string(),
+        description: None,
+        required: true,
+        timeout_seconds: None,
+        started_at: None,
+        completed_at: None,
+        approval: None,
+        retry: None,
+        retries: 0,
+    }
+}
+
+/// Level following `current`, since levels cannot be skipped
+pub fn next_verification_level(current: VerificationLevel) -> Option<VerificationLevel> {
+    match current {
+        VerificationLevel::Unverified => Some(VerificationLevel::Basic),
+        VerificationLevel::Basic => Some(VerificationLevel::Enhanced),
+        VerificationLevel::Enhanced => Some(VerificationLevel::Full),
+        VerificationLevel::Full => None,
+    }
+}
+
+/// Result of a third-party verification check
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProviderCheck {
+    /// The provider has not decided yet
+    Pending,
+    Verified,
+    Rejected(String),
+}
+
+/// Adapter to an external identity verification provider
+pub trait ThirdPartyVerificationAdapter: Send + Sync {
+    /// Ask the provider to verify an identity, returning its reference for the check
+    fn request_check(
+        &self,
+        identity_id: IdentityId,
+        workflow_id: WorkflowId,
+    ) -> IdentityResult<String>;
+
+    /// Current result of a requested check
+    fn check_result(&self, reference: &str) -> IdentityResult<ProviderCheck>;
+}
+
+/// Third-party verification adapters by provider name
+#[derive(Resource, Clone, Default)]
+pub struct VerificationProviders {
+    adapters: HashMap<String, Arc<dyn ThirdPartyVerificationAdapter>>,
+}
+
+impl VerificationProviders {
+    /// Register the adapter for a provider, replacing any previous one
+    pub fn register(
+        &mut self,
+        provider: impl Into<String>,
+        adapter: Arc<dyn ThirdPartyVerificationAdapter>,
+    ) {
+        self.adapters.insert(provider.into(), adapter);
+    }
+
+    /// Adapter registered for a provider
+    pub fn get(&self, provider: &str) -> Option<&Arc<dyn ThirdPartyVerificationAdapter>> {
+        self.adapters.get(provider)
+    }
+}
+
+impl fmt::Debug for VerificationProviders {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VerificationProviders")
+            .field("providers", &self.adapters.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
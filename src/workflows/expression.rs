@@ -0,0 +1,579 @@
+//! Expressions of `TransitionCondition::Expression`
+//!
+//! A small language without side effects for transition conditions, such as
+//! `identity.verification_rank >= 2 && output.score * 2 > context.threshold`:
+//!
+//! - literals: numbers, `'strings'` or `"strings"`, `true`, `false` and `null`
+//! - fields: dotted paths and indexes such as `output.documents[0].country`
+//!   or `context["risk-band"]`; missing fields are `null`
+//! - arithmetic: `+ - * / %` on numbers
+//! - comparisons: `== !=` on any values, `< <= > >=` on two numbers or two strings
+//! - boolean logic: `&& || !` on booleans, evaluated left to right and short-circuiting
+//!
+//! Fields are read from four roots:
+//!
+//! | Root       | Value                                                                    |
+//! |------------|--------------------------------------------------------------------------|
+//! | `context`  | Context the workflow was started with                                    |
+//! | `output`   | Data of the processed step                                               |
+//! | `outcome`  | `"Completed"`, `"Failed"` or `"Skipped"`                                 |
+//! | `identity` | `identity_type`, `status`, `verification_level` (`"Enhanced"`), `verification_rank` (`2`) and `claims.<type>.verified`, such as `claims.email.verified` |
+//!
+//! Expressions have no loops or calls and their length and nesting are
+//! limited, so evaluation always terminates and gives the same result for the
+//! same fields.
+
+use crate::components::{
+    ClaimType, IdentityClaims, IdentityEntity, IdentityVerification, VerificationLevel,
+};
+use crate::{IdentityError, IdentityResult};
+use serde_json::{json, Map, Value};
+use std::cmp::Ordering;
+
+/// Longest accepted expression, in characters
+const MAX_LENGTH: usize = 1024;
+/// Deepest accepted nesting of parentheses and unary operators
+const MAX_DEPTH: usize = 32;
+const ROOTS: [&str; 4] = ["context", "output", "outcome", "identity"];
+
+/// Parsed transition expression
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+    source: String,
+    root: Node,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Literal(Value),
+    Path(Vec<Segment>),
+    Not(Box<Node>),
+    Negate(Box<Node>),
+    /// Right side only evaluated if the left one is false
+    Or(Box<Node>, Box<Node>),
+    /// Right side only evaluated if the left one is true
+    And(Box<Node>, Box<Node>),
+    Binary(Operator, Box<Node>, Box<Node>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Field(String),
+    Index(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operator {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Str(String),
+    Ident(String),
+    Symbol(&'static str),
+}
+
+const SYMBOLS: [&str; 19] = [
+    "==", "!=", "<=", ">=", "&&", "||", "<", ">", "!", "+", "-", "*", "/", "%", "(", ")", ".", "[",
+    "]",
+];
+
+impl Expression {
+    /// Parse an expression, checking its syntax and the roots of its fields
+    pub fn parse(source: &str) -> IdentityResult<Self> {
+        let invalid = |problem: String| {
+            IdentityError::WorkflowError(format!("Invalid expression `{source}`: {problem}"))
+        };
+        if source.chars().count() > MAX_LENGTH {
+            return Err(invalid(format!("longer than {MAX_LENGTH} characters")));
+        }
+
+        let tokens = tokenize(source).map_err(invalid)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            position: 0,
+            depth: 0,
+            end: source.chars().count() + 1,
+        };
+        let root = parser.or().map_err(invalid)?;
+        if parser.position < tokens.len() {
+            return Err(invalid(format!(
+                "unexpected {} at column {}",
+                describe(&tokens[parser.position].1),
+                parser.column()
+            )));
+        }
+
+        Ok(Self {
+            source: source.to_string(),
+            root,
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Value of the expression for the fields of `scope`
+    pub fn evaluate(&self, scope: &Value) -> IdentityResult<Value> {
+        evaluate(&self.root, scope).map_err(|problem| {
+            IdentityError::WorkflowError(format!("Expression `{}` failed: {problem}", self.source))
+        })
+    }
+
+    /// Evaluate an expression that must give a boolean
+    pub fn evaluate_bool(&self, scope: &Value) -> IdentityResult<bool> {
+        match self.evaluate(scope)? {
+            Value::Bool(value) => Ok(value),
+            other => Err(IdentityError::WorkflowError(format!(
+                "Expression `{}` gave {} instead of a boolean",
+                self.source,
+                kind(&other)
+            ))),
+        }
+    }
+}
+
+/// Fields of an identity read under the `identity` root
+///
+/// Every built-in claim type is listed, unverified unless the identity holds
+/// a verified claim of that type.
+pub fn identity_scope(
+    identity: &IdentityEntity,
+    verification: Option<&IdentityVerification>,
+    claims: Option<&IdentityClaims>,
+) -> Value {
+    let level = verification.map_or(VerificationLevel::Unverified, |v| v.verification_level);
+
+    let mut claim_flags = Map::new();
+    for claim_type in [
+        ClaimType::Email,
+        ClaimType::Phone,
+        ClaimType::Name,
+        ClaimType::DateOfBirth,
+        ClaimType::Address,
+        ClaimType::NationalId,
+        ClaimType::TaxId,
+    ] {
+        claim_flags.insert(claim_key(&claim_type), json!({ "verified": false }));
+    }
+    for claim in claims.into_iter().flat_map(|claims| claims.iter()) {
+        let flags = claim_flags
+            .entry(claim_key(&claim.claim_type))
+            .or_insert_with(|| json!({ "verified": false }));
+        if claim.verified {
+            flags["verified"] = Value::Bool(true);
+        }
+    }
+
+    json!({
+        "id": identity.identity_id,
+        "identity_type": identity.identity_type,
+        "status": identity.status,
+        "verification_level": level,
+        "verification_rank": level as u8,
+        "claims": claim_flags,
+    })
+}
+
+fn claim_key(claim_type: &ClaimType) -> String {
+    match claim_type {
+        ClaimType::Email => "email",
+        ClaimType::Phone => "phone",
+        ClaimType::Name => "name",
+        ClaimType::DateOfBirth => "date_of_birth",
+        ClaimType::Address => "address",
+        ClaimType::NationalId => "national_id",
+        ClaimType::TaxId => "tax_id",
+        ClaimType::Custom(name) => name,
+    }
+    .to_string()
+}
+
+/// Split an expression into tokens with the column each starts at
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            let number = text
+                .parse::<f64>()
+                .map_err(|_| format!("invalid number {text} at column {column}"))?;
+            tokens.push((column, Token::Number(number)));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push((column, Token::Ident(chars[start..i].iter().collect())));
+        } else if c == '\'' || c == '"' {
+            let mut text = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err(format!("unterminated string at column {column}")),
+                    Some(&end) if end == c => break,
+                    Some('\\') => {
+                        let escaped = chars
+                            .get(i + 1)
+                            .ok_or_else(|| format!("unterminated string at column {column}"))?;
+                        text.push(*escaped);
+                        i += 2;
+                    }
+                    Some(other) => {
+                        text.push(*other);
+                        i += 1;
+                    }
+                }
+            }
+            i += 1;
+            tokens.push((column, Token::Str(text)));
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) else {
+                return Err(format!("unexpected character {c} at column {column}"));
+            };
+            i += symbol.len();
+            tokens.push((column, Token::Symbol(symbol)));
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Number(number) => format!("number {number}"),
+        Token::Str(text) => format!("string {text:?}"),
+        Token::Ident(name) => format!("name {name}"),
+        Token::Symbol(symbol) => format!("`{symbol}`"),
+    }
+}
+
+/// Recursive descent parser, lowest precedence first
+struct Parser<'a> {
+    tokens: &'a [(usize, Token)],
+    position: usize,
+    depth: usize,
+    /// Column just past the last character
+    end: usize,
+}
+
+impl Parser<'_> {
+    fn column(&self) -> usize {
+        self.tokens
+            .get(self.position)
+            .map_or(self.end, |(column, _)| *column)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .map(|(_, token)| token.clone());
+        self.position += 1;
+        token
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        let matches = matches!(
+            self.tokens.get(self.position),
+            Some((_, Token::Symbol(s))) if *s == symbol
+        );
+        if matches {
+            self.position += 1;
+        }
+        matches
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            Err(format!("expected `{symbol}` at column {}", self.column()))
+        }
+    }
+
+    /// Parse one nesting level deeper
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Node, String>) -> Result<Node, String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(format!("nested deeper than {MAX_DEPTH} levels"));
+        }
+        let node = parse(self);
+        self.depth -= 1;
+        node
+    }
+
+    fn binary(
+        &mut self,
+        operators: &[(&str, Operator)],
+        operand: fn(&mut Self) -> Result<Node, String>,
+        chained: bool,
+    ) -> Result<Node, String> {
+        let mut left = operand(self)?;
+        while let Some((_, operator)) = operators.iter().find(|(symbol, _)| self.eat(symbol)) {
+            let right = operand(self)?;
+            left = Node::Binary(*operator, Box::new(left), Box::new(right));
+            if !chained {
+                break;
+            }
+        }
+        Ok(left)
+    }
+
+    fn or(&mut self) -> Result<Node, String> {
+        let mut left = self.and()?;
+        while self.eat("||") {
+            left = Node::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Node, String> {
+        let mut left = self.comparison()?;
+        while self.eat("&&") {
+            left = Node::And(Box::new(left), Box::new(self.comparison()?));
+        }
+        Ok(left)
+    }
+
+    /// Comparisons do not chain, so `a < b < c` is rejected
+    fn comparison(&mut self) -> Result<Node, String> {
+        self.binary(
+            &[
+                ("==", Operator::Equal),
+                ("!=", Operator::NotEqual),
+                ("<=", Operator::LessOrEqual),
+                (">=", Operator::GreaterOrEqual),
+                ("<", Operator::Less),
+                (">", Operator::Greater),
+            ],
+            Self::sum,
+            false,
+        )
+    }
+
+    fn sum(&mut self) -> Result<Node, String> {
+        self.binary(
+            &[("+", Operator::Add), ("-", Operator::Subtract)],
+            Self::product,
+            true,
+        )
+    }
+
+    fn product(&mut self) -> Result<Node, String> {
+        self.binary(
+            &[
+                ("*", Operator::Multiply),
+                ("/", Operator::Divide),
+                ("%", Operator::Remainder),
+            ],
+            Self::unary,
+            true,
+        )
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        if self.eat("!") {
+            return Ok(Node::Not(Box::new(self.nested(Self::unary)?)));
+        }
+        if self.eat("-") {
+            return Ok(Node::Negate(Box::new(self.nested(Self::unary)?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        let column = self.column();
+        match self.next() {
+            Some(Token::Number(number)) => Ok(Node::Literal(number_value(number)?)),
+            Some(Token::Str(text)) => Ok(Node::Literal(Value::String(text))),
+            Some(Token::Ident(name)) => match name.as_str() {
+                "true" => Ok(Node::Literal(Value::Bool(true))),
+                "false" => Ok(Node::Literal(Value::Bool(false))),
+                "null" => Ok(Node::Literal(Value::Null)),
+                root if ROOTS.contains(&root) => self.path(name),
+                _ => Err(format!(
+                    "unknown field {name} at column {column}, expected one of {}",
+                    ROOTS.join(", ")
+                )),
+            },
+            Some(Token::Symbol("(")) => {
+                let node = self.nested(Self::or)?;
+                self.expect(")")?;
+                Ok(node)
+            }
+            Some(token) => Err(format!(
+                "unexpected {} at column {column}",
+                describe(&token)
+            )),
+            None => Err(format!("unexpected end at column {column}")),
+        }
+    }
+
+    fn path(&mut self, root: String) -> Result<Node, String> {
+        let mut segments = vec![Segment::Field(root)];
+        loop {
+            if self.eat(".") {
+                let column = self.column();
+                match self.next() {
+                    Some(Token::Ident(field)) => segments.push(Segment::Field(field)),
+                    _ => return Err(format!("expected a field name at column {column}")),
+                }
+            } else if self.eat("[") {
+                let column = self.column();
+                match self.next() {
+                    Some(Token::Str(field)) => segments.push(Segment::Field(field)),
+                    Some(Token::Number(index)) if index.fract() == 0.0 => {
+                        segments.push(Segment::Index(index as usize))
+                    }
+                    _ => {
+                        return Err(format!(
+                            "expected an index or a quoted field at column {column}"
+                        ))
+                    }
+                }
+                self.expect("]")?;
+            } else {
+                return Ok(Node::Path(segments));
+            }
+        }
+    }
+}
+
+fn evaluate(node: &Node, scope: &Value) -> Result<Value, String> {
+    match node {
+        Node::Literal(value) => Ok(value.clone()),
+        Node::Path(segments) => {
+            let mut value = scope;
+            for segment in segments {
+                let next = match segment {
+                    Segment::Field(field) => value.get(field),
+                    Segment::Index(index) => value.get(index),
+                };
+                let Some(next) = next else {
+                    return Ok(Value::Null);
+                };
+                value = next;
+            }
+            Ok(value.clone())
+        }
+        Node::Not(operand) => Ok(Value::Bool(!boolean(&evaluate(operand, scope)?, "!")?)),
+        Node::Negate(operand) => number_value(-number(&evaluate(operand, scope)?, "-")?),
+        Node::Or(left, right) => {
+            let value =
+                boolean(&evaluate(left, scope)?, "||")? || boolean(&evaluate(right, scope)?, "||")?;
+            Ok(Value::Bool(value))
+        }
+        Node::And(left, right) => {
+            let value =
+                boolean(&evaluate(left, scope)?, "&&")? && boolean(&evaluate(right, scope)?, "&&")?;
+            Ok(Value::Bool(value))
+        }
+        Node::Binary(operator, left, right) => {
+            binary(*operator, &evaluate(left, scope)?, &evaluate(right, scope)?)
+        }
+    }
+}
+
+fn binary(operator: Operator, left: &Value, right: &Value) -> Result<Value, String> {
+    let value = match operator {
+        Operator::Equal => Value::Bool(equal(left, right)),
+        Operator::NotEqual => Value::Bool(!equal(left, right)),
+        Operator::Less => Value::Bool(compare(left, right, "<")? == Ordering::Less),
+        Operator::LessOrEqual => Value::Bool(compare(left, right, "<=")? != Ordering::Greater),
+        Operator::Greater => Value::Bool(compare(left, right, ">")? == Ordering::Greater),
+        Operator::GreaterOrEqual => Value::Bool(compare(left, right, ">=")? != Ordering::Less),
+        Operator::Add => number_value(number(left, "+")? + number(right, "+")?)?,
+        Operator::Subtract => number_value(number(left, "-")? - number(right, "-")?)?,
+        Operator::Multiply => number_value(number(left, "*")? * number(right, "*")?)?,
+        Operator::Divide => number_value(number(left, "/")? / divisor(right, "/")?)?,
+        Operator::Remainder => number_value(number(left, "%")? % divisor(right, "%")?)?,
+    };
+    Ok(value)
+}
+
+fn compare(left: &Value, right: &Value, symbol: &str) -> Result<Ordering, String> {
+    let ordering = match (left, right) {
+        (Value::Number(_), Value::Number(_)) => {
+            number(left, symbol)?.partial_cmp(&number(right, symbol)?)
+        }
+        (Value::String(left), Value::String(right)) => Some(left.cmp(right)),
+        _ => None,
+    };
+    ordering.ok_or_else(|| format!("cannot compare {} {symbol} {}", kind(left), kind(right)))
+}
+
+fn divisor(value: &Value, symbol: &str) -> Result<f64, String> {
+    let divisor = number(value, symbol)?;
+    if divisor == 0.0 {
+        return Err(format!("`{symbol}` by zero"));
+    }
+    Ok(divisor)
+}
+
+/// Numbers are equal by value, so `1 == 1.0`
+fn equal(left: &Value, right: &Value) -> bool {
+    match (left.as_f64(), right.as_f64()) {
+        (Some(left), Some(right)) => left == right,
+        _ => left == right,
+    }
+}
+
+fn boolean(value: &Value, symbol: &str) -> Result<bool, String> {
+    value
+        .as_bool()
+        .ok_or_else(|| format!("`{symbol}` needs booleans, found {}", kind(value)))
+}
+
+fn number(value: &Value, symbol: &str) -> Result<f64, String> {
+    value
+        .as_f64()
+        .ok_or_else(|| format!("`{symbol}` needs numbers, found {}", kind(value)))
+}
+
+/// JSON value of a number, keeping whole numbers integral
+fn number_value(number: f64) -> Result<Value, String> {
+    if number.fract() == 0.0 && number.abs() < i64::MAX as f64 {
+        return Ok(Value::from(number as i64));
+    }
+    serde_json::Number::from_f64(number)
+        .map(Value::Number)
+        .ok_or_else(|| format!("{number} is not a finite number"))
+}
+
+fn kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
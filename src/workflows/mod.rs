@@ -0,0 +1,455 @@
+//! Workflow definitions
+//!
+//! Every `WorkflowType` started with a `StartWorkflowCommand` runs the steps
+//! and transitions of its [`WorkflowDefinition`], looked up in the
+//! [`WorkflowDefinitions`] registry. The registry comes with a definition for
+//! each built-in type; custom types, and replacements for the built-in ones,
+//! are registered from code or loaded from JSON or YAML files:
+//!
+//! | Type           | Steps                                                                   |
+//! |----------------|-------------------------------------------------------------------------|
+//! | `Verification` | `submit_evidence` → `review_evidence`                                   |
+//! | `Onboarding`   | `collect_profile` → `verify_contact` → `welcome`                        |
+//! | `Recovery`     | `request_recovery` → `verify_ownership` → `reset_credentials` / `notify_failure` |
+//! | `Migration`    | `export_identity` → `approve_migration` → `import_identity` / `rollback` |
+//!
+//! Definitions are validated when registered: the step graph has a single
+//! entry step without incoming transitions, every step is reachable from it,
+//! every step leads to a terminal step without outgoing transitions, every
+//! [`Expression`] condition parses, only approval steps carry an
+//! [`ApprovalPolicy`](crate::components::ApprovalPolicy), and every
+//! [`RetryPolicy`](crate::components::RetryPolicy) allows at least one attempt.
+
+pub mod expression;
+
+pub use expression::{identity_scope, Expression};
+
+use crate::components::{
+    IdentityId, IdentityWorkflow, StepStatus, StepType, TransitionCondition, WorkflowId,
+    WorkflowStatus, WorkflowStep, WorkflowTransition, WorkflowType,
+};
+use crate::{IdentityError, IdentityResult};
+use bevy::ecs::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::Path;
+
+/// Steps and transitions run by workflows of one type
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowDefinition {
+    pub workflow_type: WorkflowType,
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    pub steps: Vec<WorkflowStep>,
+    #[serde(default)]
+    pub transitions: Vec<WorkflowTransition>,
+}
+
+impl WorkflowDefinition {
+    /// The single step without incoming transitions
+    pub fn entry_step(&self) -> IdentityResult<&WorkflowStep> {
+        let targets: HashSet<&str> = self
+            .transitions
+            .iter()
+            .map(|t| t.to_step.as_str())
+            .collect();
+        let entries: Vec<&WorkflowStep> = self
+            .steps
+            .iter()
+            .filter(|s| !targets.contains(s.step_id.as_str()))
+            .collect();
+
+        match entries.as_slice() {
+            [entry] => Ok(entry),
+            [] => Err(self.invalid("has no entry step")),
+            _ => Err(self.invalid(&format!(
+                "has {} entry steps: {}",
+                entries.len(),
+                step_list(entries.iter().map(|s| s.step_id.as_str()))
+            ))),
+        }
+    }
+
+    /// Steps without outgoing transitions
+    pub fn terminal_steps(&self) -> impl Iterator<Item = &WorkflowStep> + '_ {
+        self.steps
+            .iter()
+            .filter(|s| !self.transitions.iter().any(|t| t.from_step == s.step_id))
+    }
+
+    /// Check the step graph
+    pub fn validate(&self) -> IdentityResult<()> {
+        if self.steps.is_empty() {
+            return Err(self.invalid("has no steps"));
+        }
+
+        let mut step_ids = HashSet::new();
+        if let Some(duplicate) = self
+            .steps
+            .iter()
+            .find(|s| !step_ids.insert(s.step_id.as_str()))
+        {
+            return Err(self.invalid(&format!("repeats step {}", duplicate.step_id)));
+        }
+        if let Some(transition) = self.transitions.iter().find(|t| {
+            !step_ids.contains(t.from_step.as_str()) || !step_ids.contains(t.to_step.as_str())
+        }) {
+            return Err(self.invalid(&format!(
+                "has a transition {} → {} to an unknown step",
+                transition.from_step, transition.to_step
+            )));
+        }
+
+        for transition in &self.transitions {
+            if let TransitionCondition::Expression { expr } = &transition.condition {
+                Expression::parse(expr)?;
+            }
+        }
+        for step in &self.steps {
+            if let Some(policy) = &step.approval {
+                if step.step_type != StepType::Approval {
+                    return Err(self.invalid(&format!(
+                        "has an approval policy on non-approval step {}",
+                        step.step_id
+                    )));
+                }
+                policy.validate(&step.step_id)?;
+            }
+            if step
+                .retry
+                .as_ref()
+                .is_some_and(|retry| retry.max_attempts == 0)
+            {
+                return Err(self.invalid(&format!("allows no attempts of step {}", step.step_id)));
+            }
+        }
+
+        let entry = self.entry_step()?;
+        let reachable = self.walk(&entry.step_id, |t| (&t.from_step, &t.to_step));
+        let unreachable: Vec<&str> = self
+            .steps
+            .iter()
+            .map(|s| s.step_id.as_str())
+            .filter(|id| !reachable.contains(id))
+            .collect();
+        if !unreachable.is_empty() {
+            return Err(self.invalid(&format!(
+                "has steps unreachable from {}: {}",
+                entry.step_id,
+                step_list(unreachable.into_iter())
+            )));
+        }
+
+        // Walking back from the terminal steps finds every step that can finish
+        let mut finishing = HashSet::new();
+        for terminal in self.terminal_steps() {
+            finishing.extend(self.walk(&terminal.step_id, |t| (&t.to_step, &t.from_step)));
+        }
+        if finishing.is_empty() {
+            return Err(self.invalid("has no terminal step"));
+        }
+        let trapped: Vec<&str> = self
+            .steps
+            .iter()
+            .map(|s| s.step_id.as_str())
+            .filter(|id| !finishing.contains(id))
+            .collect();
+        if !trapped.is_empty() {
+            return Err(self.invalid(&format!(
+                "has steps that never reach a terminal step: {}",
+                step_list(trapped.into_iter())
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Workflow instance of this definition with its entry step active
+    pub fn instantiate(
+        &self,
+        workflow_id: WorkflowId,
+        identity_id: IdentityId,
+        context: serde_json::Value,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> IdentityResult<IdentityWorkflow> {
+        let entry = self.entry_step()?.step_id.clone();
+        let mut workflow = IdentityWorkflow {
+            workflow_id,
+            identity_id,
+            workflow_type: self.workflow_type.clone(),
+            status: WorkflowStatus::NotStarted,
+            current_step: None,
+            steps: self
+                .steps
+                .iter()
+                .map(|step| WorkflowStep {
+                    status: StepStatus::Pending,
+                    started_at: None,
+                    completed_at: None,
+                    retries: 0,
+                    ..step.clone()
+                })
+                .collect(),
+            transitions: self.transitions.clone(),
+            context,
+            started_at: Some(now),
+            completed_at: None,
+            paused_at: None,
+        };
+        workflow.activate_step(&entry, now);
+        Ok(workflow)
+    }
+
+    /// Steps reached from `start` following transitions in the direction given by `edge`
+    fn walk<'a>(
+        &'a self,
+        start: &'a str,
+        edge: impl Fn(&'a WorkflowTransition) -> (&'a String, &'a String),
+    ) -> HashSet<&'a str> {
+        let mut seen = HashSet::from([start]);
+        let mut queue = VecDeque::from([start]);
+        while let Some(step_id) = queue.pop_front() {
+            for (from, to) in self.transitions.iter().map(&edge) {
+                if from == step_id && seen.insert(to.as_str()) {
+                    queue.push_back(to);
+                }
+            }
+        }
+        seen
+    }
+
+    fn invalid(&self, problem: &str) -> IdentityError {
+        IdentityError::WorkflowError(format!("Workflow definition {} {}", self.name, problem))
+    }
+}
+
+fn step_list<'a>(step_ids: impl Iterator<Item = &'a str>) -> String {
+    step_ids.collect::<Vec<_>>().join(", ")
+}
+
+/// Registry of the workflow definition of each workflow type
+#[derive(Resource, Debug, Clone)]
+pub struct WorkflowDefinitions {
+    definitions: HashMap<WorkflowType, WorkflowDefinition>,
+}
+
+impl Default for WorkflowDefinitions {
+    /// Registry holding the built-in definitions
+    fn default() -> Self {
+        let mut definitions = Self::empty();
+        for definition in builtin_definitions() {
+            definitions
+                .register(definition)
+                .expect("built-in workflow definitions are valid");
+        }
+        definitions
+    }
+}
+
+impl WorkflowDefinitions {
+    /// Registry without any definition
+    pub fn empty() -> Self {
+        Self {
+            definitions: HashMap::new(),
+        }
+    }
+
+    /// Register a definition, replacing the one of its workflow type
+    pub fn register(&mut self, definition: WorkflowDefinition) -> IdentityResult<()> {
+        definition.validate()?;
+        self.definitions
+            .insert(definition.workflow_type.clone(), definition);
+        Ok(())
+    }
+
+    pub fn get(&self, workflow_type: &WorkflowType) -> Option<&WorkflowDefinition> {
+        self.definitions.get(workflow_type)
+    }
+
+    /// Register the definitions of a JSON array
+    ///
+    /// Nothing is registered unless every definition is valid.
+    pub fn load_json(&mut self, json: &str) -> IdentityResult<usize> {
+        let definitions: Vec<WorkflowDefinition> = serde_json::from_str(json).map_err(|e| {
+            IdentityError::WorkflowError(format!("Invalid workflow definitions: {e}"))
+        })?;
+        self.register_all(definitions)
+    }
+
+    /// Register the definitions of a YAML sequence
+    ///
+    /// Nothing is registered unless every definition is valid.
+    pub fn load_yaml(&mut self, yaml: &str) -> IdentityResult<usize> {
+        let definitions: Vec<WorkflowDefinition> = serde_yaml::from_str(yaml).map_err(|e| {
+            IdentityError::WorkflowError(format!("Invalid workflow definitions: {e}"))
+        })?;
+        self.register_all(definitions)
+    }
+
+    /// Register the definitions of a `.json`, `.yaml` or `.yml` file
+    pub fn load_file(&mut self, path: impl AsRef<Path>) -> IdentityResult<usize> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path).map_err(|e| {
+            IdentityError::WorkflowError(format!("Reading {}: {e}", path.display()))
+        })?;
+
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => self.load_json(&contents),
+            Some("yaml" | "yml") => self.load_yaml(&contents),
+            _ => Err(IdentityError::WorkflowError(format!(
+                "Unsupported workflow definition file: {}",
+                path.display()
+            ))),
+        }
+    }
+
+    fn register_all(&mut self, definitions: Vec<WorkflowDefinition>) -> IdentityResult<usize> {
+        for definition in &definitions {
+            definition.validate()?;
+        }
+
+        let count = definitions.len();
+        for definition in definitions {
+            self.definitions
+                .insert(definition.workflow_type.clone(), definition);
+        }
+        Ok(count)
+    }
+}
+
+/// Definitions of the built-in workflow types
+pub fn builtin_definitions() -> Vec<WorkflowDefinition> {
+    vec![
+        WorkflowDefinition {
+            workflow_type: WorkflowType::Verification,
+            name: "verification".to_string(),
+            description: Some("Review evidence submitted for an identity".to_string()),
+            steps: vec![
+                step("submit_evidence", "Submit evidence", StepType::Manual),
+                step("review_evidence", "Review evidence", StepType::Verification),
+            ],
+            transitions: vec![transition(
+                "submit_evidence",
+                "review_evidence",
+                TransitionCondition::OnSuccess,
+            )],
+        },
+        WorkflowDefinition {
+            workflow_type: WorkflowType::Onboarding,
+            name: "onboarding".to_string(),
+            description: Some("Bring a new identity into use".to_string()),
+            steps: vec![
+                step("collect_profile", "Collect profile", StepType::Manual),
+                step("verify_contact", "Verify contact", StepType::Verification),
+                step("welcome", "Send welcome", StepType::Notification),
+            ],
+            transitions: vec![
+                transition(
+                    "collect_profile",
+                    "verify_contact",
+                    TransitionCondition::OnSuccess,
+                ),
+                transition("verify_contact", "welcome", TransitionCondition::OnSuccess),
+            ],
+        },
+        WorkflowDefinition {
+            workflow_type: WorkflowType::Recovery,
+            name: "recovery".to_string(),
+            description: Some("Give an identity back to its owner".to_string()),
+            steps: vec![
+                step("request_recovery", "Request recovery", StepType::Manual),
+                step(
+                    "verify_ownership",
+                    "Verify ownership",
+                    StepType::Verification,
+                ),
+                step(
+                    "reset_credentials",
+                    "Reset credentials",
+                    StepType::Automated,
+                ),
+                step(
+                    "notify_failure",
+                    "Notify failed recovery",
+                    StepType::Notification,
+                ),
+            ],
+            transitions: vec![
+                transition(
+                    "request_recovery",
+                    "verify_ownership",
+                    TransitionCondition::OnSuccess,
+                ),
+                transition(
+                    "verify_ownership",
+                    "reset_credentials",
+                    TransitionCondition::OnSuccess,
+                ),
+                transition(
+                    "verify_ownership",
+                    "notify_failure",
+                    TransitionCondition::OnFailure,
+                ),
+            ],
+        },
+        WorkflowDefinition {
+            workflow_type: WorkflowType::Migration,
+            name: "migration".to_string(),
+            description: Some("Move an identity to another system".to_string()),
+            steps: vec![
+                step("export_identity", "Export identity", StepType::Automated),
+                step("approve_migration", "Approve migration", StepType::Approval),
+                step("import_identity", "Import identity", StepType::Automated),
+                step("rollback", "Roll back migration", StepType::Automated),
+            ],
+            transitions: vec![
+                transition(
+                    "export_identity",
+                    "approve_migration",
+                    TransitionCondition::OnSuccess,
+                ),
+                transition(
+                    "approve_migration",
+                    "import_identity",
+                    TransitionCondition::OnSuccess,
+                ),
+                transition(
+                    "approve_migration",
+                    "rollback",
+                    TransitionCondition::OnFailure,
+                ),
+            ],
+        },
+    ]
+}
+
+fn step(step_id: &str, name: &str, step_type: StepType) -> WorkflowStep {
+    WorkflowStep {
+        step_id: step_id.to_string(),
+        step_type,
+        status: StepStatus::Pending,
+        name: name.to_string(),
+        description: None,
+        required: true,
+        timeout_seconds: None,
+        started_at: None,
+        completed_at: None,
+        approval: None,
+        retry: None,
+        retries: 0,
+    }
+}
+
+fn transition(
+    from_step: &str,
+    to_step: &str,
+    condition: TransitionCondition,
+) -> WorkflowTransition {
+    WorkflowTransition {
+        from_step: from_step.to_string(),
+        to_step: to_step.to_string(),
+        condition,
+        metadata: serde_json::Value::Null,
+    }
+}
@@ -0,0 +1,167 @@
+//! Tests for API keys of service and system identities
+//!
+//! User Story F20: Service API Keys
+//! As an operator of a service identity, I want to issue, rotate and revoke API keys
+//! So that machines authenticate with scoped, revocable credentials
+//!
+//! ```mermaid
+//! graph LR
+//!     A[IssueApiKey] --> B[Hash Stored]
+//!     B --> C[AuthenticateApiKey]
+//!     C --> D{Usable and Permitted?}
+//!     D -->|Yes| E[ApiKeyAuthenticated]
+//!     D -->|No| F[Rejected]
+//!     B --> G[Rotate / Revoke]
+//! ```
+
+mod common;
+
+use bevy::app::App;
+use bevy::ecs::prelude::*;
+use cim_domain_identity::{
+    ApiKeyAuthenticated, ApiKeyIssueRejected, ApiKeySecret, AuthenticateApiKeyCommand,
+    CommandEnvelope, IdentityApiKeys, IdentityError, IdentityPlugin, IdentityType,
+    IssueApiKeyCommand, RevokeApiKeyCommand, RotateApiKeyCommand,
+};
+use common::{create_active_identity, send};
+use uuid::Uuid;
+
+/// App with one active identity of the given type
+fn app_with_identity(identity_type: IdentityType) -> (App, Uuid) {
+    let mut app = App::new();
+    app.add_plugins(IdentityPlugin::default());
+    let identity_id = create_active_identity(&mut app, identity_type);
+    (app, identity_id)
+}
+
+fn issue(app: &mut App, identity_id: Uuid, permissions: &[&str]) -> ApiKeySecret {
+    let key = ApiKeySecret::generate();
+    app.world_mut()
+        .send_event(CommandEnvelope::new(IssueApiKeyCommand {
+            identity_id,
+            key: key.clone(),
+            name: "deploy".to_string(),
+            permissions: permissions.iter().map(|p| p.to_string()).collect(),
+            expires_at: None,
+            issued_by: Uuid::new_v4(),
+        }));
+    app.update();
+    key
+}
+
+/// Authenticate and return the error if the attempt was rejected
+fn authenticate(app: &mut App, key: &ApiKeySecret, permissions: &[&str]) -> Option<IdentityError> {
+    send(
+        app,
+        AuthenticateApiKeyCommand {
+            key: key.clone(),
+            required_permissions: permissions.iter().map(|p| p.to_string()).collect(),
+        },
+    )
+}
+
+#[test]
+fn test_issued_key_authenticates_and_is_stored_hashed() {
+    // Given: An active service identity with an issued key
+    let (mut app, identity_id) = app_with_identity(IdentityType::Service);
+    let key = issue(&mut app, identity_id, &["identity:read"]);
+
+    // When: The key is presented
+    let error = authenticate(&mut app, &key, &["identity:read"]);
+
+    // Then: Authentication succeeds for the owning identity
+    assert_eq!(error, None);
+    let events = app.world().resource::<Events<ApiKeyAuthenticated>>();
+    let mut reader = events.get_cursor();
+    let authenticated: Vec<_> = reader.read(events).collect();
+    assert_eq!(authenticated.len(), 1);
+    assert_eq!(authenticated[0].identity_id, identity_id);
+
+    // Then: Only the hash is stored and last use is recorded
+    let world = app.world_mut();
+    let keys = world.query::<&IdentityApiKeys>().single(world).unwrap();
+    assert_eq!(keys.keys[0].key_hash, key.hash());
+    assert_ne!(keys.keys[0].key_hash, key.expose());
+    assert!(keys.keys[0].last_used.is_some());
+}
+
+#[test]
+fn test_keys_only_issued_to_machine_identities() {
+    // Given: An active person identity
+    let (mut app, identity_id) = app_with_identity(IdentityType::Person);
+
+    // When: A key is issued to it
+    issue(&mut app, identity_id, &["*"]);
+
+    // Then: The command is rejected
+    let rejections = app.world().resource::<Events<ApiKeyIssueRejected>>();
+    let mut reader = rejections.get_cursor();
+    let rejected: Vec<_> = reader.read(rejections).collect();
+    assert_eq!(rejected.len(), 1);
+    assert_eq!(rejected[0].error, IdentityError::InvalidIdentityType);
+}
+
+#[test]
+fn test_permissions_and_unknown_keys_are_checked() {
+    // Given: A system identity with a key scoped to `identity:*`
+    let (mut app, identity_id) = app_with_identity(IdentityType::System);
+    let key = issue(&mut app, identity_id, &["identity:*"]);
+
+    // Then: Permissions in scope are granted and others denied
+    assert_eq!(authenticate(&mut app, &key, &["identity:write"]), None);
+    assert_eq!(
+        authenticate(&mut app, &key, &["billing:read"]),
+        Some(IdentityError::PermissionDenied("billing:read".to_string()))
+    );
+
+    // Then: A tampered key is rejected
+    let tampered = ApiKeySecret::from_presented(format!("{}0", key.expose()));
+    assert_eq!(
+        authenticate(&mut app, &tampered, &[]),
+        Some(IdentityError::InvalidApiKey)
+    );
+}
+
+#[test]
+fn test_rotation_and_revocation_disable_old_keys() {
+    // Given: A service identity with a key
+    let (mut app, identity_id) = app_with_identity(IdentityType::Service);
+    let old_key = issue(&mut app, identity_id, &["*"]);
+    let old_key_id = old_key.key_id().unwrap();
+
+    // When: The key is rotated without a grace period
+    let new_key = ApiKeySecret::generate();
+    app.world_mut()
+        .send_event(CommandEnvelope::new(RotateApiKeyCommand {
+            identity_id,
+            key_id: old_key_id,
+            new_key: new_key.clone(),
+            old_key_valid_until: None,
+            rotated_by: Uuid::new_v4(),
+        }));
+    app.update();
+
+    // Then: Only the new key works
+    assert_eq!(
+        authenticate(&mut app, &old_key, &[]),
+        Some(IdentityError::ApiKeyInactive(old_key_id))
+    );
+    assert_eq!(authenticate(&mut app, &new_key, &["anything"]), None);
+
+    // When: The new key is revoked
+    let new_key_id = new_key.key_id().unwrap();
+    app.world_mut()
+        .send_event(CommandEnvelope::new(RevokeApiKeyCommand {
+            identity_id,
+            key_id: new_key_id,
+            revoked_by: Uuid::new_v4(),
+            reason: Some("leaked".to_string()),
+        }));
+    app.update();
+
+    // Then: It no longer authenticates
+    assert_eq!(
+        authenticate(&mut app, &new_key, &[]),
+        Some(IdentityError::ApiKeyInactive(new_key_id))
+    );
+}
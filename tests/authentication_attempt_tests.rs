@@ -0,0 +1,547 @@
+//! Tests for multi-factor authentication attempts of person identities
+//!
+//! User Story F37: Authentication Decision
+//! As a security officer, I want each sign-in to require the factors its risk calls for
+//! So that risky attempts need a second factor and every attempt ends in a recorded decision
+//!
+//! ```mermaid
+//! graph TD
+//!     A[BeginAuthentication] --> B{Active, Unlocked, Factors Available?}
+//!     B -->|No| C[AuthenticationDecided: Denied]
+//!     B -->|Yes| D[AuthenticationChallengeIssued: Password]
+//!     D --> E[SubmitAuthenticationFactor]
+//!     E -->|Wrong| C
+//!     E -->|Right, MFA Enabled| F[AuthenticationChallengeIssued: Totp]
+//!     F --> G[SubmitAuthenticationFactor]
+//!     G -->|Wrong| C
+//!     E -->|Right, Last Factor| H[AuthenticationDecided: Granted]
+//!     G -->|Right| H
+//! ```
+
+mod common;
+
+use bevy::app::App;
+use bevy::ecs::prelude::*;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use cim_domain_identity::authentication::{generate_backup_codes, totp};
+use cim_domain_identity::persistence::{replay_events, EventStore, InMemoryEventStore};
+use cim_domain_identity::{
+    AuthFactor, AuthMethod, AuthenticationChallengeIssued, AuthenticationClock,
+    AuthenticationDecided, AuthenticationDecision, AuthenticationPolicy,
+    BeginAuthenticationCommand, Clock, CommandEnvelope, EnableMfaCommand, FactorResponse,
+    IdentityCredentials, IdentityEntity, IdentityError, IdentityStatus, IdentityVerification,
+    LocationContext, LocationRisk, ManualClock, MfaCode, Password, RefreshToken, RiskSignal,
+    StartSessionCommand, SubmitAuthenticationFactorCommand, SuspiciousAuthenticationDetected,
+    TotpConfig, TotpSecret, UpdateIdentityCommand, VerificationLevel,
+};
+use common::{create_person, events, persistent_app, send, TEST_PARAMS};
+use std::sync::Arc;
+use uuid::Uuid;
+
+fn fixed_time() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap()
+}
+
+/// App recording to `store` on a manual clock, with cheap hashing parameters
+fn app(store: Arc<InMemoryEventStore>, snapshots: &tempfile::TempDir, clock: &ManualClock) -> App {
+    let mut app = persistent_app(
+        store,
+        snapshots,
+        AuthenticationPolicy {
+            password: TEST_PARAMS,
+            ..Default::default()
+        },
+    );
+    app.insert_resource(AuthenticationClock::new(clock.clone()));
+    app
+}
+
+/// Enable MFA for a person, returning the secret
+fn enroll(app: &mut App, clock: &ManualClock, person: Uuid) -> TotpSecret {
+    let secret = TotpSecret::generate();
+    let error = send(
+        app,
+        EnableMfaCommand {
+            identity_id: person,
+            secret: secret.clone(),
+            backup_codes: generate_backup_codes(3).iter().map(MfaCode::new).collect(),
+            confirmation_code: MfaCode::new(totp(&secret, clock.now(), &TotpConfig::default())),
+        },
+    );
+    assert_eq!(error, None);
+    secret
+}
+
+fn laptop() -> LocationContext {
+    LocationContext {
+        ip_address: Some("203.0.113.7".to_string()),
+        country: Some("DE".to_string()),
+        device_id: Some("laptop".to_string()),
+        ..Default::default()
+    }
+}
+
+/// Send a command and return the challenge or the decision it caused
+fn run<C: Clone + Send + Sync + 'static>(
+    app: &mut App,
+    command: C,
+) -> Result<AuthenticationChallengeIssued, AuthenticationDecided> {
+    let envelope = CommandEnvelope::new(command);
+    let caused_by = Some(envelope.command_id);
+    app.world_mut().send_event(envelope);
+    app.update();
+
+    if let Some(decided) = events::<AuthenticationDecided>(app)
+        .into_iter()
+        .find(|decided| decided.causation_id == caused_by)
+    {
+        return Err(decided);
+    }
+    Ok(events::<AuthenticationChallengeIssued>(app)
+        .into_iter()
+        .find(|issued| issued.causation_id == caused_by)
+        .expect("neither a challenge nor a decision"))
+}
+
+fn begin(
+    app: &mut App,
+    person: Uuid,
+    available_factors: Vec<AuthFactor>,
+    location: LocationContext,
+) -> Result<AuthenticationChallengeIssued, AuthenticationDecided> {
+    run(
+        app,
+        BeginAuthenticationCommand {
+            identity_id: person,
+            available_factors,
+            location,
+        },
+    )
+}
+
+fn submit(
+    app: &mut App,
+    challenge: &AuthenticationChallengeIssued,
+    response: FactorResponse,
+) -> Result<AuthenticationChallengeIssued, AuthenticationDecided> {
+    run(
+        app,
+        SubmitAuthenticationFactorCommand {
+            identity_id: challenge.identity_id,
+            attempt_id: challenge.attempt_id,
+            challenge_id: challenge.challenge.challenge_id,
+            response,
+        },
+    )
+}
+
+fn credentials(world: &mut World, person: Uuid) -> IdentityCredentials {
+    world
+        .query::<(&IdentityEntity, &IdentityCredentials)>()
+        .iter(world)
+        .find(|(identity, _)| identity.identity_id == person)
+        .map(|(_, credentials)| credentials.clone())
+        .unwrap()
+}
+
+/// Test for User Story F37: Password and TOTP challenged in turn
+#[test]
+fn test_factors_are_challenged_in_turn_until_granted() {
+    // Given: A person with MFA enabled
+    let store = Arc::new(InMemoryEventStore::new());
+    let dir = tempfile::tempdir().unwrap();
+    let clock = ManualClock::new(fixed_time());
+    let mut app = app(store.clone(), &dir, &clock);
+    let person = create_person(&mut app);
+    let secret = enroll(&mut app, &clock, person);
+    clock.advance(Duration::minutes(1));
+
+    // When: The person begins an attempt from a known device
+    let challenge = begin(
+        &mut app,
+        person,
+        vec![AuthFactor::Password, AuthFactor::Totp],
+        laptop(),
+    )
+    .unwrap();
+
+    // Then: The password is challenged first
+    assert_eq!(challenge.challenge.factor, AuthFactor::Password);
+    let attempt = credentials(app.world_mut(), person).attempt.unwrap();
+    assert_eq!(
+        attempt.required_factors,
+        vec![AuthFactor::Password, AuthFactor::Totp]
+    );
+    assert_eq!(attempt.location_risk, LocationRisk::Low);
+
+    // When: The password is right
+    let challenge = submit(
+        &mut app,
+        &challenge,
+        FactorResponse::Password(Password::new("hunter2")),
+    )
+    .unwrap();
+
+    // Then: The second factor is challenged
+    assert_eq!(challenge.challenge.factor, AuthFactor::Totp);
+
+    // When: The TOTP code is right
+    let code = totp(&secret, clock.now(), &TotpConfig::default());
+    let decided = submit(
+        &mut app,
+        &challenge,
+        FactorResponse::Code(MfaCode::new(code)),
+    )
+    .unwrap_err();
+
+    // Then: The attempt is granted with both factors and closed
+    assert_eq!(decided.decision, AuthenticationDecision::Granted);
+    assert_eq!(
+        decided.factors_used,
+        vec![AuthFactor::Password, AuthFactor::Totp]
+    );
+    let before = credentials(app.world_mut(), person);
+    assert_eq!(before.attempt, None);
+    assert_eq!(
+        before.session_grant.unwrap().attempt_id,
+        Some(decided.attempt_id)
+    );
+
+    // Then: Replaying the recorded events rebuilds the same credentials
+    let mut replayed = World::new();
+    replay_events(&mut replayed, &store.read_from(0).unwrap());
+    assert_eq!(credentials(&mut replayed, person), before);
+
+    // Then: The granted attempt starts one session
+    let start = StartSessionCommand {
+        identity_id: person,
+        auth_method: AuthMethod::Password,
+        location: laptop(),
+        refresh_token: RefreshToken::generate(),
+        attempt_id: Some(decided.attempt_id),
+    };
+    assert_eq!(send(&mut app, start.clone()), None);
+    assert_eq!(
+        send(&mut app, start),
+        Some(IdentityError::AuthenticationRequired)
+    );
+}
+
+/// Test for User Story F37: Wrong factors end the attempt
+#[test]
+fn test_wrong_factor_denies_attempt() {
+    // Given: A person with MFA enabled who presented the right password
+    let dir = tempfile::tempdir().unwrap();
+    let clock = ManualClock::new(fixed_time());
+    let mut app = app(Arc::new(InMemoryEventStore::new()), &dir, &clock);
+    let person = create_person(&mut app);
+    enroll(&mut app, &clock, person);
+    clock.advance(Duration::minutes(1));
+    let challenge = begin(
+        &mut app,
+        person,
+        vec![AuthFactor::Password, AuthFactor::Totp],
+        laptop(),
+    )
+    .unwrap();
+    let challenge = submit(
+        &mut app,
+        &challenge,
+        FactorResponse::Password(Password::new("hunter2")),
+    )
+    .unwrap();
+
+    // When: The code is wrong
+    let decided = submit(
+        &mut app,
+        &challenge,
+        FactorResponse::Code(MfaCode::new("000000")),
+    )
+    .unwrap_err();
+
+    // Then: The attempt is denied with the factors presented so far and counts as a failure
+    assert_eq!(
+        decided.decision,
+        AuthenticationDecision::Denied(IdentityError::InvalidCredentials)
+    );
+    assert_eq!(decided.factors_used, vec![AuthFactor::Password]);
+    let credentials = credentials(app.world_mut(), person);
+    assert_eq!(credentials.failed_attempts, 1);
+    assert_eq!(credentials.attempt, None);
+
+    // Then: The closed attempt takes no further answers
+    assert_eq!(
+        send(
+            &mut app,
+            SubmitAuthenticationFactorCommand {
+                identity_id: person,
+                attempt_id: challenge.attempt_id,
+                challenge_id: challenge.challenge.challenge_id,
+                response: FactorResponse::Code(MfaCode::new("000000")),
+            },
+        ),
+        Some(IdentityError::AuthenticationAttemptNotFound(
+            challenge.attempt_id
+        ))
+    );
+
+    // Then: A client that cannot present the second factor is denied
+    let decided = begin(&mut app, person, vec![AuthFactor::Password], laptop()).unwrap_err();
+    assert_eq!(
+        decided.decision,
+        AuthenticationDecision::Denied(IdentityError::FactorUnavailable(AuthFactor::Totp))
+    );
+
+    // When: A new attempt lets its challenge expire
+    let challenge = begin(
+        &mut app,
+        person,
+        vec![AuthFactor::Password, AuthFactor::Totp],
+        laptop(),
+    )
+    .unwrap();
+    clock.advance(Duration::minutes(10));
+    let decided = submit(
+        &mut app,
+        &challenge,
+        FactorResponse::Password(Password::new("hunter2")),
+    )
+    .unwrap_err();
+
+    // Then: It is denied without checking the password
+    assert!(matches!(
+        decided.decision,
+        AuthenticationDecision::Denied(IdentityError::VerificationFailed(_))
+    ));
+}
+
+/// Test for User Story F37: Right passwords do not clear wrong second factors
+#[test]
+fn test_wrong_second_factors_across_attempts_lock_account() {
+    // Given: A person with MFA enabled under the default lockout policy
+    let store = Arc::new(InMemoryEventStore::new());
+    let dir = tempfile::tempdir().unwrap();
+    let clock = ManualClock::new(fixed_time());
+    let mut app = app(store.clone(), &dir, &clock);
+    let person = create_person(&mut app);
+    enroll(&mut app, &clock, person);
+    clock.advance(Duration::minutes(1));
+    let threshold = AuthenticationPolicy::default().lockout.threshold;
+
+    // When: Attempt after attempt presents the right password and a wrong code
+    for failures in 1..=threshold {
+        let challenge = begin(
+            &mut app,
+            person,
+            vec![AuthFactor::Password, AuthFactor::Totp],
+            laptop(),
+        )
+        .unwrap();
+        let challenge = submit(
+            &mut app,
+            &challenge,
+            FactorResponse::Password(Password::new("hunter2")),
+        )
+        .unwrap();
+
+        // Then: The right password leaves the earlier failures counted
+        assert_eq!(
+            credentials(app.world_mut(), person).failed_attempts,
+            failures - 1
+        );
+
+        let decided = submit(
+            &mut app,
+            &challenge,
+            FactorResponse::Code(MfaCode::new("000000")),
+        )
+        .unwrap_err();
+        assert_eq!(
+            decided.decision,
+            AuthenticationDecision::Denied(IdentityError::InvalidCredentials)
+        );
+    }
+
+    // Then: The account is locked and further attempts are denied
+    let locked = credentials(app.world_mut(), person);
+    let until = locked.locked_until.expect("account should be locked");
+    assert_eq!(locked.lockout_count, 1);
+    let decided = begin(
+        &mut app,
+        person,
+        vec![AuthFactor::Password, AuthFactor::Totp],
+        laptop(),
+    )
+    .unwrap_err();
+    assert_eq!(
+        decided.decision,
+        AuthenticationDecision::Denied(IdentityError::AccountLocked(until))
+    );
+
+    // Then: Replaying the recorded events rebuilds the lock
+    let mut replayed = World::new();
+    replay_events(&mut replayed, &store.read_from(0).unwrap());
+    assert_eq!(credentials(&mut replayed, person), locked);
+}
+
+/// Test for User Story F37: Factors follow location risk and verification level
+#[test]
+fn test_required_factors_follow_risk_and_verification() {
+    // Given: A person without MFA
+    let dir = tempfile::tempdir().unwrap();
+    let clock = ManualClock::new(fixed_time());
+    let mut app = app(Arc::new(InMemoryEventStore::new()), &dir, &clock);
+    let person = create_person(&mut app);
+    let password_only = vec![AuthFactor::Password];
+
+    // Then: A known device needs the password only
+    let challenge = begin(&mut app, person, password_only.clone(), laptop()).unwrap();
+    let decided = submit(
+        &mut app,
+        &challenge,
+        FactorResponse::Password(Password::new("hunter2")),
+    )
+    .unwrap_err();
+    assert_eq!(decided.decision, AuthenticationDecision::Granted);
+    assert_eq!(decided.factors_used, password_only);
+
+    // Then: Anonymizing networks are refused without a second factor
+    let tor = LocationContext {
+        network_type: Some("Tor".to_string()),
+        ..laptop()
+    };
+    let decided = begin(&mut app, person, password_only.clone(), tor).unwrap_err();
+    assert_eq!(
+        decided.decision,
+        AuthenticationDecision::Denied(IdentityError::LocationRiskTooHigh(LocationRisk::High))
+    );
+
+    // Then: A country not seen before is refused below enhanced verification
+    let abroad = LocationContext {
+        country: Some("AT".to_string()),
+        ..laptop()
+    };
+    let decided = begin(&mut app, person, password_only.clone(), abroad.clone()).unwrap_err();
+    assert_eq!(decided.location_risk, LocationRisk::Elevated);
+    assert!(decided.factors_used.is_empty());
+
+    // When: The person reaches enhanced verification
+    {
+        let world = app.world_mut();
+        let (_, mut verification) = world
+            .query::<(&IdentityEntity, &mut IdentityVerification)>()
+            .iter_mut(world)
+            .find(|(identity, _)| identity.identity_id == person)
+            .unwrap();
+        verification.verification_level = VerificationLevel::Enhanced;
+    }
+
+    // Then: The password suffices from the new country
+    let challenge = begin(&mut app, person, password_only.clone(), abroad).unwrap();
+    assert_eq!(challenge.challenge.factor, AuthFactor::Password);
+
+    // When: The person is suspended
+    send(
+        &mut app,
+        UpdateIdentityCommand {
+            identity_id: person,
+            new_status: Some(IdentityStatus::Suspended),
+            updated_by: person,
+        },
+    );
+
+    // Then: Attempts are denied
+    let decided = begin(&mut app, person, password_only, laptop()).unwrap_err();
+    assert_eq!(
+        decided.decision,
+        AuthenticationDecision::Denied(IdentityError::IdentityNotActive)
+    );
+
+    // Then: Unknown identities are rejected like wrong passwords
+    assert_eq!(
+        send(
+            &mut app,
+            BeginAuthenticationCommand {
+                identity_id: Uuid::new_v4(),
+                available_factors: vec![AuthFactor::Password],
+                location: laptop(),
+            },
+        ),
+        Some(IdentityError::InvalidCredentials)
+    );
+}
+
+/// Test for User Story F37: Suspicious attempts are reported and stepped up
+#[test]
+fn test_suspicious_attempts_are_reported_and_stepped_up() {
+    // Given: A person without MFA who signed in from Berlin
+    let store = Arc::new(InMemoryEventStore::new());
+    let dir = tempfile::tempdir().unwrap();
+    let clock = ManualClock::new(fixed_time());
+    let mut app = app(store.clone(), &dir, &clock);
+    let person = create_person(&mut app);
+    let password_only = vec![AuthFactor::Password];
+    let berlin = LocationContext {
+        coordinates: Some((52.52, 13.405)),
+        ..laptop()
+    };
+    let challenge = begin(&mut app, person, password_only.clone(), berlin).unwrap();
+    submit(
+        &mut app,
+        &challenge,
+        FactorResponse::Password(Password::new("hunter2")),
+    )
+    .unwrap_err();
+    assert!(events::<SuspiciousAuthenticationDetected>(&app).is_empty());
+
+    // When: The same laptop signs in from New York an hour later
+    clock.advance(Duration::hours(1));
+    let new_york = LocationContext {
+        coordinates: Some((40.7128, -74.006)),
+        country: Some("US".to_string()),
+        ..laptop()
+    };
+    let decided = begin(&mut app, person, password_only, new_york.clone()).unwrap_err();
+
+    // Then: The attempt is reported with its signals and refused
+    let reported = events::<SuspiciousAuthenticationDetected>(&app);
+    assert_eq!(reported.len(), 1);
+    assert_eq!(reported[0].attempt_id, decided.attempt_id);
+    assert_eq!(reported[0].assessment.level, LocationRisk::High);
+    assert!(matches!(
+        reported[0].assessment.signals[..],
+        [
+            RiskSignal::NewCountry { .. },
+            RiskSignal::ImpossibleTravel { .. }
+        ]
+    ));
+    assert_eq!(
+        decided.decision,
+        AuthenticationDecision::Denied(IdentityError::LocationRiskTooHigh(LocationRisk::High))
+    );
+
+    // When: The person enables MFA and tries again
+    enroll(&mut app, &clock, person);
+    let challenge = begin(
+        &mut app,
+        person,
+        vec![AuthFactor::Password, AuthFactor::Totp],
+        new_york,
+    )
+    .unwrap();
+
+    // Then: The attempt needs both factors
+    let attempt = credentials(app.world_mut(), person).attempt.unwrap();
+    assert_eq!(challenge.challenge.factor, AuthFactor::Password);
+    assert_eq!(
+        attempt.required_factors,
+        vec![AuthFactor::Password, AuthFactor::Totp]
+    );
+
+    // Then: Only the granted attempt is in the history, also after replay
+    let before = credentials(app.world_mut(), person);
+    assert_eq!(before.history.observations().count(), 1);
+    let mut replayed = World::new();
+    replay_events(&mut replayed, &store.read_from(0).unwrap());
+    assert_eq!(credentials(&mut replayed, person), before);
+}
@@ -0,0 +1,292 @@
+//! Tests for password authentication of person identities
+//!
+//! User Story F34: Password Authentication
+//! As a person, I want to sign in with a password that is never stored in clear
+//! So that a leaked identity store does not reveal how to sign in as me
+//!
+//! ```mermaid
+//! graph LR
+//!     A[SetPassword] --> B[Argon2id Hash Stored]
+//!     B --> C[AuthenticatePassword]
+//!     C --> D{Matches?}
+//!     D -->|Yes| E[AuthenticationSucceeded]
+//!     D -->|No| F[AuthenticationFailed]
+//!     E --> G{Outdated Parameters?}
+//!     G -->|Yes| H[PasswordRehashed]
+//!     F --> I{Threshold Reached?}
+//!     I -->|Yes| J[AccountLocked with Backoff]
+//!     J --> K[AccountUnlocked after Window]
+//! ```
+
+mod common;
+
+use bevy::app::App;
+use bevy::ecs::prelude::*;
+use chrono::{Duration, Utc};
+use cim_domain_identity::persistence::{
+    replay_events, EventStore, IdentityDomainEvent, InMemoryEventStore,
+};
+use cim_domain_identity::{
+    AccountLocked, AuthenticatePasswordCommand, AuthenticationClock, AuthenticationFailed,
+    AuthenticationPolicy, AuthenticationSucceeded, Clock, CommandEnvelope, CommandRejected,
+    IdentityCredentials, IdentityEntity, IdentityError, IdentityType, LockoutPolicy, ManualClock,
+    Password, PasswordHashParams, PasswordRehashed, SetPasswordCommand,
+};
+use common::{create_active_identity, events, persistent_app, send, TEST_PARAMS};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// App recording to `store`, with cheap hashing parameters
+fn app(store: Arc<InMemoryEventStore>, snapshots: &tempfile::TempDir) -> App {
+    persistent_app(
+        store,
+        snapshots,
+        AuthenticationPolicy {
+            password: TEST_PARAMS,
+            ..Default::default()
+        },
+    )
+}
+
+fn credentials(world: &mut World, identity_id: Uuid) -> Option<IdentityCredentials> {
+    world
+        .query::<(&IdentityEntity, &IdentityCredentials)>()
+        .iter(world)
+        .find(|(identity, _)| identity.identity_id == identity_id)
+        .map(|(_, credentials)| credentials.clone())
+}
+
+fn authenticate(app: &mut App, identity_id: Uuid, password: &str) -> Option<IdentityError> {
+    send(
+        app,
+        AuthenticatePasswordCommand {
+            identity_id,
+            password: Password::new(password),
+        },
+    )
+}
+
+#[test]
+fn test_password_is_stored_hashed_and_verified() {
+    // Given: An active person
+    let dir = tempfile::tempdir().unwrap();
+    let mut app = app(Arc::new(InMemoryEventStore::new()), &dir);
+    let person = create_active_identity(&mut app, IdentityType::Person);
+
+    // When: A password is set
+    assert_eq!(
+        send(
+            &mut app,
+            SetPasswordCommand {
+                identity_id: person,
+                password: Password::new("correct horse battery staple"),
+                set_by: person,
+            },
+        ),
+        None
+    );
+
+    // Then: Only an argon2id hash is stored
+    let stored = credentials(app.world_mut(), person).unwrap();
+    assert!(stored.password_hash.starts_with("$argon2id$"));
+    assert!(!stored.password_hash.contains("correct horse"));
+
+    // When: The right password is presented
+    assert_eq!(
+        authenticate(&mut app, person, "correct horse battery staple"),
+        None
+    );
+
+    // Then: The person is authenticated
+    assert_eq!(events::<AuthenticationSucceeded>(&app).len(), 1);
+    assert!(credentials(app.world_mut(), person)
+        .unwrap()
+        .last_authenticated_at
+        .is_some());
+
+    // When: A wrong password is presented
+    let envelope = CommandEnvelope::new(AuthenticatePasswordCommand {
+        identity_id: person,
+        password: Password::new("Tr0ub4dor&3"),
+    });
+    app.world_mut().send_event(envelope);
+    app.update();
+
+    // Then: It fails without the password appearing in the rejection
+    assert_eq!(events::<AuthenticationFailed>(&app).len(), 1);
+    let rejection = events::<CommandRejected<AuthenticatePasswordCommand>>(&app)
+        .pop()
+        .unwrap();
+    assert_eq!(rejection.error, IdentityError::InvalidCredentials);
+    assert!(!format!("{rejection:?}").contains("Tr0ub4dor"));
+    assert!(!serde_json::to_string(&rejection)
+        .unwrap()
+        .contains("Tr0ub4dor"));
+
+    // Then: Unknown identities fail the same way and services hold no password
+    assert_eq!(
+        authenticate(&mut app, Uuid::new_v4(), "correct horse battery staple"),
+        Some(IdentityError::InvalidCredentials)
+    );
+    let service = create_active_identity(&mut app, IdentityType::Service);
+    assert_eq!(
+        send(
+            &mut app,
+            SetPasswordCommand {
+                identity_id: service,
+                password: Password::new("secret"),
+                set_by: service,
+            },
+        ),
+        Some(IdentityError::InvalidIdentityType)
+    );
+}
+
+#[test]
+fn test_outdated_hash_is_upgraded_and_replayed() {
+    // Given: A person whose password was hashed with the test parameters
+    let dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(InMemoryEventStore::new());
+    let mut app = app(store.clone(), &dir);
+    let person = create_active_identity(&mut app, IdentityType::Person);
+    send(
+        &mut app,
+        SetPasswordCommand {
+            identity_id: person,
+            password: Password::new("hunter2"),
+            set_by: person,
+        },
+    );
+    let original = credentials(app.world_mut(), person).unwrap().password_hash;
+
+    // When: The parameters are raised and the person signs in
+    let raised = PasswordHashParams {
+        memory_kib: 2048,
+        ..TEST_PARAMS
+    };
+    app.world_mut()
+        .resource_mut::<AuthenticationPolicy>()
+        .password = raised;
+    assert_eq!(authenticate(&mut app, person, "hunter2"), None);
+
+    // Then: The hash was upgraded by an event and still verifies
+    let rehashed = events::<PasswordRehashed>(&app);
+    assert_eq!(rehashed.len(), 1);
+    let mut live = credentials(app.world_mut(), person).unwrap();
+    assert_ne!(live.password_hash, original);
+    assert!(live.password_hash.contains("m=2048"));
+    assert_eq!(authenticate(&mut app, person, "hunter2"), None);
+    assert_eq!(
+        credentials(app.world_mut(), person).unwrap().password_hash,
+        live.password_hash
+    );
+
+    // Then: Replaying the stored events rebuilds the upgraded credentials
+    live = credentials(app.world_mut(), person).unwrap();
+    let mut world = World::new();
+    replay_events(&mut world, &store.read_from(0).unwrap());
+    assert_eq!(credentials(&mut world, person), Some(live));
+}
+
+#[test]
+fn test_repeated_failures_lock_the_account_with_backoff() {
+    // Given: A person with a password, a manual clock and a lockout after 3 failures
+    let dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(InMemoryEventStore::new());
+    let mut app = app(store.clone(), &dir);
+    let clock = ManualClock::new(Utc::now());
+    app.insert_resource(AuthenticationClock::new(clock.clone()));
+    app.world_mut()
+        .resource_mut::<AuthenticationPolicy>()
+        .lockout = LockoutPolicy {
+        threshold: 3,
+        initial_lockout: Duration::minutes(5),
+        backoff_factor: 2,
+        max_lockout: Duration::hours(1),
+    };
+    let person = create_active_identity(&mut app, IdentityType::Person);
+    send(
+        &mut app,
+        SetPasswordCommand {
+            identity_id: person,
+            password: Password::new("hunter2"),
+            set_by: person,
+        },
+    );
+
+    // When: The wrong password is presented three times
+    for _ in 0..3 {
+        assert_eq!(
+            authenticate(&mut app, person, "hunter3"),
+            Some(IdentityError::InvalidCredentials)
+        );
+    }
+
+    // Then: The account is locked for the initial window, even for the right password
+    let locked_until = clock.now() + Duration::minutes(5);
+    let locked = events::<AccountLocked>(&app);
+    assert_eq!(locked.last().unwrap().locked_until, locked_until);
+    assert_eq!(locked.last().unwrap().lockout_count, 1);
+    assert_eq!(
+        authenticate(&mut app, person, "hunter2"),
+        Some(IdentityError::AccountLocked(locked_until))
+    );
+
+    // When: The lock runs out and three more attempts fail
+    clock.advance(Duration::minutes(6));
+    for _ in 0..3 {
+        authenticate(&mut app, person, "hunter3");
+    }
+
+    // Then: The lock was released and the next lock lasts twice as long
+    let stored = store.read_from(0).unwrap();
+    assert_eq!(
+        stored
+            .iter()
+            .filter(|e| matches!(e.event, IdentityDomainEvent::AccountUnlocked(_)))
+            .count(),
+        1
+    );
+    let live = credentials(app.world_mut(), person).unwrap();
+    assert_eq!(live.lockout_count, 2);
+    assert_eq!(live.locked_until, Some(clock.now() + Duration::minutes(10)));
+
+    // Then: Replaying the stored events rebuilds the lockout state
+    let mut world = World::new();
+    replay_events(&mut world, &store.read_from(0).unwrap());
+    assert_eq!(credentials(&mut world, person), Some(live));
+
+    // When: The second lock runs out and the right password is presented
+    clock.advance(Duration::minutes(11));
+    assert_eq!(authenticate(&mut app, person, "hunter2"), None);
+
+    // Then: The failures and the backoff are cleared, live and on replay
+    let live = credentials(app.world_mut(), person).unwrap();
+    assert_eq!(
+        (live.failed_attempts, live.lockout_count, live.locked_until),
+        (0, 0, None)
+    );
+    let mut world = World::new();
+    replay_events(&mut world, &store.read_from(0).unwrap());
+    assert_eq!(credentials(&mut world, person), Some(live));
+}
+
+#[test]
+fn test_lockout_backoff_saturates_at_the_maximum() {
+    // Given: A lockout policy with a factor too large for chrono to multiply by
+    let policy = LockoutPolicy {
+        threshold: 3,
+        initial_lockout: Duration::minutes(5),
+        backoff_factor: u32::MAX,
+        max_lockout: Duration::hours(24),
+    };
+
+    // Then: Windows grow to the maximum without overflowing and stay there
+    assert_eq!(policy.lockout_duration(0), Duration::minutes(5));
+    assert_eq!(policy.lockout_duration(1), Duration::hours(24));
+    assert_eq!(policy.lockout_duration(u32::MAX), Duration::hours(24));
+
+    // Then: So do the windows of a default policy after many lockouts
+    let policy = LockoutPolicy::default();
+    assert_eq!(policy.lockout_duration(40), policy.max_lockout);
+}
@@ -0,0 +1,249 @@
+//! Tests for adding, changing, revoking and expiring claims
+//!
+//! User Story F25: Claim Lifecycle
+//! As an identity administrator, I want claims to be revoked or to expire
+//! So that an identity only keeps the verification level its current claims support
+//!
+//! ```mermaid
+//! graph LR
+//!     A[AddClaim] --> B[Claim Held]
+//!     B -->|UpdateClaim| B
+//!     B -->|Close to Expiry| C[Re-verification Workflow]
+//!     B -->|RevokeClaim| D[ClaimRevoked]
+//!     B -->|Expiry| E[ClaimExpired]
+//!     D --> F{Supported Level?}
+//!     E --> F
+//!     F -->|No| G[VerificationLevelDowngraded]
+//! ```
+
+mod common;
+
+use bevy::app::App;
+use chrono::{Duration, Utc};
+use cim_domain_identity::{
+    AddClaimCommand, ClaimExpired, ClaimType, ClaimUpdated, IdentityClaims, IdentityError,
+    IdentityPlugin, IdentityType, IdentityVerification, IdentityWorkflow, RevokeClaimCommand,
+    UpdateClaimCommand, VerificationLevel, VerificationLevelDowngraded, WorkflowStarted,
+    WorkflowType,
+};
+use common::{create_identity, events, send};
+use uuid::Uuid;
+
+const EMAIL: &str = "ada@example.com";
+const PHONE: &str = "+15550100";
+
+/// App with one person identity holding an email claim
+fn app_with_identity() -> (App, Uuid) {
+    let mut app = App::new();
+    app.add_plugins(IdentityPlugin::default());
+    let identity_id = create_identity(&mut app, IdentityType::Person, Some(EMAIL));
+    (app, identity_id)
+}
+
+fn add_claim(claim_type: ClaimType, value: &str, identity_id: Uuid) -> AddClaimCommand {
+    AddClaimCommand {
+        identity_id,
+        claim_type,
+        value: value.to_string(),
+        issuer: None,
+        expires_at: None,
+        added_by: identity_id,
+    }
+}
+
+fn revoke_claim(claim_type: ClaimType, value: &str, identity_id: Uuid) -> RevokeClaimCommand {
+    RevokeClaimCommand {
+        identity_id,
+        claim_type,
+        value: value.to_string(),
+        revoked_by: Uuid::new_v4(),
+        reason: None,
+    }
+}
+
+fn claims(app: &mut App) -> IdentityClaims {
+    let world = app.world_mut();
+    world
+        .query::<&IdentityClaims>()
+        .single(world)
+        .unwrap()
+        .clone()
+}
+
+fn level(app: &mut App) -> VerificationLevel {
+    let world = app.world_mut();
+    world
+        .query::<&IdentityVerification>()
+        .single(world)
+        .unwrap()
+        .verification_level
+}
+
+/// Verify every held claim, give each the expiry and set the verification level
+fn verify_all(app: &mut App, level: VerificationLevel, expires_at: Option<chrono::DateTime<Utc>>) {
+    let world = app.world_mut();
+    let (mut claims, mut verification) = world
+        .query::<(&mut IdentityClaims, &mut IdentityVerification)>()
+        .single_mut(world)
+        .unwrap();
+    for claim in claims.iter_mut() {
+        claim.verified = true;
+        claim.expires_at = expires_at;
+    }
+    verification.verification_level = level;
+}
+
+#[test]
+fn test_claims_are_added_updated_and_revoked() {
+    // Given: An identity holding an email claim
+    let (mut app, identity_id) = app_with_identity();
+
+    // When: A phone claim is added
+    assert_eq!(
+        send(&mut app, add_claim(ClaimType::Phone, PHONE, identity_id)),
+        None
+    );
+
+    // Then: The same claim cannot be added twice, nor already expired
+    assert_eq!(
+        send(&mut app, add_claim(ClaimType::Phone, PHONE, identity_id)),
+        Some(IdentityError::ClaimAlreadyExists(ClaimType::Phone))
+    );
+    let expired = AddClaimCommand {
+        expires_at: Some(Utc::now() - Duration::days(1)),
+        ..add_claim(ClaimType::Address, "1 Main St", identity_id)
+    };
+    assert!(matches!(
+        send(&mut app, expired),
+        Some(IdentityError::InvalidOperation(_))
+    ));
+    assert_eq!(claims(&mut app).len(), 2);
+
+    // When: The verified email address is replaced
+    verify_all(&mut app, VerificationLevel::Unverified, None);
+    let update = UpdateClaimCommand {
+        identity_id,
+        claim_type: ClaimType::Email,
+        value: EMAIL.to_string(),
+        new_value: Some("ada@new.example".to_string()),
+        expires_at: None,
+        updated_by: identity_id,
+    };
+    assert_eq!(send(&mut app, update), None);
+
+    // Then: The new address must be verified again
+    let updated = events::<ClaimUpdated>(&app);
+    assert_eq!(updated[0].old_value, EMAIL);
+    assert!(!updated[0].verified);
+    let held = claims(&mut app);
+    assert!(held.find(&ClaimType::Email, EMAIL).is_none());
+    assert!(
+        !held
+            .find(&ClaimType::Email, "ada@new.example")
+            .unwrap()
+            .verified
+    );
+
+    // When: The phone claim is revoked
+    assert_eq!(
+        send(&mut app, revoke_claim(ClaimType::Phone, PHONE, identity_id)),
+        None
+    );
+
+    // Then: It is gone and cannot be revoked again
+    assert!(claims(&mut app).first(&ClaimType::Phone).is_none());
+    assert_eq!(
+        send(&mut app, revoke_claim(ClaimType::Phone, PHONE, identity_id)),
+        Some(IdentityError::ClaimNotFound(ClaimType::Phone))
+    );
+}
+
+#[test]
+fn test_revoking_the_last_supporting_claim_lowers_the_level() {
+    // Given: A basic verified identity holding two verified email addresses
+    let (mut app, identity_id) = app_with_identity();
+    let second = "ada@work.example";
+    send(&mut app, add_claim(ClaimType::Email, second, identity_id));
+    verify_all(&mut app, VerificationLevel::Basic, None);
+
+    // When: One address is revoked
+    send(&mut app, revoke_claim(ClaimType::Email, EMAIL, identity_id));
+
+    // Then: The other address still supports the level
+    assert_eq!(level(&mut app), VerificationLevel::Basic);
+    assert!(events::<VerificationLevelDowngraded>(&app).is_empty());
+
+    // When: The last address is revoked
+    send(
+        &mut app,
+        revoke_claim(ClaimType::Email, second, identity_id),
+    );
+
+    // Then: The identity is no longer verified
+    assert_eq!(level(&mut app), VerificationLevel::Unverified);
+    let downgraded = events::<VerificationLevelDowngraded>(&app);
+    assert_eq!(downgraded.len(), 1);
+    assert_eq!(downgraded[0].previous_level, VerificationLevel::Basic);
+    assert_eq!(downgraded[0].value, second);
+}
+
+#[test]
+fn test_expired_phone_claim_lowers_enhanced_to_basic() {
+    // Given: An enhanced identity whose verified phone claim has just expired
+    let (mut app, identity_id) = app_with_identity();
+    send(&mut app, add_claim(ClaimType::Phone, PHONE, identity_id));
+    verify_all(&mut app, VerificationLevel::Enhanced, None);
+    {
+        let world = app.world_mut();
+        let mut held = world
+            .query::<&mut IdentityClaims>()
+            .single_mut(world)
+            .unwrap();
+        held.find_mut(&ClaimType::Phone, PHONE).unwrap().expires_at =
+            Some(Utc::now() - Duration::seconds(1));
+    }
+
+    // When: Expiry runs
+    app.update();
+
+    // Then: The phone claim expired and the email still supports basic verification
+    let expired = events::<ClaimExpired>(&app);
+    assert_eq!(expired.len(), 1);
+    assert_eq!(expired[0].claim_type, ClaimType::Phone);
+    assert!(claims(&mut app).first(&ClaimType::Phone).is_none());
+    assert_eq!(level(&mut app), VerificationLevel::Basic);
+    let downgraded = events::<VerificationLevelDowngraded>(&app);
+    assert_eq!(downgraded[0].new_level, VerificationLevel::Basic);
+    assert_eq!(downgraded[0].correlation_id, expired[0].correlation_id);
+}
+
+#[test]
+fn test_expiring_claim_raises_one_reverification_workflow() {
+    // Given: A verified email claim expiring within the notice period
+    let (mut app, identity_id) = app_with_identity();
+    verify_all(
+        &mut app,
+        VerificationLevel::Basic,
+        Some(Utc::now() + Duration::days(10)),
+    );
+
+    // When: Several frames pass
+    for _ in 0..3 {
+        app.update();
+    }
+
+    // Then: A single verification workflow names the claim
+    let world = app.world_mut();
+    let workflows: Vec<IdentityWorkflow> = world
+        .query::<&IdentityWorkflow>()
+        .iter(world)
+        .filter(|workflow| workflow.identity_id == identity_id)
+        .cloned()
+        .collect();
+    assert_eq!(workflows.len(), 1);
+    assert_eq!(workflows[0].workflow_type, WorkflowType::Verification);
+    let started = events::<WorkflowStarted>(&app);
+    assert_eq!(started[0].context["reason"], "claim_reverification");
+    assert_eq!(started[0].context["value"], EMAIL);
+    assert_eq!(level(&mut app), VerificationLevel::Basic);
+}
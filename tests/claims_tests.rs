@@ -0,0 +1,162 @@
+//! Tests for the claims held by each identity
+//!
+//! User Story F24: Identity Claims
+//! As an identity holder, I want to hold several claims, such as two email addresses
+//! So that verifying one of them never changes the claims of another identity
+//!
+//! ```mermaid
+//! graph LR
+//!     A[Identity] --> B[IdentityClaims]
+//!     B --> C[Email: work]
+//!     B --> D[Email: home]
+//!     B --> E[Phone]
+//!     F[Verification] -->|Own claims only| B
+//! ```
+
+mod common;
+
+use bevy::app::App;
+use bevy::ecs::prelude::*;
+use chrono::Utc;
+use cim_domain_identity::persistence::WorldSnapshot;
+use cim_domain_identity::queries::find_identities_by_claim;
+use cim_domain_identity::{
+    ClaimType, CommandEnvelope, CompleteVerificationCommand, IdentityClaim, IdentityClaims,
+    IdentityEntity, IdentityPlugin, IdentityType, InMemoryNotifier, ProcessVerificationCommand,
+    StartVerificationCommand, VerificationCodePolicy, VerificationCodes, VerificationLevel,
+    VerificationMethod,
+};
+use common::create_identity;
+use serde_json::json;
+use std::sync::Arc;
+use uuid::Uuid;
+
+fn claim(claim_type: ClaimType, value: &str) -> IdentityClaim {
+    IdentityClaim {
+        claim_type,
+        value: value.to_string(),
+        verified: false,
+        issuer: None,
+        issued_at: Utc::now(),
+        expires_at: None,
+    }
+}
+
+fn claims_of(app: &mut App, identity_id: Uuid) -> IdentityClaims {
+    let world = app.world_mut();
+    world
+        .query::<(&IdentityEntity, &IdentityClaims)>()
+        .iter(world)
+        .find(|(identity, _)| identity.identity_id == identity_id)
+        .map(|(_, claims)| claims.clone())
+        .unwrap()
+}
+
+#[test]
+fn test_identity_holds_many_claims() {
+    // Given: Two email addresses and a phone number
+    let mut claims = IdentityClaims::new(vec![
+        claim(ClaimType::Email, "ada@work.example"),
+        claim(ClaimType::Email, "ada@home.example"),
+        claim(ClaimType::Phone, "+15550100"),
+    ]);
+    assert_eq!(claims.len(), 3);
+    assert_eq!(claims.of_type(&ClaimType::Email).count(), 2);
+
+    // When: One address is verified and the same claim is added again
+    assert!(claims.verify(&ClaimType::Email, "ada@home.example"));
+    assert!(!claims.verify(&ClaimType::Email, "ada@other.example"));
+    claims.add(claim(ClaimType::Phone, "+15550100"));
+
+    // Then: Only that address is verified and the phone is not duplicated
+    assert!(
+        !claims
+            .find(&ClaimType::Email, "ada@work.example")
+            .unwrap()
+            .verified
+    );
+    assert!(
+        claims
+            .find(&ClaimType::Email, "ada@home.example")
+            .unwrap()
+            .verified
+    );
+    assert_eq!(claims.len(), 3);
+
+    // Given: An identity with a second email added to its claims
+    let mut app = App::new();
+    app.add_plugins(IdentityPlugin::default());
+    let identity_id = create_identity(&mut app, IdentityType::Person, Some("ada@work.example"));
+    {
+        let world = app.world_mut();
+        let (_, mut held) = world
+            .query::<(&IdentityEntity, &mut IdentityClaims)>()
+            .single_mut(world)
+            .unwrap();
+        held.add(claim(ClaimType::Email, "ada@home.example"));
+    }
+
+    // Then: Both addresses find the identity and survive a snapshot
+    assert_eq!(
+        find_identities_by_claim(app.world_mut(), ClaimType::Email, "ada@home.example"),
+        vec![identity_id]
+    );
+    let snapshot = WorldSnapshot::capture(app.world_mut(), 1);
+    let mut restored = World::new();
+    snapshot.restore(&mut restored);
+    let held = restored
+        .query::<&IdentityClaims>()
+        .single(&restored)
+        .unwrap();
+    assert_eq!(held.of_type(&ClaimType::Email).count(), 2);
+}
+
+#[test]
+fn test_verification_only_touches_the_owning_identity() {
+    // Given: Two identities holding email claims
+    let notifier = InMemoryNotifier::default();
+    let mut app = App::new();
+    app.insert_resource(VerificationCodes::new(
+        VerificationCodePolicy::default(),
+        Arc::new(notifier.clone()),
+    ));
+    app.add_plugins(IdentityPlugin::default());
+    let ada = create_identity(&mut app, IdentityType::Person, Some("ada@example.com"));
+    let alan = create_identity(&mut app, IdentityType::Person, Some("alan@example.com"));
+
+    // When: A completion is sent for Alan without any code being confirmed
+    app.world_mut()
+        .send_event(CommandEnvelope::new(CompleteVerificationCommand {
+            identity_id: alan,
+            verification_result: true,
+            verification_level: VerificationLevel::Basic,
+            verification_method: VerificationMethod::Email,
+            verified_by: alan,
+        }));
+    app.update();
+
+    // When: Ada confirms the code sent to her email
+    app.world_mut()
+        .send_event(CommandEnvelope::new(StartVerificationCommand {
+            identity_id: ada,
+            verification_method: VerificationMethod::Email,
+            initiated_by: ada,
+            claim_value: Some("ada@example.com".to_string()),
+        }));
+    app.update();
+    let code = notifier.last_code_for("ada@example.com").unwrap();
+    app.world_mut()
+        .send_event(CommandEnvelope::new(ProcessVerificationCommand {
+            identity_id: ada,
+            verification_data: json!({ "code": code }),
+            processed_by: ada,
+        }));
+    app.update();
+    app.update();
+
+    // Then: Ada's email is verified and Alan's is not, whatever his level
+    let ada_claims = claims_of(&mut app, ada);
+    let alan_claims = claims_of(&mut app, alan);
+    assert!(ada_claims.first(&ClaimType::Email).unwrap().verified);
+    assert!(!alan_claims.first(&ClaimType::Email).unwrap().verified);
+}
@@ -0,0 +1,118 @@
+//! Fixtures shared by the integration tests
+//!
+//! Each test binary uses only some of these, so unused items are allowed.
+
+#![allow(dead_code)]
+
+use bevy::app::App;
+use bevy::ecs::prelude::*;
+use cim_domain_identity::persistence::{
+    EventStore, FileSnapshotStore, IdentityPersistencePlugin, InMemoryEventStore,
+    PersistenceConfig, SnapshotStore,
+};
+use cim_domain_identity::{
+    AuthenticationPolicy, ClaimType, CommandEnvelope, CommandRejected, CreateIdentityCommand,
+    IdentityCreated, IdentityError, IdentityPlugin, IdentityStatus, IdentityType, Password,
+    PasswordHashParams, SetPasswordCommand, UpdateIdentityCommand,
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Argon2id parameters cheap enough for tests
+pub const TEST_PARAMS: PasswordHashParams = PasswordHashParams {
+    memory_kib: 1024,
+    iterations: 1,
+    parallelism: 1,
+};
+
+/// App recording to `store` under the given authentication policy
+pub fn persistent_app(
+    store: Arc<InMemoryEventStore>,
+    snapshots: &tempfile::TempDir,
+    policy: AuthenticationPolicy,
+) -> App {
+    let events: Arc<dyn EventStore> = store;
+    let snapshots: Arc<dyn SnapshotStore> =
+        Arc::new(FileSnapshotStore::open(snapshots.path()).unwrap());
+    let mut app = App::new();
+    app.insert_resource(policy);
+    app.add_plugins(IdentityPlugin::default()).add_plugins(
+        IdentityPersistencePlugin::new(events, snapshots).with_config(PersistenceConfig {
+            snapshot_interval: 1000,
+            restore_on_startup: false,
+        }),
+    );
+    app.finish();
+    app
+}
+
+/// Send a command and return the error it was rejected with, if any
+pub fn send<C: Clone + Send + Sync + 'static>(app: &mut App, command: C) -> Option<IdentityError> {
+    let envelope = CommandEnvelope::new(command);
+    let command_id = envelope.command_id;
+    app.world_mut().send_event(envelope);
+    app.update();
+
+    let rejections = app.world().resource::<Events<CommandRejected<C>>>();
+    let mut reader = rejections.get_cursor();
+    reader
+        .read(rejections)
+        .find(|r| r.command_id == command_id)
+        .map(|r| r.error.clone())
+}
+
+pub fn events<E: Event + Clone>(app: &App) -> Vec<E> {
+    let events = app.world().resource::<Events<E>>();
+    let mut reader = events.get_cursor();
+    reader.read(events).cloned().collect()
+}
+
+/// Create an identity, holding an email claim if given, and return its id
+pub fn create_identity(app: &mut App, identity_type: IdentityType, email: Option<&str>) -> Uuid {
+    let envelope = CommandEnvelope::new(CreateIdentityCommand {
+        identity_type,
+        initial_claims: email.map(|email| HashMap::from([(ClaimType::Email, email.to_string())])),
+        created_by: Uuid::new_v4(),
+        tags: vec![],
+        metadata: serde_json::Value::Null,
+        external_reference: None,
+    });
+    let correlation_id = envelope.correlation_id;
+    app.world_mut().send_event(envelope);
+    app.update();
+
+    events::<IdentityCreated>(app)
+        .into_iter()
+        .find(|created| created.correlation_id == correlation_id)
+        .unwrap()
+        .identity_id
+}
+
+/// Create an active identity of the given type
+pub fn create_active_identity(app: &mut App, identity_type: IdentityType) -> Uuid {
+    let identity_id = create_identity(app, identity_type, None);
+    send(
+        app,
+        UpdateIdentityCommand {
+            identity_id,
+            new_status: Some(IdentityStatus::Active),
+            updated_by: identity_id,
+        },
+    );
+    identity_id
+}
+
+/// Create an active person with a password
+pub fn create_person(app: &mut App) -> Uuid {
+    let identity_id = create_active_identity(app, IdentityType::Person);
+    send(
+        app,
+        SetPasswordCommand {
+            identity_id,
+            password: Password::new("hunter2"),
+            set_by: identity_id,
+        },
+    );
+    identity_id
+}
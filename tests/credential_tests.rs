@@ -0,0 +1,209 @@
+//! Tests for exporting and importing Verifiable Credentials
+//!
+//! User Story F26: Verifiable Credentials
+//! As an identity holder, I want my verified claims as signed credentials
+//! So that other systems can check them offline, and credentials from issuers I trust become my claims
+//!
+//! ```mermaid
+//! graph LR
+//!     A[Verified Claim] -->|ExportCredential| B[Signed VC]
+//!     B -->|Ed25519 Proof| C[Offline Verification]
+//!     D[External VC] -->|ImportCredential| E{Issuer Trusted?}
+//!     E -->|Trusts Relationship| F[Verified Claim]
+//!     E -->|No| G[UntrustedIssuer]
+//! ```
+
+mod common;
+
+use bevy::app::App;
+use chrono::{Duration, Utc};
+use cim_domain_identity::{
+    ClaimType, CredentialExported, CredentialImported, CredentialIssuer,
+    EstablishRelationshipCommand, ExportCredentialCommand, IdentityClaim, IdentityClaims,
+    IdentityEntity, IdentityError, IdentityPlugin, IdentityType, ImportCredentialCommand,
+    IssuerKeys, RelationshipRules, RelationshipType, TokenSigningKey,
+};
+use common::{create_identity, events, send};
+use uuid::Uuid;
+
+const EMAIL: &str = "ada@example.com";
+const ADDRESS: &str = "1 Main St";
+
+fn claims_of(app: &mut App, identity_id: Uuid) -> IdentityClaims {
+    let world = app.world_mut();
+    world
+        .query::<(&IdentityEntity, &IdentityClaims)>()
+        .iter(world)
+        .find(|(identity, _)| identity.identity_id == identity_id)
+        .map(|(_, claims)| claims.clone())
+        .unwrap()
+}
+
+fn address_claim() -> IdentityClaim {
+    IdentityClaim {
+        claim_type: ClaimType::Address,
+        value: ADDRESS.to_string(),
+        verified: true,
+        issuer: None,
+        issued_at: Utc::now(),
+        expires_at: Some(Utc::now() + Duration::days(365)),
+    }
+}
+
+fn import(identity_id: Uuid, credential: serde_json::Value) -> ImportCredentialCommand {
+    ImportCredentialCommand {
+        identity_id,
+        credential,
+        imported_by: identity_id,
+    }
+}
+
+#[test]
+fn test_exported_credential_verifies_offline() {
+    // Given: An identity whose email claim is not yet verified
+    let mut app = App::new();
+    app.add_plugins(IdentityPlugin::default());
+    let identity_id = create_identity(&mut app, IdentityType::Person, Some(EMAIL));
+    let export = ExportCredentialCommand {
+        identity_id,
+        claim_type: ClaimType::Email,
+        value: EMAIL.to_string(),
+    };
+
+    // Then: The unverified claim cannot be exported
+    assert!(matches!(
+        send(&mut app, export.clone()),
+        Some(IdentityError::InvalidOperation(_))
+    ));
+
+    // When: The claim is verified and exported
+    {
+        let world = app.world_mut();
+        let mut held = world
+            .query::<&mut IdentityClaims>()
+            .single_mut(world)
+            .unwrap();
+        held.verify(&ClaimType::Email, EMAIL);
+    }
+    assert_eq!(send(&mut app, export), None);
+
+    // Then: The credential names the identity as subject
+    let exported = events::<CredentialExported>(&app);
+    let credential = exported[0].credential.clone();
+    assert_eq!(credential.subject_identity(), Some(identity_id));
+    assert_eq!(credential.credential_subject.value, EMAIL);
+    let document = credential.to_json().unwrap();
+    assert_eq!(document["type"][1], "IdentityClaimCredential");
+    assert_eq!(document["proof"]["cryptosuite"], "eddsa-jcs-2022");
+
+    // Then: Anyone holding the issuer's public key verifies it offline
+    let issuer = app.world().resource::<CredentialIssuer>();
+    let (kid, public_key) = issuer.public_key();
+    let mut keys = IssuerKeys::default();
+    keys.register(issuer.issuer_id(), kid, public_key);
+    let (issuer_id, verified) = keys.verify(&document, Utc::now()).unwrap();
+    assert_eq!(issuer_id, issuer.issuer_id());
+    assert_eq!(verified, credential);
+
+    // Then: A tampered value breaks the proof
+    let mut tampered = document.clone();
+    tampered["credentialSubject"]["value"] = "eve@example.com".into();
+    assert_eq!(
+        keys.verify(&tampered, Utc::now()),
+        Err(IdentityError::InvalidCredential(
+            "signature mismatch".to_string()
+        ))
+    );
+}
+
+#[test]
+fn test_credential_from_trusted_issuer_becomes_verified_claim() {
+    // Given: A person and an organization issuing address credentials
+    let mut app = App::new();
+    app.add_plugins(IdentityPlugin::default());
+    let person = create_identity(&mut app, IdentityType::Person, Some(EMAIL));
+    let registry = create_identity(&mut app, IdentityType::Organization, None);
+    let registry_issuer = CredentialIssuer::new(registry, TokenSigningKey::generate());
+    let (kid, public_key) = registry_issuer.public_key();
+    app.world_mut()
+        .resource_mut::<IssuerKeys>()
+        .register(registry, kid, public_key);
+    let credential = registry_issuer
+        .issue(person, &address_claim(), Utc::now())
+        .unwrap()
+        .to_json()
+        .unwrap();
+
+    // When: The person imports the credential without trusting the registry
+    let rejected = send(&mut app, import(person, credential.clone()));
+
+    // Then: The issuer is not trusted
+    assert_eq!(rejected, Some(IdentityError::UntrustedIssuer(registry)));
+    assert!(claims_of(&mut app, person)
+        .first(&ClaimType::Address)
+        .is_none());
+
+    // When: The person trusts the registry and imports again
+    send(
+        &mut app,
+        EstablishRelationshipCommand {
+            from_identity: person,
+            to_identity: registry,
+            relationship_type: RelationshipType::Trusts,
+            rules: RelationshipRules {
+                allowed_types: vec![],
+                constraints: vec![],
+                require_mutual_consent: false,
+                allow_multiple: false,
+            },
+            established_by: person,
+            metadata: None,
+        },
+    );
+    assert_eq!(send(&mut app, import(person, credential)), None);
+
+    // Then: The address is a verified claim issued by the registry
+    let claim = claims_of(&mut app, person)
+        .find(&ClaimType::Address, ADDRESS)
+        .cloned()
+        .unwrap();
+    assert!(claim.verified);
+    assert_eq!(claim.issuer, Some(registry));
+    let imported = events::<CredentialImported>(&app);
+    assert_eq!(imported[0].issuer_id, registry);
+}
+
+#[test]
+fn test_credential_about_another_identity_is_rejected() {
+    // Given: A credential of a known issuer about someone else
+    let mut app = App::new();
+    app.add_plugins(IdentityPlugin::default());
+    let person = create_identity(&mut app, IdentityType::Person, Some(EMAIL));
+    let (issuer_id, kid, public_key) = {
+        let issuer = app.world().resource::<CredentialIssuer>();
+        let (kid, public_key) = issuer.public_key();
+        (issuer.issuer_id(), kid.to_string(), public_key)
+    };
+    app.world_mut()
+        .resource_mut::<IssuerKeys>()
+        .register(issuer_id, &kid, public_key);
+    let credential = app
+        .world()
+        .resource::<CredentialIssuer>()
+        .issue(Uuid::new_v4(), &address_claim(), Utc::now())
+        .unwrap()
+        .to_json()
+        .unwrap();
+
+    // When: The person imports it
+    let rejected = send(&mut app, import(person, credential));
+
+    // Then: The subject does not match and nothing is added
+    assert!(matches!(
+        rejected,
+        Some(IdentityError::InvalidCredential(_))
+    ));
+    assert!(claims_of(&mut app, person)
+        .first(&ClaimType::Address)
+        .is_none());
+}
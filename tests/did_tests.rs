@@ -0,0 +1,198 @@
+//! Tests for DID documents of identities
+//!
+//! User Story F27: Decentralized Identifiers
+//! As a domain integrating with identities, I want every identity to publish a DID document
+//! So that cross-domain references name DIDs and resolve to current keys and services
+//!
+//! ```mermaid
+//! graph LR
+//!     A[PublishDid] --> B[IdentityDid]
+//!     B --> C[DidResolver]
+//!     C --> D[DID Document]
+//!     B -->|RotateDidKey| E[DidKeyRotated]
+//!     E --> F[Key History]
+//!     G[CrossDomainReference] -->|DID| C
+//! ```
+
+mod common;
+
+use bevy::app::App;
+use bevy::ecs::prelude::*;
+use cim_domain_identity::did::ed25519_multikey;
+use cim_domain_identity::persistence::{replay_events, EventStore, FileEventStore};
+use cim_domain_identity::{
+    CrossDomainReference, DidKeyRotated, DidMethod, DidPublished, DidResolver, DidService,
+    IdentityCreated, IdentityDid, IdentityError, IdentityPlugin, IdentityType, PublishDidCommand,
+    ReferenceType, RotateDidKeyCommand, TokenSigningKey,
+};
+use common::{create_identity, events, send};
+use uuid::Uuid;
+
+/// App with one person identity
+fn app_with_identity() -> (App, Uuid) {
+    let mut app = App::new();
+    app.add_plugins(IdentityPlugin::default());
+    let identity_id = create_identity(&mut app, IdentityType::Person, Some("ada@example.com"));
+    (app, identity_id)
+}
+
+fn public_key() -> [u8; 32] {
+    TokenSigningKey::generate().verifying_key().to_bytes()
+}
+
+fn publish(identity_id: Uuid, method: DidMethod, public_key: [u8; 32]) -> PublishDidCommand {
+    PublishDidCommand {
+        identity_id,
+        method,
+        public_key,
+        services: vec![DidService {
+            id: "inbox".to_string(),
+            service_type: "DIDCommMessaging".to_string(),
+            endpoint: "https://example.com/inbox".to_string(),
+        }],
+        published_by: identity_id,
+    }
+}
+
+fn rotate(identity_id: Uuid, public_key: [u8; 32]) -> RotateDidKeyCommand {
+    RotateDidKeyCommand {
+        identity_id,
+        public_key,
+        rotated_by: identity_id,
+    }
+}
+
+#[test]
+fn test_did_web_document_resolves_and_rotates() {
+    // Given: A person publishing a did:web document
+    let (mut app, identity_id) = app_with_identity();
+    let first_key = public_key();
+    let web = DidMethod::Web {
+        domain: "example.com:8443".to_string(),
+    };
+    assert_eq!(send(&mut app, publish(identity_id, web, first_key)), None);
+
+    // Then: The DID resolves to a document listing the key and the service
+    let did = events::<DidPublished>(&app)[0].did.clone();
+    assert_eq!(
+        did,
+        format!("did:web:example.com%3A8443:identities:{identity_id}")
+    );
+    let resolver = app.world().resource::<DidResolver>();
+    let document = resolver.resolve(&did).unwrap();
+    assert_eq!(document.verification_method.len(), 1);
+    assert_eq!(document.service[0].id, format!("{did}#inbox"));
+    let first_method = document.verification_method[0].id.clone();
+    assert_eq!(document.authentication, vec![first_method.clone()]);
+    assert_eq!(
+        document.verifying_key(&first_method).unwrap().to_bytes(),
+        first_key
+    );
+
+    // Then: A cross-domain reference by DID finds the identity
+    let reference = CrossDomainReference::did(&did, ReferenceType::Linked);
+    assert_eq!(resolver.resolve_reference(&reference), Some(identity_id));
+
+    // When: The key is rotated
+    let second_key = public_key();
+    assert_eq!(send(&mut app, rotate(identity_id, second_key)), None);
+
+    // Then: The DID is kept, only the new key is listed and the old one is history
+    let rotated = events::<DidKeyRotated>(&app);
+    assert_eq!(rotated[0].did, did);
+    assert_eq!(rotated[0].retired_keys.len(), 1);
+    let document = app.world().resource::<DidResolver>().resolve(&did).unwrap();
+    assert_eq!(document.verification_method.len(), 1);
+    assert_ne!(document.verification_method[0].id, first_method);
+    {
+        let world = app.world_mut();
+        let identity_did = world.query::<&IdentityDid>().single(world).unwrap();
+        assert_eq!(identity_did.keys.len(), 2);
+        assert_eq!(identity_did.active_keys().count(), 1);
+    }
+
+    // Then: A retired key cannot be brought back
+    assert!(matches!(
+        send(&mut app, rotate(identity_id, first_key)),
+        Some(IdentityError::InvalidOperation(_))
+    ));
+}
+
+#[test]
+fn test_did_key_is_derived_from_its_key() {
+    // Given: A person publishing a did:key document
+    let (mut app, identity_id) = app_with_identity();
+    let key = TokenSigningKey::generate().verifying_key();
+    assert_eq!(
+        send(
+            &mut app,
+            publish(identity_id, DidMethod::Key, key.to_bytes())
+        ),
+        None
+    );
+
+    // Then: The DID is the key, and a second DID cannot be published
+    let did = format!("did:key:{}", ed25519_multikey(&key));
+    assert!(did.starts_with("did:key:z6Mk"));
+    assert_eq!(events::<DidPublished>(&app)[0].did, did);
+    assert!(matches!(
+        send(&mut app, publish(identity_id, DidMethod::Key, public_key())),
+        Some(IdentityError::InvalidOperation(_))
+    ));
+
+    // Then: Its key cannot rotate
+    assert!(matches!(
+        send(&mut app, rotate(identity_id, public_key())),
+        Some(IdentityError::InvalidOperation(_))
+    ));
+
+    // Then: A did:key of another party resolves from the DID alone
+    let resolver = app.world().resource::<DidResolver>();
+    let other_key = TokenSigningKey::generate().verifying_key();
+    let other_did = format!("did:key:{}", ed25519_multikey(&other_key));
+    let document = resolver.resolve(&format!("{other_did}#keys")).unwrap();
+    assert_eq!(document.id, other_did);
+    assert_eq!(resolver.identity(&other_did), None);
+    assert_eq!(
+        resolver.resolve("did:web:unknown.example"),
+        Err(IdentityError::DidNotFound(
+            "did:web:unknown.example".to_string()
+        ))
+    );
+}
+
+#[test]
+fn test_did_history_is_replayed() {
+    // Given: Recorded creation, publication and rotation events
+    let (mut app, identity_id) = app_with_identity();
+    let created = events::<IdentityCreated>(&app)[0].clone();
+    let web = DidMethod::Web {
+        domain: "example.com".to_string(),
+    };
+    send(&mut app, publish(identity_id, web, public_key()));
+    let published = events::<DidPublished>(&app)[0].clone();
+    send(&mut app, rotate(identity_id, public_key()));
+    let rotated = events::<DidKeyRotated>(&app)[0].clone();
+    let dir = tempfile::tempdir().unwrap();
+    let store = FileEventStore::open(dir.path().join("events.jsonl")).unwrap();
+    store
+        .append(&[created.into(), published.into(), rotated.clone().into()])
+        .unwrap();
+
+    // When: The events are replayed into an empty world
+    let mut world = World::new();
+    replay_events(&mut world, &store.read_from(0).unwrap());
+
+    // Then: The DID holds both keys, one of them retired
+    let identity_did = world.query::<&IdentityDid>().single(&world).unwrap();
+    assert_eq!(identity_did.keys.len(), 2);
+    assert_eq!(identity_did.active_keys().count(), 1);
+    assert_eq!(
+        identity_did
+            .active_keys()
+            .next()
+            .unwrap()
+            .public_key_multibase,
+        rotated.public_key_multibase
+    );
+}
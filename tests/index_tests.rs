@@ -0,0 +1,173 @@
+//! Tests for the identity lookup index
+//!
+//! User Story F18: Constant-Time Identity Lookup
+//! As a platform operator, I want identity, relationship and workflow lookups to use an index
+//! So that command handling stays fast with hundreds of thousands of identities
+//!
+//! ```mermaid
+//! graph LR
+//!     A[Spawn] --> B[on_insert hook]
+//!     B --> C[IdentityIndex]
+//!     D[Despawn] --> E[on_replace hook]
+//!     E --> C
+//!     F[Merge] --> C
+//! ```
+
+use bevy::app::App;
+use bevy::ecs::prelude::*;
+use cim_domain_identity::{
+    CommandEnvelope, IdentityEntity, IdentityIndex, IdentityPlugin, IdentityRelationship,
+    IdentityStatus, IdentityType, IdentityVerification, MergeIdentitiesCommand,
+    RelationshipRules, RelationshipType, VerificationLevel,
+};
+use uuid::Uuid;
+
+fn spawn_identity(world: &mut World, identity_type: IdentityType) -> (Uuid, Entity) {
+    let identity_id = Uuid::new_v4();
+    let entity = world
+        .spawn((
+            IdentityEntity {
+                identity_id,
+                identity_type,
+                status: IdentityStatus::Active,
+            },
+            IdentityVerification {
+                verification_level: VerificationLevel::Unverified,
+                verified_at: None,
+                verified_by: None,
+                verification_method: None,
+            },
+        ))
+        .id();
+    (identity_id, entity)
+}
+
+#[test]
+fn test_index_tracks_spawn_and_despawn() {
+    // Given: A world with an identity index
+    let mut world = World::new();
+    world.init_resource::<IdentityIndex>();
+
+    // When: An identity is spawned
+    let (identity_id, entity) = spawn_identity(&mut world, IdentityType::Person);
+
+    // Then: The index resolves it to its entity
+    let index = world.resource::<IdentityIndex>();
+    assert_eq!(index.identity(identity_id), Some(entity));
+    assert_eq!(index.identity_count(), 1);
+
+    // When: The entity is despawned
+    world.despawn(entity);
+
+    // Then: The index no longer contains it
+    let index = world.resource::<IdentityIndex>();
+    assert!(!index.contains_identity(identity_id));
+    assert_eq!(index.identity_count(), 0);
+}
+
+#[test]
+fn test_index_tracks_relationship_endpoints() {
+    // Given: Two identities in an indexed world
+    let mut world = World::new();
+    world.init_resource::<IdentityIndex>();
+    let (person_id, _) = spawn_identity(&mut world, IdentityType::Person);
+    let (org_id, _) = spawn_identity(&mut world, IdentityType::Organization);
+
+    // When: A relationship between them is spawned
+    let relationship_id = Uuid::new_v4();
+    let relationship = world
+        .spawn(IdentityRelationship {
+            relationship_id,
+            source_identity: person_id,
+            target_identity: org_id,
+            relationship_type: RelationshipType::MemberOf,
+            rules: RelationshipRules {
+                allowed_types: vec![],
+                constraints: vec![],
+                require_mutual_consent: false,
+                allow_multiple: false,
+            },
+            established_at: chrono::Utc::now(),
+            established_by: None,
+            expires_at: None,
+        })
+        .id();
+
+    // Then: It is reachable by id and from both endpoints
+    let index = world.resource::<IdentityIndex>();
+    assert_eq!(index.relationship(relationship_id), Some(relationship));
+    assert_eq!(index.outgoing(person_id).collect::<Vec<_>>(), vec![relationship]);
+    assert_eq!(index.incoming(org_id).collect::<Vec<_>>(), vec![relationship]);
+    assert_eq!(index.outgoing(org_id).count(), 0);
+
+    // When: The relationship is despawned
+    world.despawn(relationship);
+
+    // Then: The endpoint indexes are cleared
+    let index = world.resource::<IdentityIndex>();
+    assert!(index.relationship(relationship_id).is_none());
+    assert_eq!(index.relationships_of(person_id).count(), 0);
+}
+
+#[test]
+fn test_index_resolves_merged_identities() {
+    // Given: Two person identities managed by the plugin
+    let mut app = App::new();
+    app.add_plugins(IdentityPlugin::default());
+    let (source_id, source_entity) = spawn_identity(app.world_mut(), IdentityType::Person);
+    let (target_id, target_entity) = spawn_identity(app.world_mut(), IdentityType::Person);
+
+    // When: The source is merged into the target
+    app.world_mut()
+        .send_event(CommandEnvelope::new(MergeIdentitiesCommand {
+            source_identity: source_id,
+            target_identity: target_id,
+            merged_by: Uuid::new_v4(),
+            merge_reason: "Duplicate".to_string(),
+        }));
+    app.update();
+
+    // Then: Lookups of the source resolve to the target entity
+    let index = app.world().resource::<IdentityIndex>();
+    assert_eq!(index.resolve(source_id), target_id);
+    assert_eq!(index.resolve_entity(source_id), Some(target_entity));
+
+    // When: The target is merged into a third identity
+    let (final_id, final_entity) = spawn_identity(app.world_mut(), IdentityType::Person);
+    app.world_mut()
+        .send_event(CommandEnvelope::new(MergeIdentitiesCommand {
+            source_identity: target_id,
+            target_identity: final_id,
+            merged_by: Uuid::new_v4(),
+            merge_reason: "Duplicate".to_string(),
+        }));
+    app.update();
+
+    // Then: Both earlier identities resolve to the third one
+    let index = app.world().resource::<IdentityIndex>();
+    assert_eq!(index.resolve(source_id), final_id);
+    assert_eq!(index.resolve_entity(target_id), Some(final_entity));
+    assert_eq!(index.merge_count(), 2);
+
+    // When: The merged identities are despawned
+    app.world_mut().despawn(source_entity);
+    app.world_mut().despawn(target_entity);
+
+    // Then: Their merges are forgotten
+    let index = app.world().resource::<IdentityIndex>();
+    assert_eq!(index.merge_count(), 0);
+    assert_eq!(index.resolve(source_id), source_id);
+}
+
+#[test]
+fn test_rebuild_matches_hook_maintained_index() {
+    // Given: A world populated without an index resource
+    let mut world = World::new();
+    let (identity_id, entity) = spawn_identity(&mut world, IdentityType::Service);
+
+    // When: The index is rebuilt from the world
+    let index = IdentityIndex::rebuild(&mut world);
+
+    // Then: It contains the existing identity
+    assert_eq!(index.identity(identity_id), Some(entity));
+}
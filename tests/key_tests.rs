@@ -0,0 +1,225 @@
+//! Tests for cryptographic keys of device, service and system identities
+//!
+//! User Story F28: Identity Keys
+//! As a service identity, I want to hold signing and encryption keys
+//! So that I can sign messages, agree on secrets and rotate or revoke compromised keys
+//!
+//! ```mermaid
+//! graph LR
+//!     A[GenerateKey] --> B[IdentityKeys]
+//!     C[ImportKey] --> B
+//!     A --> D[KeyStore]
+//!     C --> D
+//!     B -->|RotateKey| E[Superseded Key]
+//!     B -->|RevokeKey| F[Revoked Key]
+//!     D --> G[sign / shared_secret]
+//!     B --> H[verify]
+//! ```
+
+mod common;
+
+use bevy::app::App;
+use cim_domain_identity::keys::{self, KeyStore};
+use cim_domain_identity::{
+    EncryptedFileKeyStore, GenerateKeyCommand, IdentityError, IdentityKeyStore, IdentityKeys,
+    IdentityPlugin, IdentityType, ImportKeyCommand, KeyAlgorithm, KeyGenerated, KeyPurpose,
+    KeySecret, RevokeKeyCommand, RotateKeyCommand,
+};
+use common::{create_identity, events, send};
+use uuid::Uuid;
+
+fn generate(
+    identity_id: Uuid,
+    algorithm: KeyAlgorithm,
+    purposes: Vec<KeyPurpose>,
+) -> GenerateKeyCommand {
+    GenerateKeyCommand {
+        identity_id,
+        algorithm,
+        purposes,
+        generated_by: identity_id,
+    }
+}
+
+fn identity_keys(app: &mut App, identity_id: Uuid) -> IdentityKeys {
+    let world = app.world_mut();
+    let entity = world
+        .resource::<cim_domain_identity::IdentityIndex>()
+        .identity(identity_id)
+        .unwrap();
+    world.get::<IdentityKeys>(entity).unwrap().clone()
+}
+
+#[test]
+fn test_signing_key_signs_rotates_and_revokes() {
+    // Given: A service identity with a signing key
+    let mut app = App::new();
+    app.add_plugins(IdentityPlugin::default());
+    let service = create_identity(&mut app, IdentityType::Service, None);
+    assert_eq!(
+        send(
+            &mut app,
+            generate(service, KeyAlgorithm::Ed25519, vec![KeyPurpose::Signing])
+        ),
+        None
+    );
+    let first_key = events::<KeyGenerated>(&app)[0].key_id;
+
+    // When: A message is signed
+    let store = app.world().resource::<IdentityKeyStore>().0.clone();
+    let keys = identity_keys(&mut app, service);
+    let signature = keys::sign(&keys, store.as_ref(), KeyPurpose::Signing, b"hello").unwrap();
+
+    // Then: The signature verifies for that message only
+    assert_eq!(signature.key_id, first_key);
+    assert_eq!(keys::verify(&keys, b"hello", &signature), Ok(()));
+    assert!(matches!(
+        keys::verify(&keys, b"tampered", &signature),
+        Err(IdentityError::VerificationFailed(_))
+    ));
+
+    // When: The key is rotated
+    let rotate = RotateKeyCommand {
+        identity_id: service,
+        key_id: first_key,
+        rotated_by: service,
+    };
+    assert_eq!(send(&mut app, rotate.clone()), None);
+
+    // Then: New signatures use the new key and old signatures keep verifying
+    let keys = identity_keys(&mut app, service);
+    let second = keys::sign(&keys, store.as_ref(), KeyPurpose::Signing, b"hello").unwrap();
+    assert_ne!(second.key_id, first_key);
+    assert_eq!(keys::verify(&keys, b"hello", &signature), Ok(()));
+    assert_eq!(
+        send(&mut app, rotate),
+        Some(IdentityError::KeyInactive(first_key))
+    );
+
+    // When: The new key is revoked
+    let revoke = RevokeKeyCommand {
+        identity_id: service,
+        key_id: second.key_id,
+        reason: "key compromised".to_string(),
+        revoked_by: service,
+    };
+    assert_eq!(send(&mut app, revoke), None);
+
+    // Then: Its signatures no longer verify and its private key is gone
+    let keys = identity_keys(&mut app, service);
+    assert_eq!(
+        keys::verify(&keys, b"hello", &second),
+        Err(IdentityError::KeyInactive(second.key_id))
+    );
+    assert_eq!(
+        keys.key(second.key_id)
+            .unwrap()
+            .revocation_reason
+            .as_deref(),
+        Some("key compromised")
+    );
+    assert_eq!(
+        store.load(second.key_id),
+        Err(IdentityError::KeyNotFound(second.key_id))
+    );
+
+    // Then: People do not hold keys
+    let person = create_identity(&mut app, IdentityType::Person, None);
+    assert_eq!(
+        send(
+            &mut app,
+            generate(person, KeyAlgorithm::Ed25519, vec![KeyPurpose::Signing])
+        ),
+        Some(IdentityError::InvalidIdentityType)
+    );
+}
+
+#[test]
+fn test_devices_agree_on_shared_secret() {
+    // Given: Two devices with encryption keys, one of them imported
+    let mut app = App::new();
+    app.add_plugins(IdentityPlugin::default());
+    let first = create_identity(&mut app, IdentityType::Device, None);
+    let second = create_identity(&mut app, IdentityType::Device, None);
+    let encryption = vec![KeyPurpose::Encryption];
+    assert_eq!(
+        send(
+            &mut app,
+            generate(first, KeyAlgorithm::X25519, encryption.clone())
+        ),
+        None
+    );
+    let secret = KeySecret::generate(KeyAlgorithm::X25519);
+    let import = ImportKeyCommand {
+        identity_id: second,
+        secret: secret.clone(),
+        purposes: encryption.clone(),
+        imported_by: second,
+    };
+    assert_eq!(send(&mut app, import.clone()), None);
+
+    // When: Each derives a secret from the other's public key
+    let store = app.world().resource::<IdentityKeyStore>().0.clone();
+    let first_keys = identity_keys(&mut app, first);
+    let second_keys = identity_keys(&mut app, second);
+    let first_public = first_keys
+        .current(KeyPurpose::Encryption)
+        .unwrap()
+        .public_key;
+    let (_, first_shared) =
+        keys::shared_secret(&first_keys, store.as_ref(), &secret.public_key()).unwrap();
+    let (_, second_shared) =
+        keys::shared_secret(&second_keys, store.as_ref(), &first_public).unwrap();
+
+    // Then: Both hold the same secret
+    assert_eq!(first_shared, second_shared);
+
+    // Then: A key is imported once, and an encryption key cannot sign
+    assert!(matches!(
+        send(&mut app, import),
+        Some(IdentityError::InvalidOperation(_))
+    ));
+    assert!(matches!(
+        send(
+            &mut app,
+            generate(first, KeyAlgorithm::X25519, vec![KeyPurpose::Signing])
+        ),
+        Some(IdentityError::InvalidOperation(_))
+    ));
+}
+
+#[test]
+fn test_encrypted_file_key_store() {
+    // Given: An encrypted key store in a directory
+    let dir = tempfile::tempdir().unwrap();
+    let store = EncryptedFileKeyStore::open(dir.path(), "correct horse").unwrap();
+    let secret = KeySecret::generate(KeyAlgorithm::Ed25519);
+    let key_id = Uuid::new_v4();
+
+    // When: A key is stored
+    store.store(key_id, &secret).unwrap();
+
+    // Then: It loads back, and the file does not hold it in the clear
+    assert_eq!(store.load(key_id).unwrap(), secret);
+    let file = std::fs::read_to_string(dir.path().join(format!("{key_id}.key"))).unwrap();
+    let plain = serde_json::to_value(&secret).unwrap()["key"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert!(!file.contains(&plain));
+
+    // Then: The store reopens with its passphrase only
+    let reopened = EncryptedFileKeyStore::open(dir.path(), "correct horse").unwrap();
+    assert_eq!(reopened.load(key_id).unwrap(), secret);
+    assert!(matches!(
+        EncryptedFileKeyStore::open(dir.path(), "wrong"),
+        Err(IdentityError::KeyStoreError(_))
+    ));
+
+    // Then: A deleted key is gone
+    reopened.delete(key_id).unwrap();
+    assert_eq!(
+        reopened.load(key_id),
+        Err(IdentityError::KeyNotFound(key_id))
+    );
+}
@@ -0,0 +1,301 @@
+//! Tests for TOTP multi-factor authentication of person identities
+//!
+//! User Story F35: Multi-Factor Authentication
+//! As a person, I want a second factor checked after my password
+//! So that a leaked password alone cannot be used to access my identity
+//!
+//! ```mermaid
+//! graph TD
+//!     A[EnableMfa with Confirmation Code] --> B[MfaEnabled]
+//!     B --> C[IssueMfaChallenge]
+//!     C --> D{Code Valid?}
+//!     D -->|TOTP or Backup Code| E[MfaVerified]
+//!     D -->|No| F[MfaFailed]
+//!     F --> G[Lockout Policy]
+//! ```
+
+mod common;
+
+use bevy::app::App;
+use bevy::ecs::prelude::*;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use cim_domain_identity::authentication::{
+    generate_backup_codes, hash_backup_code, hotp, match_backup_code, totp, verify_totp,
+};
+use cim_domain_identity::persistence::{replay_events, EventStore, InMemoryEventStore};
+use cim_domain_identity::{
+    AuthenticationClock, AuthenticationPolicy, Clock, CommandRejected, EnableMfaCommand,
+    IdentityCredentials, IdentityEntity, IdentityError, IdentityMfa, IssueMfaChallengeCommand,
+    LockoutPolicy, ManualClock, MfaChallengeIssued, MfaCode, MfaFactor, MfaVerified, TotpAlgorithm,
+    TotpConfig, TotpSecret, VerifyMfaCommand,
+};
+use common::{create_person, events, persistent_app, send, TEST_PARAMS};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Shared secret used by the RFC 4226 and RFC 6238 test vectors
+fn rfc_secret() -> TotpSecret {
+    TotpSecret::from_bytes(b"12345678901234567890".to_vec())
+}
+
+fn fixed_time() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap()
+}
+
+/// App recording to `store` on a manual clock, with cheap hashing parameters
+fn app(store: Arc<InMemoryEventStore>, snapshots: &tempfile::TempDir, clock: &ManualClock) -> App {
+    let mut app = persistent_app(
+        store,
+        snapshots,
+        AuthenticationPolicy {
+            password: TEST_PARAMS,
+            lockout: LockoutPolicy {
+                threshold: 3,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    );
+    app.insert_resource(AuthenticationClock::new(clock.clone()));
+    app
+}
+
+/// Enable MFA for a person, returning the secret and the backup codes handed out
+fn enroll(app: &mut App, clock: &ManualClock, person: Uuid) -> (TotpSecret, Vec<String>) {
+    let secret = TotpSecret::generate();
+    let backup_codes = generate_backup_codes(3);
+    let error = send(
+        app,
+        EnableMfaCommand {
+            identity_id: person,
+            secret: secret.clone(),
+            backup_codes: backup_codes.iter().map(MfaCode::new).collect(),
+            confirmation_code: MfaCode::new(totp(&secret, clock.now(), &TotpConfig::default())),
+        },
+    );
+    assert_eq!(error, None);
+    (secret, backup_codes)
+}
+
+fn issue_challenge(app: &mut App, person: Uuid) -> Uuid {
+    send(
+        app,
+        IssueMfaChallengeCommand {
+            identity_id: person,
+        },
+    );
+    events::<MfaChallengeIssued>(app)
+        .pop()
+        .unwrap()
+        .challenge
+        .challenge_id
+}
+
+fn verify(app: &mut App, person: Uuid, challenge_id: Uuid, code: &str) -> Option<IdentityError> {
+    send(
+        app,
+        VerifyMfaCommand {
+            identity_id: person,
+            challenge_id,
+            code: MfaCode::new(code),
+        },
+    )
+}
+
+fn state(world: &mut World, person: Uuid) -> (IdentityMfa, IdentityCredentials) {
+    world
+        .query::<(&IdentityEntity, &IdentityMfa, &IdentityCredentials)>()
+        .iter(world)
+        .find(|(identity, _, _)| identity.identity_id == person)
+        .map(|(_, mfa, credentials)| (mfa.clone(), credentials.clone()))
+        .unwrap()
+}
+
+/// Test for User Story F35: RFC 4226 and RFC 6238 reference values
+#[test]
+fn test_codes_match_rfc_vectors() {
+    // Given: The RFC test secret
+    let secret = rfc_secret();
+    let config = TotpConfig {
+        algorithm: TotpAlgorithm::Sha1,
+        digits: 8,
+        period_secs: 30,
+        skew: 1,
+    };
+
+    // Then: HOTP and TOTP produce the published values
+    assert_eq!(hotp(&secret, 0, &TotpConfig::default()), "755224");
+    assert_eq!(hotp(&secret, 9, &TotpConfig::default()), "520489");
+    assert_eq!(
+        totp(&secret, Utc.timestamp_opt(59, 0).unwrap(), &config),
+        "94287082"
+    );
+    assert_eq!(
+        totp(
+            &secret,
+            Utc.timestamp_opt(1_111_111_109, 0).unwrap(),
+            &config
+        ),
+        "07081804"
+    );
+
+    // Then: Codes within the drift window verify once, stale ones never
+    let config = TotpConfig::default();
+    let now = fixed_time();
+    let previous = totp(&secret, now - Duration::seconds(30), &config);
+    let step = verify_totp(&secret, &previous, now, &config, None);
+    assert_eq!(step, Some(config.step_at(now) - 1));
+    assert_eq!(verify_totp(&secret, &previous, now, &config, step), None);
+    let stale = totp(&secret, now - Duration::minutes(5), &config);
+    assert_eq!(verify_totp(&secret, &stale, now, &config, None), None);
+
+    // Then: The provisioning URI carries the encoded labels and the base32 secret
+    assert_eq!(
+        secret.provisioning_uri("CIM Identity", "gus@example.com", &config),
+        "otpauth://totp/CIM%20Identity:gus%40example.com\
+         ?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=CIM%20Identity\
+         &algorithm=SHA1&digits=6&period=30"
+    );
+
+    // Then: Backup codes match their hash ignoring case and separators
+    let codes = generate_backup_codes(2);
+    let hashes: Vec<String> = codes.iter().map(|c| hash_backup_code(c)).collect();
+    let entered = codes[0].replace('-', "").to_lowercase();
+    assert_eq!(
+        match_backup_code(&hashes, &entered),
+        Some(hashes[0].clone())
+    );
+    assert_eq!(match_backup_code(&hashes, "AAAAA-AAAAA"), None);
+}
+
+/// Test for User Story F35: Enrollment needs a password and a confirmation code
+#[test]
+fn test_enrollment_requires_confirmation_code() {
+    // Given: A person with a password and a fresh secret
+    let dir = tempfile::tempdir().unwrap();
+    let clock = ManualClock::new(fixed_time());
+    let mut app = app(Arc::new(InMemoryEventStore::new()), &dir, &clock);
+    let person = create_person(&mut app);
+    let secret = TotpSecret::generate();
+    let command = EnableMfaCommand {
+        identity_id: person,
+        secret: secret.clone(),
+        backup_codes: vec![MfaCode::new("ABCDE-FGHIJ")],
+        confirmation_code: MfaCode::new("000000"),
+    };
+
+    // When: Enrolling with a wrong confirmation code
+    let error = send(&mut app, command.clone());
+
+    // Then: MFA stays disabled
+    assert!(matches!(error, Some(IdentityError::VerificationFailed(_))));
+    assert_eq!(
+        send(
+            &mut app,
+            IssueMfaChallengeCommand {
+                identity_id: person
+            }
+        ),
+        Some(IdentityError::InvalidOperation(
+            "MFA is not enabled".to_string()
+        ))
+    );
+
+    // Then: Neither the secret nor the codes appear in the rejection
+    let rejection = events::<CommandRejected<EnableMfaCommand>>(&app)
+        .pop()
+        .unwrap();
+    let serialized = serde_json::to_string(&rejection).unwrap();
+    assert!(!serialized.contains(&secret.to_base32()));
+    assert!(!serialized.contains("ABCDE"));
+    assert!(!format!("{rejection:?}").contains("ABCDE"));
+}
+
+/// Test for User Story F35: Challenges are answered once and replay the same way
+#[test]
+fn test_challenge_verified_once_and_replayed() {
+    // Given: An enrolled person with an open challenge
+    let dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(InMemoryEventStore::new());
+    let clock = ManualClock::new(fixed_time());
+    let mut app = app(store.clone(), &dir, &clock);
+    let person = create_person(&mut app);
+    let (secret, backup_codes) = enroll(&mut app, &clock, person);
+    assert!(state(app.world_mut(), person)
+        .0
+        .backup_code_hashes
+        .iter()
+        .all(|hash| hash.len() == 64 && !backup_codes.contains(hash)));
+    clock.advance(Duration::seconds(60));
+    let challenge_id = issue_challenge(&mut app, person);
+    let code = totp(&secret, clock.now(), &TotpConfig::default());
+
+    // When: The current code is submitted
+    assert_eq!(verify(&mut app, person, challenge_id, &code), None);
+
+    // Then: The challenge is verified with the TOTP factor
+    let verified = events::<MfaVerified>(&app).pop().unwrap();
+    assert!(matches!(verified.factor, MfaFactor::Totp { .. }));
+
+    // Then: The same code on a new challenge is refused as a failure
+    let challenge_id = issue_challenge(&mut app, person);
+    assert_eq!(
+        verify(&mut app, person, challenge_id, &code),
+        Some(IdentityError::InvalidCredentials)
+    );
+    assert_eq!(state(app.world_mut(), person).1.failed_attempts, 1);
+
+    // When: A backup code answers the challenge
+    assert_eq!(
+        verify(&mut app, person, challenge_id, &backup_codes[1]),
+        None
+    );
+
+    // Then: The code is consumed and the failures are cleared
+    let (mfa, credentials) = state(app.world_mut(), person);
+    assert_eq!(mfa.backup_code_hashes.len(), 2);
+    assert_eq!(credentials.failed_attempts, 0);
+    let challenge_id = issue_challenge(&mut app, person);
+    assert_eq!(
+        verify(&mut app, person, challenge_id, &backup_codes[1]),
+        Some(IdentityError::InvalidCredentials)
+    );
+
+    // Then: Replaying the stored events rebuilds the same MFA state
+    let live = state(app.world_mut(), person);
+    let mut world = World::new();
+    replay_events(&mut world, &store.read_from(0).unwrap());
+    assert_eq!(state(&mut world, person), live);
+}
+
+/// Test for User Story F35: Wrong codes lock the account and challenges expire
+#[test]
+fn test_wrong_codes_lock_the_account() {
+    // Given: An enrolled person with an open challenge
+    let dir = tempfile::tempdir().unwrap();
+    let clock = ManualClock::new(fixed_time());
+    let mut app = app(Arc::new(InMemoryEventStore::new()), &dir, &clock);
+    let person = create_person(&mut app);
+    let (_, backup_codes) = enroll(&mut app, &clock, person);
+    let challenge_id = issue_challenge(&mut app, person);
+
+    // When: Three wrong codes are submitted
+    for _ in 0..3 {
+        verify(&mut app, person, challenge_id, "AAAAA-AAAAA");
+    }
+
+    // Then: The account is locked, even for a valid backup code
+    let locked_until = state(app.world_mut(), person).1.locked_until.unwrap();
+    assert_eq!(
+        verify(&mut app, person, challenge_id, &backup_codes[0]),
+        Some(IdentityError::AccountLocked(locked_until))
+    );
+
+    // When: The lock runs out after the challenge has expired
+    clock.set(locked_until + Duration::seconds(1));
+    let error = verify(&mut app, person, challenge_id, &backup_codes[0]);
+
+    // Then: Verification is refused and the code is not consumed
+    assert!(matches!(error, Some(IdentityError::VerificationFailed(_))));
+    assert_eq!(state(app.world_mut(), person).0.backup_code_hashes.len(), 3);
+}
@@ -0,0 +1,384 @@
+//! Tests for the Identity plugin
+//!
+//! User Story F17: Single-Plugin Identity Domain
+//! As an application developer, I want to add the identity domain with one plugin
+//! So that events, commands and system ordering are wired consistently
+//!
+//! ```mermaid
+//! graph LR
+//!     A[Validation] --> B[Mutation]
+//!     B --> C[Projection]
+//!     C --> D[Expiry]
+//! ```
+
+use bevy::app::App;
+use bevy::ecs::prelude::*;
+use cim_domain_identity::{
+    CommandEnvelope, CommandOutcome, CommandOutcomes, CreateIdentityCommand,
+    CreateProjectionCommand, EstablishRelationshipCommand, IdentityEntity, IdentityError,
+    IdentityIndex, IdentityPlugin, IdentityPluginConfig, IdentityProjection, IdentityRelationship,
+    IdentityStatus, IdentityType, IdentityUpdateRejected, PersonMarker, ProjectionContext,
+    ProjectionSyncRejected, ProjectionSyncStatus, ProjectionType, ProjectionsSynced,
+    RelationshipRevocationRejected, RelationshipRevoked, RelationshipRules, RelationshipType,
+    RevokeRelationshipCommand, SyncProjectionsCommand, UpdateIdentityCommand,
+};
+use std::time::Duration;
+use uuid::Uuid;
+
+fn create_person_command() -> CreateIdentityCommand {
+    CreateIdentityCommand {
+        identity_type: IdentityType::Person,
+        initial_claims: None,
+        created_by: Uuid::new_v4(),
+        tags: vec![],
+        metadata: serde_json::Value::Null,
+        external_reference: None,
+    }
+}
+
+#[test]
+fn test_plugin_creates_identity_in_single_update() {
+    // Given: An app with the identity plugin
+    let mut app = App::new();
+    app.add_plugins(IdentityPlugin::default());
+
+    // When: A create command is sent and the app updates once
+    app.world_mut()
+        .send_event(CommandEnvelope::new(create_person_command()));
+    app.update();
+
+    // Then: The identity exists, has a marker and a primary projection
+    let world = app.world_mut();
+    let identities: Vec<_> = world.query::<&IdentityEntity>().iter(world).collect();
+    assert_eq!(identities.len(), 1);
+
+    let people = world
+        .query_filtered::<Entity, With<PersonMarker>>()
+        .iter(world)
+        .count();
+    assert_eq!(people, 1);
+
+    let projections = world.query::<&IdentityProjection>().iter(world).count();
+    assert!(projections >= 1);
+}
+
+#[test]
+fn test_plugin_config_disables_optional_systems() {
+    // Given: An app with projections and markers disabled
+    let mut app = App::new();
+    app.add_plugins(IdentityPlugin::new(IdentityPluginConfig {
+        enable_projections: false,
+        enable_markers: false,
+        enable_expiry: false,
+    }));
+
+    // When: A create command is processed
+    app.world_mut()
+        .send_event(CommandEnvelope::new(create_person_command()));
+    app.update();
+
+    // Then: Only the identity itself is spawned
+    let world = app.world_mut();
+    assert_eq!(world.query::<&IdentityEntity>().iter(world).count(), 1);
+    assert_eq!(world.query::<&IdentityProjection>().iter(world).count(), 0);
+    assert_eq!(
+        world
+            .query_filtered::<Entity, With<PersonMarker>>()
+            .iter(world)
+            .count(),
+        0
+    );
+}
+
+#[test]
+fn test_update_of_unknown_identity_is_rejected() {
+    // Given: An app with the identity plugin and no identities
+    let mut app = App::new();
+    app.add_plugins(IdentityPlugin::default());
+
+    // When: An update is sent for an unknown identity
+    let identity_id = Uuid::new_v4();
+    app.world_mut().send_event(CommandEnvelope::new(UpdateIdentityCommand {
+        identity_id,
+        new_status: Some(IdentityStatus::Active),
+        updated_by: Uuid::new_v4(),
+    }));
+    app.update();
+
+    // Then: A rejection carrying the command and the error is emitted
+    let rejections = app.world().resource::<Events<IdentityUpdateRejected>>();
+    let mut reader = rejections.get_cursor();
+    let rejected: Vec<_> = reader.read(rejections).collect();
+    assert_eq!(rejected.len(), 1);
+    assert_eq!(rejected[0].command.identity_id, identity_id);
+    assert_eq!(rejected[0].error, IdentityError::IdentityNotFound(identity_id));
+}
+
+#[test]
+fn test_command_outcome_links_created_identity_to_request() {
+    // Given: An app with the identity plugin and a subscribed command
+    let mut app = App::new();
+    app.add_plugins(IdentityPlugin::default());
+
+    let envelope = CommandEnvelope::new(create_person_command());
+    let command_id = envelope.command_id;
+    let mut receiver = app
+        .world()
+        .resource::<CommandOutcomes>()
+        .subscribe(command_id);
+
+    // When: The command is processed
+    app.world_mut().send_event(envelope);
+    app.update();
+
+    // Then: The outcome carries the created identity and the command's ids
+    let world = app.world_mut();
+    let identity_id = world
+        .query::<&IdentityEntity>()
+        .single(world)
+        .unwrap()
+        .identity_id;
+
+    match receiver.try_recv().unwrap() {
+        CommandOutcome::Accepted {
+            command_id: resolved_id,
+            correlation_id,
+            event_type,
+            event,
+        } => {
+            assert_eq!(resolved_id, command_id);
+            assert_eq!(correlation_id, command_id);
+            assert_eq!(event_type, "IdentityCreated");
+            assert_eq!(event["identity_id"], serde_json::json!(identity_id));
+            assert_eq!(event["causation_id"], serde_json::json!(command_id));
+        }
+        other => panic!("Expected accepted outcome, got {other:?}"),
+    }
+}
+
+#[test]
+fn test_relationship_is_revoked_by_a_party_only() {
+    // Given: Two identities in a relationship and an outsider
+    let mut app = App::new();
+    app.add_plugins(IdentityPlugin::default());
+    for _ in 0..3 {
+        app.world_mut()
+            .send_event(CommandEnvelope::new(create_person_command()));
+    }
+    app.update();
+    let ids: Vec<Uuid> = {
+        let world = app.world_mut();
+        world
+            .query::<&IdentityEntity>()
+            .iter(world)
+            .map(|i| i.identity_id)
+            .collect()
+    };
+    let (manager, employee, outsider) = (ids[0], ids[1], ids[2]);
+    app.world_mut()
+        .send_event(CommandEnvelope::new(EstablishRelationshipCommand {
+            from_identity: manager,
+            to_identity: employee,
+            relationship_type: RelationshipType::Manages,
+            rules: RelationshipRules {
+                allowed_types: vec![],
+                constraints: vec![],
+                require_mutual_consent: false,
+                allow_multiple: false,
+            },
+            established_by: manager,
+            metadata: None,
+        }));
+    app.update();
+    let relationship_id = {
+        let world = app.world_mut();
+        world
+            .query::<&IdentityRelationship>()
+            .single(world)
+            .unwrap()
+            .relationship_id
+    };
+    let revoke = |revoked_by| {
+        CommandEnvelope::new(RevokeRelationshipCommand {
+            relationship_id,
+            revoked_by,
+            reason: "Reorganisation".to_string(),
+        })
+    };
+
+    // When: The outsider tries to revoke it
+    app.world_mut().send_event(revoke(outsider));
+    app.update();
+
+    // Then: The revocation is denied and the relationship remains
+    let rejections = app
+        .world()
+        .resource::<Events<RelationshipRevocationRejected>>();
+    let mut reader = rejections.get_cursor();
+    let rejected: Vec<_> = reader.read(rejections).collect();
+    assert_eq!(rejected.len(), 1);
+    assert!(matches!(
+        rejected[0].error,
+        IdentityError::PermissionDenied(_)
+    ));
+    assert!(app
+        .world()
+        .resource::<IdentityIndex>()
+        .relationship(relationship_id)
+        .is_some());
+
+    // When: The employee revokes it with a subscribed outcome
+    let envelope = revoke(employee);
+    let mut receiver = app
+        .world()
+        .resource::<CommandOutcomes>()
+        .subscribe(envelope.command_id);
+    app.world_mut().send_event(envelope);
+    app.update();
+
+    // Then: The relationship is gone from the world and the index
+    let world = app.world_mut();
+    assert_eq!(
+        world.query::<&IdentityRelationship>().iter(world).count(),
+        0
+    );
+    let index = app.world().resource::<IdentityIndex>();
+    assert!(index.relationship(relationship_id).is_none());
+    assert_eq!(index.outgoing(manager).count(), 0);
+    let revoked = app.world().resource::<Events<RelationshipRevoked>>();
+    let mut reader = revoked.get_cursor();
+    let revoked: Vec<_> = reader.read(revoked).collect();
+    assert_eq!(revoked.len(), 1);
+    assert_eq!(revoked[0].revoked_by, employee);
+    assert_eq!(revoked[0].reason.as_deref(), Some("Reorganisation"));
+    assert!(matches!(
+        receiver.try_recv().unwrap(),
+        CommandOutcome::Accepted { event_type, .. } if event_type == "RelationshipRevoked"
+    ));
+}
+
+#[test]
+fn test_unanswered_subscription_times_out() {
+    // Given: An outcome channel with a short timeout
+    let mut app = App::new();
+    app.insert_resource(CommandOutcomes::with_timeout(Duration::from_millis(500)))
+        .add_plugins(IdentityPlugin::default());
+    let outcomes = app.world().resource::<CommandOutcomes>().clone();
+
+    // When: A command is subscribed but never sent, and another receiver is dropped
+    let command_id = Uuid::new_v4();
+    let mut receiver = outcomes.subscribe(command_id);
+    drop(outcomes.subscribe(Uuid::new_v4()));
+    app.update();
+
+    // Then: The dropped subscription is discarded while the other still waits
+    assert_eq!(outcomes.pending_count(), 1);
+    assert!(receiver.try_recv().is_err());
+
+    // When: The timeout passes
+    std::thread::sleep(Duration::from_millis(600));
+    app.update();
+
+    // Then: The subscription resolves as timed out and is cleaned up
+    assert!(matches!(
+        receiver.try_recv().unwrap(),
+        CommandOutcome::TimedOut { command_id: resolved } if resolved == command_id
+    ));
+    assert_eq!(outcomes.pending_count(), 0);
+}
+
+#[test]
+fn test_sync_projections_command_reports_synced_projections() {
+    // Given: An identity with its primary projection and a pending secondary one
+    let mut app = App::new();
+    app.add_plugins(IdentityPlugin::default());
+    app.world_mut()
+        .send_event(CommandEnvelope::new(create_person_command()));
+    app.update();
+    let identity_id = {
+        let world = app.world_mut();
+        world
+            .query::<&IdentityEntity>()
+            .single(world)
+            .unwrap()
+            .identity_id
+    };
+    app.world_mut()
+        .send_event(CommandEnvelope::new(CreateProjectionCommand {
+            identity_id,
+            projection_type: ProjectionType::Secondary,
+            target_domain: "billing".to_string(),
+            context: ProjectionContext {
+                source_domain: "identity".to_string(),
+                target_domain: "billing".to_string(),
+                sync_interval: chrono::Duration::minutes(5),
+                last_sync: None,
+                sync_errors: 0,
+                metadata: serde_json::Value::Null,
+            },
+        }));
+    app.update();
+    let sync = |identity_id, force| {
+        CommandEnvelope::new(SyncProjectionsCommand {
+            identity_id,
+            projection_type: None,
+            force,
+        })
+    };
+    let synced = |app: &App| {
+        let events = app.world().resource::<Events<ProjectionsSynced>>();
+        let mut reader = events.get_cursor();
+        reader.read(events).last().unwrap().projections_synced
+    };
+
+    // When: The identity's projections are synced with a subscribed outcome
+    let envelope = sync(Some(identity_id), false);
+    let mut receiver = app
+        .world()
+        .resource::<CommandOutcomes>()
+        .subscribe(envelope.command_id);
+    app.world_mut().send_event(envelope);
+    app.update();
+
+    // Then: Only the pending projection is synced and the outcome reports it
+    assert_eq!(synced(&app), 1);
+    let world = app.world_mut();
+    assert!(world
+        .query::<&IdentityProjection>()
+        .iter(world)
+        .all(|p| p.sync_status == ProjectionSyncStatus::Synced));
+    match receiver.try_recv().unwrap() {
+        CommandOutcome::Accepted {
+            event_type, event, ..
+        } => {
+            assert_eq!(event_type, "ProjectionsSynced");
+            assert_eq!(event["projections_synced"], 1);
+        }
+        other => panic!("Expected accepted outcome, got {other:?}"),
+    }
+
+    // When: A forced sync is requested
+    app.world_mut().send_event(sync(Some(identity_id), true));
+    app.update();
+    // Then: Every projection of the identity is synced again
+    let world = app.world_mut();
+    let projections = world
+        .query::<&IdentityProjection>()
+        .iter(world)
+        .filter(|p| p.identity_id == identity_id)
+        .count();
+    assert!(projections >= 2);
+    assert_eq!(synced(&app), projections);
+
+    // When: Projections of an unknown identity are synced
+    let unknown = Uuid::new_v4();
+    app.world_mut().send_event(sync(Some(unknown), false));
+    app.update();
+
+    // Then: The command is rejected
+    let rejections = app.world().resource::<Events<ProjectionSyncRejected>>();
+    let mut reader = rejections.get_cursor();
+    let rejected: Vec<_> = reader.read(rejections).collect();
+    assert_eq!(rejected.len(), 1);
+    assert_eq!(rejected[0].error, IdentityError::IdentityNotFound(unknown));
+}
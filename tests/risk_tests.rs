@@ -0,0 +1,147 @@
+//! Authentication Risk Tests for Identity Domain
+//!
+//! User Story I6: Authentication Risk Scoring
+//! As a security officer, I want unusual sign-ins scored against past ones
+//! So that risky attempts need stronger factors and are reported
+//!
+//! ```mermaid
+//! graph TD
+//!     A[Login Attempt] --> B[Compare With History]
+//!     B --> C{Signals}
+//!     C -->|New Device / Country| D[Score]
+//!     C -->|Impossible Travel| D
+//!     C -->|Unusual Network| D
+//!     D --> E[Low / Elevated / High]
+//!     E --> F[Step-Up Factors]
+//! ```
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use cim_domain_identity::authentication::{
+    haversine_km, AuthenticationHistory, LocationRisk, LoginObservation, RiskEngine, RiskSignal,
+};
+
+const BERLIN: (f64, f64) = (52.52, 13.405);
+const NEW_YORK: (f64, f64) = (40.7128, -74.006);
+
+fn start() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 5, 1, 8, 0, 0).unwrap()
+}
+
+fn observation(
+    at: DateTime<Utc>,
+    device_id: &str,
+    country: &str,
+    coordinates: (f64, f64),
+) -> LoginObservation {
+    LoginObservation {
+        at,
+        ip_address: Some("203.0.113.7".to_string()),
+        coordinates: Some(coordinates),
+        country: Some(country.to_string()),
+        network_type: Some("residential".to_string()),
+        device_id: Some(device_id.to_string()),
+    }
+}
+
+/// History of a person who always signs in from the same laptop in Berlin
+fn berlin_history() -> AuthenticationHistory {
+    let mut history = AuthenticationHistory::default();
+    for day in 0..3 {
+        history.record(observation(
+            start() + Duration::days(day),
+            "laptop",
+            "DE",
+            BERLIN,
+        ));
+    }
+    history
+}
+
+#[test]
+fn test_familiar_attempt_is_low_risk() {
+    // Given: A person's usual device, country and location
+    let history = berlin_history();
+    let attempt = observation(start() + Duration::days(4), "laptop", "DE", BERLIN);
+
+    // When: The attempt is scored
+    let assessment = RiskEngine::default().assess(&history, &attempt);
+
+    // Then: Nothing is unusual
+    assert_eq!(assessment.score, 0);
+    assert_eq!(assessment.level, LocationRisk::Low);
+    assert!(!assessment.is_suspicious());
+}
+
+#[test]
+fn test_new_device_and_country_are_elevated() {
+    // Given: A new phone used in Austria, a plausible drive away
+    let history = berlin_history();
+    let attempt = observation(start() + Duration::days(5), "phone", "AT", (48.2082, 16.3738));
+
+    // When: The attempt is scored
+    let assessment = RiskEngine::default().assess(&history, &attempt);
+
+    // Then: Both novelties are signalled and the risk is elevated
+    assert_eq!(
+        assessment.signals,
+        vec![
+            RiskSignal::NewDevice {
+                device_id: "phone".to_string()
+            },
+            RiskSignal::NewCountry {
+                country: "AT".to_string()
+            },
+        ]
+    );
+    assert_eq!(assessment.level, LocationRisk::Elevated);
+}
+
+#[test]
+fn test_impossible_travel_is_high_risk() {
+    // Given: A sign-in from New York an hour after the last one in Berlin
+    let history = berlin_history();
+    let last = history.observations().last().unwrap().at;
+    let attempt = observation(last + Duration::hours(1), "laptop", "DE", NEW_YORK);
+
+    // When: The attempt is scored
+    let assessment = RiskEngine::default().assess(&history, &attempt);
+
+    // Then: The trip is flagged as impossible
+    let distance = haversine_km(BERLIN, NEW_YORK);
+    assert!((distance - 6385.0).abs() < 20.0);
+    assert!(matches!(
+        assessment.signals[..],
+        [RiskSignal::ImpossibleTravel { speed_kmh, .. }] if speed_kmh > 6000.0
+    ));
+    assert_eq!(assessment.level, LocationRisk::High);
+
+    // Then: The same trip a day later is plausible
+    let attempt = observation(last + Duration::days(1), "laptop", "DE", NEW_YORK);
+    assert_eq!(RiskEngine::default().assess(&history, &attempt).score, 0);
+}
+
+#[test]
+fn test_unusual_networks_and_missing_devices_are_scored() {
+    // Given: A first-ever attempt over Tor without a device id
+    let attempt = LoginObservation {
+        network_type: Some("TOR".to_string()),
+        device_id: None,
+        ..observation(start(), "unused", "DE", BERLIN)
+    };
+
+    // When: The attempt is scored without any history
+    let assessment = RiskEngine::default().assess(&AuthenticationHistory::default(), &attempt);
+
+    // Then: Only the network and the missing device count
+    assert_eq!(
+        assessment.signals,
+        vec![
+            RiskSignal::UnknownDevice,
+            RiskSignal::UnusualNetwork {
+                network_type: "TOR".to_string()
+            },
+        ]
+    );
+    assert_eq!(assessment.score, 80);
+    assert_eq!(assessment.level, LocationRisk::High);
+}
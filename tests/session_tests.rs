@@ -0,0 +1,370 @@
+//! Tests for sessions of authenticated identities
+//!
+//! User Story F36: Session Management
+//! As a person, I want my sign-ins tracked as sessions per device
+//! So that I can see where I am signed in and end sessions I no longer trust
+//!
+//! ```mermaid
+//! graph TD
+//!     A[Authentication Succeeded] -->|Once| B[StartSession]
+//!     B --> C{Same Device?}
+//!     C -->|Yes| D[Old Session Superseded]
+//!     B --> E[RefreshSession]
+//!     E --> F{Idle or Absolute Timeout?}
+//!     F -->|No| G[SessionRefreshed]
+//!     F -->|Yes| H[SessionEnded]
+//!     B --> I[RevokeSession / RevokeAllSessions]
+//! ```
+
+mod common;
+
+use bevy::app::App;
+use bevy::ecs::prelude::*;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use cim_domain_identity::persistence::{replay_events, EventStore, InMemoryEventStore};
+use cim_domain_identity::queries::{find_active_sessions, find_device_session};
+use cim_domain_identity::{
+    AuthMethod, AuthenticatePasswordCommand, AuthenticationClock, AuthenticationPolicy,
+    IdentityCredentials, IdentityEntity, IdentityError, IdentitySessions, LocationContext,
+    ManualClock, Password, RefreshSessionCommand, RefreshToken, RevokeAllSessionsCommand,
+    RevokeSessionCommand, SessionEndReason, SessionEnded, SessionPolicy, SessionStarted,
+    StartSessionCommand,
+};
+use common::{create_person, events, persistent_app, send, TEST_PARAMS};
+use std::sync::Arc;
+use uuid::Uuid;
+
+fn fixed_time() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 5, 1, 9, 0, 0).unwrap()
+}
+
+/// App recording to `store` on a manual clock, with short session timeouts
+/// and cheap hashing parameters
+fn app(store: Arc<InMemoryEventStore>, snapshots: &tempfile::TempDir, clock: &ManualClock) -> App {
+    let mut app = persistent_app(
+        store,
+        snapshots,
+        AuthenticationPolicy {
+            password: TEST_PARAMS,
+            session: SessionPolicy {
+                idle_timeout: Duration::minutes(30),
+                absolute_timeout: Duration::hours(8),
+                grant_timeout: Duration::minutes(5),
+            },
+            ..Default::default()
+        },
+    );
+    app.insert_resource(AuthenticationClock::new(clock.clone()));
+    app
+}
+
+fn authenticate(app: &mut App, person: Uuid) {
+    let error = send(
+        app,
+        AuthenticatePasswordCommand {
+            identity_id: person,
+            password: Password::new("hunter2"),
+        },
+    );
+    assert_eq!(error, None);
+}
+
+fn start_command(person: Uuid, device_id: &str) -> StartSessionCommand {
+    StartSessionCommand {
+        identity_id: person,
+        auth_method: AuthMethod::Password,
+        location: location(device_id),
+        refresh_token: RefreshToken::generate(),
+        attempt_id: None,
+    }
+}
+
+fn location(device_id: &str) -> LocationContext {
+    LocationContext {
+        ip_address: Some("10.0.0.1".to_string()),
+        country: Some("US".to_string()),
+        device_id: Some(device_id.to_string()),
+        ..Default::default()
+    }
+}
+
+/// Authenticate and start a session on a device, returning its id and refresh token
+fn start(app: &mut App, person: Uuid, device_id: &str) -> (Uuid, RefreshToken) {
+    authenticate(app, person);
+    let command = start_command(person, device_id);
+    let refresh_token = command.refresh_token.clone();
+    assert_eq!(send(app, command), None);
+    (refresh_token.session_id().unwrap(), refresh_token)
+}
+
+fn refresh(app: &mut App, refresh_token: &RefreshToken) -> (Option<IdentityError>, RefreshToken) {
+    let new_refresh_token = RefreshToken::for_session(refresh_token.session_id().unwrap());
+    let error = send(
+        app,
+        RefreshSessionCommand {
+            refresh_token: refresh_token.clone(),
+            new_refresh_token: new_refresh_token.clone(),
+        },
+    );
+    (error, new_refresh_token)
+}
+
+fn credentials(world: &mut World, person: Uuid) -> IdentityCredentials {
+    world
+        .query::<(&IdentityEntity, &IdentityCredentials)>()
+        .iter(world)
+        .find(|(identity, _)| identity.identity_id == person)
+        .map(|(_, credentials)| credentials.clone())
+        .unwrap()
+}
+
+fn sessions(world: &mut World, person: Uuid) -> IdentitySessions {
+    world
+        .query::<(&IdentityEntity, &IdentitySessions)>()
+        .iter(world)
+        .find(|(identity, _)| identity.identity_id == person)
+        .map(|(_, sessions)| sessions.clone())
+        .unwrap()
+}
+
+/// Test for User Story F36: One session per device
+#[test]
+fn test_sessions_are_tracked_per_device() {
+    // Given: A person signed in on a laptop and a phone
+    let dir = tempfile::tempdir().unwrap();
+    let clock = ManualClock::new(fixed_time());
+    let mut app = app(Arc::new(InMemoryEventStore::new()), &dir, &clock);
+    let person = create_person(&mut app);
+    let (laptop, laptop_token) = start(&mut app, person, "laptop");
+    clock.advance(Duration::minutes(1));
+    start(&mut app, person, "phone");
+
+    // When: The person signs in on the laptop again
+    clock.advance(Duration::minutes(1));
+    let (again, _) = start(&mut app, person, "laptop");
+
+    // Then: The old laptop session is superseded and two sessions remain
+    let ended = events::<SessionEnded>(&app).pop().unwrap();
+    assert_eq!(ended.session_id, laptop);
+    assert_eq!(ended.reason, SessionEndReason::Superseded);
+    let active = find_active_sessions(app.world_mut(), person);
+    assert_eq!(active.len(), 2);
+    assert_eq!(active[0].session_id, again);
+    assert_eq!(
+        find_device_session(app.world_mut(), person, "laptop").map(|s| s.session_id),
+        Some(again)
+    );
+
+    // Then: Only the hash of the refresh token is kept, and the superseded one is refused
+    let started = events::<SessionStarted>(&app);
+    assert_eq!(started[0].refresh_token_hash, laptop_token.hash());
+    assert!(!serde_json::to_string(&started[0])
+        .unwrap()
+        .contains(laptop_token.expose()));
+    assert_eq!(
+        refresh(&mut app, &laptop_token).0,
+        Some(IdentityError::SessionEnded(laptop))
+    );
+}
+
+/// Test for User Story F36: Each authentication starts one session
+#[test]
+fn test_sessions_need_an_unused_authentication() {
+    // Given: A person who has not authenticated
+    let dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(InMemoryEventStore::new());
+    let clock = ManualClock::new(fixed_time());
+    let mut app = app(store.clone(), &dir, &clock);
+    let person = create_person(&mut app);
+
+    // Then: No session can be started
+    assert_eq!(
+        send(&mut app, start_command(person, "laptop")),
+        Some(IdentityError::AuthenticationRequired)
+    );
+
+    // When: The person authenticates and starts a session
+    authenticate(&mut app, person);
+    assert_eq!(send(&mut app, start_command(person, "laptop")), None);
+
+    // Then: The same authentication cannot start another one
+    assert_eq!(
+        send(&mut app, start_command(person, "phone")),
+        Some(IdentityError::AuthenticationRequired)
+    );
+
+    // Then: An authentication cannot start a session for an attempt it did not grant
+    authenticate(&mut app, person);
+    let command = StartSessionCommand {
+        attempt_id: Some(Uuid::new_v4()),
+        ..start_command(person, "phone")
+    };
+    assert_eq!(
+        send(&mut app, command),
+        Some(IdentityError::AuthenticationRequired)
+    );
+
+    // Then: Nor once it is older than the grant timeout
+    clock.advance(Duration::minutes(6));
+    assert_eq!(
+        send(&mut app, start_command(person, "phone")),
+        Some(IdentityError::AuthenticationRequired)
+    );
+
+    // Then: Replay uses up authentications like the live world
+    let live = credentials(app.world_mut(), person);
+    assert!(live.session_grant.is_some());
+    let mut world = World::new();
+    replay_events(&mut world, &store.read_from(0).unwrap());
+    assert_eq!(credentials(&mut world, person), live);
+    authenticate(&mut app, person);
+    assert_eq!(send(&mut app, start_command(person, "phone")), None);
+    let live = credentials(app.world_mut(), person);
+    assert_eq!(live.session_grant, None);
+    let mut world = World::new();
+    replay_events(&mut world, &store.read_from(0).unwrap());
+    assert_eq!(credentials(&mut world, person), live);
+}
+
+/// Test for User Story F36: Refresh rotates the token and extends the idle deadline
+#[test]
+fn test_refresh_rotates_token_until_timeouts() {
+    // Given: A session
+    let dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(InMemoryEventStore::new());
+    let clock = ManualClock::new(fixed_time());
+    let mut app = app(store.clone(), &dir, &clock);
+    let person = create_person(&mut app);
+    let (session_id, token) = start(&mut app, person, "laptop");
+
+    // When: The session is refreshed before the idle timeout
+    clock.advance(Duration::minutes(20));
+    let (error, token2) = refresh(&mut app, &token);
+
+    // Then: The new token works and the old one does not
+    assert_eq!(error, None);
+    assert_eq!(
+        refresh(&mut app, &token).0,
+        Some(IdentityError::InvalidRefreshToken)
+    );
+    assert_eq!(
+        refresh(&mut app, &RefreshToken::from_presented("not-a-token")).0,
+        Some(IdentityError::InvalidRefreshToken)
+    );
+
+    // Then: Refreshing every 20 minutes keeps the session alive until its absolute lifetime
+    let mut token = token2;
+    for _ in 0..22 {
+        clock.advance(Duration::minutes(20));
+        let (error, next) = refresh(&mut app, &token);
+        assert_eq!(error, None);
+        token = next;
+    }
+    clock.advance(Duration::minutes(20));
+    assert_eq!(
+        refresh(&mut app, &token).0,
+        Some(IdentityError::SessionEnded(session_id))
+    );
+    let ended = events::<SessionEnded>(&app).pop().unwrap();
+    assert_eq!(ended.reason, SessionEndReason::AbsoluteTimeout);
+    assert_eq!(ended.ended_at, fixed_time() + Duration::hours(8));
+    assert_eq!(ended.causation_id, None);
+
+    // Then: Replaying the stored events rebuilds the same sessions
+    let live = sessions(app.world_mut(), person);
+    let mut world = World::new();
+    replay_events(&mut world, &store.read_from(0).unwrap());
+    assert_eq!(sessions(&mut world, person), live);
+}
+
+/// Test for User Story F36: Idle sessions end and are left out of listings
+#[test]
+fn test_idle_sessions_time_out() {
+    // Given: A session left unused
+    let dir = tempfile::tempdir().unwrap();
+    let clock = ManualClock::new(fixed_time());
+    let mut app = app(Arc::new(InMemoryEventStore::new()), &dir, &clock);
+    let person = create_person(&mut app);
+    let (session_id, token) = start(&mut app, person, "laptop");
+
+    // When: The idle timeout passes
+    clock.advance(Duration::minutes(31));
+
+    // Then: The session is no longer listed and cannot be refreshed
+    assert!(find_active_sessions(app.world_mut(), person).is_empty());
+    assert_eq!(
+        refresh(&mut app, &token).0,
+        Some(IdentityError::SessionEnded(session_id))
+    );
+    let ended = events::<SessionEnded>(&app).pop().unwrap();
+    assert_eq!(ended.reason, SessionEndReason::IdleTimeout);
+    assert_eq!(ended.ended_at, fixed_time() + Duration::minutes(30));
+}
+
+/// Test for User Story F36: Revoking sessions
+#[test]
+fn test_revoke_all_sessions_keeps_current() {
+    // Given: Sessions on three devices
+    let dir = tempfile::tempdir().unwrap();
+    let clock = ManualClock::new(fixed_time());
+    let mut app = app(Arc::new(InMemoryEventStore::new()), &dir, &clock);
+    let person = create_person(&mut app);
+    let (laptop, _) = start(&mut app, person, "laptop");
+    let (phone, _) = start(&mut app, person, "phone");
+    let (tablet, _) = start(&mut app, person, "tablet");
+
+    // When: The tablet session is revoked, then every other session but the laptop
+    assert_eq!(
+        send(
+            &mut app,
+            RevokeSessionCommand {
+                identity_id: person,
+                session_id: tablet,
+                revoked_by: person,
+                reason: "lost".to_string(),
+            }
+        ),
+        None
+    );
+    assert_eq!(
+        send(
+            &mut app,
+            RevokeAllSessionsCommand {
+                identity_id: person,
+                keep: Some(laptop),
+                revoked_by: person,
+                reason: "password changed".to_string(),
+            }
+        ),
+        None
+    );
+
+    // Then: Only the laptop session is left
+    let active = find_active_sessions(app.world_mut(), person);
+    assert_eq!(
+        active.iter().map(|s| s.session_id).collect::<Vec<_>>(),
+        vec![laptop]
+    );
+    let sessions = sessions(app.world_mut(), person);
+    assert_eq!(
+        sessions.session(phone).unwrap().end_reason,
+        Some(SessionEndReason::Revoked("password changed".to_string()))
+    );
+
+    // Then: Revoking an ended or unknown session is refused
+    let revoke = |session_id| RevokeSessionCommand {
+        identity_id: person,
+        session_id,
+        revoked_by: person,
+        reason: "again".to_string(),
+    };
+    assert_eq!(
+        send(&mut app, revoke(tablet)),
+        Some(IdentityError::SessionEnded(tablet))
+    );
+    let unknown = Uuid::new_v4();
+    assert_eq!(
+        send(&mut app, revoke(unknown)),
+        Some(IdentityError::SessionNotFound(unknown))
+    );
+}
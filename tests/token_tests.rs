@@ -0,0 +1,190 @@
+//! Tests for signed identity tokens
+//!
+//! User Story F21: Signed Identity Tokens
+//! As a downstream domain, I want a signed assertion of who the caller is
+//! So that I can trust the caller's identity without querying this domain
+//!
+//! ```mermaid
+//! graph LR
+//!     A[IssueIdentityToken] --> B{Identity Usable?}
+//!     B -->|Yes| C[Signed With Active kid]
+//!     B -->|No| D[Rejected]
+//!     C --> E[Offline Verify]
+//!     C --> F[ValidateIdentityToken]
+//!     F --> G{Still Usable?}
+//! ```
+
+mod common;
+
+use bevy::app::App;
+use bevy::ecs::prelude::*;
+use chrono::{Duration, Utc};
+use cim_domain_identity::{
+    ClaimType, CommandEnvelope, CommandRejected, IdentityClaims, IdentityError, IdentityPlugin,
+    IdentityStatus, IdentityTokenIssued, IdentityTokenValidated, IdentityType,
+    IssueIdentityTokenCommand, TokenIssuer, TokenSigningKey, UpdateIdentityCommand,
+    ValidateIdentityTokenCommand, VerificationLevel,
+};
+use common::{create_identity, send};
+use uuid::Uuid;
+
+/// App with one active person identity holding a verified email claim
+fn app_with_identity() -> (App, Uuid) {
+    let mut app = App::new();
+    app.add_plugins(IdentityPlugin::default());
+    let identity_id = create_identity(&mut app, IdentityType::Person, Some("ivy@example.com"));
+    {
+        let world = app.world_mut();
+        let mut claims = world
+            .query::<&mut IdentityClaims>()
+            .single_mut(world)
+            .unwrap();
+        assert!(claims.verify(&ClaimType::Email, "ivy@example.com"));
+    }
+    set_status(&mut app, identity_id, IdentityStatus::Active);
+    (app, identity_id)
+}
+
+fn set_status(app: &mut App, identity_id: Uuid, status: IdentityStatus) {
+    send(
+        app,
+        UpdateIdentityCommand {
+            identity_id,
+            new_status: Some(status),
+            updated_by: Uuid::new_v4(),
+        },
+    );
+}
+
+/// Issue a token, returning it or the rejection error
+fn issue(app: &mut App, identity_id: Uuid) -> Result<IdentityTokenIssued, IdentityError> {
+    let envelope = CommandEnvelope::new(IssueIdentityTokenCommand {
+        identity_id,
+        claim_types: vec![ClaimType::Email],
+        expires_at: None,
+    });
+    let command_id = envelope.command_id;
+    app.world_mut().send_event(envelope);
+    app.update();
+
+    let issued = app.world().resource::<Events<IdentityTokenIssued>>();
+    let mut reader = issued.get_cursor();
+    if let Some(token) = reader
+        .read(issued)
+        .find(|e| e.causation_id == Some(command_id))
+    {
+        return Ok(token.clone());
+    }
+    let rejections = app
+        .world()
+        .resource::<Events<CommandRejected<IssueIdentityTokenCommand>>>();
+    let mut reader = rejections.get_cursor();
+    Err(reader
+        .read(rejections)
+        .find(|r| r.command_id == command_id)
+        .map(|r| r.error.clone())
+        .expect("token was neither issued nor rejected"))
+}
+
+/// Validate a token against the current identity state
+fn validate(app: &mut App, token: &str) -> Result<IdentityTokenValidated, IdentityError> {
+    let envelope = CommandEnvelope::new(ValidateIdentityTokenCommand {
+        token: token.to_string(),
+    });
+    let command_id = envelope.command_id;
+    app.world_mut().send_event(envelope);
+    app.update();
+
+    let validated = app.world().resource::<Events<IdentityTokenValidated>>();
+    let mut reader = validated.get_cursor();
+    if let Some(event) = reader
+        .read(validated)
+        .find(|e| e.causation_id == Some(command_id))
+    {
+        return Ok(event.clone());
+    }
+    let rejections = app
+        .world()
+        .resource::<Events<CommandRejected<ValidateIdentityTokenCommand>>>();
+    let mut reader = rejections.get_cursor();
+    Err(reader
+        .read(rejections)
+        .find(|r| r.command_id == command_id)
+        .map(|r| r.error.clone())
+        .expect("token was neither validated nor rejected"))
+}
+
+#[test]
+fn test_token_carries_identity_and_verifies_offline() {
+    // Given: An active identity with a verified email claim
+    let (mut app, identity_id) = app_with_identity();
+
+    // When: A token is issued
+    let issued = issue(&mut app, identity_id).unwrap();
+
+    // Then: A verifier holding only public keys accepts it
+    let verifier = app.world().resource::<TokenIssuer>().verifier();
+    let claims = verifier.verify(&issued.token, Utc::now()).unwrap();
+    assert_eq!(claims.sub, identity_id);
+    assert_eq!(claims.identity_type, IdentityType::Person);
+    assert_eq!(claims.verification_level, VerificationLevel::Unverified);
+    assert_eq!(claims.claim(&ClaimType::Email), Some("ivy@example.com"));
+
+    // Then: Expired and tampered tokens are refused
+    assert_eq!(
+        verifier.verify(&issued.token, Utc::now() + Duration::hours(1)),
+        Err(IdentityError::TokenExpired)
+    );
+    let (signing_input, _) = issued.token.rsplit_once('.').unwrap();
+    let forged = format!("{signing_input}.{}", "A".repeat(86));
+    assert!(matches!(
+        verifier.verify(&forged, Utc::now()),
+        Err(IdentityError::InvalidToken(_))
+    ));
+}
+
+#[test]
+fn test_rotated_keys_verify_until_retired() {
+    // Given: A token signed before a key rotation
+    let (mut app, identity_id) = app_with_identity();
+    let old = issue(&mut app, identity_id).unwrap();
+    app.world_mut()
+        .resource_mut::<TokenIssuer>()
+        .rotate(TokenSigningKey::generate());
+
+    // When: A token is issued after the rotation
+    let new = issue(&mut app, identity_id).unwrap();
+
+    // Then: It is signed with the new key and both tokens validate
+    assert_ne!(new.kid, old.kid);
+    assert!(validate(&mut app, &old.token).is_ok());
+    assert!(validate(&mut app, &new.token).is_ok());
+
+    // Then: Retiring the old key invalidates tokens signed with it
+    assert!(app.world_mut().resource_mut::<TokenIssuer>().retire(&old.kid));
+    assert_eq!(
+        validate(&mut app, &old.token).unwrap_err(),
+        IdentityError::InvalidToken("unknown key id".to_string())
+    );
+    assert!(validate(&mut app, &new.token).is_ok());
+}
+
+#[test]
+fn test_tokens_refused_for_suspended_identities() {
+    // Given: A token issued to an identity that is then suspended
+    let (mut app, identity_id) = app_with_identity();
+    let issued = issue(&mut app, identity_id).unwrap();
+    set_status(&mut app, identity_id, IdentityStatus::Suspended);
+
+    // Then: The existing token no longer validates
+    assert_eq!(
+        validate(&mut app, &issued.token).unwrap_err(),
+        IdentityError::IdentityNotActive
+    );
+
+    // Then: No new token is issued
+    assert_eq!(
+        issue(&mut app, identity_id).unwrap_err(),
+        IdentityError::IdentityNotActive
+    );
+}
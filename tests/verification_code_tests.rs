@@ -0,0 +1,234 @@
+//! Tests for one-time verification codes
+//!
+//! User Story F23: Claim Verification Codes
+//! As an identity holder, I want to prove I own my email address or phone number
+//! So that exactly that claim is marked verified
+//!
+//! ```mermaid
+//! graph LR
+//!     A[SendVerificationCode] --> B{Throttled?}
+//!     B -->|No| C[Hash Stored]
+//!     C --> D[Notifier Delivers Code]
+//!     D --> E[ConfirmVerificationCode]
+//!     E --> F{Expired / Exhausted?}
+//!     F -->|No, Matches| G[ClaimVerified]
+//! ```
+
+use bevy::app::App;
+use bevy::ecs::prelude::*;
+use chrono::{Duration, TimeZone, Utc};
+use cim_domain_identity::{
+    ClaimType, ClaimVerified, CommandEnvelope, CommandRejected, ConfirmVerificationCodeCommand,
+    CreateIdentityCommand, IdentityClaims, IdentityEntity, IdentityError, IdentityPlugin,
+    IdentityType, InMemoryNotifier, SendVerificationCodeCommand, VerificationCodePolicy,
+    VerificationCodes,
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+use uuid::Uuid;
+
+const PHONE: &str = "+15550100";
+
+fn codes() -> (VerificationCodes, InMemoryNotifier) {
+    let notifier = InMemoryNotifier::default();
+    let codes = VerificationCodes::new(
+        VerificationCodePolicy::default(),
+        Arc::new(notifier.clone()),
+    );
+    (codes, notifier)
+}
+
+#[test]
+fn test_codes_expire_and_run_out_of_attempts() {
+    // Given: A phone code sent at a fixed time
+    let (mut codes, notifier) = codes();
+    let identity_id = Uuid::new_v4();
+    let sent_at = Utc.with_ymd_and_hms(2024, 5, 1, 8, 0, 0).unwrap();
+    let expires_at = codes
+        .send_code(identity_id, &ClaimType::Phone, PHONE, sent_at)
+        .unwrap()
+        .expires_at;
+    assert_eq!(expires_at, sent_at + Duration::minutes(5));
+    let code = notifier.last_code_for(PHONE).unwrap();
+
+    // When: It is presented after expiry
+    let late = codes.confirm_code(identity_id, &ClaimType::Phone, PHONE, &code, expires_at);
+
+    // Then: It is refused and dropped
+    assert_eq!(late, Err(IdentityError::VerificationCodeExpired));
+    assert!(codes
+        .challenge(identity_id, &ClaimType::Phone, PHONE)
+        .is_none());
+
+    // Given: A fresh code
+    let resent_at = expires_at + Duration::minutes(1);
+    codes
+        .send_code(identity_id, &ClaimType::Phone, PHONE, resent_at)
+        .unwrap();
+    let code = notifier.last_code_for(PHONE).unwrap();
+    let wrong = if code == "000000" { "111111" } else { "000000" };
+
+    // When: Wrong codes are presented up to the attempt limit
+    for _ in 1..codes.policy().max_attempts {
+        assert!(matches!(
+            codes.confirm_code(identity_id, &ClaimType::Phone, PHONE, wrong, resent_at),
+            Err(IdentityError::VerificationFailed(_))
+        ));
+    }
+    let last = codes.confirm_code(identity_id, &ClaimType::Phone, PHONE, wrong, resent_at);
+
+    // Then: The challenge is gone and even the right code fails
+    assert_eq!(last, Err(IdentityError::VerificationAttemptsExceeded));
+    assert!(codes
+        .confirm_code(identity_id, &ClaimType::Phone, PHONE, &code, resent_at)
+        .is_err());
+
+    // Then: A new code still waits for the resend interval
+    assert_eq!(
+        codes.send_code(identity_id, &ClaimType::Phone, PHONE, resent_at),
+        Err(IdentityError::VerificationCodeThrottled(
+            resent_at + codes.policy().resend_interval
+        ))
+    );
+}
+
+#[test]
+fn test_resending_is_throttled_and_replaces_the_code() {
+    // Given: An email code just sent
+    let (mut codes, notifier) = codes();
+    let identity_id = Uuid::new_v4();
+    let email = "grace@example.com";
+    let now = Utc::now();
+    codes
+        .send_code(identity_id, &ClaimType::Email, email, now)
+        .unwrap();
+    let first = notifier.last_code_for(email).unwrap();
+
+    // Then: Another one cannot be sent right away
+    let retry_at = now + codes.policy().resend_interval;
+    assert_eq!(
+        codes.send_code(
+            identity_id,
+            &ClaimType::Email,
+            email,
+            now + Duration::seconds(10)
+        ),
+        Err(IdentityError::VerificationCodeThrottled(retry_at))
+    );
+    assert_eq!(notifier.sent().len(), 1);
+
+    // When: A new code is sent once the interval has passed
+    codes
+        .send_code(identity_id, &ClaimType::Email, email, retry_at)
+        .unwrap();
+    let second = notifier.last_code_for(email).unwrap();
+
+    // Then: Only the new code is accepted
+    if first != second {
+        assert!(codes
+            .confirm_code(identity_id, &ClaimType::Email, email, &first, retry_at)
+            .is_err());
+    }
+    let challenge = codes
+        .confirm_code(identity_id, &ClaimType::Email, email, &second, retry_at)
+        .unwrap();
+    assert_eq!(challenge.value, email);
+
+    // Then: Names and other claims do not take codes
+    assert!(matches!(
+        codes.send_code(identity_id, &ClaimType::Name, "Grace", retry_at),
+        Err(IdentityError::InvalidOperation(_))
+    ));
+}
+
+#[test]
+fn test_confirming_a_code_verifies_only_that_claim() {
+    // Given: Two identities with email claims
+    let notifier = InMemoryNotifier::default();
+    let mut app = App::new();
+    app.insert_resource(VerificationCodes::new(
+        VerificationCodePolicy::default(),
+        Arc::new(notifier.clone()),
+    ));
+    app.add_plugins(IdentityPlugin::default());
+    for email in ["ada@example.com", "alan@example.com"] {
+        app.world_mut()
+            .send_event(CommandEnvelope::new(CreateIdentityCommand {
+                identity_type: IdentityType::Person,
+                initial_claims: Some(HashMap::from([(ClaimType::Email, email.to_string())])),
+                created_by: Uuid::new_v4(),
+                tags: vec![],
+                metadata: serde_json::Value::Null,
+                external_reference: None,
+            }));
+    }
+    app.update();
+    let ada = {
+        let world = app.world_mut();
+        world
+            .query::<(&IdentityEntity, &IdentityClaims)>()
+            .iter(world)
+            .find(|(_, claims)| claims.find(&ClaimType::Email, "ada@example.com").is_some())
+            .map(|(identity, _)| identity.identity_id)
+            .unwrap()
+    };
+
+    // Then: A code cannot be sent to an address the identity does not hold
+    let envelope = CommandEnvelope::new(SendVerificationCodeCommand {
+        identity_id: ada,
+        claim_type: ClaimType::Email,
+        value: "alan@example.com".to_string(),
+    });
+    let command_id = envelope.command_id;
+    app.world_mut().send_event(envelope);
+    app.update();
+    let rejections = app
+        .world()
+        .resource::<Events<CommandRejected<SendVerificationCodeCommand>>>();
+    let mut reader = rejections.get_cursor();
+    assert_eq!(
+        reader
+            .read(rejections)
+            .find(|r| r.command_id == command_id)
+            .map(|r| r.error.clone()),
+        Some(IdentityError::ClaimNotFound(ClaimType::Email))
+    );
+
+    // When: A code is sent to Ada's email and confirmed
+    app.world_mut()
+        .send_event(CommandEnvelope::new(SendVerificationCodeCommand {
+            identity_id: ada,
+            claim_type: ClaimType::Email,
+            value: "ada@example.com".to_string(),
+        }));
+    app.update();
+    let code = notifier.last_code_for("ada@example.com").unwrap();
+    app.world_mut()
+        .send_event(CommandEnvelope::new(ConfirmVerificationCodeCommand {
+            identity_id: ada,
+            claim_type: ClaimType::Email,
+            value: "ada@example.com".to_string(),
+            code,
+        }));
+    app.update();
+
+    // Then: Only Ada's claim is verified
+    let verified = app.world().resource::<Events<ClaimVerified>>();
+    let mut reader = verified.get_cursor();
+    assert_eq!(
+        reader
+            .read(verified)
+            .filter(|e| e.identity_id == ada)
+            .count(),
+        1
+    );
+    let world = app.world_mut();
+    for (identity, claims) in world
+        .query::<(&IdentityEntity, &IdentityClaims)>()
+        .iter(world)
+    {
+        for claim in claims.iter() {
+            assert_eq!(claim.verified, identity.identity_id == ada);
+        }
+    }
+}
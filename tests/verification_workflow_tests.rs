@@ -0,0 +1,405 @@
+//! Tests for per-method verification workflows
+//!
+//! User Story F22: Verification Workflows
+//! As an identity administrator, I want each verification method to run its own steps
+//! So that verification levels are only raised once those steps have succeeded
+//!
+//! ```mermaid
+//! graph LR
+//!     A[StartVerification] --> B[Workflow Per Method]
+//!     B -->|Email / Phone| C[Code Sent]
+//!     B -->|Document / In Person| D[Submission]
+//!     B -->|Third Party| E[Provider Check]
+//!     C --> F[ProcessVerification]
+//!     D --> G[Approval]
+//!     E --> H[Polled Result]
+//!     F --> I[CompleteVerification]
+//!     G --> I
+//!     H --> I
+//! ```
+
+mod common;
+
+use bevy::app::App;
+use bevy::ecs::prelude::*;
+use cim_domain_identity::{
+    ClaimType, CompleteWorkflowCommand, IdentityClaims, IdentityError, IdentityPlugin,
+    IdentityResult, IdentityType, IdentityVerification, IdentityWorkflow, InMemoryNotifier,
+    ProcessVerificationCommand, ProviderCheck, StartVerificationCommand,
+    ThirdPartyVerificationAdapter, VerificationCodePolicy, VerificationCodes,
+    VerificationCompleted, VerificationLevel, VerificationMethod, VerificationProviders,
+    VerificationWorkflow, WorkflowHistory, WorkflowOutcome, WorkflowStatus,
+};
+use common::{create_identity, events, send};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+/// Provider whose check results are set by the test
+#[derive(Default)]
+struct FakeProvider {
+    results: Mutex<HashMap<String, ProviderCheck>>,
+}
+
+impl FakeProvider {
+    fn decide(&self, check: ProviderCheck) {
+        for result in self.results.lock().unwrap().values_mut() {
+            *result = check.clone();
+        }
+    }
+}
+
+impl ThirdPartyVerificationAdapter for FakeProvider {
+    fn request_check(&self, identity_id: Uuid, _workflow_id: Uuid) -> IdentityResult<String> {
+        let reference = format!("check-{identity_id}");
+        self.results
+            .lock()
+            .unwrap()
+            .insert(reference.clone(), ProviderCheck::Pending);
+        Ok(reference)
+    }
+
+    fn check_result(&self, reference: &str) -> IdentityResult<ProviderCheck> {
+        self.results
+            .lock()
+            .unwrap()
+            .get(reference)
+            .cloned()
+            .ok_or_else(|| IdentityError::VerificationFailed("unknown check".to_string()))
+    }
+}
+
+const EMAIL: &str = "ada@example.com";
+
+/// App with one person identity holding an email claim, and the notifier its codes go to
+fn app_with_identity() -> (App, Uuid, InMemoryNotifier) {
+    let notifier = InMemoryNotifier::default();
+    let mut app = App::new();
+    app.insert_resource(VerificationCodes::new(
+        VerificationCodePolicy::default(),
+        Arc::new(notifier.clone()),
+    ));
+    app.add_plugins(IdentityPlugin::default());
+    let identity_id = create_identity(&mut app, IdentityType::Person, Some(EMAIL));
+    (app, identity_id, notifier)
+}
+
+fn start(app: &mut App, identity_id: Uuid, method: VerificationMethod) -> Option<IdentityError> {
+    send(
+        app,
+        StartVerificationCommand {
+            identity_id,
+            verification_method: method,
+            initiated_by: identity_id,
+            claim_value: Some(EMAIL.to_string()),
+        },
+    )
+}
+
+fn process(
+    app: &mut App,
+    identity_id: Uuid,
+    processed_by: Uuid,
+    verification_data: serde_json::Value,
+) -> Option<IdentityError> {
+    send(
+        app,
+        ProcessVerificationCommand {
+            identity_id,
+            verification_data,
+            processed_by,
+        },
+    )
+}
+
+fn verification(app: &mut App) -> IdentityVerification {
+    let world = app.world_mut();
+    world
+        .query::<&IdentityVerification>()
+        .single(world)
+        .unwrap()
+        .clone()
+}
+
+fn active_step(app: &mut App) -> Option<String> {
+    let world = app.world_mut();
+    world
+        .query::<&IdentityWorkflow>()
+        .iter(world)
+        .next()
+        .and_then(|workflow| workflow.current_step.clone())
+}
+
+#[test]
+fn test_email_code_confirms_verification() {
+    // Given: An email verification in progress
+    let (mut app, identity_id, notifier) = app_with_identity();
+    assert_eq!(
+        start(&mut app, identity_id, VerificationMethod::Email),
+        None
+    );
+
+    // Then: A code was sent to the email claim and the workflow waits for it
+    let code = notifier.last_code_for(EMAIL).unwrap();
+    assert_eq!(code.len(), 6);
+    assert_eq!(active_step(&mut app).as_deref(), Some("confirm_email_code"));
+
+    // Then: A second verification cannot start meanwhile
+    assert_eq!(
+        start(&mut app, identity_id, VerificationMethod::Phone),
+        Some(IdentityError::WorkflowInProgress)
+    );
+
+    // Then: The workflow cannot be completed without its steps
+    let workflow_id = {
+        let world = app.world_mut();
+        world
+            .query::<&IdentityWorkflow>()
+            .single(world)
+            .unwrap()
+            .workflow_id
+    };
+    let complete = CompleteWorkflowCommand {
+        workflow_id: cim_domain::WorkflowId::from_uuid(workflow_id),
+        outcome: WorkflowOutcome::Completed,
+        completed_by: identity_id,
+    };
+    assert!(matches!(
+        send(&mut app, complete),
+        Some(IdentityError::WorkflowError(_))
+    ));
+    assert_eq!(active_step(&mut app).as_deref(), Some("confirm_email_code"));
+
+    // When: A wrong code is presented
+    let wrong = if code == "000000" { "111111" } else { "000000" };
+    assert_eq!(
+        process(&mut app, identity_id, identity_id, json!({ "code": wrong })),
+        Some(IdentityError::VerificationFailed(
+            "Invalid verification code".to_string()
+        ))
+    );
+    assert_eq!(
+        verification(&mut app).verification_level,
+        VerificationLevel::Unverified
+    );
+
+    // When: The right code is presented
+    assert_eq!(
+        process(&mut app, identity_id, identity_id, json!({ "code": code })),
+        None
+    );
+
+    // Then: The claim is verified, the identity moves up one level and the workflow is finished
+    let world = app.world_mut();
+    assert!(
+        world
+            .query::<&IdentityClaims>()
+            .single(world)
+            .unwrap()
+            .find(&ClaimType::Email, EMAIL)
+            .unwrap()
+            .verified
+    );
+    let verification = verification(&mut app);
+    assert_eq!(verification.verification_level, VerificationLevel::Basic);
+    assert_eq!(
+        verification.verification_method,
+        Some(VerificationMethod::Email)
+    );
+    assert_eq!(active_step(&mut app), None);
+    assert!(events::<VerificationCompleted>(&app)
+        .iter()
+        .any(|c| c.verification_successful));
+
+    // Then: The finished workflow is kept with its history
+    let world = app.world_mut();
+    let (workflow, history) = world
+        .query_filtered::<(&IdentityWorkflow, &WorkflowHistory), Without<VerificationWorkflow>>()
+        .single(world)
+        .unwrap();
+    assert_eq!(workflow.status, WorkflowStatus::Completed);
+    assert!(workflow.completed_at.is_some());
+    assert!(history.total_duration.is_some());
+}
+
+#[test]
+fn test_code_goes_to_the_named_claim() {
+    // Given: An identity holding a second email address
+    let (mut app, identity_id, notifier) = app_with_identity();
+    let home = "ada@home.example";
+    {
+        let world = app.world_mut();
+        let mut claims = world
+            .query::<&mut IdentityClaims>()
+            .single_mut(world)
+            .unwrap();
+        let mut claim = claims.first(&ClaimType::Email).unwrap().clone();
+        claim.value = home.to_string();
+        claims.add(claim);
+    }
+
+    // Then: An address the identity does not hold is refused
+    let verify = |value: &str| StartVerificationCommand {
+        identity_id,
+        verification_method: VerificationMethod::Email,
+        initiated_by: identity_id,
+        claim_value: Some(value.to_string()),
+    };
+    assert_eq!(
+        send(&mut app, verify("eve@example.com")),
+        Some(IdentityError::ClaimNotFound(ClaimType::Email))
+    );
+
+    // When: The second address is verified
+    assert_eq!(send(&mut app, verify(home)), None);
+    let code = notifier.last_code_for(home).unwrap();
+    assert_eq!(
+        process(&mut app, identity_id, identity_id, json!({ "code": code })),
+        None
+    );
+
+    // Then: Only that address is verified
+    let world = app.world_mut();
+    let claims = world.query::<&IdentityClaims>().single(world).unwrap();
+    assert!(claims.find(&ClaimType::Email, home).unwrap().verified);
+    assert!(!claims.find(&ClaimType::Email, EMAIL).unwrap().verified);
+    assert!(notifier.last_code_for(EMAIL).is_none());
+}
+
+#[test]
+fn test_document_review_requires_another_identity() {
+    // Given: A document verification awaiting its submission
+    let (mut app, identity_id, _) = app_with_identity();
+    let reviewer = Uuid::new_v4();
+    assert_eq!(
+        start(&mut app, identity_id, VerificationMethod::Document),
+        None
+    );
+    assert_eq!(active_step(&mut app).as_deref(), Some("submit_document"));
+
+    // When: The document is submitted
+    let submission = json!({ "document_type": "passport" });
+    assert_eq!(
+        process(&mut app, identity_id, identity_id, submission),
+        None
+    );
+    assert_eq!(active_step(&mut app).as_deref(), Some("review_document"));
+
+    // Then: The identity cannot approve its own document
+    assert!(matches!(
+        process(
+            &mut app,
+            identity_id,
+            identity_id,
+            json!({ "approved": true })
+        ),
+        Some(IdentityError::InvalidOperation(_))
+    ));
+
+    // When: A reviewer approves it
+    assert_eq!(
+        process(&mut app, identity_id, reviewer, json!({ "approved": true })),
+        None
+    );
+
+    // Then: The reviewer is recorded as verifier
+    let verification = verification(&mut app);
+    assert_eq!(verification.verification_level, VerificationLevel::Basic);
+    assert_eq!(verification.verified_by, Some(reviewer));
+
+    // Then: The email claim the document did not confirm stays unverified
+    let world = app.world_mut();
+    assert!(
+        !world
+            .query::<&IdentityClaims>()
+            .single(world)
+            .unwrap()
+            .find(&ClaimType::Email, EMAIL)
+            .unwrap()
+            .verified
+    );
+}
+
+#[test]
+fn test_rejected_review_fails_verification() {
+    // Given: A submitted in-person check
+    let (mut app, identity_id, _) = app_with_identity();
+    assert_eq!(
+        start(&mut app, identity_id, VerificationMethod::InPerson),
+        None
+    );
+    assert_eq!(process(&mut app, identity_id, identity_id, json!({})), None);
+
+    // When: The reviewer declines it
+    let decision = json!({ "approved": false, "reason": "Photo does not match" });
+    assert_eq!(
+        process(&mut app, identity_id, Uuid::new_v4(), decision),
+        None
+    );
+
+    // Then: The verification completes unsuccessfully and a new one may start
+    let completions = events::<VerificationCompleted>(&app);
+    assert!(completions.iter().all(|c| !c.verification_successful));
+    assert_eq!(completions.len(), 1);
+    assert_eq!(
+        verification(&mut app).verification_level,
+        VerificationLevel::Unverified
+    );
+    assert_eq!(
+        start(&mut app, identity_id, VerificationMethod::Email),
+        None
+    );
+}
+
+#[test]
+fn test_third_party_result_is_polled() {
+    // Given: A registered provider
+    let (mut app, identity_id, _) = app_with_identity();
+    let provider = Arc::new(FakeProvider::default());
+    app.world_mut()
+        .resource_mut::<VerificationProviders>()
+        .register("acme", provider.clone());
+
+    // Then: Unknown providers are refused
+    let unknown = VerificationMethod::ThirdParty {
+        provider: "globex".to_string(),
+    };
+    assert!(matches!(
+        start(&mut app, identity_id, unknown),
+        Some(IdentityError::VerificationFailed(_))
+    ));
+
+    // When: A check is requested and still pending
+    let acme = VerificationMethod::ThirdParty {
+        provider: "acme".to_string(),
+    };
+    assert_eq!(start(&mut app, identity_id, acme), None);
+    app.update();
+    assert_eq!(
+        active_step(&mut app).as_deref(),
+        Some("await_provider_result")
+    );
+
+    // Then: The result cannot be answered by the identity
+    assert!(matches!(
+        process(
+            &mut app,
+            identity_id,
+            identity_id,
+            json!({ "matched": true })
+        ),
+        Some(IdentityError::WorkflowError(_))
+    ));
+
+    // When: The provider verifies the identity
+    provider.decide(ProviderCheck::Verified);
+    app.update();
+
+    // Then: The verification completes
+    assert_eq!(
+        verification(&mut app).verification_level,
+        VerificationLevel::Basic
+    );
+    assert_eq!(active_step(&mut app), None);
+}
@@ -0,0 +1,419 @@
+//! Tests for workflow approval steps
+//!
+//! User Story F32: Workflow Approvals
+//! As an approver, I want approval steps assigned to me with a quorum, comments and escalation
+//! So that workflows wait for the right people to decide and record who decided what
+//!
+//! ```mermaid
+//! graph LR
+//!     A[Approval Step Active] -->|identity / relationship| B[ApprovalRequested]
+//!     B --> C[Pending Approvals]
+//!     C -->|Approve / Reject + comment| D[ApprovalDecided]
+//!     D -->|quorum reached| E[Step Completed]
+//!     D -->|quorum unreachable| F[Step Failed]
+//!     B -->|timeout| G[ApprovalEscalated]
+//!     G --> C
+//! ```
+
+mod common;
+
+use bevy::app::App;
+use bevy::ecs::prelude::*;
+use chrono::Duration;
+use cim_domain_identity::persistence::{
+    replay_events, EventStore, FileSnapshotStore, IdentityPersistencePlugin, InMemoryEventStore,
+};
+use cim_domain_identity::queries::find_pending_approvals;
+use cim_domain_identity::{
+    ApprovalEscalated, ApprovalPolicy, ApprovalRequested, ApprovalTask, ApproveWorkflowStepCommand,
+    ApproverRule, EstablishRelationshipCommand, IdentityError, IdentityPlugin, IdentityType,
+    IdentityWorkflow, ProcessWorkflowStepCommand, RejectWorkflowStepCommand, RelationshipRules,
+    RelationshipType, StartWorkflowCommand, StepOutcome, StepStatus, StepType, TransitionCondition,
+    WorkflowDefinition, WorkflowDefinitions, WorkflowHistory, WorkflowStarted, WorkflowStatus,
+    WorkflowStep, WorkflowTransition, WorkflowType,
+};
+use common::{create_identity, events, send};
+use serde_json::json;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Make `manager` manage `subject`
+fn manage(app: &mut App, manager: Uuid, subject: Uuid) {
+    let command = EstablishRelationshipCommand {
+        from_identity: manager,
+        to_identity: subject,
+        relationship_type: RelationshipType::Manages,
+        rules: RelationshipRules {
+            allowed_types: vec![],
+            constraints: vec![],
+            require_mutual_consent: false,
+            allow_multiple: false,
+        },
+        established_by: manager,
+        metadata: None,
+    };
+    assert_eq!(send(app, command), None);
+}
+
+/// Submission decided by an approval step, then granted or denied
+fn grant_definition(policy: ApprovalPolicy) -> WorkflowDefinition {
+    let step = |step_id: &str, step_type: StepType, approval| WorkflowStep {
+        step_id: step_id.to_string(),
+        step_type,
+        status: StepStatus::Pending,
+        name: step_id.to_string(),
+        description: None,
+        required: true,
+        timeout_seconds: None,
+        started_at: None,
+        completed_at: None,
+        approval,
+        retry: None,
+        retries: 0,
+    };
+    let transition = |from_step: &str, to_step: &str, condition| WorkflowTransition {
+        from_step: from_step.to_string(),
+        to_step: to_step.to_string(),
+        condition,
+        metadata: serde_json::Value::Null,
+    };
+
+    WorkflowDefinition {
+        workflow_type: WorkflowType::Custom("grant".to_string()),
+        name: "grant".to_string(),
+        description: None,
+        steps: vec![
+            step("submit", StepType::Manual, None),
+            step("approve", StepType::Approval, Some(policy)),
+            step("grant", StepType::Automated, None),
+            step("deny", StepType::Notification, None),
+        ],
+        transitions: vec![
+            transition("submit", "approve", TransitionCondition::OnSuccess),
+            transition("approve", "grant", TransitionCondition::OnSuccess),
+            transition("approve", "deny", TransitionCondition::OnFailure),
+        ],
+    }
+}
+
+/// Start a grant workflow for `subject`, submit it and return the workflow id
+fn submit(app: &mut App, subject: Uuid, policy: ApprovalPolicy) -> Uuid {
+    app.world_mut()
+        .resource_mut::<WorkflowDefinitions>()
+        .register(grant_definition(policy))
+        .unwrap();
+    let command = StartWorkflowCommand {
+        identity_id: subject,
+        workflow_type: WorkflowType::Custom("grant".to_string()),
+        started_by: subject,
+        context: serde_json::Value::Null,
+    };
+    assert_eq!(send(app, command), None);
+    let workflow_id = events::<WorkflowStarted>(app).last().unwrap().workflow_id;
+
+    let command = ProcessWorkflowStepCommand {
+        workflow_id: cim_domain::WorkflowId::from_uuid(workflow_id),
+        step_name: "submit".to_string(),
+        step_data: json!({"resource": "billing"}),
+        outcome: StepOutcome::Completed,
+        processed_by: subject,
+    };
+    assert_eq!(send(app, command), None);
+    workflow_id
+}
+
+fn approve(workflow_id: Uuid, approver: Uuid, comment: &str) -> ApproveWorkflowStepCommand {
+    ApproveWorkflowStepCommand {
+        workflow_id: cim_domain::WorkflowId::from_uuid(workflow_id),
+        step_name: "approve".to_string(),
+        approver,
+        comment: Some(comment.to_string()),
+    }
+}
+
+fn reject(workflow_id: Uuid, approver: Uuid, comment: &str) -> RejectWorkflowStepCommand {
+    RejectWorkflowStepCommand {
+        workflow_id: cim_domain::WorkflowId::from_uuid(workflow_id),
+        step_name: "approve".to_string(),
+        approver,
+        comment: Some(comment.to_string()),
+    }
+}
+
+fn managers_policy(quorum: usize) -> ApprovalPolicy {
+    ApprovalPolicy {
+        approvers: vec![ApproverRule::Relationship(RelationshipType::Manages)],
+        quorum,
+        escalate_after_seconds: None,
+        escalate_to: vec![],
+    }
+}
+
+fn workflow(world: &mut World) -> (IdentityWorkflow, WorkflowHistory, ApprovalTask) {
+    let (workflow, history, task) = world
+        .query::<(&IdentityWorkflow, &WorkflowHistory, &ApprovalTask)>()
+        .single(world)
+        .unwrap();
+    (workflow.clone(), history.clone(), task.clone())
+}
+
+#[test]
+fn test_quorum_of_managers_approves_step() {
+    // Given: A subject managed by three identities and a stored event log
+    let dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(InMemoryEventStore::default());
+    let mut app = App::new();
+    app.add_plugins(IdentityPlugin::default())
+        .add_plugins(IdentityPersistencePlugin::new(
+            store.clone(),
+            Arc::new(FileSnapshotStore::open(dir.path()).unwrap()),
+        ));
+    app.finish();
+    let subject = create_identity(&mut app, IdentityType::Person, None);
+    let managers: Vec<Uuid> = (0..3)
+        .map(|_| create_identity(&mut app, IdentityType::Person, None))
+        .collect();
+    for manager in &managers {
+        manage(&mut app, *manager, subject);
+    }
+    let stranger = create_identity(&mut app, IdentityType::Person, None);
+
+    // When: The subject submits a request needing two of its managers
+    let workflow_id = submit(&mut app, subject, managers_policy(2));
+
+    // Then: Every manager is assigned and sees the pending approval
+    let requested = events::<ApprovalRequested>(&app);
+    assert_eq!(requested.len(), 1);
+    let mut approvers = requested[0].approvers.clone();
+    approvers.sort();
+    let mut sorted = managers.clone();
+    sorted.sort();
+    assert_eq!(approvers, sorted);
+    assert_eq!(requested[0].quorum, 2);
+    for manager in &managers {
+        let pending = find_pending_approvals(app.world_mut(), *manager);
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].workflow_id, workflow_id);
+    }
+    assert!(find_pending_approvals(app.world_mut(), stranger).is_empty());
+
+    // When: The first manager approves
+    assert_eq!(
+        send(
+            &mut app,
+            approve(workflow_id, managers[0], "Budget checked")
+        ),
+        None
+    );
+
+    // Then: The step still waits for a second approval
+    let (current, _, _) = workflow(app.world_mut());
+    assert_eq!(current.current_step.as_deref(), Some("approve"));
+    assert_eq!(current.status, WorkflowStatus::WaitingForApproval);
+    assert!(find_pending_approvals(app.world_mut(), managers[0]).is_empty());
+    assert_eq!(
+        find_pending_approvals(app.world_mut(), managers[1]).len(),
+        1
+    );
+
+    // Then: Strangers, the subject and repeat decisions are rejected
+    assert!(matches!(
+        send(&mut app, approve(workflow_id, stranger, "Looks fine")),
+        Some(IdentityError::PermissionDenied(_))
+    ));
+    assert!(matches!(
+        send(&mut app, approve(workflow_id, subject, "Mine")),
+        Some(IdentityError::PermissionDenied(_))
+    ));
+    assert!(matches!(
+        send(
+            &mut app,
+            reject(workflow_id, managers[0], "Changed my mind")
+        ),
+        Some(IdentityError::InvalidOperation(_))
+    ));
+
+    // When: A second manager approves
+    assert_eq!(
+        send(&mut app, approve(workflow_id, managers[2], "Agreed")),
+        None
+    );
+
+    // Then: The step completes and the workflow moves on, recording the decisions
+    let (current, history, task) = workflow(app.world_mut());
+    assert_eq!(current.current_step.as_deref(), Some("grant"));
+    assert_eq!(current.steps[1].status, StepStatus::Completed);
+    let comments: Vec<_> = history
+        .approvals
+        .iter()
+        .map(|d| (d.approver, d.comment.as_deref().unwrap()))
+        .collect();
+    assert_eq!(
+        comments,
+        vec![(managers[0], "Budget checked"), (managers[2], "Agreed")]
+    );
+    let transition = history.step_transitions.last().unwrap();
+    assert_eq!(transition.to_step, "grant");
+    assert_eq!(transition.transitioned_by, Some(managers[2]));
+    assert_eq!(transition.data["approved"], true);
+    assert_eq!(task.decisions.len(), 2);
+    assert!(find_pending_approvals(app.world_mut(), managers[1]).is_empty());
+
+    // Then: Replaying the stored events rebuilds the task and the decisions
+    let mut world = World::new();
+    replay_events(&mut world, &store.read_from(0).unwrap());
+    let (rebuilt, rebuilt_history, rebuilt_task) = workflow(&mut world);
+    assert_eq!(rebuilt.current_step.as_deref(), Some("grant"));
+    assert_eq!(rebuilt_history.approvals, history.approvals);
+    assert_eq!(rebuilt_task.approvers, task.approvers);
+    assert_eq!(rebuilt_task.decisions, task.decisions);
+}
+
+#[test]
+fn test_rejections_fail_step_once_quorum_is_unreachable() {
+    // Given: A request needing two of three managers
+    let mut app = App::new();
+    app.add_plugins(IdentityPlugin::default());
+    let subject = create_identity(&mut app, IdentityType::Person, None);
+    let managers: Vec<Uuid> = (0..3)
+        .map(|_| create_identity(&mut app, IdentityType::Person, None))
+        .collect();
+    for manager in &managers {
+        manage(&mut app, *manager, subject);
+    }
+    let workflow_id = submit(&mut app, subject, managers_policy(2));
+
+    // Then: The approval step is not processed directly
+    let command = ProcessWorkflowStepCommand {
+        workflow_id: cim_domain::WorkflowId::from_uuid(workflow_id),
+        step_name: "approve".to_string(),
+        step_data: json!({}),
+        outcome: StepOutcome::Completed,
+        processed_by: managers[0],
+    };
+    assert!(matches!(
+        send(&mut app, command),
+        Some(IdentityError::WorkflowError(_))
+    ));
+
+    // When: One manager rejects
+    assert_eq!(
+        send(&mut app, reject(workflow_id, managers[1], "Over budget")),
+        None
+    );
+    // Then: Two approvals are still possible
+    let (current, _, _) = workflow(app.world_mut());
+    assert_eq!(current.current_step.as_deref(), Some("approve"));
+
+    // When: A second manager rejects
+    assert_eq!(
+        send(
+            &mut app,
+            reject(workflow_id, managers[2], "Not this quarter")
+        ),
+        None
+    );
+
+    // Then: The step fails and the failure transition is taken
+    let (current, history, _) = workflow(app.world_mut());
+    assert_eq!(current.current_step.as_deref(), Some("deny"));
+    assert_eq!(current.steps[1].status, StepStatus::Failed);
+    let transition = history.step_transitions.last().unwrap();
+    assert_eq!(transition.reason, "failed: Approval rejected");
+    assert_eq!(transition.data["approved"], false);
+    assert!(history.approvals.iter().all(|d| !d.approved));
+
+    // Then: The remaining manager can no longer decide
+    assert!(matches!(
+        send(&mut app, approve(workflow_id, managers[0], "Late")),
+        Some(IdentityError::WorkflowError(_))
+    ));
+
+    // Then: Definitions with invalid approval policies are not registered
+    let mut definitions = WorkflowDefinitions::empty();
+    for policy in [
+        managers_policy(0),
+        ApprovalPolicy {
+            approvers: vec![],
+            ..managers_policy(1)
+        },
+        ApprovalPolicy {
+            escalate_after_seconds: Some(60),
+            ..managers_policy(1)
+        },
+    ] {
+        assert!(matches!(
+            definitions.register(grant_definition(policy)),
+            Err(IdentityError::WorkflowError(_))
+        ));
+    }
+    let mut misplaced = grant_definition(managers_policy(1));
+    misplaced.steps[0].approval = Some(managers_policy(1));
+    assert!(matches!(
+        definitions.register(misplaced),
+        Err(IdentityError::WorkflowError(_))
+    ));
+}
+
+#[test]
+fn test_undecided_step_escalates_after_timeout() {
+    // Given: A request assigned to a reviewer, escalating to the subject's managers
+    let mut app = App::new();
+    app.add_plugins(IdentityPlugin::default());
+    let subject = create_identity(&mut app, IdentityType::Person, None);
+    let reviewer = create_identity(&mut app, IdentityType::Person, None);
+    let manager = create_identity(&mut app, IdentityType::Person, None);
+    manage(&mut app, manager, subject);
+    let workflow_id = submit(
+        &mut app,
+        subject,
+        ApprovalPolicy {
+            approvers: vec![ApproverRule::Identity(reviewer)],
+            quorum: 1,
+            escalate_after_seconds: Some(3600),
+            escalate_to: vec![ApproverRule::Relationship(RelationshipType::Manages)],
+        },
+    );
+    assert!(find_pending_approvals(app.world_mut(), manager).is_empty());
+
+    // When: Less than an hour passes
+    app.update();
+    // Then: Nothing is escalated
+    assert!(events::<ApprovalEscalated>(&app).is_empty());
+
+    // When: The request waits longer than an hour
+    {
+        let world = app.world_mut();
+        let (mut workflow, mut task) = world
+            .query::<(&mut IdentityWorkflow, &mut ApprovalTask)>()
+            .single_mut(world)
+            .unwrap();
+        let step = workflow.active_step_mut().unwrap();
+        step.started_at = step.started_at.map(|t| t - Duration::hours(2));
+        task.requested_at -= Duration::hours(2);
+    }
+    app.update();
+
+    // Then: The managers are added as approvers once
+    let escalated = events::<ApprovalEscalated>(&app);
+    assert_eq!(escalated.len(), 1);
+    assert_eq!(escalated[0].added_approvers, vec![manager]);
+    app.update();
+    assert_eq!(events::<ApprovalEscalated>(&app).len(), 1);
+    let pending = find_pending_approvals(app.world_mut(), manager);
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].approvers, vec![reviewer, manager]);
+    assert!(pending[0].escalated_at.is_some());
+
+    // When: The manager approves
+    assert_eq!(
+        send(&mut app, approve(workflow_id, manager, "Escalated")),
+        None
+    );
+
+    // Then: The step completes without the reviewer
+    let (current, history, _) = workflow(app.world_mut());
+    assert_eq!(current.current_step.as_deref(), Some("grant"));
+    assert_eq!(history.approvals.len(), 1);
+    assert!(find_pending_approvals(app.world_mut(), reviewer).is_empty());
+}
@@ -0,0 +1,396 @@
+//! Tests for pausing, resuming, cancelling and retrying workflows
+//!
+//! User Story F33: Workflow Control
+//! As a workflow operator, I want to pause, resume, cancel and retry workflows
+//! So that running workflows can be held, stopped or recovered without losing their history
+//!
+//! ```mermaid
+//! graph LR
+//!     A[Running] -->|PauseWorkflow| B[Paused]
+//!     B -->|ResumeWorkflow| A
+//!     A -->|CancelWorkflow| C[Cancelled]
+//!     B -->|CancelWorkflow| C
+//!     A -->|step fails| D[Failed]
+//!     D -->|RetryWorkflowStep within policy| A
+//!     C --> E[Kept with WorkflowHistory]
+//!     D --> E
+//! ```
+
+mod common;
+
+use bevy::app::App;
+use bevy::ecs::prelude::*;
+use chrono::Duration;
+use cim_domain_identity::persistence::{
+    replay_events, EventStore, FileSnapshotStore, IdentityPersistencePlugin, InMemoryEventStore,
+};
+use cim_domain_identity::{
+    CancelWorkflowCommand, CompleteWorkflowCommand, IdentityError, IdentityPlugin, IdentityType,
+    IdentityWorkflow, PauseWorkflowCommand, ProcessWorkflowStepCommand, ResumeWorkflowCommand,
+    RetryPolicy, RetryWorkflowStepCommand, StartWorkflowCommand, StepOutcome, StepStatus, StepType,
+    TransitionCondition, WorkflowCancelled, WorkflowCompleted, WorkflowDefinition,
+    WorkflowDefinitions, WorkflowHistory, WorkflowOutcome, WorkflowPaused, WorkflowStarted,
+    WorkflowStatus, WorkflowStep, WorkflowStepRetried, WorkflowTransition, WorkflowType,
+};
+use common::{create_identity, events, send};
+use serde_json::json;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Data import run as `fetch` then `store`, where `fetch` may be retried
+fn import_definition(
+    timeout_seconds: Option<u64>,
+    retry: Option<RetryPolicy>,
+) -> WorkflowDefinition {
+    let step = |step_id: &str, retry| WorkflowStep {
+        step_id: step_id.to_string(),
+        step_type: StepType::Manual,
+        status: StepStatus::Pending,
+        name: step_id.to_string(),
+        description: None,
+        required: true,
+        timeout_seconds,
+        started_at: None,
+        completed_at: None,
+        approval: None,
+        retry,
+        retries: 0,
+    };
+
+    WorkflowDefinition {
+        workflow_type: WorkflowType::Custom("import".to_string()),
+        name: "import".to_string(),
+        description: None,
+        steps: vec![step("fetch", retry), step("store", None)],
+        transitions: vec![WorkflowTransition {
+            from_step: "fetch".to_string(),
+            to_step: "store".to_string(),
+            condition: TransitionCondition::OnSuccess,
+            metadata: serde_json::Value::Null,
+        }],
+    }
+}
+
+/// Start an import workflow for a new identity and return its id
+fn start_import(app: &mut App, definition: WorkflowDefinition) -> Uuid {
+    app.world_mut()
+        .resource_mut::<WorkflowDefinitions>()
+        .register(definition)
+        .unwrap();
+    let identity_id = create_identity(app, IdentityType::Person, None);
+    let command = StartWorkflowCommand {
+        identity_id,
+        workflow_type: WorkflowType::Custom("import".to_string()),
+        started_by: identity_id,
+        context: serde_json::Value::Null,
+    };
+    assert_eq!(send(app, command), None);
+    events::<WorkflowStarted>(app).last().unwrap().workflow_id
+}
+
+fn process(workflow_id: Uuid, step_name: &str, outcome: StepOutcome) -> ProcessWorkflowStepCommand {
+    ProcessWorkflowStepCommand {
+        workflow_id: cim_domain::WorkflowId::from_uuid(workflow_id),
+        step_name: step_name.to_string(),
+        step_data: json!({}),
+        outcome,
+        processed_by: Uuid::new_v4(),
+    }
+}
+
+fn pause(workflow_id: Uuid) -> PauseWorkflowCommand {
+    PauseWorkflowCommand {
+        workflow_id: cim_domain::WorkflowId::from_uuid(workflow_id),
+        paused_by: Uuid::new_v4(),
+        reason: Some("Source offline".to_string()),
+    }
+}
+
+fn resume(workflow_id: Uuid) -> ResumeWorkflowCommand {
+    ResumeWorkflowCommand {
+        workflow_id: cim_domain::WorkflowId::from_uuid(workflow_id),
+        resumed_by: Uuid::new_v4(),
+    }
+}
+
+fn cancel(workflow_id: Uuid) -> CancelWorkflowCommand {
+    CancelWorkflowCommand {
+        workflow_id: cim_domain::WorkflowId::from_uuid(workflow_id),
+        cancelled_by: Uuid::new_v4(),
+        reason: Some("No longer needed".to_string()),
+    }
+}
+
+fn retry(workflow_id: Uuid) -> RetryWorkflowStepCommand {
+    RetryWorkflowStepCommand {
+        workflow_id: cim_domain::WorkflowId::from_uuid(workflow_id),
+        step_name: "fetch".to_string(),
+        retried_by: Uuid::new_v4(),
+    }
+}
+
+fn workflow(world: &mut World, workflow_id: Uuid) -> (IdentityWorkflow, WorkflowHistory) {
+    let (workflow, history) = world
+        .query::<(&IdentityWorkflow, &WorkflowHistory)>()
+        .iter(world)
+        .find(|(workflow, _)| workflow.workflow_id == workflow_id)
+        .unwrap();
+    (workflow.clone(), history.clone())
+}
+
+/// Move the failure of the `fetch` step `ago` into the past
+fn fail_earlier(app: &mut App, ago: Duration) {
+    let world = app.world_mut();
+    let mut workflow = world
+        .query::<&mut IdentityWorkflow>()
+        .single_mut(world)
+        .unwrap();
+    let step = workflow.active_step_mut().unwrap();
+    step.completed_at = step.completed_at.map(|t| t - ago);
+}
+
+#[test]
+fn test_paused_workflow_holds_its_step_until_resumed() {
+    // Given: A running import whose steps time out after an hour
+    let mut app = App::new();
+    app.add_plugins(IdentityPlugin::default());
+    let workflow_id = start_import(&mut app, import_definition(Some(3600), None));
+
+    // When: The workflow is paused
+    assert_eq!(send(&mut app, pause(workflow_id)), None);
+
+    // Then: It waits on its step and accepts no further progress
+    let (paused, _) = workflow(app.world_mut(), workflow_id);
+    assert_eq!(paused.status, WorkflowStatus::Paused);
+    assert_eq!(paused.current_step.as_deref(), Some("fetch"));
+    assert_eq!(
+        events::<WorkflowPaused>(&app)[0].reason.as_deref(),
+        Some("Source offline")
+    );
+    assert!(matches!(
+        send(
+            &mut app,
+            process(workflow_id, "fetch", StepOutcome::Completed)
+        ),
+        Some(IdentityError::WorkflowError(_))
+    ));
+    assert!(matches!(
+        send(&mut app, pause(workflow_id)),
+        Some(IdentityError::WorkflowError(_))
+    ));
+
+    // When: The workflow stays paused for two hours
+    {
+        let world = app.world_mut();
+        let mut workflow = world
+            .query::<&mut IdentityWorkflow>()
+            .single_mut(world)
+            .unwrap();
+        workflow.paused_at = workflow.paused_at.map(|t| t - Duration::hours(2));
+        let step = workflow.active_step_mut().unwrap();
+        step.started_at = step.started_at.map(|t| t - Duration::hours(2));
+    }
+    app.update();
+
+    // Then: The step does not time out while paused
+    let (paused, _) = workflow(app.world_mut(), workflow_id);
+    assert_eq!(paused.status, WorkflowStatus::Paused);
+
+    // When: The workflow is resumed
+    assert_eq!(send(&mut app, resume(workflow_id)), None);
+
+    // Then: The paused time does not count towards the timeout and the step can be processed
+    let (resumed, _) = workflow(app.world_mut(), workflow_id);
+    assert_eq!(resumed.status, WorkflowStatus::WaitingForInput);
+    assert!(resumed.paused_at.is_none());
+    assert!(matches!(
+        send(&mut app, resume(workflow_id)),
+        Some(IdentityError::WorkflowError(_))
+    ));
+    assert_eq!(
+        send(
+            &mut app,
+            process(workflow_id, "fetch", StepOutcome::Completed)
+        ),
+        None
+    );
+    let (resumed, _) = workflow(app.world_mut(), workflow_id);
+    assert_eq!(resumed.current_step.as_deref(), Some("store"));
+}
+
+#[test]
+fn test_cancelled_and_completed_workflows_keep_their_history() {
+    // Given: Two running imports and a stored event log
+    let dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(InMemoryEventStore::default());
+    let mut app = App::new();
+    app.add_plugins(IdentityPlugin::default())
+        .add_plugins(IdentityPersistencePlugin::new(
+            store.clone(),
+            Arc::new(FileSnapshotStore::open(dir.path()).unwrap()),
+        ));
+    app.finish();
+    let cancelled_id = start_import(&mut app, import_definition(None, None));
+    assert_eq!(
+        send(
+            &mut app,
+            process(cancelled_id, "fetch", StepOutcome::Completed)
+        ),
+        None
+    );
+    let completed_id = start_import(&mut app, import_definition(None, None));
+
+    // When: The first is paused and cancelled, the second completed
+    assert_eq!(send(&mut app, pause(cancelled_id)), None);
+    assert_eq!(send(&mut app, cancel(cancelled_id)), None);
+    let command = CompleteWorkflowCommand {
+        workflow_id: cim_domain::WorkflowId::from_uuid(completed_id),
+        outcome: WorkflowOutcome::Completed,
+        completed_by: Uuid::new_v4(),
+    };
+    assert_eq!(send(&mut app, command), None);
+
+    // Then: Both workflows are kept, finished, with their history
+    let (cancelled, history) = workflow(app.world_mut(), cancelled_id);
+    assert_eq!(cancelled.status, WorkflowStatus::Cancelled);
+    assert!(cancelled.completed_at.is_some());
+    assert_eq!(history.step_transitions.len(), 1);
+    assert_eq!(history.step_transitions[0].to_step, "store");
+    assert_eq!(events::<WorkflowCancelled>(&app).len(), 1);
+    let (completed, _) = workflow(app.world_mut(), completed_id);
+    assert_eq!(completed.status, WorkflowStatus::Completed);
+    assert_eq!(
+        events::<WorkflowCompleted>(&app)[0].final_status,
+        WorkflowStatus::Completed
+    );
+
+    // Then: Finished workflows accept no further commands
+    assert!(matches!(
+        send(&mut app, cancel(cancelled_id)),
+        Some(IdentityError::WorkflowError(_))
+    ));
+    assert!(matches!(
+        send(&mut app, pause(completed_id)),
+        Some(IdentityError::WorkflowError(_))
+    ));
+    assert!(matches!(
+        send(
+            &mut app,
+            process(cancelled_id, "store", StepOutcome::Completed)
+        ),
+        Some(IdentityError::WorkflowError(_))
+    ));
+
+    // Then: Replaying the stored events rebuilds both finished workflows
+    let mut world = World::new();
+    replay_events(&mut world, &store.read_from(0).unwrap());
+    let (rebuilt, rebuilt_history) = workflow(&mut world, cancelled_id);
+    assert_eq!(rebuilt.status, WorkflowStatus::Cancelled);
+    assert_eq!(rebuilt_history.step_transitions.len(), 1);
+    let (rebuilt, _) = workflow(&mut world, completed_id);
+    assert_eq!(rebuilt.status, WorkflowStatus::Completed);
+}
+
+#[test]
+fn test_failed_step_is_retried_within_its_policy() {
+    // Given: An import whose fetch step allows three attempts, a minute apart at first
+    let mut app = App::new();
+    app.add_plugins(IdentityPlugin::default());
+    let policy = RetryPolicy {
+        max_attempts: 3,
+        backoff_seconds: 60,
+    };
+    let workflow_id = start_import(&mut app, import_definition(None, Some(policy.clone())));
+
+    // When: The fetch step fails the workflow
+    let failed = StepOutcome::Failed("Source unreachable".to_string());
+    assert_eq!(
+        send(&mut app, process(workflow_id, "fetch", failed.clone())),
+        None
+    );
+
+    // Then: It cannot be retried before the backoff has passed
+    let (current, _) = workflow(app.world_mut(), workflow_id);
+    assert!(matches!(current.status, WorkflowStatus::Failed(_)));
+    assert!(matches!(
+        send(&mut app, retry(workflow_id)),
+        Some(IdentityError::InvalidOperation(_))
+    ));
+
+    // When: A minute has passed
+    fail_earlier(&mut app, Duration::seconds(61));
+    assert_eq!(send(&mut app, retry(workflow_id)), None);
+
+    // Then: The step runs again as the second attempt and the retry is recorded
+    let (current, history) = workflow(app.world_mut(), workflow_id);
+    assert_eq!(current.status, WorkflowStatus::WaitingForInput);
+    assert_eq!(current.steps[0].status, StepStatus::Active);
+    assert_eq!(current.steps[0].retries, 1);
+    assert!(current.completed_at.is_none());
+    assert_eq!(events::<WorkflowStepRetried>(&app)[0].attempt, 2);
+    let transition = history.step_transitions.last().unwrap();
+    assert_eq!(
+        (transition.from_step.as_str(), transition.to_step.as_str()),
+        ("fetch", "fetch")
+    );
+    assert_eq!(transition.reason, "retry");
+
+    // When: It fails again
+    assert_eq!(
+        send(&mut app, process(workflow_id, "fetch", failed.clone())),
+        None
+    );
+
+    // Then: The second retry waits twice as long
+    fail_earlier(&mut app, Duration::seconds(90));
+    assert!(matches!(
+        send(&mut app, retry(workflow_id)),
+        Some(IdentityError::InvalidOperation(_))
+    ));
+    fail_earlier(&mut app, Duration::seconds(31));
+    assert_eq!(send(&mut app, retry(workflow_id)), None);
+
+    // When: The last attempt fails too
+    assert_eq!(send(&mut app, process(workflow_id, "fetch", failed)), None);
+    fail_earlier(&mut app, Duration::hours(1));
+
+    // Then: No attempts are left
+    assert!(matches!(
+        send(&mut app, retry(workflow_id)),
+        Some(IdentityError::InvalidOperation(_))
+    ));
+    let (current, _) = workflow(app.world_mut(), workflow_id);
+    assert_eq!(current.steps[0].retries, 2);
+    assert!(matches!(current.status, WorkflowStatus::Failed(_)));
+
+    // Then: Steps without a retry policy and policies without attempts are refused
+    let mut definitions = WorkflowDefinitions::empty();
+    assert!(matches!(
+        definitions.register(import_definition(
+            None,
+            Some(RetryPolicy {
+                max_attempts: 0,
+                ..policy
+            })
+        )),
+        Err(IdentityError::WorkflowError(_))
+    ));
+    let mut app = App::new();
+    app.add_plugins(IdentityPlugin::default());
+    let workflow_id = start_import(&mut app, import_definition(None, None));
+    assert_eq!(
+        send(
+            &mut app,
+            process(
+                workflow_id,
+                "fetch",
+                StepOutcome::Failed("Source unreachable".to_string())
+            )
+        ),
+        None
+    );
+    assert!(matches!(
+        send(&mut app, retry(workflow_id)),
+        Some(IdentityError::InvalidOperation(_))
+    ));
+}
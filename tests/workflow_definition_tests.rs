@@ -0,0 +1,237 @@
+//! Tests for workflow definitions
+//!
+//! User Story F29: Workflow Definitions
+//! As a workflow designer, I want to declare the steps and transitions of each workflow type
+//! So that started workflows follow a validated step graph instead of an empty one
+//!
+//! ```mermaid
+//! graph LR
+//!     A[Code / JSON / YAML] -->|register| B[WorkflowDefinitions]
+//!     B -->|validate| C{Single entry, reachable, terminal}
+//!     D[StartWorkflow] --> B
+//!     B -->|instantiate| E[IdentityWorkflow]
+//!     E --> F[Entry Step Active]
+//! ```
+
+mod common;
+
+use bevy::app::App;
+use cim_domain_identity::{
+    IdentityError, IdentityPlugin, IdentityType, IdentityWorkflow, StartWorkflowCommand,
+    StepStatus, StepType, TransitionCondition, WorkflowDefinition, WorkflowDefinitions,
+    WorkflowStarted, WorkflowStatus, WorkflowStep, WorkflowTransition, WorkflowType,
+};
+use common::{create_identity, events, send};
+use uuid::Uuid;
+
+/// Start a workflow and return the error it was rejected with, if any
+fn start(app: &mut App, identity_id: Uuid, workflow_type: WorkflowType) -> Option<IdentityError> {
+    send(
+        app,
+        StartWorkflowCommand {
+            identity_id,
+            workflow_type,
+            started_by: identity_id,
+            context: serde_json::Value::Null,
+        },
+    )
+}
+
+fn step(step_id: &str) -> WorkflowStep {
+    WorkflowStep {
+        step_id: step_id.to_string(),
+        step_type: StepType::Manual,
+        status: StepStatus::Pending,
+        name: step_id.to_string(),
+        description: None,
+        required: true,
+        timeout_seconds: None,
+        started_at: None,
+        completed_at: None,
+        approval: None,
+        retry: None,
+        retries: 0,
+    }
+}
+
+fn transition(from_step: &str, to_step: &str) -> WorkflowTransition {
+    WorkflowTransition {
+        from_step: from_step.to_string(),
+        to_step: to_step.to_string(),
+        condition: TransitionCondition::Always,
+        metadata: serde_json::Value::Null,
+    }
+}
+
+fn definition(steps: &[&str], transitions: &[(&str, &str)]) -> WorkflowDefinition {
+    WorkflowDefinition {
+        workflow_type: WorkflowType::Custom("review".to_string()),
+        name: "review".to_string(),
+        description: None,
+        steps: steps.iter().map(|s| step(s)).collect(),
+        transitions: transitions
+            .iter()
+            .map(|(from, to)| transition(from, to))
+            .collect(),
+    }
+}
+
+#[test]
+fn test_start_instantiates_builtin_definition() {
+    // Given: An identity and the built-in definitions
+    let mut app = App::new();
+    app.add_plugins(IdentityPlugin::default());
+    let identity_id = create_identity(&mut app, IdentityType::Person, None);
+
+    // When: An onboarding workflow is started
+    assert_eq!(start(&mut app, identity_id, WorkflowType::Onboarding), None);
+
+    // Then: The workflow holds the definition's steps with the entry step active
+    let workflow = app
+        .world_mut()
+        .query::<&IdentityWorkflow>()
+        .single(app.world())
+        .unwrap()
+        .clone();
+    assert_eq!(workflow.steps.len(), 3);
+    assert_eq!(workflow.transitions.len(), 2);
+    assert_eq!(workflow.current_step.as_deref(), Some("collect_profile"));
+    assert_eq!(workflow.status, WorkflowStatus::WaitingForInput);
+    let entry = workflow.active_step().unwrap();
+    assert_eq!(entry.status, StepStatus::Active);
+    assert!(entry.started_at.is_some());
+    assert!(workflow.steps[1..]
+        .iter()
+        .all(|s| s.status == StepStatus::Pending));
+
+    // Then: The started event carries the instantiated graph
+    let started = events::<WorkflowStarted>(&app);
+    assert_eq!(started[0].steps.len(), 3);
+    assert_eq!(started[0].transitions.len(), 2);
+}
+
+#[test]
+fn test_invalid_graphs_are_rejected() {
+    let mut definitions = WorkflowDefinitions::empty();
+
+    // Given / When / Then: Graphs without a single entry step are rejected
+    let two_entries = definition(&["a", "b", "c"], &[("a", "c"), ("b", "c")]);
+    let cycle = definition(&["a", "b"], &[("a", "b"), ("b", "a")]);
+    // Then: Steps unreachable from the entry step are rejected
+    let unreachable = definition(&["a", "b", "c", "d"], &[("a", "b"), ("c", "d"), ("d", "c")]);
+    // Then: Steps that never reach a terminal step are rejected
+    let trapped = definition(
+        &["a", "b", "c", "d"],
+        &[("a", "b"), ("a", "c"), ("c", "d"), ("d", "c")],
+    );
+    // Then: Transitions must name known steps and step ids must be unique
+    let unknown = definition(&["a", "b"], &[("a", "b"), ("b", "z")]);
+    let duplicate = definition(&["a", "a"], &[]);
+    let empty = definition(&[], &[]);
+
+    for invalid in [
+        two_entries,
+        cycle,
+        unreachable,
+        trapped,
+        unknown,
+        duplicate,
+        empty,
+    ] {
+        assert!(
+            matches!(
+                definitions.register(invalid.clone()),
+                Err(IdentityError::WorkflowError(_))
+            ),
+            "{invalid:?} was accepted"
+        );
+    }
+    assert!(definitions
+        .get(&WorkflowType::Custom("review".to_string()))
+        .is_none());
+
+    // Then: A branching graph with two terminal steps is valid
+    let branching = definition(&["a", "b", "c"], &[("a", "b"), ("a", "c")]);
+    assert_eq!(definitions.register(branching.clone()), Ok(()));
+    let terminals: Vec<_> = branching
+        .terminal_steps()
+        .map(|s| s.step_id.as_str())
+        .collect();
+    assert_eq!(terminals, vec!["b", "c"]);
+}
+
+#[test]
+fn test_custom_definitions_load_from_files() {
+    // Given: A custom workflow type without a definition
+    let mut app = App::new();
+    app.add_plugins(IdentityPlugin::default());
+    let identity_id = create_identity(&mut app, IdentityType::Person, None);
+    let review = WorkflowType::Custom("review".to_string());
+
+    // When: It is started
+    // Then: It is rejected
+    assert!(matches!(
+        start(&mut app, identity_id, review.clone()),
+        Some(IdentityError::WorkflowError(_))
+    ));
+
+    // When: Its definition is loaded from a YAML file
+    let dir = tempfile::tempdir().unwrap();
+    let yaml = dir.path().join("workflows.yaml");
+    std::fs::write(
+        &yaml,
+        r#"
+- workflow_type: !Custom review
+  name: review
+  steps:
+    - step_id: submit
+      step_type: Manual
+      name: Submit
+      description: null
+      timeout_seconds: null
+      started_at: null
+      completed_at: null
+    - step_id: approve
+      step_type: Approval
+      name: Approve
+      description: null
+      timeout_seconds: 86400
+      started_at: null
+      completed_at: null
+  transitions:
+    - from_step: submit
+      to_step: approve
+      condition: OnSuccess
+"#,
+    )
+    .unwrap();
+    let loaded = app
+        .world_mut()
+        .resource_mut::<WorkflowDefinitions>()
+        .load_file(&yaml)
+        .unwrap();
+    assert_eq!(loaded, 1);
+
+    // Then: The custom workflow starts on its entry step
+    assert_eq!(start(&mut app, identity_id, review.clone()), None);
+    let workflow = app
+        .world_mut()
+        .query::<&IdentityWorkflow>()
+        .single(app.world())
+        .unwrap()
+        .clone();
+    assert_eq!(workflow.workflow_type, review);
+    assert_eq!(workflow.current_step.as_deref(), Some("submit"));
+    assert!(workflow.steps.iter().all(|s| s.required));
+
+    // Then: JSON files holding an invalid definition register nothing
+    let json = dir.path().join("workflows.json");
+    let invalid = definition(&["a", "b"], &[("a", "b"), ("b", "a")]);
+    std::fs::write(&json, serde_json::to_string(&vec![invalid]).unwrap()).unwrap();
+    let mut definitions = app.world().resource::<WorkflowDefinitions>().clone();
+    assert!(matches!(
+        definitions.load_file(&json),
+        Err(IdentityError::WorkflowError(_))
+    ));
+    assert_eq!(definitions.get(&review).unwrap().steps.len(), 2);
+}
@@ -0,0 +1,333 @@
+//! Tests for the workflow step engine
+//!
+//! User Story F30: Workflow Step Engine
+//! As a workflow participant, I want processed steps to move the workflow along its transitions
+//! So that each workflow follows its definition to the end and keeps a record of the path taken
+//!
+//! ```mermaid
+//! graph LR
+//!     A[ProcessWorkflowStep] --> B{Outcome}
+//!     B -->|Completed / Skipped| C[OnSuccess / FieldEquals / Always]
+//!     B -->|Failed| D[OnFailure / Always]
+//!     C --> E[Next Step Active]
+//!     D --> E
+//!     E --> F[WorkflowHistory]
+//!     C -->|terminal step| G[Workflow Completed]
+//! ```
+
+mod common;
+
+use bevy::app::App;
+use bevy::ecs::prelude::*;
+use cim_domain_identity::persistence::{
+    replay_events, EventStore, FileSnapshotStore, IdentityPersistencePlugin, InMemoryEventStore,
+};
+use cim_domain_identity::{
+    CommandEnvelope, CommandRejected, IdentityError, IdentityPlugin, IdentityType,
+    IdentityWorkflow, ProcessWorkflowStepCommand, StartWorkflowCommand, StepOutcome, StepStatus,
+    StepType, TransitionCondition, WorkflowDefinition, WorkflowDefinitions, WorkflowHistory,
+    WorkflowStarted, WorkflowStatus, WorkflowStep, WorkflowTransition, WorkflowType,
+};
+use common::{create_identity, events};
+use serde_json::json;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Start a review workflow for a new person identity and return its id
+fn start_review(app: &mut App) -> Uuid {
+    app.world_mut()
+        .resource_mut::<WorkflowDefinitions>()
+        .register(review_definition())
+        .unwrap();
+
+    let identity_id = create_identity(app, IdentityType::Person, None);
+
+    app.world_mut()
+        .send_event(CommandEnvelope::new(StartWorkflowCommand {
+            identity_id,
+            workflow_type: WorkflowType::Custom("review".to_string()),
+            started_by: identity_id,
+            context: serde_json::Value::Null,
+        }));
+    app.update();
+
+    events::<WorkflowStarted>(app)[0].workflow_id
+}
+
+/// Submission assessed by score, then a fast track or a manual review, then an optional notice
+fn review_definition() -> WorkflowDefinition {
+    let step = |step_id: &str, step_type: StepType, required: bool| WorkflowStep {
+        step_id: step_id.to_string(),
+        step_type,
+        status: StepStatus::Pending,
+        name: step_id.to_string(),
+        description: None,
+        required,
+        timeout_seconds: None,
+        started_at: None,
+        completed_at: None,
+        approval: None,
+        retry: None,
+        retries: 0,
+    };
+    let transition = |from_step: &str, to_step: &str, condition| WorkflowTransition {
+        from_step: from_step.to_string(),
+        to_step: to_step.to_string(),
+        condition,
+        metadata: serde_json::Value::Null,
+    };
+
+    WorkflowDefinition {
+        workflow_type: WorkflowType::Custom("review".to_string()),
+        name: "review".to_string(),
+        description: None,
+        steps: vec![
+            step("submit", StepType::Manual, true),
+            step("assess", StepType::Automated, true),
+            step("fast_track", StepType::Automated, true),
+            step("manual_review", StepType::Approval, true),
+            step("notify", StepType::Notification, false),
+        ],
+        transitions: vec![
+            transition("submit", "assess", TransitionCondition::Always),
+            transition(
+                "assess",
+                "fast_track",
+                TransitionCondition::FieldEquals {
+                    field: "score.band".to_string(),
+                    value: json!("low"),
+                },
+            ),
+            transition("assess", "manual_review", TransitionCondition::OnSuccess),
+            transition("fast_track", "notify", TransitionCondition::OnSuccess),
+            transition("manual_review", "notify", TransitionCondition::OnSuccess),
+            transition("manual_review", "assess", TransitionCondition::OnFailure),
+        ],
+    }
+}
+
+/// Process a step and return the error it was rejected with, if any
+fn process(
+    app: &mut App,
+    workflow_id: Uuid,
+    step_name: &str,
+    outcome: StepOutcome,
+    step_data: serde_json::Value,
+) -> Option<IdentityError> {
+    let envelope = CommandEnvelope::new(ProcessWorkflowStepCommand {
+        workflow_id: cim_domain::WorkflowId::from_uuid(workflow_id),
+        step_name: step_name.to_string(),
+        step_data,
+        outcome,
+        processed_by: Uuid::new_v4(),
+    });
+    let command_id = envelope.command_id;
+    app.world_mut().send_event(envelope);
+    app.update();
+
+    let rejections = app
+        .world()
+        .resource::<Events<CommandRejected<ProcessWorkflowStepCommand>>>();
+    let mut reader = rejections.get_cursor();
+    reader
+        .read(rejections)
+        .find(|r| r.command_id == command_id)
+        .map(|r| r.error.clone())
+}
+
+fn workflow(world: &mut World) -> (IdentityWorkflow, WorkflowHistory) {
+    let (workflow, history) = world
+        .query::<(&IdentityWorkflow, &WorkflowHistory)>()
+        .single(world)
+        .unwrap();
+    (workflow.clone(), history.clone())
+}
+
+#[test]
+fn test_steps_follow_matching_transitions_to_completion() {
+    // Given: A started review workflow whose events are stored
+    let dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(InMemoryEventStore::default());
+    let mut app = App::new();
+    app.add_plugins(IdentityPlugin::default())
+        .add_plugins(IdentityPersistencePlugin::new(
+            store.clone(),
+            Arc::new(FileSnapshotStore::open(dir.path()).unwrap()),
+        ));
+    app.finish();
+    let workflow_id = start_review(&mut app);
+    let ok = |app: &mut App, step: &str, data| {
+        process(app, workflow_id, step, StepOutcome::Completed, data)
+    };
+
+    // When: The submission is completed
+    assert_eq!(
+        ok(&mut app, "submit", json!({"document": "passport"})),
+        None
+    );
+
+    // Then: The assessment is active and started
+    let (current, history) = workflow(app.world_mut());
+    assert_eq!(current.current_step.as_deref(), Some("assess"));
+    assert_eq!(current.status, WorkflowStatus::InProgress);
+    assert!(current.active_step().unwrap().started_at.is_some());
+    assert_eq!(current.steps[0].status, StepStatus::Completed);
+    assert_eq!(history.step_transitions.len(), 1);
+    assert_eq!(history.step_transitions[0].data["document"], "passport");
+
+    // When: The assessment reports a low score
+    assert_eq!(
+        ok(&mut app, "assess", json!({"score": {"band": "low"}})),
+        None
+    );
+
+    // Then: The field condition takes the fast track
+    let (current, _) = workflow(app.world_mut());
+    assert_eq!(current.current_step.as_deref(), Some("fast_track"));
+
+    // When: The fast track completes and the optional notice is skipped
+    assert_eq!(ok(&mut app, "fast_track", json!({})), None);
+    assert_eq!(
+        process(
+            &mut app,
+            workflow_id,
+            "notify",
+            StepOutcome::Skipped,
+            json!({"sent": false})
+        ),
+        None
+    );
+
+    // Then: The terminal step completes the workflow and its history
+    let (current, history) = workflow(app.world_mut());
+    assert_eq!(current.status, WorkflowStatus::Completed);
+    assert!(current.completed_at.is_some());
+    assert_eq!(current.steps[4].status, StepStatus::Skipped);
+    assert_eq!(current.steps[3].status, StepStatus::Pending);
+    let path: Vec<_> = history
+        .step_transitions
+        .iter()
+        .map(|t| t.to_step.as_str())
+        .collect();
+    assert_eq!(path, vec!["assess", "fast_track", "notify"]);
+    assert!(history.total_duration.is_some());
+    assert_eq!(history.completion_data, Some(json!({"sent": false})));
+
+    // Then: Replaying the stored events rebuilds the same workflow and history
+    let mut world = World::new();
+    replay_events(&mut world, &store.read_from(0).unwrap());
+    let (rebuilt, rebuilt_history) = workflow(&mut world);
+    assert_eq!(rebuilt.status, WorkflowStatus::Completed);
+    assert_eq!(rebuilt_history.step_transitions.len(), 3);
+}
+
+#[test]
+fn test_failures_take_failure_transitions_or_fail_the_workflow() {
+    // Given: A review workflow waiting for its manual review
+    let mut app = App::new();
+    app.add_plugins(IdentityPlugin::default());
+    let workflow_id = start_review(&mut app);
+    for (step, data) in [
+        ("submit", json!({})),
+        ("assess", json!({"score": {"band": "high"}})),
+    ] {
+        assert_eq!(
+            process(&mut app, workflow_id, step, StepOutcome::Completed, data),
+            None
+        );
+    }
+    let (current, _) = workflow(app.world_mut());
+    assert_eq!(current.status, WorkflowStatus::WaitingForApproval);
+
+    // When: The review fails
+    let failed = StepOutcome::Failed("documents unreadable".to_string());
+    assert_eq!(
+        process(
+            &mut app,
+            workflow_id,
+            "manual_review",
+            failed.clone(),
+            json!({})
+        ),
+        None
+    );
+
+    // Then: The failure transition sends the workflow back to the assessment
+    let (current, history) = workflow(app.world_mut());
+    assert_eq!(current.current_step.as_deref(), Some("assess"));
+    assert_eq!(current.steps[3].status, StepStatus::Failed);
+    assert_eq!(
+        history.step_transitions.last().unwrap().reason,
+        "failed: documents unreadable"
+    );
+
+    // Then: Only the active step is processed and required steps are not skipped
+    assert!(matches!(
+        process(
+            &mut app,
+            workflow_id,
+            "submit",
+            StepOutcome::Completed,
+            json!({})
+        ),
+        Some(IdentityError::WorkflowError(_))
+    ));
+    assert!(matches!(
+        process(
+            &mut app,
+            workflow_id,
+            "assess",
+            StepOutcome::Skipped,
+            json!({})
+        ),
+        Some(IdentityError::InvalidOperation(_))
+    ));
+
+    // When: A step without a failure transition fails
+    assert_eq!(
+        process(&mut app, workflow_id, "assess", failed.clone(), json!({})),
+        None
+    );
+
+    // Then: The workflow fails and takes no further steps
+    let (current, _) = workflow(app.world_mut());
+    assert_eq!(
+        current.status,
+        WorkflowStatus::Failed("documents unreadable".to_string())
+    );
+    assert!(matches!(
+        process(
+            &mut app,
+            workflow_id,
+            "assess",
+            StepOutcome::Completed,
+            json!({})
+        ),
+        Some(IdentityError::WorkflowError(_))
+    ));
+}
+
+#[test]
+fn test_manual_transitions_follow_the_chosen_step() {
+    // Given: A step with two manual transitions
+    let transitions: Vec<_> = ["approve", "reject"]
+        .into_iter()
+        .map(|to_step| WorkflowTransition {
+            from_step: "decide".to_string(),
+            to_step: to_step.to_string(),
+            condition: TransitionCondition::Manual,
+            metadata: serde_json::Value::Null,
+        })
+        .collect();
+
+    // When / Then: The output names the step taken
+    let scope = json!({"output": {"next_step": "reject"}});
+    let taken: Vec<_> = transitions
+        .iter()
+        .map(|t| {
+            t.condition
+                .matches(&StepOutcome::Completed, &t.to_step, &scope)
+        })
+        .collect();
+    assert_eq!(taken, vec![Ok(false), Ok(true)]);
+}
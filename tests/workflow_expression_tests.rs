@@ -0,0 +1,275 @@
+//! Tests for transition expressions
+//!
+//! User Story F31: Transition Expressions
+//! As a workflow designer, I want transitions guarded by expressions over workflow and identity data
+//! So that workflows branch on amounts, verification levels and verified claims without custom code
+//!
+//! ```mermaid
+//! graph LR
+//!     A[Definition] -->|register| B[Expression::parse]
+//!     B -->|invalid| C[WorkflowError]
+//!     D[ProcessWorkflowStep] --> E[context / output / outcome / identity]
+//!     E --> F[Expression::evaluate_bool]
+//!     F -->|true| G[Next Step]
+//!     F -->|error| C
+//! ```
+
+mod common;
+
+use bevy::app::App;
+use bevy::ecs::prelude::*;
+use cim_domain_identity::workflows::Expression;
+use cim_domain_identity::{
+    ClaimType, CommandEnvelope, CommandRejected, IdentityClaims, IdentityError, IdentityIndex,
+    IdentityPlugin, IdentityType, IdentityWorkflow, ProcessWorkflowStepCommand,
+    StartWorkflowCommand, StepOutcome, StepStatus, StepType, TransitionCondition,
+    WorkflowDefinition, WorkflowDefinitions, WorkflowStarted, WorkflowStep, WorkflowTransition,
+    WorkflowType,
+};
+use common::{create_identity, events};
+use serde_json::json;
+use uuid::Uuid;
+
+const EMAIL: &str = "ada@example.com";
+
+fn evaluate(expr: &str, scope: &serde_json::Value) -> Result<serde_json::Value, IdentityError> {
+    Expression::parse(expr)?.evaluate(scope)
+}
+
+fn payment_definition(condition: &str) -> WorkflowDefinition {
+    let step = |step_id: &str| WorkflowStep {
+        step_id: step_id.to_string(),
+        step_type: StepType::Automated,
+        status: StepStatus::Pending,
+        name: step_id.to_string(),
+        description: None,
+        required: true,
+        timeout_seconds: None,
+        started_at: None,
+        completed_at: None,
+        approval: None,
+        retry: None,
+        retries: 0,
+    };
+    let transition = |to_step: &str, condition| WorkflowTransition {
+        from_step: "screen".to_string(),
+        to_step: to_step.to_string(),
+        condition,
+        metadata: serde_json::Value::Null,
+    };
+
+    WorkflowDefinition {
+        workflow_type: WorkflowType::Custom("payment".to_string()),
+        name: "payment".to_string(),
+        description: None,
+        steps: vec![step("screen"), step("approve"), step("review")],
+        transitions: vec![
+            transition(
+                "approve",
+                TransitionCondition::Expression {
+                    expr: condition.to_string(),
+                },
+            ),
+            transition("review", TransitionCondition::OnSuccess),
+        ],
+    }
+}
+
+/// Start a payment workflow for a person with an email claim and return the app and workflow id
+fn start_payment(condition: &str, amount: u64, email_verified: bool) -> (App, Uuid) {
+    let mut app = App::new();
+    app.add_plugins(IdentityPlugin::default());
+    app.world_mut()
+        .resource_mut::<WorkflowDefinitions>()
+        .register(payment_definition(condition))
+        .unwrap();
+
+    let identity_id = create_identity(&mut app, IdentityType::Person, Some(EMAIL));
+    if email_verified {
+        let world = app.world_mut();
+        let entity = world
+            .resource::<IdentityIndex>()
+            .identity(identity_id)
+            .unwrap();
+        let mut claims = world.get_mut::<IdentityClaims>(entity).unwrap();
+        assert!(claims.verify(&ClaimType::Email, EMAIL));
+    }
+
+    app.world_mut()
+        .send_event(CommandEnvelope::new(StartWorkflowCommand {
+            identity_id,
+            workflow_type: WorkflowType::Custom("payment".to_string()),
+            started_by: identity_id,
+            context: json!({"amount": amount, "currency": "EUR"}),
+        }));
+    app.update();
+    let workflow_id = events::<WorkflowStarted>(&app)[0].workflow_id;
+
+    (app, workflow_id)
+}
+
+/// Complete the screening step and return the step taken next or the rejection
+fn screen(app: &mut App, workflow_id: Uuid) -> Result<Option<String>, IdentityError> {
+    let envelope = CommandEnvelope::new(ProcessWorkflowStepCommand {
+        workflow_id: cim_domain::WorkflowId::from_uuid(workflow_id),
+        step_name: "screen".to_string(),
+        step_data: json!({"risk": {"score": 12}}),
+        outcome: StepOutcome::Completed,
+        processed_by: Uuid::new_v4(),
+    });
+    let command_id = envelope.command_id;
+    app.world_mut().send_event(envelope);
+    app.update();
+
+    let rejections = app
+        .world()
+        .resource::<Events<CommandRejected<ProcessWorkflowStepCommand>>>();
+    let mut reader = rejections.get_cursor();
+    if let Some(rejection) = reader.read(rejections).find(|r| r.command_id == command_id) {
+        return Err(rejection.error.clone());
+    }
+    let world = app.world_mut();
+    let workflow = world.query::<&IdentityWorkflow>().single(world).unwrap();
+    Ok(workflow.current_step.clone())
+}
+
+#[test]
+fn test_expressions_evaluate_deterministically() {
+    // Given: Fields of a processed step
+    let scope = json!({
+        "context": {"amount": 1500, "tags": ["vip", "eu"], "risk-band": "low"},
+        "output": {"score": 7.5, "country": "NL"},
+        "outcome": "Completed",
+        "identity": {"verification_rank": 2, "claims": {"email": {"verified": true}}},
+    });
+
+    // When / Then: Arithmetic, comparisons, boolean logic and field access combine
+    for (expr, expected) in [
+        ("context.amount * 2 - 500 / 5 % 3", json!(2999)),
+        ("output.score + 0.5 == 8", json!(true)),
+        (
+            "-output.score < 0 && !(context.amount <= 1000)",
+            json!(true),
+        ),
+        (
+            "context.tags[0] == 'vip' || context.missing.field",
+            json!(true),
+        ),
+        ("context[\"risk-band\"] != \"high\"", json!(true)),
+        (
+            "output.country >= 'DE' && outcome == 'Completed'",
+            json!(true),
+        ),
+        (
+            "identity.claims.email.verified && identity.verification_rank >= 2",
+            json!(true),
+        ),
+        ("context.missing == null", json!(true)),
+        ("context.tags[5]", json!(null)),
+    ] {
+        assert_eq!(evaluate(expr, &scope), Ok(expected), "{expr}");
+    }
+
+    // Then: Type mismatches and arithmetic errors are reported, not guessed
+    for expr in [
+        "context.amount && true",
+        "context.tags < 3",
+        "output.country + 1",
+        "context.amount / (output.score - 7.5)",
+        "context.missing.field || false",
+    ] {
+        assert!(
+            matches!(evaluate(expr, &scope), Err(IdentityError::WorkflowError(_))),
+            "{expr}"
+        );
+    }
+    let amount = Expression::parse("context.amount").unwrap();
+    assert!(matches!(
+        amount.evaluate_bool(&scope),
+        Err(IdentityError::WorkflowError(message)) if message.contains("instead of a boolean")
+    ));
+}
+
+#[test]
+fn test_invalid_expressions_are_rejected_when_parsed() {
+    // Given / When / Then: Syntax errors, unknown fields and oversized expressions fail to parse
+    let deep = format!("{}true{}", "(".repeat(40), ")".repeat(40));
+    let long = vec!["context.a"; 200].join(" + ");
+    for expr in [
+        "",
+        "context.amount >",
+        "context.amount > 1 > 0",
+        "(context.amount > 1",
+        "context.amount = 1",
+        "'unterminated",
+        "context.",
+        "context[-1]",
+        "env.HOME == 'x'",
+        deep.as_str(),
+        long.as_str(),
+    ] {
+        assert!(
+            matches!(
+                Expression::parse(expr),
+                Err(IdentityError::WorkflowError(_))
+            ),
+            "{expr:?} was accepted"
+        );
+    }
+    assert!(matches!(
+        Expression::parse("context.amount > # 1"),
+        Err(IdentityError::WorkflowError(message)) if message.contains("column 18")
+    ));
+
+    // Then: Definitions with an invalid expression are not registered
+    let mut definitions = WorkflowDefinitions::empty();
+    assert!(matches!(
+        definitions.register(payment_definition("context.amount >")),
+        Err(IdentityError::WorkflowError(_))
+    ));
+    assert_eq!(
+        definitions.register(payment_definition("context.amount > 1")),
+        Ok(())
+    );
+}
+
+#[test]
+fn test_expression_transitions_read_context_and_identity() {
+    let condition = "context.amount <= 1000 || identity.claims.email.verified";
+
+    // Given: A large payment by an identity with an unverified email
+    let (mut app, workflow_id) = start_payment(condition, 5000, false);
+    // When: It is screened
+    // Then: It goes to review
+    assert_eq!(
+        screen(&mut app, workflow_id),
+        Ok(Some("review".to_string()))
+    );
+
+    // Given: A small payment by the same kind of identity
+    let (mut app, workflow_id) = start_payment(condition, 200, false);
+    // Then: It is approved
+    assert_eq!(
+        screen(&mut app, workflow_id),
+        Ok(Some("approve".to_string()))
+    );
+
+    // Given: A large payment by an identity with a verified email
+    let (mut app, workflow_id) = start_payment(condition, 5000, true);
+    // Then: It is approved
+    assert_eq!(
+        screen(&mut app, workflow_id),
+        Ok(Some("approve".to_string()))
+    );
+
+    // Given: An expression that fails on the identity's data
+    let (mut app, workflow_id) = start_payment("identity.verification_level > 1", 5000, true);
+    // Then: The step is rejected and the workflow stays on it
+    assert!(matches!(
+        screen(&mut app, workflow_id),
+        Err(IdentityError::WorkflowError(message)) if message.contains("cannot compare")
+    ));
+    let world = app.world_mut();
+    let workflow = world.query::<&IdentityWorkflow>().single(world).unwrap();
+    assert_eq!(workflow.current_step.as_deref(), Some("screen"));
+}